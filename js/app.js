@@ -11,6 +11,7 @@ import { initSplitPane } from './splitpane.js';
 class App {
     constructor() {
         this.sessions = []; // Array of session objects
+        this.pendingBanners = {}; // Pre-auth banners received before the session tab exists
         this.currentSessionId = null;
         this.currentConnectionId = null;
 
@@ -39,15 +40,18 @@ class App {
         this.bindModalEvents();
         initSplitPane();
 
-        // Load saved connections
+        // Load saved connections, asking for the master password first if they're encrypted
+        await this.unlockVault();
         await this.connectionManager.loadConnections();
+        this.applyRetryPolicy();
 
         // Listen for SSH events from Tauri backend
         const { listen } = await import('@tauri-apps/api/event');
 
         listen('ssh-data', (event) => {
-            const { sessionId, data } = event.payload;
-            const session = this.sessions.find(s => s.id === sessionId);
+            // The first terminal of a connection uses the session id as its channel id
+            const { channelId, data } = event.payload;
+            const session = this.sessions.find(s => s.id === channelId);
             if (session && session.terminalManager) {
                 session.terminalManager.writeData(new Uint8Array(data));
             }
@@ -63,10 +67,76 @@ class App {
         });
 
         listen('ssh-close', (event) => {
-            const { sessionId } = event.payload;
-            this.closeSession(sessionId);
+            const { sessionId, reason } = event.payload;
+            this.closeSession(sessionId).then(() => {
+                if (reason) this.setStatus('disconnected', `Disconnected: ${reason}`);
+            });
+        });
+
+        listen('ssh-host-key', async (event) => {
+            const { sessionId, host, port, keyType, fingerprint, previousFingerprint } = event.payload;
+            const message = previousFingerprint
+                ? `WARNING: the host key for ${host}:${port} has CHANGED!\n\nPrevious: ${previousFingerprint}\nNew (${keyType}): ${fingerprint}\n\nThis could indicate a man-in-the-middle attack. Trust the new key?`
+                : `The authenticity of ${host}:${port} can't be established.\n\n${keyType} key fingerprint: ${fingerprint}\n\nTrust this host and continue connecting?`;
+            const accept = confirm(message);
+            const { invoke } = await import('@tauri-apps/api/core');
+            await invoke('ssh_host_key_respond', { sessionId, accept });
+        });
+
+        listen('ssh-auth-prompt', async (event) => {
+            const { sessionId, name, instructions, prompts } = event.payload;
+            const header = [name, instructions].filter(Boolean).join('\n');
+            let responses = [];
+            for (const p of prompts) {
+                const answer = prompt(header ? `${header}\n\n${p.prompt}` : p.prompt);
+                if (answer === null) {
+                    responses = null;
+                    break;
+                }
+                responses.push(answer);
+            }
+            const { invoke } = await import('@tauri-apps/api/core');
+            await invoke('ssh_auth_respond', { sessionId, responses });
+        });
+
+        listen('ssh-exit', (event) => {
+            const { channelId, exitStatus, signal, coreDumped } = event.payload;
+            const session = this.sessions.find(s => s.id === channelId);
+            if (!session || !session.terminalManager) return;
+            const reason = signal
+                ? `killed by signal ${signal}${coreDumped ? ' (core dumped)' : ''}`
+                : `exited with code ${exitStatus}`;
+            session.terminalManager.writeData(`\r\n[Process ${reason}]\r\n`);
+        });
+
+        listen('ssh-banner', (event) => {
+            const { sessionId, banner } = event.payload;
+            this.pendingBanners[sessionId] = (this.pendingBanners[sessionId] || '') + banner;
+        });
+
+        listen('credential-request', async (event) => {
+            const { sessionId, kind, host, username } = event.payload;
+            const secret = prompt(`Enter ${kind} for ${username}@${host}`);
+            const { invoke } = await import('@tauri-apps/api/core');
+            await invoke('ssh_credential_respond', { sessionId, secret });
         });
 
+        listen('ssh-security-key-touch', () => {
+            this.setStatus('connecting', 'Touch your security key to continue...');
+        });
+
+        // ssh:// links opened while running, plus any the app was launched with
+        listen('open-url', (event) => {
+            event.payload.urls.forEach(url => this.openUrl(url));
+        });
+        try {
+            const { invoke } = await import('@tauri-apps/api/core');
+            const urls = await invoke('get_launch_urls');
+            urls.forEach(url => this.openUrl(url));
+        } catch (e) {
+            console.error('Failed to read launch URLs:', e);
+        }
+
         // Handle window resize for active terminal
         window.addEventListener('resize', () => {
             const session = this.sessions.find(s => s.id === this.currentSessionId);
@@ -89,6 +159,10 @@ class App {
                 this.disconnect();
             }
         });
+
+        document.getElementById('btn-local-shell').addEventListener('click', () => {
+            this.openLocalShell();
+        });
     }
 
     // ── Sidebar Tabs ─────────────────────────────────────────
@@ -122,6 +196,52 @@ class App {
             this.connectionManager.hideModal();
         });
 
+        // Import from ~/.ssh/config or other tools' exports
+        document.getElementById('btn-import-connections').addEventListener('click', () => {
+            this.connectionManager.showImportModal();
+        });
+
+        document.getElementById('btn-import-close').addEventListener('click', () => {
+            this.connectionManager.hideImportModal();
+        });
+
+        document.getElementById('btn-import-cancel').addEventListener('click', () => {
+            this.connectionManager.hideImportModal();
+        });
+
+        // Encrypted bundles for sharing connections
+        document.getElementById('conn-search').addEventListener('input', (e) => {
+            this.connectionManager.search(e.target.value);
+        });
+
+        document.getElementById('btn-export-connections').addEventListener('click', () => {
+            this.connectionManager.showExportModal();
+        });
+
+        document.getElementById('btn-export-close').addEventListener('click', () => {
+            this.connectionManager.hideExportModal();
+        });
+
+        document.getElementById('btn-export-cancel').addEventListener('click', () => {
+            this.connectionManager.hideExportModal();
+        });
+
+        document.getElementById('btn-export-apply').addEventListener('click', () => {
+            this.connectionManager.exportSelected();
+        });
+
+        document.getElementById('import-format').addEventListener('change', () => {
+            this.connectionManager.onImportFormatChange();
+        });
+
+        document.getElementById('btn-import-browse').addEventListener('click', () => {
+            this.connectionManager.chooseImportFile();
+        });
+
+        document.getElementById('btn-import-apply').addEventListener('click', () => {
+            this.connectionManager.importSelected();
+        });
+
         document.getElementById('btn-modal-cancel').addEventListener('click', () => {
             this.connectionManager.hideModal();
         });
@@ -136,8 +256,10 @@ class App {
         // Auth type toggle
         document.getElementById('conn-auth-type').addEventListener('change', (e) => {
             const isKey = e.target.value === 'keyfile';
-            document.getElementById('auth-password-fields').style.display = isKey ? 'none' : 'block';
+            const isPassword = e.target.value === 'password';
+            document.getElementById('auth-password-fields').style.display = isPassword ? 'block' : 'none';
             document.getElementById('auth-keyfile-fields').style.display = isKey ? 'block' : 'none';
+            document.getElementById('auth-pkcs11-fields').style.display = e.target.value === 'pkcs11' ? 'block' : 'none';
         });
 
         // Browse key file
@@ -173,6 +295,14 @@ class App {
             this.saveSettings();
         });
 
+        document.getElementById('btn-vault-set').addEventListener('click', () => {
+            this.setVaultPassword();
+        });
+
+        document.getElementById('btn-vault-remove').addEventListener('click', () => {
+            this.removeVaultPassword();
+        });
+
         // Show/hide custom editor path input
         document.getElementById('editor-preset').addEventListener('change', (e) => {
             const customGroup = document.getElementById('custom-editor-group');
@@ -215,6 +345,18 @@ class App {
 
     // ── Connection Logic ─────────────────────────────────────
 
+    async openUrl(url) {
+        try {
+            const { invoke } = await import('@tauri-apps/api/core');
+            const conn = await invoke('open_ssh_url', { url });
+            await this.connectionManager.loadConnections();
+            await this.connect(conn.id);
+        } catch (e) {
+            console.error('Failed to open URL:', e);
+            alert(`Cannot open ${url}: ${e}`);
+        }
+    }
+
     async connect(id) {
         const connection = this.connectionManager.connections.find(c => c.id === id);
         if (!connection) return;
@@ -228,14 +370,20 @@ class App {
 
         try {
             const { invoke } = await import('@tauri-apps/api/core');
-            const sessionId = await invoke('ssh_connect', {
+            const command = {
+                Mosh: 'mosh_connect',
+                Telnet: 'telnet_connect',
+            }[connection.connection_type] || 'ssh_connect';
+            const sessionId = await invoke(command, {
                 connectionId: connection.id
             });
 
             this.setStatus('connected', `Connected to ${connection.host}`);
 
-            // Create new session tab
-            this.addSession(sessionId, connection.name || connection.host);
+            // Create new session tab; Mosh and telnet terminals aren't SSH channels
+            const protocol = command === 'ssh_connect' ? 'ssh' : 'term';
+            this.addSession(sessionId, connection.name || connection.host, protocol);
+            if (protocol === 'ssh') this.offerPendingTransfers(sessionId, connection);
 
         } catch (e) {
             this.setStatus('error', `Connection failed: ${e}`);
@@ -243,6 +391,38 @@ class App {
         }
     }
 
+    // Offer to continue transfers left unfinished when the app was last closed
+    async offerPendingTransfers(sessionId, connection) {
+        try {
+            const { invoke } = await import('@tauri-apps/api/core');
+            const pending = await invoke('transfer_pending', { connectionId: connection.id });
+            if (pending.length === 0) return;
+            const list = pending
+                .map(t => `${t.direction === 'upload' ? '↑' : '↓'} ${t.remote_path}`)
+                .join('\n');
+            const resume = confirm(
+                `${pending.length} transfer(s) to ${connection.name || connection.host} were not finished:\n${list}\n\nResume them?`);
+            if (resume) {
+                await invoke('transfer_restore', { sessionId });
+            } else {
+                await invoke('transfer_discard_pending', { connectionId: connection.id });
+            }
+        } catch (e) {
+            console.warn('Failed to check for unfinished transfers', e);
+        }
+    }
+
+    async openLocalShell() {
+        try {
+            const { invoke } = await import('@tauri-apps/api/core');
+            const sessionId = await invoke('local_open', {});
+            this.addSession(sessionId, 'Local', 'term');
+        } catch (e) {
+            this.setStatus('error', `Failed to open local shell: ${e}`);
+            console.error(e);
+        }
+    }
+
     async disconnect() {
         if (!this.currentSessionId) return;
         this.closeSession(this.currentSessionId);
@@ -250,7 +430,7 @@ class App {
 
     // ── Session Management ───────────────────────────────────
 
-    addSession(sessionId, name) {
+    addSession(sessionId, name, protocol = 'ssh') {
         // Create terminal container
         const container = document.createElement('div');
         container.className = 'terminal-instance';
@@ -261,11 +441,19 @@ class App {
 
         // Create terminal manager instance
         const termManager = new TerminalManager(this);
-        termManager.init(sessionId, container);
+        termManager.init(sessionId, container, protocol);
+
+        // Show the server's pre-auth banner above the shell output
+        const banner = this.pendingBanners[sessionId];
+        if (banner) {
+            termManager.writeData(banner.replace(/\r?\n/g, '\r\n'));
+            delete this.pendingBanners[sessionId];
+        }
 
         const session = {
             id: sessionId,
             name: name,
+            protocol: protocol,
             terminalManager: termManager,
             container: container,
             fileManagerState: {
@@ -283,8 +471,8 @@ class App {
         const disBtn = document.getElementById('btn-disconnect');
         if (disBtn) disBtn.disabled = false;
 
-        // Initial file load
-        this.loadInitialFiles(sessionId);
+        // Initial file load (Mosh sessions have no SFTP)
+        if (protocol === 'ssh') this.loadInitialFiles(sessionId);
     }
 
     async loadInitialFiles(sessionId) {
@@ -348,7 +536,7 @@ class App {
         // Cleanup backend
         try {
             const { invoke } = await import('@tauri-apps/api/core');
-            await invoke('ssh_disconnect', { sessionId });
+            await invoke(`${session.protocol}_disconnect`, { sessionId });
         } catch (e) {
             console.error('Disconnect error:', e);
         }
@@ -436,18 +624,148 @@ class App {
 
     // ── Settings ─────────────────────────────────────────────
 
-    showSettingsModal() {
+    async showSettingsModal() {
         const modal = document.getElementById('settings-modal');
         const preset = localStorage.getItem('editorPreset') || 'system';
         const customPath = localStorage.getItem('editorCustomPath') || '';
 
+        await this.loadEditorOptions();
         document.getElementById('editor-preset').value = preset;
         document.getElementById('custom-editor-path').value = customPath;
         document.getElementById('custom-editor-group').style.display = preset === 'custom' ? 'block' : 'none';
+        document.getElementById('sftp-use-trash').checked = localStorage.getItem('sftpUseTrash') === 'true';
+        document.getElementById('edit-backup').checked = localStorage.getItem('editBackup') === 'true';
+        document.getElementById('transfer-retries').value = localStorage.getItem('transferRetries') ?? 3;
+        document.getElementById('vault-password').value = '';
+        await this.refreshVaultStatus();
 
         modal.style.display = 'flex';
     }
 
+    // ── Master Password ──────────────────────────────────────
+
+    // Resolves once the connection store is unlocked, or the user skipped unlocking it
+    async unlockVault() {
+        const { invoke } = await import('@tauri-apps/api/core');
+        let status;
+        try {
+            status = await invoke('vault_status');
+        } catch (e) {
+            console.error('Failed to check master password:', e);
+            return;
+        }
+        if (!status.encrypted || status.unlocked) return;
+
+        const modal = document.getElementById('vault-unlock-modal');
+        const form = document.getElementById('vault-unlock-form');
+        const input = document.getElementById('vault-unlock-password');
+        const error = document.getElementById('vault-unlock-error');
+        const skip = document.getElementById('btn-vault-skip');
+        const button = document.getElementById('btn-vault-unlock');
+        error.textContent = '';
+        modal.style.display = 'flex';
+        input.focus();
+
+        await new Promise((resolve) => {
+            const finish = () => {
+                form.removeEventListener('submit', onSubmit);
+                skip.removeEventListener('click', finish);
+                modal.style.display = 'none';
+                input.value = '';
+                resolve();
+            };
+            const onSubmit = async (e) => {
+                e.preventDefault();
+                button.disabled = true;
+                error.textContent = '';
+                try {
+                    await invoke('vault_unlock', { password: input.value });
+                    finish();
+                } catch (err) {
+                    error.textContent = err;
+                    input.select();
+                } finally {
+                    button.disabled = false;
+                }
+            };
+            form.addEventListener('submit', onSubmit);
+            skip.addEventListener('click', finish);
+        });
+    }
+
+    async refreshVaultStatus() {
+        const text = document.getElementById('vault-status');
+        try {
+            const { invoke } = await import('@tauri-apps/api/core');
+            const status = await invoke('vault_status');
+            if (!status.encrypted) {
+                text.textContent = 'Saved passwords are stored unencrypted. Set a master password to encrypt them.';
+            } else if (status.unlocked) {
+                text.textContent = 'Saved connections are encrypted. Enter a new password to change it.';
+            } else {
+                text.textContent = 'Saved connections are encrypted and locked. Restart to unlock them.';
+            }
+            document.getElementById('btn-vault-remove').disabled = !status.encrypted;
+        } catch (e) {
+            text.textContent = `Failed to check master password: ${e}`;
+        }
+    }
+
+    async setVaultPassword() {
+        const password = document.getElementById('vault-password').value;
+        if (!password) {
+            alert('Enter a master password first.');
+            return;
+        }
+        if (prompt('Confirm the master password:') !== password) {
+            alert('The passwords do not match.');
+            return;
+        }
+        try {
+            const { invoke } = await import('@tauri-apps/api/core');
+            await invoke('vault_set_password', { password });
+            document.getElementById('vault-password').value = '';
+            await this.refreshVaultStatus();
+        } catch (e) {
+            alert(`Failed to set master password: ${e}`);
+        }
+    }
+
+    async removeVaultPassword() {
+        const password = document.getElementById('vault-password').value;
+        if (!password) {
+            alert('Enter the current master password to remove it.');
+            return;
+        }
+        try {
+            const { invoke } = await import('@tauri-apps/api/core');
+            await invoke('vault_remove_password', { password });
+            document.getElementById('vault-password').value = '';
+            await this.refreshVaultStatus();
+            await this.connectionManager.loadConnections();
+        } catch (e) {
+            alert(`Failed to remove master password: ${e}`);
+        }
+    }
+
+    // Fill the editor picker from the backend's table for this platform
+    async loadEditorOptions() {
+        const select = document.getElementById('editor-preset');
+        try {
+            const { invoke } = await import('@tauri-apps/api/core');
+            const editors = await invoke('editor_list');
+            select.innerHTML = '';
+            select.appendChild(new Option('System Default', 'system'));
+            for (const editor of editors) {
+                const label = editor.available ? editor.name : `${editor.name} (not found)`;
+                select.appendChild(new Option(label, editor.id));
+            }
+            select.appendChild(new Option('Custom...', 'custom'));
+        } catch (e) {
+            console.error('Failed to list editors:', e);
+        }
+    }
+
     hideSettingsModal() {
         document.getElementById('settings-modal').style.display = 'none';
     }
@@ -458,10 +776,27 @@ class App {
 
         localStorage.setItem('editorPreset', preset);
         localStorage.setItem('editorCustomPath', customPath);
+        localStorage.setItem('sftpUseTrash', document.getElementById('sftp-use-trash').checked);
+        localStorage.setItem('editBackup', document.getElementById('edit-backup').checked);
+        localStorage.setItem('transferRetries', document.getElementById('transfer-retries').value);
+        this.applyRetryPolicy();
 
         this.hideSettingsModal();
     }
 
+    async applyRetryPolicy() {
+        const retries = localStorage.getItem('transferRetries');
+        if (retries === null) return;
+        try {
+            const { invoke } = await import('@tauri-apps/api/core');
+            const policy = await invoke('transfer_get_retry_policy');
+            policy.max_retries = Math.max(0, parseInt(retries, 10) || 0);
+            await invoke('transfer_set_retry_policy', { policy });
+        } catch (e) {
+            console.error('Failed to apply retry policy:', e);
+        }
+    }
+
     getEditorConfig(presetOverride = null, customPathOverride = null) {
         const preset = presetOverride || localStorage.getItem('editorPreset') || 'system';
         const customPath = customPathOverride || localStorage.getItem('editorCustomPath') || '';
@@ -474,22 +809,8 @@ class App {
             return customPath || null;
         }
 
-        // Map presets to common paths based on platform
-        const isMac = document.body.classList.contains('platform-macos');
-
-        const editorPaths = isMac ? {
-            'vscode': 'code',
-            'sublime': 'subl',
-            'textedit': 'open -e'
-        } : {
-            'vscode': 'code',
-            'notepad++': 'C:\\Program Files\\Notepad++\\notepad++.exe',
-            'sublime': 'C:\\Program Files\\Sublime Text\\sublime_text.exe',
-            'atom': '%LOCALAPPDATA%\\atom\\atom.exe',
-            'notepad': 'notepad.exe'
-        };
-
-        return editorPaths[preset] || (isMac ? null : editorPaths['notepad']);
+        // Editor ids are resolved by the backend's per-platform table
+        return preset;
     }
 }
 
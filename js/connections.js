@@ -8,21 +8,54 @@ export class ConnectionManager {
         this.app = app;
         this.connections = [];
         this.activeId = null;
+        // Search results shown instead of all connections while there's a query
+        this.query = '';
+        this.results = null;
     }
 
     async loadConnections() {
         try {
             const { invoke } = await import('@tauri-apps/api/core');
             this.connections = await invoke('get_connections');
-            this.render();
+            await this.refresh();
         } catch (e) {
             console.error('Failed to load connections:', e);
         }
     }
 
+    // Re-run the search after the connections changed, then redraw
+    async refresh() {
+        if (this.query) {
+            try {
+                const { invoke } = await import('@tauri-apps/api/core');
+                this.results = await invoke('search_connections', { query: this.query });
+            } catch (e) {
+                console.error('Failed to search connections:', e);
+                this.results = null;
+            }
+        } else {
+            this.results = null;
+        }
+        this.render();
+    }
+
+    async search(query) {
+        this.query = query.trim();
+        await this.refresh();
+    }
+
     render() {
         const list = document.getElementById('connections-list');
 
+        if (this.results && this.results.length === 0) {
+            list.innerHTML = `
+        <div class="empty-state">
+          <p>No matching connections</p>
+        </div>
+      `;
+            return;
+        }
+
         if (this.connections.length === 0) {
             list.innerHTML = `
         <div class="empty-state">
@@ -36,7 +69,7 @@ export class ConnectionManager {
             return;
         }
 
-        list.innerHTML = this.connections.map(conn => `
+        list.innerHTML = (this.results || this.connections).map(conn => `
       <div class="connection-item ${this.activeId === conn.id ? 'active' : ''}"
            data-id="${conn.id}"
            title="Double-click to connect">
@@ -50,6 +83,9 @@ export class ConnectionManager {
         <div class="conn-info">
           <div class="conn-name">${this.escapeHtml(conn.name)}</div>
           <div class="conn-host">${this.escapeHtml(conn.username)}@${this.escapeHtml(conn.host)}:${conn.port}</div>
+          ${conn.tags && conn.tags.length
+            ? `<div class="conn-tags">${conn.tags.map(t => `<span class="conn-tag">${this.escapeHtml(t)}</span>`).join('')}</div>`
+            : ''}
         </div>
         ${this.activeId === conn.id ? '<div class="conn-status-dot online"></div>' : ''}
         <div class="conn-actions">
@@ -107,22 +143,34 @@ export class ConnectionManager {
             document.getElementById('conn-host').value = conn.host;
             document.getElementById('conn-port').value = conn.port;
             document.getElementById('conn-username').value = conn.username;
-            document.getElementById('conn-auth-type').value = conn.auth_type === 'KeyFile' ? 'keyfile' : 'password';
+            document.getElementById('conn-tags').value = (conn.tags || []).join(', ');
+            document.getElementById('conn-auth-type').value = conn.auth_type.toLowerCase();
             document.getElementById('conn-password').value = conn.password || '';
             document.getElementById('conn-keypath').value = conn.private_key_path || '';
             document.getElementById('conn-passphrase').value = conn.passphrase || '';
+            document.getElementById('conn-pkcs11-module').value = conn.pkcs11_module || '';
+            // Keychain secrets aren't sent back; an empty field keeps the stored one
+            const inKeychain = !!conn.secret_ref;
+            document.getElementById('conn-keychain').checked = inKeychain;
+            document.getElementById('conn-password').placeholder = inKeychain ? 'Saved in keychain' : '••••••••';
+            document.getElementById('conn-passphrase').placeholder = inKeychain ? 'Saved in keychain' : 'Key passphrase';
 
             // Toggle auth fields
             const isKey = conn.auth_type === 'KeyFile';
-            document.getElementById('auth-password-fields').style.display = isKey ? 'none' : 'block';
+            const isPassword = conn.auth_type === 'Password';
+            document.getElementById('auth-password-fields').style.display = isPassword ? 'block' : 'none';
             document.getElementById('auth-keyfile-fields').style.display = isKey ? 'block' : 'none';
+            document.getElementById('auth-pkcs11-fields').style.display = conn.auth_type === 'Pkcs11' ? 'block' : 'none';
         } else {
             title.textContent = 'New Connection';
             document.getElementById('connection-form').reset();
             document.getElementById('conn-id').value = '';
             document.getElementById('conn-port').value = '22';
+            document.getElementById('conn-password').placeholder = '••••••••';
+            document.getElementById('conn-passphrase').placeholder = 'Key passphrase';
             document.getElementById('auth-password-fields').style.display = 'block';
             document.getElementById('auth-keyfile-fields').style.display = 'none';
+            document.getElementById('auth-pkcs11-fields').style.display = 'none';
         }
 
         modal.style.display = 'flex';
@@ -132,6 +180,173 @@ export class ConnectionManager {
         document.getElementById('connection-modal').style.display = 'none';
     }
 
+    // ── Import ───────────────────────────────────────────────
+
+    async showImportModal() {
+        this._importPath = null;
+        this._bundlePassword = null;
+        document.getElementById('import-format').value = 'sshconfig';
+        document.getElementById('import-allow-risky').checked = false;
+        document.getElementById('import-allow-risky-row').style.display = 'none';
+        document.getElementById('import-modal').style.display = 'flex';
+        await this.loadImportPreview();
+    }
+
+    hideImportModal() {
+        document.getElementById('import-modal').style.display = 'none';
+    }
+
+    // Other tools' exports have to be picked; ~/.ssh/config is read directly
+    async onImportFormatChange() {
+        this._importPath = null;
+        this._bundlePassword = null;
+        const format = document.getElementById('import-format').value;
+        document.getElementById('import-allow-risky-row').style.display = format === 'bundle' ? '' : 'none';
+        if (format === 'sshconfig') {
+            await this.loadImportPreview();
+        } else if (format === 'bundle') {
+            document.getElementById('import-list').innerHTML =
+                '<p class="hint">Choose a bundle exported from RustSSH; you will be asked for its password</p>';
+        } else {
+            document.getElementById('import-list').innerHTML = '<p class="hint">Choose the exported file</p>';
+        }
+    }
+
+    async chooseImportFile() {
+        const extensions = {
+            sshconfig: [], winscp: ['ini'], termius: ['csv'], mremoteng: ['xml'], bundle: ['rustssh'],
+        };
+        const format = document.getElementById('import-format').value;
+        try {
+            const { open } = await import('@tauri-apps/plugin-dialog');
+            const file = await open({
+                multiple: false,
+                filters: extensions[format].length
+                    ? [{ name: 'Export', extensions: extensions[format] }]
+                    : [],
+            });
+            if (!file) return;
+            if (format === 'bundle') {
+                const password = prompt('Bundle password:');
+                if (!password) return;
+                this._bundlePassword = password;
+            }
+            this._importPath = file;
+            await this.loadImportPreview();
+        } catch (e) {
+            console.error('Failed to choose import file:', e);
+        }
+    }
+
+    showExportModal() {
+        document.getElementById('export-list').innerHTML = this.connections.length
+            ? this.connections.map(conn => `
+          <label class="import-item">
+            <input type="checkbox" value="${conn.id}" />
+            <div>
+              <div class="conn-name">${this.escapeHtml(conn.name)}</div>
+              <div class="conn-host">${this.escapeHtml(conn.username)}@${this.escapeHtml(conn.host)}:${conn.port}</div>
+            </div>
+          </label>
+        `).join('')
+            : '<p class="hint">No connections to export</p>';
+        document.getElementById('export-password').value = '';
+        document.getElementById('export-secrets').checked = false;
+        document.getElementById('export-modal').style.display = 'flex';
+    }
+
+    hideExportModal() {
+        document.getElementById('export-modal').style.display = 'none';
+    }
+
+    async exportSelected() {
+        const ids = [...document.querySelectorAll('#export-list input:checked')].map(i => i.value);
+        const password = document.getElementById('export-password').value;
+        if (ids.length === 0) {
+            alert('Choose the connections to export.');
+            return;
+        }
+        if (!password) {
+            alert('Enter a password for the bundle.');
+            return;
+        }
+        try {
+            const { save } = await import('@tauri-apps/plugin-dialog');
+            const path = await save({ defaultPath: 'connections.rustssh' });
+            if (!path) return;
+            const { invoke } = await import('@tauri-apps/api/core');
+            const count = await invoke('connections_export_bundle', {
+                ids,
+                path,
+                password,
+                includeSecrets: document.getElementById('export-secrets').checked,
+            });
+            this.hideExportModal();
+            alert(`Exported ${count} connection${count === 1 ? '' : 's'}.`);
+        } catch (e) {
+            console.error('Failed to export connections:', e);
+            alert(`Export failed: ${e}`);
+        }
+    }
+
+    // List the hosts in the export with what importing each would do
+    async loadImportPreview() {
+        const list = document.getElementById('import-list');
+        const format = document.getElementById('import-format').value;
+        try {
+            const { invoke } = await import('@tauri-apps/api/core');
+            const plan = format === 'bundle'
+                ? await invoke('connections_import_bundle_preview', {
+                    path: this._importPath,
+                    password: this._bundlePassword,
+                })
+                : await invoke('connections_import_preview', { format, path: this._importPath });
+            const labels = { create: 'New', update: 'Update', unchanged: 'Up to date' };
+            const hosts = plan.hosts.map(h => `
+          <label class="import-item">
+            <input type="checkbox" value="${this.escapeHtml(h.name)}" ${h.action === 'unchanged' ? '' : 'checked'} />
+            <div>
+              <div class="conn-name">${this.escapeHtml(h.name)}</div>
+              <div class="conn-host">${this.escapeHtml(h.username)}@${this.escapeHtml(h.host)}:${h.port}</div>
+              ${h.warnings.map(w => `<div class="import-warning">${this.escapeHtml(w)}</div>`).join('')}
+            </div>
+            <span class="import-action">${labels[h.action]}</span>
+          </label>
+        `).join('');
+            const skipped = plan.skipped.length
+                ? `<p class="hint">Not imported: ${plan.skipped.map(s => this.escapeHtml(s)).join(', ')}</p>`
+                : '';
+            list.innerHTML = (hosts || '<p class="hint">No hosts found</p>') + skipped;
+        } catch (e) {
+            console.error('Failed to read import file:', e);
+            list.innerHTML = `<p class="import-warning">${this.escapeHtml(String(e))}</p>`;
+        }
+    }
+
+    async importSelected() {
+        const names = [...document.querySelectorAll('#import-list input:checked')].map(i => i.value);
+        const format = document.getElementById('import-format').value;
+        try {
+            const { invoke } = await import('@tauri-apps/api/core');
+            if (names.length > 0 && format === 'bundle') {
+                this.connections = await invoke('connections_import_bundle', {
+                    path: this._importPath,
+                    password: this._bundlePassword,
+                    names,
+                    allowRisky: document.getElementById('import-allow-risky').checked,
+                });
+                await this.refresh();
+            } else if (names.length > 0) {
+                this.connections = await invoke('connections_import', { format, path: this._importPath, names });
+                await this.refresh();
+            }
+            this.hideImportModal();
+        } catch (e) {
+            console.error('Failed to import connections:', e);
+            alert(`Import failed: ${e}`);
+        }
+    }
+
     editConnection(id) {
         const conn = this.connections.find(c => c.id === id);
         if (conn) {
@@ -152,6 +367,9 @@ export class ConnectionManager {
             const password = document.getElementById('conn-password').value || null;
             const privateKeyPath = document.getElementById('conn-keypath').value || null;
             const passphrase = document.getElementById('conn-passphrase').value || null;
+            const pkcs11Module = document.getElementById('conn-pkcs11-module').value;
+            const keychain = document.getElementById('conn-keychain').checked;
+            const tags = document.getElementById('conn-tags').value.split(',');
 
             this.connections = await invoke('save_connection', {
                 id: id || null,
@@ -163,10 +381,13 @@ export class ConnectionManager {
                 password: authType === 'password' ? password : null,
                 privateKeyPath: authType === 'keyfile' ? privateKeyPath : null,
                 passphrase: authType === 'keyfile' ? passphrase : null,
+                pkcs11Module: authType === 'pkcs11' ? pkcs11Module : null,
+                keychain,
+                tags,
             });
 
             this.hideModal();
-            this.render();
+            await this.refresh();
         } catch (e) {
             console.error('Failed to save connection:', e);
             alert(`Error saving: ${e}`);
@@ -179,7 +400,7 @@ export class ConnectionManager {
         try {
             const { invoke } = await import('@tauri-apps/api/core');
             this.connections = await invoke('delete_connection', { id });
-            this.render();
+            await this.refresh();
         } catch (e) {
             console.error('Failed to delete connection:', e);
         }
@@ -60,9 +60,74 @@ export class FileManager {
         // Listen for Tauri file drop events (provides absolute paths)
         this.initFileDropListener();
         this.initSyncListener();
+        this.initProgressListener();
 
         // Permissions modal
         this.bindPermissionsModal();
+        this.bindConflictModal();
+    }
+
+    bindConflictModal() {
+        const modal = document.getElementById('edit-conflict-modal');
+        const close = () => {
+            modal.style.display = 'none';
+            this._conflict = null;
+        };
+        document.getElementById('btn-conflict-close').addEventListener('click', close);
+        document.getElementById('btn-conflict-diff').addEventListener('click', () => {
+            this.showConflictDiff();
+        });
+        document.getElementById('btn-conflict-reload').addEventListener('click', async () => {
+            await this.resolveConflict('reload');
+            close();
+        });
+        document.getElementById('btn-conflict-overwrite').addEventListener('click', async () => {
+            await this.resolveConflict('overwrite');
+            close();
+        });
+    }
+
+    // Someone changed a file on the server while it was being edited here
+    showConflictModal(conflict) {
+        this._conflict = conflict;
+        const filename = conflict.remotePath.split('/').pop();
+        document.getElementById('edit-conflict-message').textContent = conflict.current
+            ? `${filename} was changed on the server since you opened it. Your save was not uploaded.`
+            : `${filename} was deleted from the server since you opened it. Your save was not uploaded.`;
+        const diff = document.getElementById('edit-conflict-diff');
+        diff.style.display = 'none';
+        diff.innerHTML = '';
+        document.getElementById('btn-conflict-diff').disabled = !conflict.current;
+        document.getElementById('edit-conflict-modal').style.display = 'flex';
+    }
+
+    async showConflictDiff() {
+        if (!this._conflict) return;
+        const diff = document.getElementById('edit-conflict-diff');
+        try {
+            const { invoke } = await import('@tauri-apps/api/core');
+            const lines = await invoke('sftp_edit_diff', { localPath: this._conflict.localPath });
+            const prefix = { same: ' ', added: '+', removed: '-' };
+            diff.innerHTML = lines
+                .map(l => `<div class="${l.kind}">${prefix[l.kind]} ${this.escapeHtml(l.text)}</div>`)
+                .join('');
+            diff.style.display = 'block';
+        } catch (e) {
+            alert(`Failed to compare: ${e}`);
+        }
+    }
+
+    async resolveConflict(resolution) {
+        if (!this._conflict) return;
+        try {
+            const { invoke } = await import('@tauri-apps/api/core');
+            await invoke('sftp_edit_resolve', { localPath: this._conflict.localPath, resolution });
+            if (resolution === 'reload') {
+                this.showSyncToast('↻ Reloaded the server version', 'success');
+            }
+        } catch (e) {
+            alert(`Failed to resolve: ${e}`);
+        }
     }
 
     bindPermissionsModal() {
@@ -106,6 +171,7 @@ export class FileManager {
             this.currentPath = path;
 
             document.getElementById('sftp-path-display').textContent = path;
+            this.showFreeSpace(path);
 
             const fileList = document.getElementById('file-list');
             fileList.innerHTML = `
@@ -133,6 +199,20 @@ export class FileManager {
         }
     }
 
+    async showFreeSpace(path) {
+        const display = document.getElementById('sftp-path-display');
+        try {
+            const { invoke } = await import('@tauri-apps/api/core');
+            const space = await invoke('sftp_statvfs', {
+                sessionId: this.app.currentSessionId,
+                path,
+            });
+            display.title = `${this.formatSize(space.available)} free of ${this.formatSize(space.total)}`;
+        } catch (e) {
+            display.title = '';
+        }
+    }
+
     render() {
         const fileList = document.getElementById('file-list');
         const content = fileList.querySelector('.file-list-content') || fileList;
@@ -153,13 +233,14 @@ export class FileManager {
            data-perms="${file.permissions || ''}"
            data-uid="${file.uid || ''}"
            data-gid="${file.gid || ''}"
-           data-modified="${file.modified || ''}">
-        <div class="file-icon ${file.is_dir ? 'folder' : this.getFileIconClass(file.name)}">
+           data-modified="${file.modified || ''}"
+           ${file.is_symlink ? `title="→ ${this.escapeHtml(file.link_target || '?')}"` : ''}>
+        <div class="file-icon ${file.is_dir ? 'folder' : this.getFileIconClass(file.name, file.mime)}">
           ${file.is_dir ? this.folderIcon() : this.fileIcon(file.name)}
         </div>
-        <span class="file-name">${this.escapeHtml(file.name)}</span>
-        <span class="file-owner">${file.uid ?? '-'}</span>
-        <span class="file-group">${file.gid ?? '-'}</span>
+        <span class="file-name">${this.escapeHtml(file.name)}${file.is_symlink ? ' ↪' : ''}</span>
+        <span class="file-owner" title="${file.uid ?? ''}">${this.escapeHtml(file.owner ?? String(file.uid ?? '-'))}</span>
+        <span class="file-group" title="${file.gid ?? ''}">${this.escapeHtml(file.group ?? String(file.gid ?? '-'))}</span>
         ${file.permissions != null ? `<span class="file-perms">${this.formatPermissions(file.permissions)}</span>` : ''}
         ${!file.is_dir ? `<span class="file-size">${this.formatSize(file.size)}</span>` : '<span class="file-size">-</span>'}
         <span class="file-date">${file.modified ? this.formatDate(file.modified) : '-'}</span>
@@ -247,8 +328,27 @@ export class FileManager {
         switch (action) {
             case 'download': {
                 try {
-                    const { save } = await import('@tauri-apps/plugin-dialog');
+                    const { save, open } = await import('@tauri-apps/plugin-dialog');
                     const fileName = file.path.split('/').pop();
+                    if (file.isDir) {
+                        const parent = await open({ directory: true });
+                        if (!parent) break;
+                        this.app.setStatus('connected', `Downloading ${fileName}...`);
+                        const summary = await invoke('sftp_download_dir', {
+                            sessionId: this.app.currentSessionId,
+                            remotePath: file.path,
+                            localPath: `${parent}/${fileName}`,
+                        });
+                        if (summary.failures.length) {
+                            const list = summary.failures
+                                .map(f => `${f.path}: ${f.error}`)
+                                .join('\n');
+                            alert(`${summary.failures.length} item(s) failed:\n${list}`);
+                        }
+                        this.app.setStatus('connected',
+                            `Downloaded ${summary.files_done} file(s)`);
+                        break;
+                    }
                     const localPath = await save({ defaultPath: fileName });
                     if (localPath) {
                         this.app.setStatus('connected', `Downloading ${fileName}...`);
@@ -256,6 +356,7 @@ export class FileManager {
                             sessionId: this.app.currentSessionId,
                             remotePath: file.path,
                             localPath,
+                            parallel: true,
                         });
                         this.app.setStatus('connected', 'Download complete');
                     }
@@ -264,6 +365,40 @@ export class FileManager {
                 }
                 break;
             }
+            case 'download-compressed': {
+                try {
+                    const { save } = await import('@tauri-apps/plugin-dialog');
+                    const fileName = file.path.split('/').pop();
+                    const localPath = await save({ defaultPath: `${fileName}.tar.gz` });
+                    if (!localPath) break;
+                    this.app.setStatus('connected', `Compressing ${fileName}...`);
+                    await invoke('sftp_download_compressed', {
+                        sessionId: this.app.currentSessionId,
+                        remotePath: file.path,
+                        localPath,
+                    });
+                    this.app.setStatus('connected', 'Download complete');
+                } catch (e) {
+                    alert(`Download error: ${e}`);
+                }
+                break;
+            }
+            case 'stop-sync': {
+                try {
+                    const watches = await invoke('sftp_list_watches', {
+                        sessionId: this.app.currentSessionId,
+                    });
+                    const watch = watches.find(w => w.remote_path === file.path);
+                    if (!watch) {
+                        alert(`${file.path.split('/').pop()} is not being edited`);
+                        break;
+                    }
+                    await invoke('sftp_unwatch_file', { localPath: watch.local_path });
+                } catch (e) {
+                    alert(`Failed to stop syncing: ${e}`);
+                }
+                break;
+            }
             case 'open-with': {
                 // Open native system dialog
                 this.editFile(file.path, 'open-with-dialog');
@@ -288,6 +423,18 @@ export class FileManager {
                 }
                 break;
             }
+            case 'duplicate': {
+                try {
+                    await invoke('sftp_duplicate', {
+                        sessionId: this.app.currentSessionId,
+                        path: file.path,
+                    });
+                    await this.loadDirectory(this.currentPath);
+                } catch (e) {
+                    alert(`Duplicate error: ${e}`);
+                }
+                break;
+            }
             case 'delete': {
                 const name = file.path.split('/').pop();
                 if (confirm(`Delete ${name}?`)) {
@@ -296,6 +443,7 @@ export class FileManager {
                             sessionId: this.app.currentSessionId,
                             path: file.path,
                             isDir: file.isDir,
+                            trash: localStorage.getItem('sftpUseTrash') === 'true',
                         });
                         await this.loadDirectory(this.currentPath);
                     } catch (e) {
@@ -314,6 +462,24 @@ export class FileManager {
                 }
                 break;
             }
+            case 'extract': {
+                if (file.isDir) break;
+                const dest = prompt('Extract to:', this.currentPath);
+                if (!dest) break;
+                try {
+                    this.app.setStatus('connected', `Extracting ${file.path.split('/').pop()}...`);
+                    await invoke('remote_extract', {
+                        sessionId: this.app.currentSessionId,
+                        archivePath: file.path,
+                        dest,
+                    });
+                    this.app.setStatus('connected', 'Extraction complete');
+                    await this.loadDirectory(this.currentPath);
+                } catch (e) {
+                    alert(`Extract error: ${e}`);
+                }
+                break;
+            }
         }
     }
 
@@ -357,25 +523,22 @@ export class FileManager {
 
         const { invoke } = await import('@tauri-apps/api/core');
 
-        for (const localPath of paths) {
-            try {
-                // Extract filename from path (windows or unix)
-                const filename = localPath.split(/[\\/]/).pop();
-
-                const remotePath = this.currentPath.endsWith('/')
-                    ? `${this.currentPath}${filename}`
-                    : `${this.currentPath}/${filename}`;
-
-                this.app.setStatus('connected', `Uploading ${filename}...`);
-
-                await invoke('sftp_upload', {
-                    sessionId: this.app.currentSessionId,
-                    localPath,
-                    remotePath,
-                });
-            } catch (e) {
-                alert(`Upload error: ${e}`);
+        try {
+            this.app.setStatus('connected', `Uploading ${paths.length} item(s)...`);
+            // Folders are expanded on the backend
+            const summary = await invoke('sftp_upload_paths', {
+                sessionId: this.app.currentSessionId,
+                localPaths: paths,
+                remoteDir: this.currentPath,
+            });
+            if (summary.failures.length) {
+                const list = summary.failures
+                    .map(f => `${f.path}: ${f.error}`)
+                    .join('\n');
+                alert(`${summary.failures.length} item(s) failed:\n${list}`);
             }
+        } catch (e) {
+            alert(`Upload error: ${e}`);
         }
 
         this.app.setStatus('connected', 'Upload complete');
@@ -493,6 +656,7 @@ export class FileManager {
                 sessionId: this.app.currentSessionId,
                 localPath,
                 remotePath,
+                backup: localStorage.getItem('editBackup') === 'true',
             });
 
             this.app.setStatus('connected', `Editing ${filename} — changes will auto-sync`);
@@ -506,23 +670,53 @@ export class FileManager {
         try {
             const { listen } = await import('@tauri-apps/api/event');
             await listen('file-sync-status', (event) => {
-                const { status, file, error } = event.payload;
+                const { status, file, error, backup } = event.payload;
                 const filename = file?.split('/').pop() || 'file';
 
                 if (status === 'synced') {
-                    this.showSyncToast(`✓ ${filename} synced`, 'success');
+                    const note = backup ? ` (previous version: ${backup.split('/').pop()})` : '';
+                    this.showSyncToast(`✓ ${filename} synced${note}`, 'success');
                     this.loadDirectory(this.currentPath);
                 } else if (status === 'error') {
                     this.showSyncToast(`✗ Sync failed: ${error}`, 'error');
                 } else if (status === 'watching') {
                     this.showSyncToast(`👁 Watching ${filename}`, 'watching');
+                } else if (status === 'stopped') {
+                    this.showSyncToast(`Stopped syncing ${filename}`, 'watching');
                 }
             });
+            await listen('file-sync-conflict', (event) => {
+                this.showConflictModal(event.payload);
+            });
         } catch (e) {
             console.error('Failed to init sync listener:', e);
         }
     }
 
+    async initProgressListener() {
+        const { listen } = await import('@tauri-apps/api/event');
+        await listen('sftp-progress', (event) => {
+            const { bytesDone, bytesTotal, etaSecs } = event.payload;
+            const pct = bytesTotal ? Math.floor((bytesDone / bytesTotal) * 100) : null;
+            const eta = etaSecs != null ? ` — ${etaSecs}s left` : '';
+            this.app.setStatus('connected', pct != null
+                ? `Transferring... ${pct}%${eta}`
+                : `Transferring... ${bytesDone} bytes`);
+        });
+        await listen('transfer-conflict', async (event) => {
+            const { invoke } = await import('@tauri-apps/api/core');
+            const { transferId, conflict } = event.payload;
+            const name = conflict.dest_path.split(/[\\/]/).pop();
+            let resolution = null;
+            if (confirm(`${name} already exists. Overwrite it?`)) {
+                resolution = 'overwrite';
+            } else if (confirm('Keep both files by saving under a new name?')) {
+                resolution = 'rename';
+            }
+            await invoke('transfer_conflict_respond', { transferId, resolution });
+        });
+    }
+
     showSyncToast(message, type = 'info') {
         // Remove existing toast
         const existing = document.querySelector('.sync-toast');
@@ -610,7 +804,7 @@ export class FileManager {
         return rwx((m >> 6) & 7) + rwx((m >> 3) & 7) + rwx(m & 7);
     }
 
-    getFileIconClass(name) {
+    getFileIconClass(name, mime = null) {
         const ext = name.split('.').pop()?.toLowerCase();
         const codeExts = ['js', 'ts', 'py', 'rs', 'go', 'java', 'c', 'cpp', 'h', 'css', 'html', 'json', 'yaml', 'yml', 'toml', 'xml', 'sh', 'bash'];
         const imageExts = ['png', 'jpg', 'jpeg', 'gif', 'svg', 'webp', 'ico', 'bmp'];
@@ -621,6 +815,12 @@ export class FileManager {
         if (imageExts.includes(ext)) return 'file-image';
         if (archiveExts.includes(ext)) return 'file-archive';
         if (textExts.includes(ext)) return 'file-text';
+
+        // Extensionless files get their type from the server's `file`
+        if (mime?.startsWith('image/')) return 'file-image';
+        if (/zip|tar|gzip|bzip|xz|7z|rar/.test(mime ?? '')) return 'file-archive';
+        if (mime?.startsWith('text/x-')) return 'file-code';
+        if (mime?.startsWith('text/')) return 'file-text';
         return 'file';
     }
 
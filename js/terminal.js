@@ -14,11 +14,13 @@ export class TerminalManager {
         this.terminal = null;
         this.fitAddon = null;
         this.sessionId = null;
+        this.protocol = 'ssh';
         this.resizeObserver = null;
     }
 
-    init(sessionId, container) {
+    init(sessionId, container, protocol = 'ssh') {
         this.sessionId = sessionId;
+        this.protocol = protocol;
 
         // Create terminal instance
         this.terminal = new Terminal({
@@ -73,7 +75,7 @@ export class TerminalManager {
             try {
                 const { invoke } = await import('@tauri-apps/api/core');
                 const bytes = new TextEncoder().encode(data);
-                await invoke('ssh_write', {
+                await invoke(`${this.protocol}_write`, {
                     sessionId: this.sessionId,
                     data: Array.from(bytes),
                 });
@@ -87,7 +89,7 @@ export class TerminalManager {
             if (!this.sessionId) return;
             try {
                 const { invoke } = await import('@tauri-apps/api/core');
-                await invoke('ssh_resize', {
+                await invoke(`${this.protocol}_resize`, {
                     sessionId: this.sessionId,
                     cols,
                     rows,
@@ -0,0 +1,197 @@
+use russh::ChannelId;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{oneshot, Mutex};
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub enum ChannelKind {
+    Shell,
+    Sftp,
+    Exec,
+    Forward,
+}
+
+/// Byte counters of one channel
+#[derive(Debug, Default)]
+pub struct ChannelCounters {
+    /// Received from the server
+    pub bytes_in: AtomicU64,
+    /// Sent to the server
+    pub bytes_out: AtomicU64,
+    /// Set once a stream over the channel hit its end or failed
+    pub ended: AtomicBool,
+}
+
+pub struct ChannelEntry {
+    kind: ChannelKind,
+    label: String,
+    counters: Arc<ChannelCounters>,
+    /// Asks the channel's owner to close it
+    close: Option<oneshot::Sender<()>>,
+}
+
+/// The open channels of a session, shared between the handler (which sees
+/// incoming data and closes) and the code that opens channels
+pub type ChannelRegistry = Arc<Mutex<HashMap<ChannelId, ChannelEntry>>>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelInfo {
+    pub channel_id: String,
+    pub kind: ChannelKind,
+    pub label: String,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+/// Record a newly opened channel. The owner should stop using the channel
+/// once the returned receiver yields `Ok(())`; dropping it means the channel
+/// can't be closed from the inspector.
+pub async fn register(
+    registry: &ChannelRegistry,
+    id: ChannelId,
+    kind: ChannelKind,
+    label: impl Into<String>,
+) -> (Arc<ChannelCounters>, oneshot::Receiver<()>) {
+    let counters = Arc::new(ChannelCounters::default());
+    let (close_tx, close_rx) = oneshot::channel();
+    registry.lock().await.insert(
+        id,
+        ChannelEntry {
+            kind,
+            label: label.into(),
+            counters: counters.clone(),
+            close: Some(close_tx),
+        },
+    );
+    (counters, close_rx)
+}
+
+pub async fn unregister(registry: &ChannelRegistry, id: ChannelId) {
+    registry.lock().await.remove(&id);
+}
+
+pub async fn record_in(registry: &ChannelRegistry, id: ChannelId, bytes: usize) {
+    if let Some(entry) = registry.lock().await.get(&id) {
+        entry
+            .counters
+            .bytes_in
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+pub async fn record_out(registry: &ChannelRegistry, id: ChannelId, bytes: usize) {
+    if let Some(entry) = registry.lock().await.get(&id) {
+        entry
+            .counters
+            .bytes_out
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+pub async fn list(registry: &ChannelRegistry) -> Vec<ChannelInfo> {
+    let mut channels: Vec<ChannelInfo> = registry
+        .lock()
+        .await
+        .iter()
+        .map(|(id, entry)| ChannelInfo {
+            channel_id: id.to_string(),
+            kind: entry.kind,
+            label: entry.label.clone(),
+            bytes_in: entry.counters.bytes_in.load(Ordering::Relaxed),
+            bytes_out: entry.counters.bytes_out.load(Ordering::Relaxed),
+        })
+        .collect();
+    channels.sort_by(|a, b| a.channel_id.cmp(&b.channel_id));
+    channels
+}
+
+/// Find a channel by the id shown in [`ChannelInfo`]
+pub async fn find(
+    registry: &ChannelRegistry,
+    channel_id: &str,
+) -> Option<(ChannelId, ChannelKind)> {
+    registry
+        .lock()
+        .await
+        .iter()
+        .find(|(id, _)| id.to_string() == channel_id)
+        .map(|(id, entry)| (*id, entry.kind))
+}
+
+/// Ask the owner of a channel to close it
+pub async fn request_close(registry: &ChannelRegistry, id: ChannelId) -> Result<(), String> {
+    let close = registry
+        .lock()
+        .await
+        .get_mut(&id)
+        .and_then(|entry| entry.close.take())
+        .ok_or("Channel is already closing")?;
+    close
+        .send(())
+        .map_err(|_| "This channel can't be closed on its own".to_string())
+}
+
+/// Channel stream wrapper that counts the bytes written to the server
+pub struct CountingStream<S> {
+    inner: S,
+    counters: Arc<ChannelCounters>,
+}
+
+impl<S> CountingStream<S> {
+    pub fn new(inner: S, counters: Arc<ChannelCounters>) -> Self {
+        Self { inner, counters }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountingStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let ended = match &result {
+            Poll::Ready(Ok(())) => buf.filled().len() == filled && buf.remaining() > 0,
+            Poll::Ready(Err(_)) => true,
+            Poll::Pending => false,
+        };
+        if ended {
+            self.counters.ended.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountingStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        match result {
+            Poll::Ready(Ok(written)) => {
+                self.counters
+                    .bytes_out
+                    .fetch_add(written as u64, Ordering::Relaxed);
+            }
+            Poll::Ready(Err(_)) => self.counters.ended.store(true, Ordering::Relaxed),
+            Poll::Pending => {}
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -1,25 +1,354 @@
+use portable_pty::CommandBuilder;
+use russh::ChannelMsg;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tauri::{AppHandle, Emitter, Manager, State};
-use tokio::sync::mpsc;
+use tauri_plugin_deep_link::DeepLinkExt;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
 
-use crate::connection::{AuthType, Connection, ConnectionStore};
+use crate::channels::{self, ChannelInfo, ChannelKind};
+use crate::connection::{
+    normalize_tags, AlgorithmPreferences, AuthType, AutoAttach, Connection, ConnectionBundle,
+    ConnectionStore, ConnectionType, ImportFormat, ImportPlan, ImportPreview, ImportedHost,
+    IpPreference, ParsedImport, SshUrl,
+};
+use crate::diff;
+use crate::dirsync;
+use crate::editor::{self, EditorEntry, EditorInfo, EditorStore};
+use crate::keychain;
+use crate::knock::KnockStep;
+use crate::known_hosts::{KnownHost, KnownHostsStore};
+use crate::mime;
+use crate::mosh;
+use crate::proxy::ProxyConfig;
+use crate::pty::PtySession;
+use crate::telnet::TelnetSession;
+use crate::terminal::TerminalSession;
 use crate::sftp;
-use crate::ssh::{SessionManager, SshEvent, SshSession};
+use crate::ssh;
+use crate::ssh_config;
+use crate::ssh::{
+    ConnectAttempt, ExecOutput, HostExecResult, HostKeyInfo, SessionManager, SshEvent, SshSession,
+};
+use crate::transfer::{self, PendingTransferStore, RetryPolicy, TransferDirection, TransferItem};
+use crate::trash;
+use crate::tunnel::{
+    self, TunnelInfo, TunnelKind, TunnelProfile, TunnelProfileStore, TunnelSpec,
+};
+use crate::vault::{self, Envelope, VaultKey, VaultStatus};
+use crate::vpn::{self, VpnInfo};
+use crate::watch::{self, FileWatch, WatchInfo};
+use crate::wol;
+
+const LATENCY_INTERVAL_SECS: u64 = 15;
+const TUNNEL_STATS_INTERVAL_SECS: u64 = 5;
+const TUNNEL_HEALTH_INTERVAL_SECS: u64 = 10;
+/// How long `ssh_wake_and_connect` waits for the host to come up by default
+const WOL_TIMEOUT_SECS: u64 = 120;
+const WOL_POLL_SECS: u64 = 3;
+/// How long a `transfer-conflict` prompt waits for an answer before skipping
+const CONFLICT_PROMPT_TIMEOUT_SECS: u64 = 300;
 
 // ── Connection Commands ──────────────────────────────────────────────
 
+/// The connection store, with the master password key if it's been unlocked
+fn connection_store(app: &AppHandle) -> Result<ConnectionStore, String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let key = app.state::<Arc<SessionManager>>().vault_key();
+    Ok(ConnectionStore::new(data_dir).with_key(key))
+}
+
 #[tauri::command]
 pub async fn get_connections(
     app: AppHandle,
 ) -> Result<Vec<Connection>, String> {
+    let store = connection_store(&app)?;
+    Ok(store.load())
+}
+
+/// Saved connections whose name, host, user name or tags match `query`
+#[tauri::command]
+pub async fn search_connections(app: AppHandle, query: String) -> Result<Vec<Connection>, String> {
+    Ok(connection_store(&app)?.search(&query))
+}
+
+/// Resolve an `ssh://` link to a saved connection, creating one if needed
+#[tauri::command]
+pub async fn open_ssh_url(app: AppHandle, url: String) -> Result<Connection, String> {
+    let store = connection_store(&app)?;
+    store.find_or_add_url(&SshUrl::parse(&url)?)
+}
+
+/// `ssh://` links the app was launched with
+#[tauri::command]
+pub async fn get_launch_urls(app: AppHandle) -> Result<Vec<String>, String> {
+    let urls = app.deep_link().get_current().map_err(|e| e.to_string())?;
+    Ok(urls
+        .unwrap_or_default()
+        .into_iter()
+        .map(|url| url.to_string())
+        .collect())
+}
+
+/// Whether saved connections are encrypted with a master password and unlocked
+#[tauri::command]
+pub async fn vault_status(app: AppHandle) -> Result<VaultStatus, String> {
+    let store = connection_store(&app)?;
+    let unlocked = app.state::<Arc<SessionManager>>().vault_key().is_some();
+    Ok(VaultStatus {
+        encrypted: store.is_encrypted(),
+        unlocked,
+    })
+}
+
+/// Unlock the encrypted connection store for the rest of the run
+#[tauri::command]
+pub async fn vault_unlock(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    password: String,
+) -> Result<Vec<Connection>, String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let envelope = ConnectionStore::new(data_dir)
+        .envelope()
+        .ok_or("Connections are not encrypted")?;
+    // Key derivation is deliberately slow
+    let key = tokio::task::spawn_blocking(move || vault::unlock(&password, &envelope))
+        .await
+        .map_err(|e| e.to_string())??;
+    session_manager.set_vault_key(Some(key));
+    // Favorites couldn't be read at startup
+    tokio::spawn(prewarm_connections(app.clone()));
+    Ok(connection_store(&app)?.load())
+}
+
+/// Encrypt saved connections with a new master password, migrating a
+/// plaintext store or changing the password of an unlocked one
+#[tauri::command]
+pub async fn vault_set_password(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    password: String,
+) -> Result<(), String> {
+    let store = connection_store(&app)?;
+    let key = tokio::task::spawn_blocking(move || VaultKey::create(&password))
+        .await
+        .map_err(|e| e.to_string())??;
+    store.rekey(Some(&key))?;
+    session_manager.set_vault_key(Some(key));
+    Ok(())
+}
+
+/// Store saved connections as plaintext again, after checking the password
+#[tauri::command]
+pub async fn vault_remove_password(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    password: String,
+) -> Result<(), String> {
     let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
     let store = ConnectionStore::new(data_dir);
-    Ok(store.load())
+    let envelope = store.envelope().ok_or("Connections are not encrypted")?;
+    let key = tokio::task::spawn_blocking(move || vault::unlock(&password, &envelope))
+        .await
+        .map_err(|e| e.to_string())??;
+    store.with_key(Some(key)).rekey(None)?;
+    session_manager.set_vault_key(None);
+    Ok(())
+}
+
+/// Read hosts to import. Without a path, the OpenSSH config is read from
+/// `~/.ssh/config`; other formats need one.
+fn read_import(format: ImportFormat, path: Option<String>) -> Result<ParsedImport, String> {
+    let path = match (path, format) {
+        (Some(path), _) => PathBuf::from(path),
+        (None, ImportFormat::SshConfig) => {
+            ssh_config::default_path().ok_or("Could not find the home directory")?
+        }
+        (None, _) => return Err("No file to import was given".to_string()),
+    };
+    format.parse(&path)
+}
+
+/// Create or update a saved connection for each of `hosts`. Passwords of
+/// profiles that keep theirs in the keychain are moved there, since saving
+/// would otherwise drop them.
+async fn import_hosts(
+    app: &AppHandle,
+    hosts: Vec<ImportedHost>,
+) -> Result<Vec<Connection>, String> {
+    let store = connection_store(app)?;
+    let mut connections = Vec::new();
+    for conn in store.merge_import(hosts) {
+        let in_keychain = conn.secret_ref.is_some() && !conn.prompt_credentials;
+        if in_keychain && conn.password.is_some() {
+            connections.push(keychain_secrets(conn, Some(true)).await?);
+        } else {
+            connections.push(conn);
+        }
+    }
+    store.save(&connections)?;
+    Ok(connections)
+}
+
+/// The hosts in an export and what importing each would do
+#[tauri::command]
+pub async fn connections_import_preview(
+    app: AppHandle,
+    format: ImportFormat,
+    path: Option<String>,
+) -> Result<ImportPlan, String> {
+    let parsed = read_import(format, path)?;
+    Ok(ImportPlan {
+        hosts: connection_store(&app)?.preview_import(parsed.hosts),
+        skipped: parsed.skipped,
+    })
+}
+
+/// Import the named hosts from an export
+#[tauri::command]
+pub async fn connections_import(
+    app: AppHandle,
+    format: ImportFormat,
+    path: Option<String>,
+    names: Vec<String>,
+) -> Result<Vec<Connection>, String> {
+    let hosts = read_import(format, path)?
+        .hosts
+        .into_iter()
+        .filter(|host| names.contains(&host.name))
+        .collect();
+    import_hosts(&app, hosts).await
+}
+
+/// The hosts in an OpenSSH config and what importing each would do; same
+/// as `connections_import_preview` for the `sshconfig` format
+#[tauri::command]
+pub async fn ssh_config_preview(
+    app: AppHandle,
+    path: Option<String>,
+) -> Result<Vec<ImportPreview>, String> {
+    let hosts = read_import(ImportFormat::SshConfig, path)?.hosts;
+    Ok(connection_store(&app)?.preview_import(hosts))
+}
+
+/// Import the named hosts from an OpenSSH config; same as
+/// `connections_import` for the `sshconfig` format
+#[tauri::command]
+pub async fn ssh_config_import(
+    app: AppHandle,
+    path: Option<String>,
+    names: Vec<String>,
+) -> Result<Vec<Connection>, String> {
+    connections_import(app, ImportFormat::SshConfig, path, names).await
+}
+
+/// Write the chosen connections, and the jump hosts they use, to a bundle
+/// encrypted with `password` for importing on another machine
+#[tauri::command]
+pub async fn connections_export_bundle(
+    app: AppHandle,
+    ids: Vec<String>,
+    path: String,
+    password: String,
+    include_secrets: Option<bool>,
+) -> Result<usize, String> {
+    let include_secrets = include_secrets.unwrap_or(false);
+    let selected = ConnectionBundle::select(&connection_store(&app)?.load(), &ids, include_secrets);
+    let mut connections = Vec::with_capacity(selected.connections.len());
+    for conn in selected.connections {
+        // The other machine can't read this one's keychain
+        let mut conn = keychain::resolve(conn).await;
+        conn.secret_ref = None;
+        connections.push(conn);
+    }
+    let count = connections.len();
+    let data = serde_json::to_vec(&ConnectionBundle { connections }).map_err(|e| e.to_string())?;
+    let sealed = tokio::task::spawn_blocking(move || VaultKey::create(&password)?.seal(&data))
+        .await
+        .map_err(|e| e.to_string())??;
+    std::fs::write(&path, sealed).map_err(|e| format!("Failed to write bundle: {}", e))?;
+    Ok(count)
+}
+
+/// Decrypt a bundle made by `connections_export_bundle`
+async fn read_bundle(path: &str, password: String) -> Result<ConnectionBundle, String> {
+    let data =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read bundle: {}", e))?;
+    let envelope = Envelope::parse(&data).ok_or("Not a connection bundle")?;
+    let plaintext = tokio::task::spawn_blocking(move || {
+        VaultKey::derive(&password, envelope.kdf.clone())?.open(&envelope)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    serde_json::from_slice(&plaintext).map_err(|e| format!("Invalid bundle: {}", e))
+}
+
+/// The connections in a bundle and what importing each would do
+#[tauri::command]
+pub async fn connections_import_bundle_preview(
+    app: AppHandle,
+    path: String,
+    password: String,
+) -> Result<ImportPlan, String> {
+    let bundle = read_bundle(&path, password).await?;
+    Ok(ImportPlan {
+        hosts: connection_store(&app)?.preview_bundle(bundle),
+        skipped: Vec::new(),
+    })
+}
+
+/// Import the named connections from a bundle. Settings that run local
+/// commands or load libraries are dropped unless `allow_risky` is set.
+#[tauri::command]
+pub async fn connections_import_bundle(
+    app: AppHandle,
+    path: String,
+    password: String,
+    names: Vec<String>,
+    allow_risky: Option<bool>,
+) -> Result<Vec<Connection>, String> {
+    let bundle = read_bundle(&path, password).await?;
+    connection_store(&app)?.import_bundle(bundle, &names, allow_risky.unwrap_or(false))
+}
+
+fn parse_auth_type(auth_type: &str) -> Result<AuthType, String> {
+    match auth_type {
+        "password" => Ok(AuthType::Password),
+        "keyfile" => Ok(AuthType::KeyFile),
+        "agent" => Ok(AuthType::Agent),
+        "keyboardinteractive" => Ok(AuthType::KeyboardInteractive),
+        "pkcs11" => Ok(AuthType::Pkcs11),
+        _ => Err("Invalid auth type".to_string()),
+    }
+}
+
+/// Move a profile's secrets into the OS keychain or back out of it, as
+/// `keychain` asks; `None` keeps the profile's current choice. Profiles that
+/// prompt for credentials keep none anywhere.
+async fn keychain_secrets(
+    mut conn: Connection,
+    keychain: Option<bool>,
+) -> Result<Connection, String> {
+    let use_keychain = keychain.unwrap_or(conn.secret_ref.is_some()) && !conn.prompt_credentials;
+    tokio::task::spawn_blocking(move || {
+        if use_keychain {
+            keychain::store_secrets(&mut conn)?;
+        } else {
+            keychain::take_secrets(&mut conn)?;
+        }
+        Ok(conn)
+    })
+    .await
+    .map_err(|e| e.to_string())?
 }
 
 #[tauri::command]
 pub async fn save_connection(
     app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
     id: Option<String>,
     name: String,
     host: String,
@@ -29,33 +358,135 @@ pub async fn save_connection(
     password: Option<String>,
     private_key_path: Option<String>,
     passphrase: Option<String>,
+    jump_host_ids: Option<Vec<String>>,
+    proxy_command: Option<String>,
+    proxy: Option<ProxyConfig>,
+    keepalive_interval: Option<u64>,
+    keepalive_count_max: Option<usize>,
+    connect_timeout: Option<u64>,
+    algorithms: Option<AlgorithmPreferences>,
+    compression: Option<bool>,
+    forward_agent: Option<bool>,
+    env: Option<HashMap<String, String>>,
+    prompt_credentials: Option<bool>,
+    keychain: Option<bool>,
+    pkcs11_module: Option<String>,
+    ip_preference: Option<IpPreference>,
+    bind_address: Option<String>,
+    auth_methods: Option<Vec<String>>,
+    tunnels: Option<Vec<TunnelSpec>>,
+    knock_sequence: Option<Vec<KnockStep>>,
+    mac_address: Option<String>,
+    connection_type: Option<ConnectionType>,
+    auto_attach: Option<AutoAttach>,
+    prewarm: Option<bool>,
+    tags: Option<Vec<String>>,
 ) -> Result<Vec<Connection>, String> {
-    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
-    let store = ConnectionStore::new(data_dir);
+    let store = connection_store(&app)?;
 
-    let at = match auth_type.as_str() {
-        "password" => AuthType::Password,
-        "keyfile" => AuthType::KeyFile,
-        _ => return Err("Invalid auth type".to_string()),
-    };
+    let at = parse_auth_type(&auth_type)?;
+    // An empty address clears it
+    let mac_address = mac_address.map(|m| m.trim().to_string());
+    if let Some(mac) = mac_address.as_deref().filter(|m| !m.is_empty()) {
+        wol::parse_mac(mac)?;
+    }
+    let auth_methods = auth_methods
+        .map(|methods| methods.iter().map(|m| parse_auth_type(m)).collect())
+        .transpose()?;
 
     match id {
         Some(existing_id) => {
-            let conn = Connection {
-                id: existing_id,
-                name,
-                host,
-                port,
-                username,
-                auth_type: at,
-                password,
-                private_key_path,
-                passphrase,
-            };
-            store.update(conn)
+            // Start from the stored profile so settings not edited here are kept
+            let mut conn = store
+                .load()
+                .into_iter()
+                .find(|c| c.id == existing_id)
+                .ok_or("Connection not found")?;
+            conn.name = name;
+            conn.host = host;
+            conn.port = port;
+            conn.username = username;
+            conn.auth_type = at;
+            conn.password = password;
+            conn.private_key_path = private_key_path;
+            conn.passphrase = passphrase;
+            if let Some(jump_host_ids) = jump_host_ids {
+                conn.jump_host_ids = jump_host_ids;
+            }
+            if proxy_command.is_some() {
+                conn.proxy_command = proxy_command.filter(|c| !c.trim().is_empty());
+            }
+            if proxy.is_some() {
+                conn.proxy = proxy.filter(|p| !p.host.is_empty());
+            }
+            if keepalive_interval.is_some() {
+                conn.keepalive_interval = keepalive_interval;
+            }
+            if keepalive_count_max.is_some() {
+                conn.keepalive_count_max = keepalive_count_max;
+            }
+            if connect_timeout.is_some() {
+                conn.connect_timeout = connect_timeout.filter(|secs| *secs > 0);
+            }
+            if algorithms.is_some() {
+                conn.algorithms = algorithms.filter(|a| !a.is_empty());
+            }
+            if let Some(compression) = compression {
+                conn.compression = compression;
+            }
+            if let Some(forward_agent) = forward_agent {
+                conn.forward_agent = forward_agent;
+            }
+            if let Some(env) = env {
+                conn.env = env;
+            }
+            if let Some(prompt_credentials) = prompt_credentials {
+                conn.prompt_credentials = prompt_credentials;
+            }
+            if pkcs11_module.is_some() {
+                conn.pkcs11_module = pkcs11_module.filter(|m| !m.trim().is_empty());
+            }
+            if let Some(ip_preference) = ip_preference {
+                conn.ip_preference = ip_preference;
+            }
+            if bind_address.is_some() {
+                conn.bind_address = bind_address.filter(|a| !a.trim().is_empty());
+            }
+            if let Some(auth_methods) = auth_methods {
+                conn.auth_methods = auth_methods;
+            }
+            if let Some(tunnels) = tunnels {
+                conn.tunnels = tunnels;
+            }
+            if let Some(knock_sequence) = knock_sequence {
+                conn.knock_sequence = knock_sequence;
+            }
+            if mac_address.is_some() {
+                conn.mac_address = mac_address.filter(|m| !m.is_empty());
+            }
+            if let Some(connection_type) = connection_type {
+                conn.connection_type = connection_type;
+            }
+            if auto_attach.is_some() {
+                conn.auto_attach = auto_attach.filter(|a| !a.session_name.trim().is_empty());
+            }
+            if let Some(prewarm) = prewarm {
+                conn.prewarm = prewarm;
+            }
+            if let Some(tags) = tags {
+                conn.tags = normalize_tags(tags);
+            }
+            let prewarm_again = conn.prewarm;
+            let connections = store.update(keychain_secrets(conn, keychain).await?)?;
+            // A pre-warmed session still has the old settings
+            discard_prewarmed(&session_manager, &existing_id).await;
+            if prewarm_again {
+                tokio::spawn(prewarm(app.clone(), session_manager.inner().clone(), existing_id));
+            }
+            Ok(connections)
         }
         None => {
-            let conn = Connection::new(
+            let mut conn = Connection::new(
                 name,
                 host,
                 port,
@@ -65,7 +496,34 @@ pub async fn save_connection(
                 private_key_path,
                 passphrase,
             );
-            store.add(conn)
+            conn.jump_host_ids = jump_host_ids.unwrap_or_default();
+            conn.proxy_command = proxy_command.filter(|c| !c.trim().is_empty());
+            conn.proxy = proxy.filter(|p| !p.host.is_empty());
+            conn.keepalive_interval = keepalive_interval;
+            conn.keepalive_count_max = keepalive_count_max;
+            conn.connect_timeout = connect_timeout.filter(|secs| *secs > 0);
+            conn.algorithms = algorithms.filter(|a| !a.is_empty());
+            conn.compression = compression.unwrap_or(false);
+            conn.forward_agent = forward_agent.unwrap_or(false);
+            conn.env = env.unwrap_or_default();
+            conn.prompt_credentials = prompt_credentials.unwrap_or(false);
+            conn.pkcs11_module = pkcs11_module.filter(|m| !m.trim().is_empty());
+            conn.ip_preference = ip_preference.unwrap_or_default();
+            conn.bind_address = bind_address.filter(|a| !a.trim().is_empty());
+            conn.auth_methods = auth_methods.unwrap_or_default();
+            conn.tunnels = tunnels.unwrap_or_default();
+            conn.knock_sequence = knock_sequence.unwrap_or_default();
+            conn.mac_address = mac_address.filter(|m| !m.is_empty());
+            conn.connection_type = connection_type.unwrap_or_default();
+            conn.auto_attach = auto_attach.filter(|a| !a.session_name.trim().is_empty());
+            conn.prewarm = prewarm.unwrap_or(false);
+            conn.tags = normalize_tags(tags.unwrap_or_default());
+            let prewarm_id = conn.prewarm.then(|| conn.id.clone());
+            let connections = store.add(keychain_secrets(conn, keychain).await?)?;
+            if let Some(id) = prewarm_id {
+                tokio::spawn(prewarm(app.clone(), session_manager.inner().clone(), id));
+            }
+            Ok(connections)
         }
     }
 }
@@ -73,11 +531,45 @@ pub async fn save_connection(
 #[tauri::command]
 pub async fn delete_connection(
     app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
     id: String,
 ) -> Result<Vec<Connection>, String> {
+    let store = connection_store(&app)?;
+    let secret_ref = store
+        .load()
+        .into_iter()
+        .find(|c| c.id == id)
+        .and_then(|c| c.secret_ref);
+    let connections = store.delete(&id)?;
+    discard_prewarmed(&session_manager, &id).await;
+    if let Some(reference) = secret_ref {
+        // A leftover keychain entry does no harm, so it doesn't fail the delete
+        let forget = move || keychain::forget_secrets(&reference);
+        if let Ok(Err(e)) = tokio::task::spawn_blocking(forget).await {
+            log::warn!("{}", e);
+        }
+    }
+    Ok(connections)
+}
+
+#[tauri::command]
+pub async fn get_known_hosts(
+    app: AppHandle,
+) -> Result<Vec<KnownHost>, String> {
     let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
-    let store = ConnectionStore::new(data_dir);
-    store.delete(&id)
+    let store = KnownHostsStore::new(data_dir);
+    Ok(store.load())
+}
+
+#[tauri::command]
+pub async fn delete_known_host(
+    app: AppHandle,
+    host: String,
+    port: u16,
+) -> Result<Vec<KnownHost>, String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let store = KnownHostsStore::new(data_dir);
+    store.remove(&host, port)
 }
 
 // ── SSH Commands ─────────────────────────────────────────────────────
@@ -87,9 +579,178 @@ pub async fn ssh_connect(
     app: AppHandle,
     session_manager: State<'_, Arc<SessionManager>>,
     connection_id: String,
+    sftp_only: Option<bool>,
+    tunnel_profile_ids: Option<Vec<String>>,
 ) -> Result<String, String> {
-    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
-    let store = ConnectionStore::new(data_dir);
+    let sftp_only = sftp_only.unwrap_or(false);
+    // Only for this session; the saved profile keeps its own tunnels
+    let extra_tunnels = match tunnel_profile_ids {
+        Some(ids) => {
+            let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+            TunnelProfileStore::new(data_dir).specs(&ids)?
+        }
+        None => Vec::new(),
+    };
+
+    // A pre-warmed connection is already authenticated and only needs its shell
+    let prewarmed = session_manager.prewarmed.lock().await.remove(&connection_id);
+    if let Some(mut session) = prewarmed.filter(|s| !s.handle.is_closed()) {
+        if sftp_only {
+            sftp::SftpOpener::new(&session).get().await?;
+        } else {
+            session.start_shell().await?;
+        }
+        // Have another one ready for the next click, unless the profile
+        // stopped asking for that
+        if session.connection.prewarm {
+            tokio::spawn(prewarm(app.clone(), session_manager.inner().clone(), connection_id));
+        }
+        session.connection.tunnels.extend(extra_tunnels);
+        return Ok(register_session(app, &session_manager, session).await);
+    }
+
+    let (mut conn, jump_hosts) = load_profile(&app, &connection_id)?;
+    conn.tunnels.extend(extra_tunnels);
+    start_session(app, &session_manager, conn, jump_hosts, sftp_only).await
+}
+
+/// Connect every profile flagged for pre-warming in the background, so that
+/// `ssh_connect` can hand it out without waiting on the network. Profiles
+/// that would need to ask the user anything are skipped.
+pub async fn prewarm_connections(app: AppHandle) {
+    let Ok(store) = connection_store(&app) else {
+        return;
+    };
+    let session_manager = app.state::<Arc<SessionManager>>().inner().clone();
+    for conn in store.load() {
+        if conn.prewarm {
+            tokio::spawn(prewarm(app.clone(), session_manager.clone(), conn.id));
+        }
+    }
+}
+
+/// Whether `conn` can be connected without asking the user anything: its
+/// host key is already trusted and it has a way to log in that needs no
+/// prompt
+fn connects_unattended(conn: &Connection, known_hosts: &KnownHostsStore) -> bool {
+    let methods = match conn.auth_methods.is_empty() {
+        true => std::slice::from_ref(&conn.auth_type),
+        false => conn.auth_methods.as_slice(),
+    };
+    let unattended = methods.iter().any(|method| match method {
+        AuthType::Password => conn.password.is_some() || conn.secret_ref.is_some(),
+        AuthType::KeyFile => conn.private_key_path.is_some(),
+        AuthType::Agent => true,
+        AuthType::KeyboardInteractive | AuthType::Pkcs11 => false,
+    });
+    !conn.prompt_credentials && unattended && known_hosts.is_known(&conn.host, conn.port)
+}
+
+/// Disconnect the pre-warmed session for `connection_id`, if there is one
+async fn discard_prewarmed(session_manager: &SessionManager, connection_id: &str) {
+    let session = session_manager.prewarmed.lock().await.remove(connection_id);
+    if let Some(session) = session {
+        let disconnect = session
+            .handle
+            .disconnect(russh::Disconnect::ByApplication, "", "en");
+        if let Err(e) = disconnect.await {
+            log::warn!("Failed to close pre-warmed connection {}: {}", connection_id, e);
+        }
+    }
+}
+
+async fn prewarm(app: AppHandle, session_manager: Arc<SessionManager>, connection_id: String) {
+    let session = match load_profile(&app, &connection_id) {
+        Ok((conn, jump_hosts)) => {
+            let known_hosts = match app.path().app_data_dir() {
+                Ok(data_dir) => KnownHostsStore::new(data_dir),
+                Err(e) => {
+                    log::warn!("Not pre-warming connection {}: {}", connection_id, e);
+                    return;
+                }
+            };
+            if !std::iter::once(&conn)
+                .chain(&jump_hosts)
+                .all(|hop| connects_unattended(hop, &known_hosts))
+            {
+                log::info!("Not pre-warming connection {}: it needs the user", connection_id);
+                return;
+            }
+            connect_session(&app, &session_manager, conn, jump_hosts, true, false).await
+        }
+        Err(e) => Err(e),
+    };
+    match session {
+        Ok(session) => {
+            session_manager
+                .prewarmed
+                .lock()
+                .await
+                .insert(connection_id.clone(), session);
+            // The profile may have been deleted or unflagged while connecting
+            if !load_profile(&app, &connection_id).is_ok_and(|(conn, _)| conn.prewarm) {
+                discard_prewarmed(&session_manager, &connection_id).await;
+            }
+        }
+        Err(e) => log::warn!("Pre-warming connection {} failed: {}", connection_id, e),
+    }
+}
+
+/// Send a Wake-on-LAN packet to the connection's MAC address, wait for its
+/// SSH port to answer (emitting `wol-progress` while polling) and connect
+#[tauri::command]
+pub async fn ssh_wake_and_connect(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    connection_id: String,
+    timeout_secs: Option<u64>,
+) -> Result<String, String> {
+    let (conn, jump_hosts) = load_profile(&app, &connection_id)?;
+    let mac = conn
+        .mac_address
+        .as_deref()
+        .ok_or("No MAC address set for this connection")?;
+    let mac = wol::parse_mac(mac)?;
+    let timeout_secs = timeout_secs.unwrap_or(WOL_TIMEOUT_SECS);
+
+    // Behind a jump host the SSH port can't be probed from here, so the
+    // packet is sent once and the connect attempt itself has to wait
+    if !jump_hosts.is_empty() {
+        wol::send_magic_packet(&mac).await?;
+        return start_session(app, &session_manager, conn, jump_hosts, false).await;
+    }
+
+    let started = std::time::Instant::now();
+    loop {
+        // Resend periodically in case the first packet was lost
+        wol::send_magic_packet(&mac).await?;
+        let elapsed = started.elapsed().as_secs();
+        let poll = std::time::Duration::from_secs(WOL_POLL_SECS);
+        let awake = wol::port_open(&conn.host, conn.port, poll).await;
+        let _ = app.emit("wol-progress", serde_json::json!({
+            "connectionId": connection_id,
+            "elapsedSecs": elapsed,
+            "timeoutSecs": timeout_secs,
+            "awake": awake,
+        }));
+        if awake {
+            break;
+        }
+        if elapsed >= timeout_secs {
+            return Err(format!("{} did not wake up within {}s", conn.host, timeout_secs));
+        }
+        tokio::time::sleep(poll).await;
+    }
+
+    start_session(app, &session_manager, conn, jump_hosts, false).await
+}
+
+/// A saved connection and the jump hosts it goes through
+fn load_profile(
+    app: &AppHandle,
+    connection_id: &str,
+) -> Result<(Connection, Vec<Connection>), String> {
+    let store = connection_store(app)?;
     let connections = store.load();
 
     let conn = connections
@@ -98,21 +759,118 @@ pub async fn ssh_connect(
         .ok_or("Connection not found")?
         .clone();
 
-    let (tx, mut rx) = mpsc::channel::<SshEvent>(1024);
+    let jump_hosts = conn
+        .jump_host_ids
+        .iter()
+        .map(|jump_id| {
+            connections
+                .iter()
+                .find(|c| &c.id == jump_id)
+                .cloned()
+                .ok_or_else(|| format!("Jump host {} not found", jump_id))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
 
-    let session = SshSession::connect(&conn, tx).await?;
-    let session_id = session_manager.add_session(session).await;
+    Ok((conn, jump_hosts))
+}
+
+/// Connect without saving a profile. `attempt_id`, chosen by the caller,
+/// stands in for the connection id so `ssh_connect_cancel` can abort it.
+#[tauri::command]
+pub async fn ssh_quick_connect(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    host: String,
+    port: u16,
+    username: String,
+    auth_type: String,
+    password: Option<String>,
+    private_key_path: Option<String>,
+    passphrase: Option<String>,
+    attempt_id: Option<String>,
+) -> Result<String, String> {
+    // A throwaway profile that is never written to the ConnectionStore
+    let mut conn = Connection::new(
+        format!("{}@{}", username, host),
+        host,
+        port,
+        username,
+        parse_auth_type(&auth_type)?,
+        password,
+        private_key_path,
+        passphrase,
+    );
+    if let Some(attempt_id) = attempt_id {
+        conn.id = attempt_id;
+    }
+    start_session(app, &session_manager, conn, Vec::new(), false).await
+}
+
+/// Connect `conn` through `jump_hosts`, wire its events to the frontend and
+/// register the session. `sftp_only` skips the shell and only checks that the
+/// SFTP subsystem is available.
+async fn start_session(
+    app: AppHandle,
+    session_manager: &Arc<SessionManager>,
+    conn: Connection,
+    jump_hosts: Vec<Connection>,
+    sftp_only: bool,
+) -> Result<String, String> {
+    let session =
+        connect_session(&app, session_manager, conn, jump_hosts, sftp_only, true).await?;
+    if sftp_only {
+        sftp::SftpOpener::new(&session).get().await?;
+    }
+    Ok(register_session(app, session_manager, session).await)
+}
+
+/// Connect `conn` through `jump_hosts` with the session's events forwarded to
+/// the frontend. Unless `interactive`, nothing is asked of the user: host key
+/// and credential prompts are declined, failing whatever needed them.
+async fn connect_session(
+    app: &AppHandle,
+    session_manager: &Arc<SessionManager>,
+    conn: Connection,
+    jump_hosts: Vec<Connection>,
+    sftp_only: bool,
+    interactive: bool,
+) -> Result<SshSession, String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let (tx, mut rx) = mpsc::channel::<SshEvent>(1024);
+    let session_id = Uuid::new_v4().to_string();
 
-    // Spawn a task to forward SSH data to the frontend
+    // Spawn a task to forward SSH data to the frontend. It must be running
+    // before connecting so host key prompts can reach the UI.
     let app_handle = app.clone();
+    let manager = session_manager.clone();
     let sid = session_id.clone();
     tokio::spawn(async move {
         while let Some(event) = rx.recv().await {
+            // Dropping the pending reply answers the prompt with a refusal
+            if !interactive {
+                match event {
+                    SshEvent::HostKeyPrompt { .. } => {
+                        manager.host_key_prompts.lock().await.remove(&sid);
+                        continue;
+                    }
+                    SshEvent::AuthPrompt { .. } => {
+                        manager.auth_prompts.lock().await.remove(&sid);
+                        continue;
+                    }
+                    SshEvent::CredentialRequest { .. } => {
+                        manager.credential_prompts.lock().await.remove(&sid);
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
             match event {
-                SshEvent::Data(data) => {
+                SshEvent::Data { channel_id, data, stderr } => {
                     // Send raw bytes as array to frontend
                     let _ = app_handle.emit("ssh-data", serde_json::json!({
                         "sessionId": sid,
+                        "channelId": channel_id,
+                        "stderr": stderr,
                         "data": data,
                     }));
                 }
@@ -122,91 +880,2135 @@ pub async fn ssh_connect(
                         "error": err,
                     }));
                 }
-                SshEvent::Close => {
+                SshEvent::Close { reason } => {
                     let _ = app_handle.emit("ssh-close", serde_json::json!({
                         "sessionId": sid,
+                        "reason": reason,
                     }));
                     break;
                 }
+                SshEvent::HostKeyPrompt { host, port, key_type, fingerprint, previous_fingerprint } => {
+                    let _ = app_handle.emit("ssh-host-key", serde_json::json!({
+                        "sessionId": sid,
+                        "host": host,
+                        "port": port,
+                        "keyType": key_type,
+                        "fingerprint": fingerprint,
+                        "previousFingerprint": previous_fingerprint,
+                    }));
+                }
+                SshEvent::AuthPrompt { name, instructions, prompts } => {
+                    let _ = app_handle.emit("ssh-auth-prompt", serde_json::json!({
+                        "sessionId": sid,
+                        "name": name,
+                        "instructions": instructions,
+                        "prompts": prompts,
+                    }));
+                }
+                SshEvent::ExitStatus { channel_id, exit_status } => {
+                    let _ = app_handle.emit("ssh-exit", serde_json::json!({
+                        "sessionId": sid,
+                        "channelId": channel_id,
+                        "exitStatus": exit_status,
+                    }));
+                }
+                SshEvent::ExitSignal { channel_id, signal, core_dumped, error_message } => {
+                    let _ = app_handle.emit("ssh-exit", serde_json::json!({
+                        "sessionId": sid,
+                        "channelId": channel_id,
+                        "signal": signal,
+                        "coreDumped": core_dumped,
+                        "errorMessage": error_message,
+                    }));
+                }
+                SshEvent::CredentialRequest { kind, host, username } => {
+                    let _ = app_handle.emit("credential-request", serde_json::json!({
+                        "sessionId": sid,
+                        "kind": kind,
+                        "host": host,
+                        "username": username,
+                    }));
+                }
+                SshEvent::Banner(banner) => {
+                    let _ = app_handle.emit("ssh-banner", serde_json::json!({
+                        "sessionId": sid,
+                        "banner": banner,
+                    }));
+                }
+                SshEvent::SecurityKeyTouch { fingerprint } => {
+                    let _ = app_handle.emit("ssh-security-key-touch", serde_json::json!({
+                        "sessionId": sid,
+                        "fingerprint": fingerprint,
+                    }));
+                }
             }
         }
     });
 
-    Ok(session_id)
+    let (cancel_tx, cancel_rx) = oneshot::channel();
+    session_manager.connect_attempts.lock().await.insert(
+        session_id.clone(),
+        ConnectAttempt {
+            connection_id: conn.id.clone(),
+            cancel: cancel_tx,
+        },
+    );
+
+    let known_hosts = KnownHostsStore::new(data_dir);
+    let session = tokio::select! {
+        result = SshSession::connect(
+            session_id.clone(),
+            &conn,
+            &jump_hosts,
+            tx,
+            known_hosts,
+            session_manager,
+            sftp_only,
+        ) => result,
+        _ = cancel_rx => Err("Connection cancelled".to_string()),
+    };
+    session_manager.connect_attempts.lock().await.remove(&session_id);
+    session_manager.host_key_prompts.lock().await.remove(&session_id);
+    session_manager.auth_prompts.lock().await.remove(&session_id);
+    session_manager.credential_prompts.lock().await.remove(&session_id);
+    session
+}
+
+/// Add a connected session to the manager, bring up its profile tunnels and
+/// start its background monitors
+async fn register_session(
+    app: AppHandle,
+    session_manager: &Arc<SessionManager>,
+    session: SshSession,
+) -> String {
+    let profile_tunnels = session.connection.tunnels.clone();
+    let session_id = session_manager.add_session(session).await;
+    start_profile_tunnels(&app, session_manager, &session_id, profile_tunnels).await;
+
+    // Periodically probe the round trip time for the tab's ping badge
+    let sm = session_manager.clone();
+    let app_handle = app.clone();
+    let sid = session_id.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(LATENCY_INTERVAL_SECS)).await;
+            let handle = match sm.sessions.lock().await.get(&sid) {
+                Some(session) => session.handle.clone(),
+                None => break,
+            };
+            if let Ok(latency) = ssh::measure_latency(&handle).await {
+                let _ = app_handle.emit("ssh-latency", serde_json::json!({
+                    "sessionId": sid,
+                    "latencyMs": latency,
+                }));
+            }
+        }
+    });
+
+    // Report traffic on the session's tunnels while it has any
+    let sm = session_manager.clone();
+    let app_handle = app.clone();
+    let sid = session_id.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(TUNNEL_STATS_INTERVAL_SECS)).await;
+            let stats: Vec<_> = match sm.sessions.lock().await.get(&sid) {
+                Some(session) => session
+                    .tunnels
+                    .values()
+                    .map(|t| t.stats.snapshot(&t.info.id))
+                    .collect(),
+                None => break,
+            };
+            if !stats.is_empty() {
+                let _ = app_handle.emit("tunnel-stats", serde_json::json!({
+                    "sessionId": sid,
+                    "tunnels": stats,
+                }));
+            }
+        }
+    });
+
+    // Bring back tunnels whose listener or channels died
+    let sm = session_manager.clone();
+    let app_handle = app.clone();
+    let sid = session_id.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(TUNNEL_HEALTH_INTERVAL_SECS)).await;
+            // Unhealthy tunnels are taken out of the session while they're
+            // restarted, so the lock isn't held across the round-trips
+            let (context, mut unhealthy) = {
+                let mut sessions = sm.sessions.lock().await;
+                let Some(session) = sessions.get_mut(&sid) else {
+                    break;
+                };
+                // Nothing can be restored over a dead connection
+                if session.handle.is_closed() {
+                    continue;
+                }
+                let ids: Vec<String> = session
+                    .tunnels
+                    .iter()
+                    .filter(|(_, t)| !t.is_healthy())
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                let unhealthy: Vec<tunnel::Tunnel> =
+                    ids.iter().filter_map(|id| session.tunnels.remove(id)).collect();
+                (tunnel::TunnelContext::new(session), unhealthy)
+            };
+            if unhealthy.is_empty() {
+                continue;
+            }
+            for tunnel in &mut unhealthy {
+                match tunnel::restart(&context, tunnel).await {
+                    Ok(()) => {
+                        let _ = app_handle.emit("tunnel-restored", serde_json::json!({
+                            "sessionId": sid,
+                            "tunnelId": tunnel.info.id,
+                            "boundPort": tunnel.info.bound_port,
+                        }));
+                    }
+                    Err(e) => log::warn!("Failed to restart tunnel {}: {}", tunnel.info.id, e),
+                }
+            }
+            let mut sessions = sm.sessions.lock().await;
+            let Some(session) = sessions.get_mut(&sid) else {
+                break;
+            };
+            for tunnel in unhealthy {
+                session.tunnels.insert(tunnel.info.id.clone(), tunnel);
+            }
+        }
+    });
+
+    session_id
+}
+
+/// Fetch a server's host key without connecting, so it can be verified out
+/// of band before the connection is saved or trusted. With `connection_id`
+/// the host is reached the way that profile reaches it (ProxyCommand, proxy,
+/// jump hosts); jump hosts must connect without asking the user anything.
+#[tauri::command]
+pub async fn ssh_probe_host(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    host: String,
+    port: u16,
+    connection_id: Option<String>,
+) -> Result<HostKeyInfo, String> {
+    let Some(connection_id) = connection_id else {
+        let target = Connection::new(
+            host.clone(),
+            host,
+            port,
+            String::new(),
+            AuthType::Password,
+            None,
+            None,
+            None,
+        );
+        return ssh::probe_host_key(&target, None).await;
+    };
+
+    let (mut target, mut jump_hosts) = load_profile(&app, &connection_id)?;
+    // The form may be probing an address not saved yet
+    target.host = host;
+    target.port = port;
+    let target = keychain::resolve(target).await;
+    let Some(bastion) = jump_hosts.pop() else {
+        return ssh::probe_host_key(&target, None).await;
+    };
+    let bastion =
+        connect_session(&app, &session_manager, bastion, jump_hosts, true, false).await?;
+    let probed = ssh::probe_host_key(&target, Some(bastion.handle.as_ref())).await;
+    let _ = bastion
+        .handle
+        .disconnect(russh::Disconnect::ByApplication, "", "en")
+        .await;
+    probed
+}
+
+/// Bring up the tunnels saved on the connection profile. A tunnel that fails
+/// (e.g. its port is taken) is reported but doesn't fail the connection.
+async fn start_profile_tunnels(
+    app: &AppHandle,
+    session_manager: &SessionManager,
+    session_id: &str,
+    specs: Vec<TunnelSpec>,
+) {
+    for spec in specs {
+        let payload = match create_tunnel(session_manager, session_id, spec.clone()).await {
+            Ok(info) => serde_json::json!({
+                "sessionId": session_id,
+                "tunnelId": info.id,
+                "spec": info.spec,
+                "boundPort": info.bound_port,
+                "status": "active",
+            }),
+            Err(e) => serde_json::json!({
+                "sessionId": session_id,
+                "spec": spec,
+                "status": "failed",
+                "error": e,
+            }),
+        };
+        let _ = app.emit("tunnel-status", payload);
+    }
+}
+
+#[tauri::command]
+pub async fn ssh_get_latency(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+) -> Result<u64, String> {
+    // Release the session lock before the round trip so terminal I/O isn't blocked
+    let handle = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        session.handle.clone()
+    };
+    ssh::measure_latency(&handle).await
+}
+
+#[tauri::command]
+pub async fn ssh_host_key_respond(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    accept: bool,
+) -> Result<(), String> {
+    let reply = session_manager
+        .host_key_prompts
+        .lock()
+        .await
+        .remove(&session_id)
+        .ok_or("No pending host key prompt")?;
+    let _ = reply.send(accept);
+    Ok(())
+}
+
+/// Abort any in-flight connection attempts for a saved connection, or for
+/// a quick connect started with this `attempt_id`
+#[tauri::command]
+pub async fn ssh_connect_cancel(
+    session_manager: State<'_, Arc<SessionManager>>,
+    connection_id: String,
+) -> Result<(), String> {
+    let mut attempts = session_manager.connect_attempts.lock().await;
+    let cancelled: Vec<String> = attempts
+        .iter()
+        .filter(|(_, attempt)| attempt.connection_id == connection_id)
+        .map(|(id, _)| id.clone())
+        .collect();
+    for id in cancelled {
+        if let Some(attempt) = attempts.remove(&id) {
+            let _ = attempt.cancel.send(());
+        }
+    }
+    Ok(())
+}
+
+/// Supply a password/passphrase requested at connect time; `None` cancels
+#[tauri::command]
+pub async fn ssh_credential_respond(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    secret: Option<String>,
+) -> Result<(), String> {
+    let reply = session_manager
+        .credential_prompts
+        .lock()
+        .await
+        .remove(&session_id)
+        .ok_or("No pending credential request")?;
+    if let Some(secret) = secret {
+        let _ = reply.send(secret);
+    }
+    Ok(())
+}
+
+/// Answer keyboard-interactive prompts; `None` cancels authentication
+#[tauri::command]
+pub async fn ssh_auth_respond(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    responses: Option<Vec<String>>,
+) -> Result<(), String> {
+    let reply = session_manager
+        .auth_prompts
+        .lock()
+        .await
+        .remove(&session_id)
+        .ok_or("No pending authentication prompt")?;
+    if let Some(responses) = responses {
+        let _ = reply.send(responses);
+    }
+    Ok(())
+}
+
+/// Connect with Mosh: start mosh-server over a short-lived SSH session, then
+/// hand the terminal to a local mosh-client. Its output arrives through the
+/// usual `ssh-data`/`ssh-close` events under the returned session id.
+#[tauri::command]
+pub async fn mosh_connect(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    connection_id: String,
+) -> Result<String, String> {
+    let (mut conn, jump_hosts) = load_profile(&app, &connection_id)?;
+    if !jump_hosts.is_empty() || conn.proxy.is_some() || conn.proxy_command.is_some() {
+        return Err("Mosh needs a direct UDP path, not jump hosts or proxies".to_string());
+    }
+    let server = tokio::net::lookup_host((conn.host.as_str(), conn.port))
+        .await
+        .map_err(|e| format!("Failed to resolve {}: {}", conn.host, e))?
+        .next()
+        .ok_or_else(|| format!("No addresses found for {}", conn.host))?
+        .ip();
+
+    // The SSH session only lives long enough to start mosh-server, which
+    // runs on an exec channel of its own, so no shell or PTY is opened
+    conn.tunnels.clear();
+    let bootstrap =
+        connect_session(&app, &session_manager, conn, Vec::new(), true, true).await?;
+    let connect = mosh::bootstrap(&bootstrap.handle, &bootstrap.channel_registry).await;
+    let _ = bootstrap.close().await;
+    let connect = connect?;
+
+    let (tx, rx) = mpsc::channel::<SshEvent>(1024);
+    let session_id = Uuid::new_v4().to_string();
+    let session = mosh::spawn_client(session_id.clone(), server, &connect, tx)?;
+    add_terminal_session(app, &session_manager, session_id, Box::new(session), rx).await
+}
+
+/// Open a telnet connection to a saved connection's host and port
+#[tauri::command]
+pub async fn telnet_connect(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    connection_id: String,
+) -> Result<String, String> {
+    let (conn, _) = load_profile(&app, &connection_id)?;
+    let (tx, rx) = mpsc::channel::<SshEvent>(1024);
+    let session_id = Uuid::new_v4().to_string();
+    let session = TelnetSession::connect(session_id.clone(), &conn.host, conn.port, tx).await?;
+    add_terminal_session(app, &session_manager, session_id, Box::new(session), rx).await
+}
+
+/// Open a shell on this machine in a new tab; `shell` defaults to the
+/// user's login shell (PowerShell on Windows)
+#[tauri::command]
+pub async fn local_open(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    shell: Option<String>,
+) -> Result<String, String> {
+    let shell = shell.filter(|s| !s.trim().is_empty()).unwrap_or_else(|| {
+        if cfg!(windows) {
+            "powershell.exe".to_string()
+        } else {
+            std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string())
+        }
+    });
+    let mut cmd = CommandBuilder::new(shell);
+    if let Some(home) = dirs::home_dir() {
+        cmd.cwd(home);
+    }
+    cmd.env("TERM", "xterm-256color");
+
+    let (tx, rx) = mpsc::channel::<SshEvent>(1024);
+    let session_id = Uuid::new_v4().to_string();
+    let session = PtySession::spawn(session_id.clone(), cmd, tx)?;
+    add_terminal_session(app, &session_manager, session_id, Box::new(session), rx).await
+}
+
+/// Register a non-SSH terminal and forward its output through the same
+/// `ssh-data`/`ssh-close` events as SSH terminals
+async fn add_terminal_session(
+    app: AppHandle,
+    session_manager: &Arc<SessionManager>,
+    session_id: String,
+    session: Box<dyn TerminalSession>,
+    mut rx: mpsc::Receiver<SshEvent>,
+) -> Result<String, String> {
+    session_manager
+        .terminal_sessions
+        .lock()
+        .await
+        .insert(session_id.clone(), session);
+
+    let sm = session_manager.clone();
+    let sid = session_id.clone();
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            match event {
+                SshEvent::Data { channel_id, data, stderr } => {
+                    let _ = app.emit("ssh-data", serde_json::json!({
+                        "sessionId": sid,
+                        "channelId": channel_id,
+                        "stderr": stderr,
+                        "data": data,
+                    }));
+                }
+                SshEvent::Close { reason } => {
+                    sm.terminal_sessions.lock().await.remove(&sid);
+                    let _ = app.emit("ssh-close", serde_json::json!({
+                        "sessionId": sid,
+                        "reason": reason,
+                    }));
+                    break;
+                }
+                _ => {}
+            }
+        }
+    });
+
+    Ok(session_id)
+}
+
+// The term_* commands drive every `TerminalSession` tab (Mosh, local shells,
+// telnet) whatever backs it, so new terminal types need no commands of their own
+
+/// Send input to a non-SSH terminal tab
+#[tauri::command]
+pub async fn term_write(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    data: Vec<u8>,
+) -> Result<(), String> {
+    let mut sessions = session_manager.terminal_sessions.lock().await;
+    let session = sessions.get_mut(&session_id).ok_or("Session not found")?;
+    session.write(&data)
+}
+
+/// Tell a non-SSH terminal tab its new size
+#[tauri::command]
+pub async fn term_resize(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    cols: u32,
+    rows: u32,
+) -> Result<(), String> {
+    let mut sessions = session_manager.terminal_sessions.lock().await;
+    let session = sessions.get_mut(&session_id).ok_or("Session not found")?;
+    session.resize(cols, rows)
+}
+
+/// Close a non-SSH terminal tab
+#[tauri::command]
+pub async fn term_disconnect(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+) -> Result<(), String> {
+    // Dropping the session closes it (and stops the program behind it)
+    session_manager.terminal_sessions.lock().await.remove(&session_id);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn ssh_write(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    channel_id: Option<String>,
+    data: Vec<u8>,
+) -> Result<(), String> {
+    let sessions = session_manager.sessions.lock().await;
+    let session = sessions.get(&session_id).ok_or("Session not found")?;
+    let channel_id = channel_id.unwrap_or_else(|| session_id.clone());
+    session.write(&channel_id, &data).await
+}
+
+#[tauri::command]
+pub async fn ssh_resize(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    channel_id: Option<String>,
+    cols: u32,
+    rows: u32,
+) -> Result<(), String> {
+    let sessions = session_manager.sessions.lock().await;
+    let session = sessions.get(&session_id).ok_or("Session not found")?;
+    let channel_id = channel_id.unwrap_or_else(|| session_id.clone());
+    session.resize(&channel_id, cols, rows).await
+}
+
+#[tauri::command]
+pub async fn ssh_send_signal(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    channel_id: Option<String>,
+    signal: String,
+) -> Result<(), String> {
+    let sessions = session_manager.sessions.lock().await;
+    let session = sessions.get(&session_id).ok_or("Session not found")?;
+    let channel_id = channel_id.unwrap_or_else(|| session_id.clone());
+    session.send_signal(&channel_id, &signal).await
+}
+
+/// Open an additional terminal on an existing connection without re-authenticating
+#[tauri::command]
+pub async fn ssh_open_channel(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    cols: u32,
+    rows: u32,
+) -> Result<String, String> {
+    let mut sessions = session_manager.sessions.lock().await;
+    let session = sessions.get_mut(&session_id).ok_or("Session not found")?;
+    session.open_channel(cols, rows).await
+}
+
+#[tauri::command]
+pub async fn ssh_close_channel(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    channel_id: String,
+) -> Result<(), String> {
+    let mut sessions = session_manager.sessions.lock().await;
+    let session = sessions.get_mut(&session_id).ok_or("Session not found")?;
+    session.close_channel(&channel_id).await
+}
+
+#[tauri::command]
+pub async fn ssh_list_channels(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+) -> Result<Vec<ChannelInfo>, String> {
+    let sessions = session_manager.sessions.lock().await;
+    let session = sessions.get(&session_id).ok_or("Session not found")?;
+    Ok(channels::list(&session.channel_registry).await)
+}
+
+/// Close one channel listed by `ssh_list_channels`, leaving the session up
+#[tauri::command]
+pub async fn ssh_kill_channel(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    channel_id: String,
+) -> Result<(), String> {
+    let mut sessions = session_manager.sessions.lock().await;
+    let session = sessions.get_mut(&session_id).ok_or("Session not found")?;
+    let (id, kind) = channels::find(&session.channel_registry, &channel_id)
+        .await
+        .ok_or("Channel not found")?;
+    if kind == ChannelKind::Shell {
+        // Terminals are owned by the session, so close them the usual way
+        let terminal_id = session.terminal_channels.lock().await.get(&id).cloned();
+        if let Some(terminal_id) = terminal_id {
+            return session.close_channel(&terminal_id).await;
+        }
+    }
+    channels::request_close(&session.channel_registry, id).await
+}
+
+#[tauri::command]
+pub async fn ssh_exec(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    command: String,
+) -> Result<ExecOutput, String> {
+    let (handle, registry) = session_manager.handle(&session_id).await?;
+    ssh::exec(&handle, &registry, &command).await
+}
+
+/// Run the same command on several sessions at once, one result per session
+/// in the order given
+#[tauri::command]
+pub async fn ssh_exec_multi(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_ids: Vec<String>,
+    command: String,
+) -> Result<Vec<HostExecResult>, String> {
+    // Take the handles up front so the runs don't hold the sessions lock
+    let targets: Vec<_> = {
+        let sessions = session_manager.sessions.lock().await;
+        session_ids
+            .into_iter()
+            .map(|id| {
+                let session = sessions.get(&id);
+                let host = session.map(|s| s.connection.host.clone()).unwrap_or_default();
+                let handle = session.map(|s| (s.handle.clone(), s.channel_registry.clone()));
+                (id, host, handle)
+            })
+            .collect()
+    };
+
+    let runs = targets.into_iter().map(|(session_id, host, handle)| {
+        let command = command.clone();
+        tokio::spawn(async move {
+            let started = std::time::Instant::now();
+            let result = match handle {
+                Some((handle, registry)) => ssh::exec(&handle, &registry, &command).await,
+                None => Err("Session not found".to_string()),
+            };
+            let duration_ms = started.elapsed().as_millis() as u64;
+            match result {
+                Ok(output) => HostExecResult {
+                    session_id,
+                    host,
+                    exit_code: output.exit_code,
+                    stdout: output.stdout,
+                    stderr: output.stderr,
+                    error: None,
+                    duration_ms,
+                },
+                Err(e) => HostExecResult {
+                    session_id,
+                    host,
+                    exit_code: None,
+                    stdout: String::new(),
+                    stderr: String::new(),
+                    error: Some(e),
+                    duration_ms,
+                },
+            }
+        })
+    });
+
+    let mut results = Vec::new();
+    for run in runs.collect::<Vec<_>>() {
+        results.push(run.await.map_err(|e| e.to_string())?);
+    }
+    Ok(results)
+}
+
+/// Run a long command and stream its output as `ssh-exec-output` events,
+/// ending with `ssh-exec-exit`. Returns the exec id for `ssh_exec_cancel`.
+#[tauri::command]
+pub async fn ssh_exec_stream(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    command: String,
+) -> Result<String, String> {
+    let (handle, registry) = session_manager.handle(&session_id).await?;
+    let (mut channel, mut close) = ssh::open_exec(&handle, &registry, &command).await?;
+
+    let exec_id = Uuid::new_v4().to_string();
+    let (cancel_tx, mut cancel_rx) = oneshot::channel();
+    session_manager
+        .exec_streams
+        .lock()
+        .await
+        .insert(exec_id.clone(), cancel_tx);
+
+    let sm = session_manager.inner().clone();
+    let eid = exec_id.clone();
+    tokio::spawn(async move {
+        let mut exit_code = None;
+        let mut cancelled = false;
+        let mut close_seen = false;
+        loop {
+            tokio::select! {
+                msg = channel.wait() => match msg {
+                    Some(ChannelMsg::Data { data }) => {
+                        let _ = app.emit("ssh-exec-output", serde_json::json!({
+                            "sessionId": session_id,
+                            "execId": eid,
+                            "stderr": false,
+                            "data": data.to_vec(),
+                        }));
+                    }
+                    Some(ChannelMsg::ExtendedData { data, ext: 1 }) => {
+                        let _ = app.emit("ssh-exec-output", serde_json::json!({
+                            "sessionId": session_id,
+                            "execId": eid,
+                            "stderr": true,
+                            "data": data.to_vec(),
+                        }));
+                    }
+                    Some(ChannelMsg::ExitStatus { exit_status }) => exit_code = Some(exit_status),
+                    Some(_) => {}
+                    None => break,
+                },
+                _ = &mut cancel_rx, if !cancelled => {
+                    cancelled = true;
+                    let _ = channel.close().await;
+                }
+                // Closed from the channel inspector
+                requested = &mut close, if !close_seen => {
+                    close_seen = true;
+                    if requested.is_ok() && !cancelled {
+                        cancelled = true;
+                        let _ = channel.close().await;
+                    }
+                }
+            }
+        }
+
+        sm.exec_streams.lock().await.remove(&eid);
+        let _ = app.emit("ssh-exec-exit", serde_json::json!({
+            "sessionId": session_id,
+            "execId": eid,
+            "exitCode": exit_code,
+            "cancelled": cancelled,
+        }));
+    });
+
+    Ok(exec_id)
+}
+
+#[tauri::command]
+pub async fn ssh_exec_cancel(
+    session_manager: State<'_, Arc<SessionManager>>,
+    exec_id: String,
+) -> Result<(), String> {
+    let cancel = session_manager
+        .exec_streams
+        .lock()
+        .await
+        .remove(&exec_id)
+        .ok_or("Command is not running")?;
+    let _ = cancel.send(());
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn ssh_disconnect(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+) -> Result<(), String> {
+    watch::clear_session(&session_manager.file_watches, &session_id).await;
+    if let Some(session) = session_manager.remove_session(&session_id).await {
+        // Tunnels go down with the session (dropping them stops the listeners)
+        for tunnel in session.tunnels.values() {
+            let _ = app.emit("tunnel-status", serde_json::json!({
+                "sessionId": session_id,
+                "tunnelId": tunnel.info.id,
+                "spec": tunnel.info.spec,
+                "status": "closed",
+            }));
+        }
+        session.close().await?;
+    }
+    Ok(())
+}
+
+// ── Tunnel Commands ──────────────────────────────────────────────────
+
+async fn create_tunnel(
+    session_manager: &SessionManager,
+    session_id: &str,
+    spec: TunnelSpec,
+) -> Result<TunnelInfo, String> {
+    let mut sessions = session_manager.sessions.lock().await;
+    let session = sessions.get_mut(session_id).ok_or("Session not found")?;
+    let tunnel = tunnel::open(session, spec).await?;
+    let info = tunnel.info.clone();
+    session.tunnels.insert(info.id.clone(), tunnel);
+    Ok(info)
+}
+
+#[tauri::command]
+pub async fn tunnel_create_remote(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    bind_host: String,
+    bind_port: u16,
+    target_host: String,
+    target_port: u16,
+) -> Result<TunnelInfo, String> {
+    let spec = TunnelSpec {
+        kind: TunnelKind::Remote,
+        bind_host,
+        bind_port,
+        target_host,
+        target_port,
+        auto_port: false,
+    };
+    create_tunnel(&session_manager, &session_id, spec).await
+}
+
+#[tauri::command]
+pub async fn tunnel_create_local(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    bind_host: String,
+    bind_port: u16,
+    target_host: String,
+    target_port: u16,
+    auto_port: Option<bool>,
+) -> Result<TunnelInfo, String> {
+    let spec = TunnelSpec {
+        kind: TunnelKind::Local,
+        bind_host,
+        bind_port,
+        target_host,
+        target_port,
+        auto_port: auto_port.unwrap_or(false),
+    };
+    create_tunnel(&session_manager, &session_id, spec).await
+}
+
+/// Start a SOCKS5 proxy (e.g. on 127.0.0.1:1080) that browses via the server
+#[tauri::command]
+pub async fn tunnel_create_dynamic(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    bind_host: String,
+    bind_port: u16,
+    auto_port: Option<bool>,
+) -> Result<TunnelInfo, String> {
+    let spec = TunnelSpec {
+        kind: TunnelKind::Dynamic,
+        bind_host,
+        bind_port,
+        target_host: String::new(),
+        target_port: 0,
+        auto_port: auto_port.unwrap_or(false),
+    };
+    create_tunnel(&session_manager, &session_id, spec).await
+}
+
+/// Forward a free local port to an HTTP server on the remote host and open
+/// it in the default browser
+#[tauri::command]
+pub async fn tunnel_open_web(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    remote_port: u16,
+    path: Option<String>,
+) -> Result<TunnelInfo, String> {
+    let spec = TunnelSpec {
+        kind: TunnelKind::Local,
+        bind_host: "127.0.0.1".to_string(),
+        bind_port: 0,
+        target_host: "localhost".to_string(),
+        target_port: remote_port,
+        auto_port: true,
+    };
+    let info = create_tunnel(&session_manager, &session_id, spec).await?;
+
+    let path = path.unwrap_or_default();
+    let url = format!(
+        "http://127.0.0.1:{}/{}",
+        info.bound_port,
+        path.trim_start_matches('/')
+    );
+    if let Err(e) = opener::open_browser(&url) {
+        // The forward is only there for the browser
+        let mut sessions = session_manager.sessions.lock().await;
+        if let Some(session) = sessions.get_mut(&session_id) {
+            if let Some(tunnel) = session.tunnels.remove(&info.id) {
+                let _ = tunnel::close(session, tunnel).await;
+            }
+        }
+        return Err(format!("Failed to open browser: {}", e));
+    }
+    Ok(info)
+}
+
+#[tauri::command]
+pub async fn tunnel_list(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+) -> Result<Vec<TunnelInfo>, String> {
+    let sessions = session_manager.sessions.lock().await;
+    let session = sessions.get(&session_id).ok_or("Session not found")?;
+    Ok(session.tunnels.values().map(|t| t.info.clone()).collect())
+}
+
+#[tauri::command]
+pub async fn tunnel_close(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    tunnel_id: String,
+) -> Result<(), String> {
+    let mut sessions = session_manager.sessions.lock().await;
+    let session = sessions.get_mut(&session_id).ok_or("Session not found")?;
+    let tunnel = session
+        .tunnels
+        .remove(&tunnel_id)
+        .ok_or("Tunnel not found")?;
+    tunnel::close(session, tunnel).await
+}
+
+#[tauri::command]
+pub async fn get_tunnel_profiles(app: AppHandle) -> Result<Vec<TunnelProfile>, String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    Ok(TunnelProfileStore::new(data_dir).load())
+}
+
+#[tauri::command]
+pub async fn save_tunnel_profile(
+    app: AppHandle,
+    id: Option<String>,
+    name: String,
+    tunnels: Vec<TunnelSpec>,
+) -> Result<Vec<TunnelProfile>, String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let profile = TunnelProfile {
+        id: id.unwrap_or_else(|| Uuid::new_v4().to_string()),
+        name,
+        tunnels,
+    };
+    TunnelProfileStore::new(data_dir).upsert(profile)
+}
+
+#[tauri::command]
+pub async fn delete_tunnel_profile(
+    app: AppHandle,
+    id: String,
+) -> Result<Vec<TunnelProfile>, String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    TunnelProfileStore::new(data_dir).delete(&id)
+}
+
+/// Bring up a saved tunnel profile on a running session, reporting each
+/// forward through `tunnel-status` like the ones started at connect time
+#[tauri::command]
+pub async fn tunnel_apply_profile(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    profile_id: String,
+) -> Result<(), String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let specs = TunnelProfileStore::new(data_dir).specs(&[profile_id])?;
+    start_profile_tunnels(&app, &session_manager, &session_id, specs).await;
+    Ok(())
+}
+
+/// Route TCP traffic for `subnets` (IPv4 CIDRs) through the session
+#[tauri::command]
+pub async fn vpn_start(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    subnets: Vec<String>,
+) -> Result<VpnInfo, String> {
+    // Starting may wait on the polkit prompt, so it runs without the lock
+    let (handle, registry, host, port) = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        if session.vpn.is_some() {
+            return Err("VPN is already running on this session".to_string());
+        }
+        (
+            session.handle.clone(),
+            session.channel_registry.clone(),
+            session.connection.host.clone(),
+            session.connection.port,
+        )
+    };
+    let vpn = vpn::start(handle, registry, &host, port, subnets).await?;
+
+    let mut sessions = session_manager.sessions.lock().await;
+    let session = sessions.get_mut(&session_id).ok_or("Session not found")?;
+    // Started twice at once; dropping this one removes its rules again
+    if session.vpn.is_some() {
+        return Err("VPN is already running on this session".to_string());
+    }
+    let info = vpn.info.clone();
+    session.vpn = Some(vpn);
+    Ok(info)
+}
+
+#[tauri::command]
+pub async fn vpn_stop(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+) -> Result<(), String> {
+    let mut sessions = session_manager.sessions.lock().await;
+    let session = sessions.get_mut(&session_id).ok_or("Session not found")?;
+    // Dropping it removes the firewall rules
+    session.vpn.take().ok_or("VPN is not running")?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn vpn_status(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+) -> Result<Option<VpnInfo>, String> {
+    let sessions = session_manager.sessions.lock().await;
+    let session = sessions.get(&session_id).ok_or("Session not found")?;
+    Ok(session.vpn.as_ref().map(|v| v.info.clone()))
+}
+
+// ── SFTP Commands ────────────────────────────────────────────────────
+
+/// List a directory, optionally narrowed down by `filter` before anything is
+/// sent to the frontend
+#[tauri::command]
+pub async fn sftp_list(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    path: String,
+    filter: Option<sftp::ListFilter>,
+) -> Result<Vec<sftp::FileEntry>, String> {
+    let (opener, ids, mime_cache) = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        let ids = sftp::IdLookup::new(session);
+        (sftp::SftpOpener::new(session), ids, session.mime_cache.clone())
+    };
+    let sftp_session = opener.get().await?;
+    let mut entries = sftp::list_dir(&sftp_session, &path, &filter.unwrap_or_default()).await?;
+    ids.apply(&mut entries).await;
+    mime::detect(&ids.handle, &ids.channel_registry, &mime_cache, &mut entries).await;
+    Ok(entries)
+}
+
+/// List a directory incrementally: entries arrive as `sftp-list-chunk`
+/// events tagged with `listing_id`, and the total is returned at the end.
+/// Meant for directories too big to send in one `sftp_list` reply; types
+/// are only told from the name, as asking the server would hold up chunks.
+#[tauri::command]
+pub async fn sftp_list_stream(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    path: String,
+    listing_id: String,
+    filter: Option<sftp::ListFilter>,
+) -> Result<usize, String> {
+    let (opener, ids) = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        (sftp::SftpOpener::new(session), sftp::IdLookup::new(session))
+    };
+    let sftp_session = opener.get().await?;
+    let filter = filter.unwrap_or_default();
+    sftp::list_dir_chunked(&sftp_session, &path, &filter, &ids, |mut entries| {
+        mime::detect_local(&mut entries);
+        let _ = app.emit("sftp-list-chunk", serde_json::json!({
+            "sessionId": session_id,
+            "listingId": listing_id,
+            "entries": entries,
+        }));
+    })
+    .await
+}
+
+/// Emits `sftp-progress` for a transfer
+fn progress_reporter(
+    app: AppHandle,
+    session_id: String,
+    transfer_id: String,
+) -> impl FnMut(sftp::TransferProgress) {
+    move |progress| {
+        let _ = app.emit("sftp-progress", serde_json::json!({
+            "sessionId": session_id,
+            "transferId": transfer_id,
+            "bytesDone": progress.bytes_done,
+            "bytesTotal": progress.bytes_total,
+            "bytesPerSec": progress.bytes_per_sec,
+            "etaSecs": progress.eta_secs,
+        }));
+    }
+}
+
+/// Run a transfer until it finishes or `sftp_cancel_transfer` is called for it
+async fn run_transfer<T>(
+    session_manager: &SessionManager,
+    transfer_id: &str,
+    transfer: impl std::future::Future<Output = Result<T, String>>,
+) -> Result<T, String> {
+    let run = session_manager.register_transfer(transfer_id);
+    run_registered(session_manager, transfer_id, run, transfer).await
+}
+
+/// Run a transfer already registered as `run` (see
+/// `SessionManager::register_transfer`) until it finishes or is cancelled
+async fn run_registered<T>(
+    session_manager: &SessionManager,
+    transfer_id: &str,
+    (generation, cancelled): (u64, oneshot::Receiver<()>),
+    transfer: impl std::future::Future<Output = Result<T, String>>,
+) -> Result<T, String> {
+    let result = tokio::select! {
+        result = transfer => result,
+        Ok(()) = cancelled => Err("Transfer cancelled".to_string()),
+    };
+    session_manager.unregister_transfer(transfer_id, generation);
+    result
+}
+
+/// Settles a transfer's conflicts by its policy. For `Ask`,
+/// `transfer-conflict` is emitted and the answer from
+/// `transfer_conflict_respond` is awaited; a dismissed or unanswered prompt
+/// counts as skip.
+struct ConflictPrompt {
+    app: AppHandle,
+    session_manager: Arc<SessionManager>,
+    session_id: String,
+    transfer_id: String,
+    policy: sftp::ConflictPolicy,
+}
+
+#[async_trait::async_trait]
+impl sftp::ConflictHandler for ConflictPrompt {
+    async fn settle(&self, conflict: &sftp::Conflict) -> sftp::ConflictResolution {
+        if let Some(resolution) = self.policy.resolve(conflict) {
+            return resolution;
+        }
+        let (tx, rx) = oneshot::channel();
+        let prompts = &self.session_manager.conflict_prompts;
+        prompts.lock().await.insert(self.transfer_id.clone(), tx);
+        let _ = self.app.emit("transfer-conflict", serde_json::json!({
+            "sessionId": self.session_id,
+            "transferId": self.transfer_id,
+            "conflict": conflict,
+        }));
+        let timeout = std::time::Duration::from_secs(CONFLICT_PROMPT_TIMEOUT_SECS);
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(answer) => answer.unwrap_or(sftp::ConflictResolution::Skip),
+            Err(_) => {
+                prompts.lock().await.remove(&self.transfer_id);
+                sftp::ConflictResolution::Skip
+            }
+        }
+    }
+}
+
+/// Answer a `transfer-conflict` event; `None` skips the file
+#[tauri::command]
+pub async fn transfer_conflict_respond(
+    session_manager: State<'_, Arc<SessionManager>>,
+    transfer_id: String,
+    resolution: Option<sftp::ConflictResolution>,
+) -> Result<(), String> {
+    let reply = session_manager
+        .conflict_prompts
+        .lock()
+        .await
+        .remove(&transfer_id)
+        .ok_or("No pending conflict for this transfer")?;
+    let _ = reply.send(resolution.unwrap_or(sftp::ConflictResolution::Skip));
+    Ok(())
+}
+
+/// Download a file, emitting `sftp-progress` under `transfer_id` (generated
+/// when not given) so the frontend can follow and cancel it. `resume`
+/// continues from an existing partial local file and `preserve` keeps the
+/// remote mtime and permissions; with `verify`, both copies are hashed
+/// afterwards and the result says whether they match. An existing local
+/// file is handled by `conflict_policy` (overwritten by default). `parallel`
+/// reads large files over several SFTP channels at once; it is ignored when
+/// resuming.
+#[tauri::command]
+pub async fn sftp_download(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    remote_path: String,
+    mut local_path: String,
+    transfer_id: Option<String>,
+    resume: Option<bool>,
+    preserve: Option<bool>,
+    verify: Option<bool>,
+    conflict_policy: Option<sftp::ConflictPolicy>,
+    parallel: Option<bool>,
+) -> Result<sftp::TransferResult, String> {
+    let (handle, registry, opener) = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        (session.handle.clone(), session.channel_registry.clone(), sftp::SftpOpener::new(session))
+    };
+    let sftp_session = opener.get().await?;
+    let transfer_id = transfer_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+    let options = sftp::TransferOptions {
+        resume: resume.unwrap_or(false),
+        preserve: preserve.unwrap_or(false),
+        ..Default::default()
+    };
+
+    // Resuming means the partial file is meant to be there
+    if !options.resume {
+        let conflicts = ConflictPrompt {
+            app: app.clone(),
+            session_manager: session_manager.inner().clone(),
+            session_id: session_id.clone(),
+            transfer_id: transfer_id.clone(),
+            policy: conflict_policy.unwrap_or_default(),
+        };
+        match sftp::download_target(&sftp_session, &conflicts, &remote_path, &local_path).await {
+            Some(target) => local_path = target,
+            None => {
+                return Ok(sftp::TransferResult {
+                    destination: local_path,
+                    skipped: true,
+                    checksum: None,
+                })
+            }
+        }
+    }
+
+    let report = progress_reporter(app, session_id, transfer_id.clone());
+    if parallel.unwrap_or(false) && !options.resume {
+        let transfer = sftp::download_parallel(
+            &handle,
+            &registry,
+            &sftp_session,
+            &remote_path,
+            &local_path,
+            options,
+            report,
+        );
+        run_transfer(&session_manager, &transfer_id, transfer).await?;
+    } else {
+        let transfer =
+            sftp::download_file(&sftp_session, &remote_path, &local_path, options, report);
+        run_transfer(&session_manager, &transfer_id, transfer).await?;
+    }
+
+    let checksum = match verify.unwrap_or(false) {
+        true => Some(
+            sftp::verify_checksum(&handle, &registry, &sftp_session, &local_path, &remote_path)
+                .await?,
+        ),
+        false => None,
+    };
+    Ok(sftp::TransferResult {
+        destination: local_path,
+        skipped: false,
+        checksum,
+    })
+}
+
+/// Upload a file; progress, cancellation and the options work as for
+/// `sftp_download`. `append` adds the file to the end of the remote one
+/// instead of replacing it; `atomic` uploads to a `.part` file and renames it
+/// into place.
+#[tauri::command]
+pub async fn sftp_upload(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    local_path: String,
+    mut remote_path: String,
+    transfer_id: Option<String>,
+    resume: Option<bool>,
+    preserve: Option<bool>,
+    append: Option<bool>,
+    atomic: Option<bool>,
+    verify: Option<bool>,
+    conflict_policy: Option<sftp::ConflictPolicy>,
+) -> Result<sftp::TransferResult, String> {
+    let options = sftp::TransferOptions {
+        resume: resume.unwrap_or(false),
+        preserve: preserve.unwrap_or(false),
+        append: append.unwrap_or(false),
+        atomic: atomic.unwrap_or(false),
+    };
+    if options.append && verify.unwrap_or(false) {
+        return Err("An appended upload can't be verified".to_string());
+    }
+    let (handle, registry, opener) = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        (session.handle.clone(), session.channel_registry.clone(), sftp::SftpOpener::new(session))
+    };
+    let sftp_session = opener.get().await?;
+    let transfer_id = transfer_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    // Resuming and appending both mean to reuse the remote file
+    if !options.resume && !options.append {
+        let conflicts = ConflictPrompt {
+            app: app.clone(),
+            session_manager: session_manager.inner().clone(),
+            session_id: session_id.clone(),
+            transfer_id: transfer_id.clone(),
+            policy: conflict_policy.unwrap_or_default(),
+        };
+        match sftp::upload_target(&sftp_session, &conflicts, &local_path, &remote_path).await {
+            Some(target) => remote_path = target,
+            None => {
+                return Ok(sftp::TransferResult {
+                    destination: remote_path,
+                    skipped: true,
+                    checksum: None,
+                })
+            }
+        }
+    }
+
+    let report = progress_reporter(app, session_id, transfer_id.clone());
+    let transfer = sftp::upload_file(&sftp_session, &local_path, &remote_path, options, report);
+    run_transfer(&session_manager, &transfer_id, transfer).await?;
+
+    let checksum = match verify.unwrap_or(false) {
+        true => Some(
+            sftp::verify_checksum(&handle, &registry, &sftp_session, &local_path, &remote_path)
+                .await?,
+        ),
+        false => None,
+    };
+    Ok(sftp::TransferResult {
+        destination: remote_path,
+        skipped: false,
+        checksum,
+    })
+}
+
+/// Download a whole directory tree; progress is aggregated over all its files
+/// and files that fail are listed in the summary rather than ending the job.
+/// `preserve` keeps each file's mtime and permissions; files that exist
+/// locally are handled by `conflict_policy` as in `sftp_download`.
+#[tauri::command]
+pub async fn sftp_download_dir(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    remote_path: String,
+    local_path: String,
+    transfer_id: Option<String>,
+    preserve: Option<bool>,
+    conflict_policy: Option<sftp::ConflictPolicy>,
+) -> Result<sftp::DirTransferSummary, String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    let transfer_id = transfer_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+    let conflicts = ConflictPrompt {
+        app: app.clone(),
+        session_manager: session_manager.inner().clone(),
+        session_id: session_id.clone(),
+        transfer_id: transfer_id.clone(),
+        policy: conflict_policy.unwrap_or_default(),
+    };
+    let report = progress_reporter(app, session_id, transfer_id.clone());
+    let preserve = preserve.unwrap_or(false);
+    let transfer =
+        sftp::download_dir(&sftp_session, &remote_path, &local_path, preserve, &conflicts, report);
+    run_transfer(&session_manager, &transfer_id, transfer).await
+}
+
+/// Upload a mix of local files and directories (say, from an OS drag) into
+/// `remote_dir`, with one combined progress stream under `transfer_id`.
+/// Files that exist remotely are handled by `conflict_policy`.
+#[tauri::command]
+pub async fn sftp_upload_paths(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    local_paths: Vec<String>,
+    remote_dir: String,
+    transfer_id: Option<String>,
+    conflict_policy: Option<sftp::ConflictPolicy>,
+) -> Result<sftp::DirTransferSummary, String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    let transfer_id = transfer_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+    let conflicts = ConflictPrompt {
+        app: app.clone(),
+        session_manager: session_manager.inner().clone(),
+        session_id: session_id.clone(),
+        transfer_id: transfer_id.clone(),
+        policy: conflict_policy.unwrap_or_default(),
+    };
+    let report = progress_reporter(app, session_id, transfer_id.clone());
+    let transfer =
+        sftp::upload_paths(&sftp_session, &local_paths, &remote_dir, &conflicts, report);
+    run_transfer(&session_manager, &transfer_id, transfer).await
+}
+
+/// Sync `local_dir` and `remote_dir`. `upload` makes the server match the
+/// local side and `download` the reverse, copying only files whose size or
+/// mtime differ; `delete_extra` also removes what the source doesn't have.
+/// `both` carries changes over in either direction since the pair's last
+/// two-way sync and reports edits made on both sides as conflicts. With
+/// `dry_run` the plan is returned and nothing is touched; passing its `hash`
+/// as `plan_hash` then runs exactly that plan, or fails if either tree has
+/// changed since.
+#[tauri::command]
+pub async fn sync_dirs(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    local_dir: String,
+    remote_dir: String,
+    mode: dirsync::SyncMode,
+    delete_extra: Option<bool>,
+    dry_run: Option<bool>,
+    transfer_id: Option<String>,
+    plan_hash: Option<String>,
+) -> Result<dirsync::SyncReport, String> {
+    let local_dir = std::path::PathBuf::from(local_dir);
+    let (opener, pair_key) = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        let pair_key = dirsync::pair_key(&session.connection, &local_dir, &remote_dir);
+        (sftp::SftpOpener::new(session), pair_key)
+    };
+    let sftp_session = opener.get().await?;
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let store = dirsync::SnapshotStore::new(data_dir);
+    let snapshot = match mode {
+        dirsync::SyncMode::Both => store.load(&pair_key),
+        _ => dirsync::Snapshot::new(),
+    };
+    let delete_extra = delete_extra.unwrap_or(false);
+    let plan = dirsync::plan(&sftp_session, &local_dir, &remote_dir, mode, delete_extra, &snapshot)
+        .await?;
+    if dry_run.unwrap_or(false) {
+        return Ok(dirsync::SyncReport {
+            plan,
+            summary: None,
+        });
+    }
+    // The same inputs make the same plan, so a matching hash means this is
+    // the plan that was previewed
+    if plan_hash.is_some_and(|hash| hash != plan.hash) {
+        return Err("The directories changed since the sync was previewed".to_string());
+    }
+
+    let transfer_id = transfer_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+    let report = progress_reporter(app, session_id, transfer_id.clone());
+    let sync = dirsync::execute(&sftp_session, &local_dir, &remote_dir, &plan, report);
+    let summary = run_transfer(&session_manager, &transfer_id, sync).await?;
+
+    if mode == dirsync::SyncMode::Both {
+        let next = dirsync::snapshot_after(
+            &sftp_session,
+            &local_dir,
+            &remote_dir,
+            &snapshot,
+            &plan,
+            &summary,
+        )
+        .await?;
+        store.save(&pair_key, next)?;
+    }
+    Ok(dirsync::SyncReport {
+        plan,
+        summary: Some(summary),
+    })
+}
+
+/// Download a directory as one `.tar.gz` at `local_path`: the server packs
+/// it with `tar` and the archive is deleted there afterwards. Much faster
+/// than `sftp_download_dir` for trees of many small files.
+#[tauri::command]
+pub async fn sftp_download_compressed(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    remote_path: String,
+    local_path: String,
+    transfer_id: Option<String>,
+) -> Result<(), String> {
+    let (handle, registry, opener) = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        (session.handle.clone(), session.channel_registry.clone(), sftp::SftpOpener::new(session))
+    };
+    let sftp_session = opener.get().await?;
+    let archive = sftp::archive_dir(&handle, &registry, &remote_path)
+        .await?
+        .ok_or("The server can't run tar; download the directory instead")?;
+
+    let transfer_id = transfer_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+    let report = progress_reporter(app, session_id, transfer_id.clone());
+    let options = sftp::TransferOptions::default();
+    let transfer = sftp::download_file(&sftp_session, &archive, &local_path, options, report);
+    let result = run_transfer(&session_manager, &transfer_id, transfer).await;
+
+    // Clean up whether or not the download made it
+    if let Err(e) = sftp::remove_file(&sftp_session, &archive).await {
+        log::warn!("Failed to remove remote archive {}: {}", archive, e);
+    }
+    result
+}
+
+#[tauri::command]
+pub async fn sftp_cancel_transfer(
+    session_manager: State<'_, Arc<SessionManager>>,
+    transfer_id: String,
+) -> Result<(), String> {
+    if !session_manager.cancel_transfer(&transfer_id) {
+        return Err("Transfer is not running".to_string());
+    }
+    Ok(())
+}
+
+/// Announce the queue state and save its unfinished transfers, so they can
+/// be picked up again if the app is closed. Saving happens off the async
+/// runtime.
+fn emit_transfer_queue(app: &AppHandle, session_manager: &Arc<SessionManager>) {
+    let _ = app.emit("transfer-queue-changed", session_manager.transfer_queue.list());
+    let data_dir = match app.path().app_data_dir() {
+        Ok(data_dir) => data_dir,
+        Err(e) => {
+            log::warn!("Failed to save the transfer queue: {}", e);
+            return;
+        }
+    };
+    let manager = session_manager.clone();
+    tokio::task::spawn_blocking(move || {
+        let store = PendingTransferStore::new(data_dir);
+        if let Err(e) = manager.transfer_queue.persist(&store) {
+            log::warn!("Failed to save the transfer queue: {}", e);
+        }
+    });
+}
+
+/// Start whatever queued transfers the concurrency limit allows and
+/// announce the new queue state
+fn pump_transfer_queue(app: AppHandle, session_manager: Arc<SessionManager>) {
+    for item in session_manager.transfer_queue.start_next() {
+        // Registered before the task starts, so pausing right away stops it
+        let run = session_manager.register_transfer(&item.id);
+        tokio::spawn(run_queued_transfer(app.clone(), session_manager.clone(), item, run));
+    }
+    emit_transfer_queue(&app, &session_manager);
+}
+
+async fn run_queued_transfer(
+    app: AppHandle,
+    session_manager: Arc<SessionManager>,
+    item: TransferItem,
+    run: (u64, oneshot::Receiver<()>),
+) {
+    let transfer = transfer_queued_item(&app, &session_manager, &item);
+    let result = run_registered(&session_manager, &item.id, run, transfer).await;
+    session_manager.transfer_queue.finish(&item.id, result);
+    pump_transfer_queue(app, session_manager);
+}
+
+/// Run a queued transfer, retrying transient failures under the queue's
+/// `RetryPolicy`. Retries continue from where the failed attempt got to.
+async fn transfer_queued_item(
+    app: &AppHandle,
+    session_manager: &Arc<SessionManager>,
+    item: &TransferItem,
+) -> Result<(), String> {
+    // Settled once up front, so retries carry on writing the same file
+    let mut item = item.clone();
+    if !item.resume && !settle_queued_conflict(app, session_manager, &mut item).await? {
+        return Ok(());
+    }
+
+    let policy = session_manager.transfer_queue.retry_policy();
+    let mut resume = item.resume;
+    let mut retry = 0;
+    loop {
+        let error = match transfer_queued_attempt(app, session_manager, &item, resume).await {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if retry >= policy.max_retries || !transfer::is_transient(&error) {
+            return Err(error);
+        }
+        session_manager.transfer_queue.record_retry(&item.id, &error);
+        emit_transfer_queue(app, session_manager);
+        tokio::time::sleep(policy.delay(retry)).await;
+        // Paused while waiting
+        if !session_manager.transfer_queue.is_running(&item.id) {
+            return Err(error);
+        }
+        retry += 1;
+        resume = true;
+    }
+}
+
+/// Apply a queued transfer's conflict policy if its destination exists,
+/// pointing it at a free name when renamed. Returns `false` to skip it.
+async fn settle_queued_conflict(
+    app: &AppHandle,
+    session_manager: &Arc<SessionManager>,
+    item: &mut TransferItem,
+) -> Result<bool, String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&item.session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    let conflicts = ConflictPrompt {
+        app: app.clone(),
+        session_manager: session_manager.clone(),
+        session_id: item.session_id.clone(),
+        transfer_id: item.id.clone(),
+        policy: item.conflict_policy,
+    };
+    let (local, remote) = (item.local_path.as_str(), item.remote_path.as_str());
+    let target = match item.direction {
+        TransferDirection::Download => {
+            sftp::download_target(&sftp_session, &conflicts, remote, local).await
+        }
+        TransferDirection::Upload => {
+            sftp::upload_target(&sftp_session, &conflicts, local, remote).await
+        }
+    };
+    let Some(target) = target else {
+        return Ok(false);
+    };
+    session_manager.transfer_queue.set_destination(&item.id, &target);
+    match item.direction {
+        TransferDirection::Download => item.local_path = target,
+        TransferDirection::Upload => item.remote_path = target,
+    }
+    Ok(true)
+}
+
+async fn transfer_queued_attempt(
+    app: &AppHandle,
+    session_manager: &Arc<SessionManager>,
+    item: &TransferItem,
+    resume: bool,
+) -> Result<(), String> {
+    // Reopens the SFTP channel if the last attempt lost it
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&item.session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+
+    let mut emit = progress_reporter(app.clone(), item.session_id.clone(), item.id.clone());
+    let manager = session_manager.clone();
+    let id = item.id.clone();
+    let report = move |progress: sftp::TransferProgress| {
+        manager.transfer_queue.update_progress(&id, &progress);
+        emit(progress);
+    };
+
+    let (local, remote) = (item.local_path.as_str(), item.remote_path.as_str());
+    let options = sftp::TransferOptions {
+        resume,
+        ..Default::default()
+    };
+    match item.direction {
+        TransferDirection::Download => {
+            sftp::download_file(&sftp_session, remote, local, options, report).await
+        }
+        TransferDirection::Upload => {
+            sftp::upload_file(&sftp_session, local, remote, options, report).await
+        }
+    }
+}
+
+/// Add a transfer to the queue; it starts once fewer than
+/// `MAX_CONCURRENT_PER_SESSION` transfers are running on its session. An
+/// existing destination is handled by `conflict_policy` when it starts.
+#[tauri::command]
+pub async fn transfer_enqueue(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    direction: TransferDirection,
+    local_path: String,
+    remote_path: String,
+    conflict_policy: Option<sftp::ConflictPolicy>,
+) -> Result<String, String> {
+    let connection_id = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        session.connection.id.clone()
+    };
+    let id = session_manager.transfer_queue.enqueue(
+        session_id,
+        connection_id,
+        direction,
+        local_path,
+        remote_path,
+        conflict_policy.unwrap_or_default(),
+    );
+    pump_transfer_queue(app, session_manager.inner().clone());
+    Ok(id)
+}
+
+#[tauri::command]
+pub async fn transfer_list(
+    session_manager: State<'_, Arc<SessionManager>>,
+) -> Result<Vec<TransferItem>, String> {
+    Ok(session_manager.transfer_queue.list())
+}
+
+/// Hold a queued transfer back, or stop a running one so it can later
+/// continue from where it got to
+#[tauri::command]
+pub async fn transfer_pause(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    transfer_id: String,
+) -> Result<(), String> {
+    if session_manager.transfer_queue.pause(&transfer_id)? {
+        session_manager.cancel_transfer(&transfer_id);
+    }
+    pump_transfer_queue(app, session_manager.inner().clone());
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn transfer_resume(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    transfer_id: String,
+) -> Result<(), String> {
+    session_manager.transfer_queue.resume(&transfer_id)?;
+    pump_transfer_queue(app, session_manager.inner().clone());
+    Ok(())
+}
+
+/// Transfers of a connection left unfinished when the app last closed
+#[tauri::command]
+pub async fn transfer_pending(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    connection_id: String,
+) -> Result<Vec<TransferItem>, String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let queued: Vec<String> = session_manager
+        .transfer_queue
+        .list()
+        .into_iter()
+        .map(|item| item.id)
+        .collect();
+    let mut pending = PendingTransferStore::new(data_dir).load(&connection_id);
+    pending.retain(|item| !queued.contains(&item.id));
+    Ok(pending)
+}
+
+/// Queue the transfers from `transfer_pending` on a session to the same
+/// connection. Partial files are kept, so each continues where it stopped.
+#[tauri::command]
+pub async fn transfer_restore(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+) -> Result<Vec<String>, String> {
+    let connection_id = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        session.connection.id.clone()
+    };
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let saved = PendingTransferStore::new(data_dir).load(&connection_id);
+    let ids = session_manager.transfer_queue.restore(saved, &session_id);
+    pump_transfer_queue(app, session_manager.inner().clone());
+    Ok(ids)
+}
+
+/// Forget a connection's unfinished transfers instead of restoring them
+#[tauri::command]
+pub async fn transfer_discard_pending(app: AppHandle, connection_id: String) -> Result<(), String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    PendingTransferStore::new(data_dir).discard(&connection_id)
+}
+
+#[tauri::command]
+pub async fn transfer_get_retry_policy(
+    session_manager: State<'_, Arc<SessionManager>>,
+) -> Result<RetryPolicy, String> {
+    Ok(session_manager.transfer_queue.retry_policy())
+}
+
+/// Set how queued transfers retry transient errors; applies to transfers
+/// started from now on
+#[tauri::command]
+pub async fn transfer_set_retry_policy(
+    session_manager: State<'_, Arc<SessionManager>>,
+    policy: RetryPolicy,
+) -> Result<(), String> {
+    session_manager.transfer_queue.set_retry_policy(policy);
+    Ok(())
+}
+
+/// Move a transfer to position `index`; earlier transfers start first
+#[tauri::command]
+pub async fn transfer_reorder(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    transfer_id: String,
+    index: usize,
+) -> Result<(), String> {
+    session_manager.transfer_queue.reorder(&transfer_id, index)?;
+    emit_transfer_queue(&app, &session_manager);
+    Ok(())
+}
+
+/// Search below `root` for names matching the glob `pattern`. Matches arrive
+/// in batches as `sftp-find-results` and the search ends with
+/// `sftp-find-done`; the returned search id can be passed to
+/// `sftp_find_cancel`.
+#[tauri::command]
+pub async fn sftp_find(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    root: String,
+    pattern: String,
+) -> Result<String, String> {
+    let (handle, registry, opener) = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        (session.handle.clone(), session.channel_registry.clone(), sftp::SftpOpener::new(session))
+    };
+    let sftp_session = opener.get().await?;
+
+    let search_id = Uuid::new_v4().to_string();
+    let (cancel_tx, cancel_rx) = oneshot::channel();
+    session_manager
+        .searches
+        .lock()
+        .await
+        .insert(search_id.clone(), cancel_tx);
+
+    let sm = session_manager.inner().clone();
+    let sid = search_id.clone();
+    tokio::spawn(async move {
+        let mut count = 0;
+        let mut on_matches = |paths: Vec<String>| {
+            count += paths.len();
+            let _ = app.emit("sftp-find-results", serde_json::json!({
+                "sessionId": session_id,
+                "searchId": sid,
+                "paths": paths,
+            }));
+        };
+        let search = async {
+            let found =
+                sftp::find_by_exec(&handle, &registry, &root, &pattern, &mut on_matches).await;
+            match found {
+                Ok(true) => Ok(()),
+                Ok(false) | Err(_) => {
+                    sftp::find_by_walk(&sftp_session, &root, &pattern, &mut on_matches).await
+                }
+            }
+        };
+        let (cancelled, error) = tokio::select! {
+            result = search => (false, result.err()),
+            Ok(()) = cancel_rx => (true, None),
+        };
+
+        sm.searches.lock().await.remove(&sid);
+        let _ = app.emit("sftp-find-done", serde_json::json!({
+            "sessionId": session_id,
+            "searchId": sid,
+            "count": count,
+            "cancelled": cancelled,
+            "error": error,
+        }));
+    });
+
+    Ok(search_id)
+}
+
+#[tauri::command]
+pub async fn sftp_find_cancel(
+    session_manager: State<'_, Arc<SessionManager>>,
+    search_id: String,
+) -> Result<(), String> {
+    let cancel = session_manager
+        .searches
+        .lock()
+        .await
+        .remove(&search_id)
+        .ok_or("Search is not running")?;
+    let _ = cancel.send(());
+    Ok(())
+}
+
+/// Total size and file count below `path`. A slow SFTP walk reports its
+/// running total as `sftp-dir-size-progress` before the final result.
+#[tauri::command]
+pub async fn sftp_dir_size(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    path: String,
+) -> Result<sftp::DirSize, String> {
+    let (handle, registry, opener) = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        (session.handle.clone(), session.channel_registry.clone(), sftp::SftpOpener::new(session))
+    };
+    let sftp_session = opener.get().await?;
+    if let Ok(Some(size)) = sftp::dir_size_by_exec(&handle, &registry, &path).await {
+        return Ok(size);
+    }
+    sftp::dir_size_by_walk(&sftp_session, &path, |size| {
+        let _ = app.emit("sftp-dir-size-progress", serde_json::json!({
+            "sessionId": session_id,
+            "path": path,
+            "bytes": size.bytes,
+            "files": size.files,
+        }));
+    })
+    .await
+}
+
+/// Unpack a tar, tar.gz/bz2/xz or zip archive into `dest` on the server
+#[tauri::command]
+pub async fn remote_extract(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    archive_path: String,
+    dest: String,
+) -> Result<(), String> {
+    let (handle, registry) = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        (session.handle.clone(), session.channel_registry.clone())
+    };
+    sftp::extract(&handle, &registry, &archive_path, &dest).await
 }
 
+/// Total, free and available space of the filesystem holding `path`
 #[tauri::command]
-pub async fn ssh_write(
+pub async fn sftp_statvfs(
     session_manager: State<'_, Arc<SessionManager>>,
     session_id: String,
-    data: Vec<u8>,
-) -> Result<(), String> {
-    let sessions = session_manager.sessions.lock().await;
-    let session = sessions.get(&session_id).ok_or("Session not found")?;
-    session.write(&data).await
+    path: String,
+) -> Result<sftp::DiskSpace, String> {
+    let (handle, registry, opener) = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        (session.handle.clone(), session.channel_registry.clone(), sftp::SftpOpener::new(session))
+    };
+    let sftp_session = opener.get().await?;
+    if let Ok(Some(space)) = sftp::disk_space_by_statvfs(&sftp_session, &path).await {
+        return Ok(space);
+    }
+    sftp::disk_space_by_exec(&handle, &registry, &path)
+        .await?
+        .ok_or_else(|| "The server can't report free space".to_string())
 }
 
+/// Load a remote text file for the built-in editor
 #[tauri::command]
-pub async fn ssh_resize(
+pub async fn sftp_read_file(
     session_manager: State<'_, Arc<SessionManager>>,
     session_id: String,
-    cols: u32,
-    rows: u32,
-) -> Result<(), String> {
-    let sessions = session_manager.sessions.lock().await;
-    let session = sessions.get(&session_id).ok_or("Session not found")?;
-    session.resize(cols, rows).await
+    path: String,
+) -> Result<sftp::TextFile, String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    sftp::read_text_file(&sftp_session, &path).await
 }
 
+/// Save the built-in editor's content; pass the `modified` time from
+/// `sftp_read_file` to avoid overwriting changes made on the server
 #[tauri::command]
-pub async fn ssh_disconnect(
+pub async fn sftp_write_file(
     session_manager: State<'_, Arc<SessionManager>>,
     session_id: String,
+    path: String,
+    content: String,
+    expected_modified: Option<u64>,
 ) -> Result<(), String> {
-    if let Some(session) = session_manager.remove_session(&session_id).await {
-        session.close().await?;
-    }
-    Ok(())
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    sftp::write_text_file(&sftp_session, &path, &content, expected_modified).await
 }
 
-// ── SFTP Commands ────────────────────────────────────────────────────
-
+/// Read part of a remote file without downloading it
 #[tauri::command]
-pub async fn sftp_list(
+pub async fn sftp_read_range(
     session_manager: State<'_, Arc<SessionManager>>,
     session_id: String,
     path: String,
-) -> Result<Vec<sftp::FileEntry>, String> {
-    let sessions = session_manager.sessions.lock().await;
-    let session = sessions.get(&session_id).ok_or("Session not found")?;
-    let sftp_session = sftp::open_sftp(session).await?;
-    sftp::list_dir(&sftp_session, &path).await
+    offset: u64,
+    len: u64,
+) -> Result<sftp::FileRange, String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    sftp::read_range(&sftp_session, &path, offset, len).await
 }
 
+/// Stream the end of a remote file as `sftp-tail-lines` events and, with
+/// `follow`, keep streaming appended lines until `sftp_tail_stop`.
+/// `sftp-tail-end` is emitted when the stream ends.
 #[tauri::command]
-pub async fn sftp_download(
+pub async fn sftp_tail(
+    app: AppHandle,
     session_manager: State<'_, Arc<SessionManager>>,
     session_id: String,
-    remote_path: String,
-    local_path: String,
+    path: String,
+    follow: bool,
+) -> Result<String, String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+
+    let tail_id = Uuid::new_v4().to_string();
+    let (stop_tx, stop_rx) = oneshot::channel();
+    session_manager
+        .tails
+        .lock()
+        .await
+        .insert(tail_id.clone(), stop_tx);
+
+    let sm = session_manager.inner().clone();
+    let tid = tail_id.clone();
+    tokio::spawn(async move {
+        let on_lines = |lines: Vec<String>| {
+            let _ = app.emit("sftp-tail-lines", serde_json::json!({
+                "sessionId": session_id,
+                "tailId": tid,
+                "lines": lines,
+            }));
+        };
+        let error = tokio::select! {
+            result = sftp::tail(&sftp_session, &path, follow, on_lines) => result.err(),
+            Ok(()) = stop_rx => None,
+        };
+
+        sm.tails.lock().await.remove(&tid);
+        let _ = app.emit("sftp-tail-end", serde_json::json!({
+            "sessionId": session_id,
+            "tailId": tid,
+            "error": error,
+        }));
+    });
+
+    Ok(tail_id)
+}
+
+#[tauri::command]
+pub async fn sftp_tail_stop(
+    session_manager: State<'_, Arc<SessionManager>>,
+    tail_id: String,
 ) -> Result<(), String> {
-    let sessions = session_manager.sessions.lock().await;
-    let session = sessions.get(&session_id).ok_or("Session not found")?;
-    let sftp_session = sftp::open_sftp(session).await?;
-    sftp::download_file(&sftp_session, &remote_path, &local_path).await
+    let stop = session_manager
+        .tails
+        .lock()
+        .await
+        .remove(&tail_id)
+        .ok_or("Tail is not running")?;
+    let _ = stop.send(());
+    Ok(())
 }
 
+/// Full attributes of one path, for the properties dialog
 #[tauri::command]
-pub async fn sftp_upload(
+pub async fn sftp_stat(
     session_manager: State<'_, Arc<SessionManager>>,
     session_id: String,
-    local_path: String,
-    remote_path: String,
-) -> Result<(), String> {
-    let sessions = session_manager.sessions.lock().await;
-    let session = sessions.get(&session_id).ok_or("Session not found")?;
-    let sftp_session = sftp::open_sftp(session).await?;
-    sftp::upload_file(&sftp_session, &local_path, &remote_path).await
+    path: String,
+) -> Result<sftp::FileStat, String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    sftp::stat(&sftp_session, &path).await
 }
 
 #[tauri::command]
@@ -215,23 +3017,34 @@ pub async fn sftp_mkdir(
     session_id: String,
     path: String,
 ) -> Result<(), String> {
-    let sessions = session_manager.sessions.lock().await;
-    let session = sessions.get(&session_id).ok_or("Session not found")?;
-    let sftp_session = sftp::open_sftp(session).await?;
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
     sftp::mkdir(&sftp_session, &path).await
 }
 
+/// Delete a file or empty directory; with `trash`, move it (directories
+/// with their contents) to the remote trash instead
 #[tauri::command]
 pub async fn sftp_delete(
     session_manager: State<'_, Arc<SessionManager>>,
     session_id: String,
     path: String,
     is_dir: bool,
+    trash: Option<bool>,
 ) -> Result<(), String> {
-    let sessions = session_manager.sessions.lock().await;
-    let session = sessions.get(&session_id).ok_or("Session not found")?;
-    let sftp_session = sftp::open_sftp(session).await?;
-    if is_dir {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    if trash.unwrap_or(false) {
+        trash::move_to_trash(&sftp_session, &path).await
+    } else if is_dir {
         sftp::remove_dir(&sftp_session, &path).await
     } else {
         sftp::remove_file(&sftp_session, &path).await
@@ -245,20 +3058,43 @@ pub async fn sftp_rename(
     from: String,
     to: String,
 ) -> Result<(), String> {
-    let sessions = session_manager.sessions.lock().await;
-    let session = sessions.get(&session_id).ok_or("Session not found")?;
-    let sftp_session = sftp::open_sftp(session).await?;
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
     sftp::rename(&sftp_session, &from, &to).await
 }
 
+/// Create a symlink at `to` pointing to `from`, like `ln -s from to`
+#[tauri::command]
+pub async fn sftp_symlink(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    from: String,
+    to: String,
+) -> Result<(), String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    sftp::symlink(&sftp_session, &from, &to).await
+}
+
 #[tauri::command]
 pub async fn sftp_get_home(
     session_manager: State<'_, Arc<SessionManager>>,
     session_id: String,
 ) -> Result<String, String> {
-    let sessions = session_manager.sessions.lock().await;
-    let session = sessions.get(&session_id).ok_or("Session not found")?;
-    let sftp_session = sftp::open_sftp(session).await?;
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
     sftp::get_home_dir(&sftp_session).await
 }
 
@@ -269,21 +3105,292 @@ pub async fn sftp_chmod(
     path: String,
     mode: u32,
 ) -> Result<(), String> {
-    let sessions = session_manager.sessions.lock().await;
-    let session = sessions.get(&session_id).ok_or("Session not found")?;
-    let sftp_session = sftp::open_sftp(session).await?;
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
     sftp::set_permissions(&sftp_session, &path, mode).await
 }
 
+/// Copy a file or directory to another path on the same server, with the
+/// server's `cp` when possible and over SFTP otherwise
+#[tauri::command]
+pub async fn sftp_copy(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    from: String,
+    to: String,
+) -> Result<(), String> {
+    let (handle, registry, opener) = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        (session.handle.clone(), session.channel_registry.clone(), sftp::SftpOpener::new(session))
+    };
+    let sftp_session = opener.get().await?;
+    if sftp::copy_by_exec(&handle, &registry, &from, &to).await? {
+        return Ok(());
+    }
+    sftp::copy_by_sftp(&sftp_session, &from, &to).await
+}
+
+/// Copy a file or directory to `name (copy)` beside it, numbering the copy
+/// when that name is taken. Returns the new path.
+#[tauri::command]
+pub async fn sftp_duplicate(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    path: String,
+) -> Result<String, String> {
+    let (handle, registry, opener) = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        (session.handle.clone(), session.channel_registry.clone(), sftp::SftpOpener::new(session))
+    };
+    let sftp_session = opener.get().await?;
+    let is_dir = matches!(sftp::stat(&sftp_session, &path).await?.kind, sftp::FileKind::Dir);
+    let copy = sftp::free_copy_name(&sftp_session, &path, is_dir).await;
+    if !sftp::copy_by_exec(&handle, &registry, &path, &copy).await? {
+        sftp::copy_by_sftp(&sftp_session, &path, &copy).await?;
+    }
+    Ok(copy)
+}
+
+/// Copy `from_path` on one session to `to_path` on another, streaming
+/// straight between the two servers. Progress and cancellation work as for
+/// `sftp_download`.
+#[tauri::command]
+pub async fn sftp_transfer_between(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    from_session_id: String,
+    from_path: String,
+    to_session_id: String,
+    to_path: String,
+    transfer_id: Option<String>,
+) -> Result<(), String> {
+    let (source, dest) = {
+        let sessions = session_manager.sessions.lock().await;
+        let from = sessions.get(&from_session_id).ok_or("Source session not found")?;
+        let to = sessions.get(&to_session_id).ok_or("Destination session not found")?;
+        (sftp::SftpOpener::new(from), sftp::SftpOpener::new(to))
+    };
+    let (source, dest) = (source.get().await?, dest.get().await?);
+    let transfer_id = transfer_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+    let report = progress_reporter(app, to_session_id, transfer_id.clone());
+    let transfer = sftp::copy_between(&source, &from_path, &dest, &to_path, report);
+    run_transfer(&session_manager, &transfer_id, transfer).await
+}
+
+/// Change the owner and/or group of a path; names are resolved on the server
+#[tauri::command]
+pub async fn sftp_chown(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    path: String,
+    owner: Option<String>,
+    group: Option<String>,
+) -> Result<(), String> {
+    let (handle, registry, opener) = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        (session.handle.clone(), session.channel_registry.clone(), sftp::SftpOpener::new(session))
+    };
+    let sftp_session = opener.get().await?;
+    sftp::chown(
+        &handle,
+        &registry,
+        &sftp_session,
+        &path,
+        owner.as_deref().filter(|o| !o.is_empty()),
+        group.as_deref().filter(|g| !g.is_empty()),
+    )
+    .await
+}
+
+// Batch variants for multi-select: each path is tried even when earlier ones
+// fail, and the result lists what happened to every one of them
+
+#[tauri::command]
+pub async fn sftp_delete_batch(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    paths: Vec<String>,
+    trash: Option<bool>,
+) -> Result<Vec<sftp::BatchResult>, String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    let trash = trash.unwrap_or(false);
+    let mut results = Vec::with_capacity(paths.len());
+    for path in paths {
+        let result = match trash {
+            true => trash::move_to_trash(&sftp_session, &path).await,
+            false => sftp::remove(&sftp_session, &path).await,
+        };
+        results.push(sftp::BatchResult::new(path, result));
+    }
+    Ok(results)
+}
+
+/// What is in the remote trash, newest first
+#[tauri::command]
+pub async fn trash_list(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+) -> Result<Vec<trash::TrashItem>, String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    trash::list(&sftp_session).await
+}
+
+/// Move a trashed item back to where it came from; returns that path
+#[tauri::command]
+pub async fn trash_restore(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    id: String,
+) -> Result<String, String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    trash::restore(&sftp_session, &id).await
+}
+
+#[tauri::command]
+pub async fn trash_empty(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+) -> Result<(), String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    trash::empty(&sftp_session).await
+}
+
+#[tauri::command]
+pub async fn sftp_chmod_batch(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    paths: Vec<String>,
+    mode: u32,
+) -> Result<Vec<sftp::BatchResult>, String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    let mut results = Vec::with_capacity(paths.len());
+    for path in paths {
+        let result = sftp::set_permissions(&sftp_session, &path, mode).await;
+        results.push(sftp::BatchResult::new(path, result));
+    }
+    Ok(results)
+}
+
+/// Move every path into the remote directory `dest_dir`
+#[tauri::command]
+pub async fn sftp_move_batch(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    paths: Vec<String>,
+    dest_dir: String,
+) -> Result<Vec<sftp::BatchResult>, String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    let mut results = Vec::with_capacity(paths.len());
+    for path in paths {
+        let to = sftp::join(&dest_dir, sftp::file_name(&path));
+        let result = sftp::rename(&sftp_session, &path, &to).await;
+        results.push(sftp::BatchResult::new(path, result));
+    }
+    Ok(results)
+}
+
+/// Download every path into the local directory `local_dir`; directories
+/// are downloaded recursively. Files that exist locally are handled by
+/// `conflict_policy`, with prompts for `Ask` under one id for the batch.
+#[tauri::command]
+pub async fn sftp_download_batch(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    paths: Vec<String>,
+    local_dir: String,
+    conflict_policy: Option<sftp::ConflictPolicy>,
+) -> Result<Vec<sftp::BatchResult>, String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    let conflicts = ConflictPrompt {
+        app,
+        session_manager: session_manager.inner().clone(),
+        session_id,
+        transfer_id: Uuid::new_v4().to_string(),
+        policy: conflict_policy.unwrap_or_default(),
+    };
+    let mut results = Vec::with_capacity(paths.len());
+    for path in paths {
+        let local = std::path::Path::new(&local_dir).join(sftp::file_name(&path));
+        let local = local.to_string_lossy();
+        let result = match sftp_session.metadata(path.as_str()).await {
+            Ok(attrs) if attrs.is_dir() => {
+                sftp::download_dir(&sftp_session, &path, &local, false, &conflicts, |_| {})
+                    .await
+                    .and_then(|summary| match summary.failures.len() {
+                        0 => Ok(()),
+                        n => Err(format!("{} item(s) inside failed", n)),
+                    })
+            }
+            Ok(_) => match sftp::download_target(&sftp_session, &conflicts, &path, &local).await {
+                Some(local) => {
+                    let options = sftp::TransferOptions::default();
+                    sftp::download_file(&sftp_session, &path, &local, options, |_| {}).await
+                }
+                None => Ok(()),
+            },
+            Err(e) => Err(format!("Failed to stat {}: {}", path, e)),
+        };
+        results.push(sftp::BatchResult::new(path, result));
+    }
+    Ok(results)
+}
+
+/// Download a file to a temp folder and open it locally. `editor_path` is
+/// an editor id from `editor_list`, `"open-with-dialog"` for the platform's
+/// chooser, or a custom command; without it the system default is used.
 #[tauri::command]
 pub async fn sftp_edit_file(
+    app: AppHandle,
     session_manager: State<'_, Arc<SessionManager>>,
     session_id: String,
     remote_path: String,
     editor_path: Option<String>,
 ) -> Result<String, String> {
     // Create temp directory for editing
-    let temp_dir = std::env::temp_dir().join("rustssh-edit").join(&session_id);
+    let temp_dir = watch::edit_dir(&session_id);
     std::fs::create_dir_all(&temp_dir)
         .map_err(|e| format!("Failed to create temp dir: {}", e))?;
 
@@ -294,61 +3401,61 @@ pub async fn sftp_edit_file(
     let local_path_str = local_path.to_string_lossy().to_string();
 
     // Download file
-    let sessions = session_manager.sessions.lock().await;
-    let session = sessions.get(&session_id).ok_or("Session not found")?;
-    let sftp_session = sftp::open_sftp(session).await?;
-    sftp::download_file(&sftp_session, &remote_path, &local_path_str).await?;
-
-    // Open file with configured editor or system default
-    if let Some(editor) = editor_path {
-        // Special flag to show "Open With" dialog
-        if editor == "open-with-dialog" {
-            std::process::Command::new("rundll32.exe")
-                .args(&["shell32.dll,OpenAs_RunDLL", &local_path_str])
-                .spawn()
-                .map_err(|e| format!("Failed to open system dialog: {}", e))?;
-            return Ok(local_path_str);
-        }
-
-        let mut cmd = std::process::Command::new(&editor);
-        cmd.arg(&local_path);
-        
-        // Try launching specified editor
-        if let Err(e) = cmd.spawn() {
-            // If failed and editor is "code", try common paths
-            if editor == "code" {
-                let common_paths = [
-                    format!("{}\\Programs\\Microsoft VS Code\\Code.exe", std::env::var("LOCALAPPDATA").unwrap_or_default()),
-                    String::from("C:\\Program Files\\Microsoft VS Code\\Code.exe"),
-                    String::from("C:\\Program Files (x86)\\Microsoft VS Code\\Code.exe"),
-                ];
-
-                let mut launched = false;
-                for path in common_paths {
-                    if std::path::Path::new(&path).exists() {
-                        if std::process::Command::new(&path).arg(&local_path).spawn().is_ok() {
-                            launched = true;
-                            break;
-                        }
-                    }
-                }
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    let options = sftp::TransferOptions::default();
+    sftp::download_file(&sftp_session, &remote_path, &local_path_str, options, |_| {}).await?;
 
-                if !launched {
-                    return Err(format!("Failed to launch editor '{}' and could not find it in common locations: {}", editor, e));
-                }
-            } else {
-                return Err(format!("Failed to launch editor '{}': {}", editor, e));
-            }
-        }
-    } else {
-        // Use system default
-        opener::open(&local_path)
-            .map_err(|e| format!("Failed to open file: {}", e))?;
-    }
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let table = EditorStore::new(data_dir).table();
+    // The "open with" choosers block until the user has picked
+    tokio::task::spawn_blocking(move || {
+        editor::open_file(editor_path.as_deref(), &local_path, &table)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
 
     Ok(local_path_str)
 }
 
+/// The editors this platform knows, built-in and user-defined, and whether
+/// each is installed
+#[tauri::command]
+pub async fn editor_list(app: AppHandle) -> Result<Vec<EditorInfo>, String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let table = EditorStore::new(data_dir).table();
+    // Checking may run `open` on macOS
+    tokio::task::spawn_blocking(move || {
+        table
+            .into_iter()
+            .map(|entry| EditorInfo {
+                available: entry.is_available(),
+                entry,
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Replace the user-defined editors; ones sharing an id with a built-in
+/// editor override it
+#[tauri::command]
+pub async fn editor_save(app: AppHandle, editors: Vec<EditorEntry>) -> Result<(), String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    EditorStore::new(data_dir).save(&editors)
+}
+
+/// Upload `local_path` to `remote_path` whenever it's saved, until
+/// `sftp_unwatch_file` or the session disconnects. Watching a file again
+/// replaces the earlier watch. If the server's copy changed since it was
+/// downloaded or last synced, nothing is uploaded and `file-sync-conflict`
+/// is emitted; `sftp_edit_resolve` settles it. With `backup`, each upload
+/// first copies the server's version to `<file>.bak-<timestamp>`.
 #[tauri::command]
 pub async fn sftp_watch_file(
     app: AppHandle,
@@ -356,6 +3463,7 @@ pub async fn sftp_watch_file(
     session_id: String,
     local_path: String,
     remote_path: String,
+    backup: Option<bool>,
 ) -> Result<(), String> {
     use notify::{Watcher, RecursiveMode, Config};
     use std::sync::mpsc::channel;
@@ -363,17 +3471,43 @@ pub async fn sftp_watch_file(
     let sm = session_manager.inner().clone();
     let app_handle = app.clone();
 
-    // Spawn blocking task for file watcher
-    tokio::task::spawn_blocking(move || {
-        let (tx, rx) = channel();
-
-        let mut watcher = notify::RecommendedWatcher::new(tx, Config::default())
-            .map_err(|e| format!("Failed to create watcher: {}", e)).unwrap();
+    // The file was just downloaded, so this is what it's in step with
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    let state = watch::EditState {
+        remote: watch::remote_stamp(&sftp_session, &remote_path).await,
+        local_hash: sftp::local_sha256(&local_path).await.ok(),
+        ..Default::default()
+    };
+    let state: watch::SharedEditState = Arc::new(tokio::sync::Mutex::new(state));
 
-        let path = std::path::Path::new(&local_path);
-        watcher.watch(path, RecursiveMode::NonRecursive)
-            .map_err(|e| format!("Failed to watch file: {}", e)).unwrap();
+    let (tx, rx) = channel();
+    let mut watcher = notify::RecommendedWatcher::new(tx, Config::default())
+        .map_err(|e| format!("Failed to create watcher: {}", e))?;
+    watcher
+        .watch(std::path::Path::new(&local_path), RecursiveMode::NonRecursive)
+        .map_err(|e| format!("Failed to watch file: {}", e))?;
+    let info = WatchInfo {
+        session_id,
+        local_path: local_path.clone(),
+        remote_path: remote_path.clone(),
+        backup: backup.unwrap_or(false),
+    };
+    session_manager.file_watches.lock().await.insert(
+        local_path,
+        FileWatch {
+            info: info.clone(),
+            watcher,
+            state: state.clone(),
+        },
+    );
 
+    // Runs until the watcher is dropped, which disconnects `rx`
+    tokio::task::spawn_blocking(move || {
         let _ = app_handle.emit("file-sync-status", serde_json::json!({
             "status": "watching",
             "file": remote_path,
@@ -388,49 +3522,194 @@ pub async fn sftp_watch_file(
                         std::thread::sleep(std::time::Duration::from_millis(500));
 
                         let rt = tokio::runtime::Handle::current();
-                        let sm_clone = sm.clone();
-                        let sid = session_id.clone();
-                        let lp = local_path.clone();
-                        let rp = remote_path.clone();
+                        let sm = sm.clone();
                         let ah = app_handle.clone();
-
+                        let info = info.clone();
+                        let state = state.clone();
                         rt.spawn(async move {
-                            let sessions = sm_clone.sessions.lock().await;
-                            if let Some(session) = sessions.get(&sid) {
-                                match sftp::open_sftp(session).await {
-                                    Ok(sftp_session) => {
-                                        match sftp::upload_file(&sftp_session, &lp, &rp).await {
-                                            Ok(_) => {
-                                                let _ = ah.emit("file-sync-status", serde_json::json!({
-                                                    "status": "synced",
-                                                    "file": rp,
-                                                }));
-                                            }
-                                            Err(e) => {
-                                                let _ = ah.emit("file-sync-status", serde_json::json!({
-                                                    "status": "error",
-                                                    "file": rp,
-                                                    "error": e,
-                                                }));
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        let _ = ah.emit("file-sync-status", serde_json::json!({
-                                            "status": "error",
-                                            "file": rp,
-                                            "error": e,
-                                        }));
-                                    }
-                                }
-                            }
+                            // Held throughout, so saves in quick succession upload in turn
+                            let mut state = state.lock().await;
+                            let result = sync_watched(&sm, &info, &mut state, false).await;
+                            emit_sync_result(&ah, &info, result);
                         });
                     }
                 }
                 Ok(Err(_)) | Err(_) => break,
             }
         }
+
+        let _ = app_handle.emit("file-sync-status", serde_json::json!({
+            "status": "stopped",
+            "file": remote_path,
+        }));
     });
 
     Ok(())
 }
+
+/// Upload a watched file's local copy if it needs it; see `watch::sync_save`
+async fn sync_watched(
+    session_manager: &SessionManager,
+    info: &WatchInfo,
+    state: &mut watch::EditState,
+    force: bool,
+) -> Result<watch::SaveOutcome, String> {
+    let (handle, registry, opener) = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&info.session_id).ok_or("Session not found")?;
+        (session.handle.clone(), session.channel_registry.clone(), sftp::SftpOpener::new(session))
+    };
+    let sftp_session = opener.get().await?;
+    watch::sync_save(&handle, &registry, &sftp_session, info, state, force).await
+}
+
+fn emit_sync_result(
+    app: &AppHandle,
+    info: &WatchInfo,
+    result: Result<watch::SaveOutcome, String>,
+) {
+    match result {
+        Ok(watch::SaveOutcome::Unchanged) => {}
+        Ok(watch::SaveOutcome::Uploaded { bytes_sent, backup }) => {
+            let _ = app.emit("file-sync-status", serde_json::json!({
+                "status": "synced",
+                "file": info.remote_path,
+                "bytesSent": bytes_sent,
+                "backup": backup,
+            }));
+        }
+        Ok(watch::SaveOutcome::Conflict { expected, current }) => {
+            let _ = app.emit("file-sync-conflict", serde_json::json!({
+                "sessionId": info.session_id,
+                "localPath": info.local_path,
+                "remotePath": info.remote_path,
+                "expected": expected,
+                "current": current,
+            }));
+        }
+        Err(e) => {
+            let _ = app.emit("file-sync-status", serde_json::json!({
+                "status": "error",
+                "file": info.remote_path,
+                "error": e,
+            }));
+        }
+    }
+}
+
+/// The watch of `local_path`, with its sync state
+async fn find_watch(
+    session_manager: &SessionManager,
+    local_path: &str,
+) -> Result<(WatchInfo, watch::SharedEditState), String> {
+    let watches = session_manager.file_watches.lock().await;
+    let watch = watches.get(local_path).ok_or("File is not being watched")?;
+    Ok((watch.info.clone(), watch.state.clone()))
+}
+
+/// Settle a `file-sync-conflict`: upload the local copy over the server's,
+/// or replace it with the server's version
+#[tauri::command]
+pub async fn sftp_edit_resolve(
+    app: AppHandle,
+    session_manager: State<'_, Arc<SessionManager>>,
+    local_path: String,
+    resolution: watch::EditResolution,
+) -> Result<(), String> {
+    let (info, state) = find_watch(&session_manager, &local_path).await?;
+    let mut state = state.lock().await;
+    match resolution {
+        watch::EditResolution::Overwrite => {
+            let outcome = sync_watched(&session_manager, &info, &mut state, true).await?;
+            emit_sync_result(&app, &info, Ok(outcome));
+            Ok(())
+        }
+        watch::EditResolution::Reload => {
+            let opener = {
+                let sessions = session_manager.sessions.lock().await;
+                let session = sessions.get(&info.session_id).ok_or("Session not found")?;
+                sftp::SftpOpener::new(session)
+            };
+            let sftp_session = opener.get().await?;
+            watch::reload(&sftp_session, &info, &mut state).await
+        }
+    }
+}
+
+/// Line diff from the server's copy of a watched file to the local one
+#[tauri::command]
+pub async fn sftp_edit_diff(
+    session_manager: State<'_, Arc<SessionManager>>,
+    local_path: String,
+) -> Result<Vec<diff::DiffLine>, String> {
+    let (info, _) = find_watch(&session_manager, &local_path).await?;
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&info.session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    let remote = sftp::read_text_file(&sftp_session, &info.remote_path).await?;
+    let local = tokio::fs::read_to_string(&info.local_path)
+        .await
+        .map_err(|e| format!("Failed to read local file: {}", e))?;
+    Ok(diff::line_diff(&remote.content, &local))
+}
+
+/// Unified diff from a remote file to a local one, empty if they're the same.
+/// Both files are streamed, so large files that are mostly alike are cheap.
+#[tauri::command]
+pub async fn sftp_diff(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: String,
+    local_path: String,
+    remote_path: String,
+) -> Result<String, String> {
+    let opener = {
+        let sessions = session_manager.sessions.lock().await;
+        let session = sessions.get(&session_id).ok_or("Session not found")?;
+        sftp::SftpOpener::new(session)
+    };
+    let sftp_session = opener.get().await?;
+    let remote = sftp_session
+        .open(remote_path.as_str())
+        .await
+        .map_err(|e| format!("Failed to open remote file: {}", e))?;
+    let local = tokio::fs::File::open(&local_path)
+        .await
+        .map_err(|e| format!("Failed to open local file: {}", e))?;
+    diff::unified_diff(
+        tokio::io::BufReader::new(remote),
+        tokio::io::BufReader::new(local),
+        &remote_path,
+        &local_path,
+    )
+    .await
+}
+
+/// Stop syncing an edited file and delete its temp copy
+#[tauri::command]
+pub async fn sftp_unwatch_file(
+    session_manager: State<'_, Arc<SessionManager>>,
+    local_path: String,
+) -> Result<(), String> {
+    watch::unwatch(&session_manager.file_watches, &local_path).await?;
+    Ok(())
+}
+
+/// Files being synced back to the server, optionally only one session's
+#[tauri::command]
+pub async fn sftp_list_watches(
+    session_manager: State<'_, Arc<SessionManager>>,
+    session_id: Option<String>,
+) -> Result<Vec<WatchInfo>, String> {
+    let watches = session_manager.file_watches.lock().await;
+    Ok(watches
+        .values()
+        .map(|watch| watch.info.clone())
+        .filter(|info| match &session_id {
+            Some(id) => &info.session_id == id,
+            None => true,
+        })
+        .collect())
+}
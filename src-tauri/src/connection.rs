@@ -1,12 +1,384 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
+use crate::import;
+use crate::knock::KnockStep;
+use crate::proxy::ProxyConfig;
+use crate::ssh_config;
+use crate::tunnel::TunnelSpec;
+use crate::vault::{Envelope, VaultKey};
+
+/// A host read from another tool's configuration, not saved yet
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportedHost {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+    pub private_key_path: Option<String>,
+    /// Hosts to hop through, as `[user@]host[:port]` or a connection name
+    pub jump_hosts: Vec<String>,
+    /// Settings that couldn't be carried over
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportAction {
+    Create,
+    Update,
+    /// Matches a saved connection that already has the same settings
+    Unchanged,
+}
+
+/// Where imported hosts come from
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// OpenSSH client config
+    SshConfig,
+    /// WinSCP.ini from WinSCP's configuration export
+    WinScp,
+    /// Termius CSV export
+    Termius,
+    /// mRemoteNG confCons.xml
+    MRemoteNg,
+}
+
+/// Hosts read from an export, before they're matched against saved
+/// connections
+#[derive(Debug, Default)]
+pub struct ParsedImport {
+    pub hosts: Vec<ImportedHost>,
+    /// Entries that can't become SSH connections, with the reason
+    pub skipped: Vec<String>,
+}
+
+impl ImportFormat {
+    pub fn parse(self, path: &Path) -> Result<ParsedImport, String> {
+        match self {
+            ImportFormat::SshConfig => Ok(ParsedImport {
+                hosts: ssh_config::parse_file(path)?,
+                skipped: Vec::new(),
+            }),
+            ImportFormat::WinScp => import::winscp(&import::read_export(path)?),
+            ImportFormat::Termius => import::termius(&import::read_export(path)?),
+            ImportFormat::MRemoteNg => import::mremoteng(&import::read_export(path)?),
+        }
+    }
+}
+
+/// What an import would do, for the user to confirm
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportPlan {
+    pub hosts: Vec<ImportPreview>,
+    pub skipped: Vec<String>,
+}
+
+/// What importing a host does
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportPreview {
+    #[serde(flatten)]
+    pub host: ImportedHost,
+    pub action: ImportAction,
+    /// The saved connection it updates
+    pub existing_id: Option<String>,
+}
+
+/// The connection a jump host spec refers to, by name or by address
+fn find_jump<'a>(connections: &'a [Connection], spec: &str) -> Option<&'a Connection> {
+    let target = SshUrl::parse(&format!("ssh://{}", spec)).ok()?;
+    connections
+        .iter()
+        .find(|c| c.name == spec || c.name == target.host)
+        .or_else(|| {
+            connections
+                .iter()
+                .find(|c| c.host.eq_ignore_ascii_case(&target.host) && c.port == target.port)
+        })
+}
+
+/// Create or update a connection in `connections` for each host. A host
+/// updates the saved connection with its name, or failing that with its
+/// address and user.
+fn merge_imported(
+    connections: &mut Vec<Connection>,
+    hosts: Vec<ImportedHost>,
+) -> Vec<ImportPreview> {
+    let mut merged = Vec::new();
+    for host in hosts {
+        let existing = connections
+            .iter()
+            .position(|c| c.name == host.name)
+            .or_else(|| {
+                connections.iter().position(|c| {
+                    c.host.eq_ignore_ascii_case(&host.host)
+                        && c.port == host.port
+                        && c.username == host.username
+                })
+            });
+        // Snapshot of the saved connection, to tell whether anything changed
+        let mut before = None;
+        let index = match existing {
+            Some(index) => {
+                let conn = &mut connections[index];
+                before = serde_json::to_value(&*conn).ok();
+                conn.host = host.host.clone();
+                conn.port = host.port;
+                conn.username = host.username.clone();
+                if host.password.is_some() {
+                    conn.password = host.password.clone();
+                }
+                if host.private_key_path.is_some() {
+                    conn.private_key_path = host.private_key_path.clone();
+                }
+                index
+            }
+            None => {
+                let auth_type = match (&host.password, &host.private_key_path) {
+                    (_, Some(_)) => AuthType::KeyFile,
+                    (Some(_), None) => AuthType::Password,
+                    (None, None) => AuthType::Agent,
+                };
+                connections.push(Connection::new(
+                    host.name.clone(),
+                    host.host.clone(),
+                    host.port,
+                    host.username.clone(),
+                    auth_type,
+                    host.password.clone(),
+                    host.private_key_path.clone(),
+                    None,
+                ));
+                connections.len() - 1
+            }
+        };
+        merged.push((index, before, host));
+    }
+
+    // Jumps may name hosts imported after them, so they're resolved last
+    let mut previews = Vec::new();
+    for (index, before, mut host) in merged {
+        let mut jump_host_ids = Vec::new();
+        for spec in &host.jump_hosts {
+            match find_jump(connections, spec) {
+                Some(jump) if jump.id != connections[index].id => {
+                    jump_host_ids.push(jump.id.clone())
+                }
+                _ => host
+                    .warnings
+                    .push(format!("Jump host '{}' is not a saved connection; skipped", spec)),
+            }
+        }
+        if !host.jump_hosts.is_empty() {
+            connections[index].jump_host_ids = jump_host_ids;
+        }
+        let conn = &connections[index];
+        let action = match &before {
+            None => ImportAction::Create,
+            Some(before) if serde_json::to_value(conn).ok().as_ref() == Some(before) => {
+                ImportAction::Unchanged
+            }
+            Some(_) => ImportAction::Update,
+        };
+        previews.push(ImportPreview {
+            host,
+            action,
+            existing_id: before.is_some().then(|| conn.id.clone()),
+        });
+    }
+    previews
+}
+
+/// Settings of a connection from someone else that run a command or load a
+/// library on this machine, or hand the server the local agent; a shared
+/// bundle only brings them in once the user agreed
+fn risky_settings(conn: &Connection) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if let Some(command) = &conn.proxy_command {
+        warnings.push(format!("Runs the local command '{}' to connect", command));
+    }
+    if let Some(module) = &conn.pkcs11_module {
+        warnings.push(format!("Loads the PKCS#11 library {}", module));
+    }
+    if conn.forward_agent {
+        warnings.push("Forwards your SSH agent to the server".to_string());
+    }
+    warnings
+}
+
+/// Add the bundle connections named in `names` (all of them for `None`) to
+/// `connections`, replacing saved ones with the same id. The settings
+/// `risky_settings` reports are dropped unless `keep_risky`.
+fn merge_bundle(
+    connections: &mut Vec<Connection>,
+    bundle: ConnectionBundle,
+    names: Option<&[String]>,
+    keep_risky: bool,
+) -> Vec<ImportPreview> {
+    let jump_name = |id: &String| {
+        bundle
+            .connections
+            .iter()
+            .find(|c| &c.id == id)
+            .map(|c| c.name.clone())
+            .unwrap_or_else(|| id.clone())
+    };
+    let mut previews = Vec::new();
+    for mut conn in bundle.connections.clone() {
+        if names.is_some_and(|names| !names.contains(&conn.name)) {
+            continue;
+        }
+        let host = ImportedHost {
+            name: conn.name.clone(),
+            host: conn.host.clone(),
+            port: conn.port,
+            username: conn.username.clone(),
+            password: None,
+            private_key_path: conn.private_key_path.clone(),
+            jump_hosts: conn.jump_host_ids.iter().map(jump_name).collect(),
+            warnings: risky_settings(&conn),
+        };
+        if !keep_risky {
+            conn.proxy_command = None;
+            conn.pkcs11_module = None;
+            conn.forward_agent = false;
+        }
+        let (action, existing_id) = match connections.iter_mut().find(|c| c.id == conn.id) {
+            Some(existing) => {
+                // Keep using secrets already in this machine's keychain
+                // unless the bundle brings its own
+                if conn.password.is_none() && conn.passphrase.is_none() {
+                    conn.secret_ref = existing.secret_ref.clone();
+                }
+                let unchanged = serde_json::to_value(&*existing).ok()
+                    == serde_json::to_value(&conn).ok();
+                *existing = conn;
+                let action = if unchanged {
+                    ImportAction::Unchanged
+                } else {
+                    ImportAction::Update
+                };
+                (action, Some(existing.id.clone()))
+            }
+            None => {
+                connections.push(conn);
+                (ImportAction::Create, None)
+            }
+        };
+        previews.push(ImportPreview {
+            host,
+            action,
+            existing_id,
+        });
+    }
+    previews
+}
+
+/// Connections exported to share with another machine, sealed with a
+/// password of their own
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionBundle {
+    pub connections: Vec<Connection>,
+}
+
+impl ConnectionBundle {
+    /// The connections with `ids`, plus the jump hosts they go through so
+    /// the bundle works on its own. Secrets are only kept if asked; those in
+    /// the keychain are left for the caller to resolve.
+    pub fn select(all: &[Connection], ids: &[String], include_secrets: bool) -> Self {
+        let mut wanted: Vec<&str> = ids.iter().map(String::as_str).collect();
+        let mut i = 0;
+        while i < wanted.len() {
+            if let Some(conn) = all.iter().find(|c| c.id == wanted[i]) {
+                for jump in &conn.jump_host_ids {
+                    if !wanted.contains(&jump.as_str()) {
+                        wanted.push(jump);
+                    }
+                }
+            }
+            i += 1;
+        }
+        let connections = all
+            .iter()
+            .filter(|c| wanted.contains(&c.id.as_str()))
+            .cloned()
+            .map(|mut c| {
+                if !include_secrets {
+                    c.password = None;
+                    c.passphrase = None;
+                    c.secret_ref = None;
+                    if let Some(proxy) = c.proxy.as_mut() {
+                        proxy.password = None;
+                    }
+                }
+                c
+            })
+            .collect();
+        Self { connections }
+    }
+}
+
+/// Error for writes to an encrypted store that hasn't been unlocked
+const LOCKED: &str = "Connections are locked; unlock them with the master password";
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum AuthType {
     Password,
     KeyFile,
+    Agent,
+    KeyboardInteractive,
+    Pkcs11,
+}
+
+/// Protocol used for the terminal of a connection
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum ConnectionType {
+    #[default]
+    Ssh,
+    /// Bootstrapped over SSH, then carried by a local mosh-client over UDP
+    Mosh,
+    /// Plain telnet for legacy devices; no authentication or encryption
+    Telnet,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Multiplexer {
+    Tmux,
+    Screen,
+}
+
+/// Terminal multiplexer session joined (or created) once the shell starts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoAttach {
+    pub multiplexer: Multiplexer,
+    pub session_name: String,
+}
+
+impl AutoAttach {
+    /// Shell command that attaches to the session, creating it if needed
+    pub fn command(&self) -> String {
+        let name = crate::ssh::shell_quote(&self.session_name);
+        match self.multiplexer {
+            Multiplexer::Tmux => format!("tmux new -A -s {}", name),
+            Multiplexer::Screen => format!("screen -D -R -S {}", name),
+        }
+    }
+}
+
+/// Which address family to try first when a host name resolves to both
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum IpPreference {
+    /// Alternate families in resolver order (RFC 8305)
+    #[default]
+    Auto,
+    Ipv4,
+    Ipv6,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,6 +395,98 @@ pub struct Connection {
     pub private_key_path: Option<String>,
     #[serde(default)]
     pub passphrase: Option<String>,
+    /// IDs of saved connections to hop through, in order (like `ssh -J`)
+    #[serde(default)]
+    pub jump_host_ids: Vec<String>,
+    /// Command whose stdin/stdout carry the SSH transport; `%h`/`%p`/`%r` are expanded
+    #[serde(default)]
+    pub proxy_command: Option<String>,
+    /// HTTP CONNECT or SOCKS5 proxy used instead of a direct TCP connection
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+    /// Seconds between keepalive requests (ServerAliveInterval); `None` uses the
+    /// default interval and `0` disables them
+    #[serde(default)]
+    pub keepalive_interval: Option<u64>,
+    /// Unanswered keepalives before the session is considered dead (ServerAliveCountMax)
+    #[serde(default)]
+    pub keepalive_count_max: Option<usize>,
+    /// Seconds to wait for the network connection to each hop
+    #[serde(default)]
+    pub connect_timeout: Option<u64>,
+    #[serde(default)]
+    pub algorithms: Option<AlgorithmPreferences>,
+    /// Negotiate zlib transport compression (helps on slow links)
+    #[serde(default)]
+    pub compression: bool,
+    /// Forward the local ssh-agent to the remote host (`ssh -A`)
+    #[serde(default)]
+    pub forward_agent: bool,
+    /// Variables sent with `env` requests before the shell starts (the server's
+    /// AcceptEnv decides which are honoured)
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Never persist the password/passphrase; ask for it on every connect
+    #[serde(default)]
+    pub prompt_credentials: bool,
+    /// Name the password/passphrase are filed under in the OS keychain; when
+    /// set they're only read from there at connect time, never stored here
+    #[serde(default)]
+    pub secret_ref: Option<String>,
+    /// PKCS#11 provider library (e.g. opensc-pkcs11.so) for smartcard auth
+    #[serde(default)]
+    pub pkcs11_module: Option<String>,
+    #[serde(default)]
+    pub ip_preference: IpPreference,
+    /// Local IP address outgoing connections are bound to (like `ssh -b`)
+    #[serde(default)]
+    pub bind_address: Option<String>,
+    /// Authentication methods tried in order; empty falls back to `auth_type`
+    /// followed by the OpenSSH-style default chain
+    #[serde(default)]
+    pub auth_methods: Vec<AuthType>,
+    /// Forwards brought up automatically once the session is connected
+    #[serde(default)]
+    pub tunnels: Vec<TunnelSpec>,
+    /// Ports knocked on, in order, before dialing the host directly
+    #[serde(default)]
+    pub knock_sequence: Vec<KnockStep>,
+    /// MAC address for Wake-on-LAN before connecting
+    #[serde(default)]
+    pub mac_address: Option<String>,
+    #[serde(default)]
+    pub connection_type: ConnectionType,
+    /// Run tmux/screen in the first terminal so remote work survives disconnects
+    #[serde(default)]
+    pub auto_attach: Option<AutoAttach>,
+    /// Favorite kept connected in the background so it opens instantly
+    #[serde(default)]
+    pub prewarm: bool,
+    /// Labels for grouping and finding the connection
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Algorithm names in preference order; an empty list keeps russh's defaults
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AlgorithmPreferences {
+    #[serde(default)]
+    pub kex: Vec<String>,
+    #[serde(default)]
+    pub ciphers: Vec<String>,
+    #[serde(default)]
+    pub macs: Vec<String>,
+    #[serde(default)]
+    pub host_key_algorithms: Vec<String>,
+}
+
+impl AlgorithmPreferences {
+    pub fn is_empty(&self) -> bool {
+        self.kex.is_empty()
+            && self.ciphers.is_empty()
+            && self.macs.is_empty()
+            && self.host_key_algorithms.is_empty()
+    }
 }
 
 impl Connection {
@@ -46,36 +510,224 @@ impl Connection {
             password,
             private_key_path,
             passphrase,
+            jump_host_ids: Vec::new(),
+            proxy_command: None,
+            proxy: None,
+            keepalive_interval: None,
+            keepalive_count_max: None,
+            connect_timeout: None,
+            algorithms: None,
+            compression: false,
+            forward_agent: false,
+            env: HashMap::new(),
+            prompt_credentials: false,
+            secret_ref: None,
+            pkcs11_module: None,
+            ip_preference: IpPreference::Auto,
+            bind_address: None,
+            auth_methods: Vec::new(),
+            tunnels: Vec::new(),
+            knock_sequence: Vec::new(),
+            mac_address: None,
+            connection_type: ConnectionType::Ssh,
+            auto_attach: None,
+            prewarm: false,
+            tags: Vec::new(),
+        }
+    }
+
+    /// Whether every word of `query` is found in the name, host, user name or
+    /// a tag, ignoring case. `tag:x` words only look at tags.
+    pub fn matches(&self, query: &str) -> bool {
+        query.split_whitespace().all(|word| {
+            let word = word.to_lowercase();
+            let in_tags =
+                |needle: &str| self.tags.iter().any(|t| t.to_lowercase().contains(needle));
+            match word.strip_prefix("tag:") {
+                Some(tag) => in_tags(tag),
+                None => {
+                    self.name.to_lowercase().contains(&word)
+                        || self.host.to_lowercase().contains(&word)
+                        || self.username.to_lowercase().contains(&word)
+                        || in_tags(&word)
+                }
+            }
+        })
+    }
+}
+
+/// Trim tags and drop empty and repeated ones, keeping the first spelling.
+/// Repeats are found ignoring case the way `Connection::matches` does.
+pub fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    let mut seen: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim();
+        let folded = tag.to_lowercase();
+        if !tag.is_empty() && !seen.contains(&folded) {
+            normalized.push(tag.to_string());
+            seen.push(folded);
+        }
+    }
+    normalized
+}
+
+/// Target of an `ssh://[user@]host[:port]` link
+#[derive(Debug, Clone, PartialEq)]
+pub struct SshUrl {
+    pub username: Option<String>,
+    pub host: String,
+    pub port: u16,
+}
+
+impl SshUrl {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("ssh://")
+            .ok_or_else(|| format!("Not an ssh:// URL: {}", url))?;
+        // Ignore any path, query or fragment
+        let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+
+        let (username, host_port) = match authority.rsplit_once('@') {
+            // OpenSSH-style `user;fingerprint=...` parameters are not supported
+            Some((user, host_port)) => {
+                let user = user.split([';', ':']).next().unwrap_or("");
+                (Some(user.to_string()).filter(|u| !u.is_empty()), host_port)
+            }
+            None => (None, authority),
+        };
+
+        let (host, port) = if let Some(bracketed) = host_port.strip_prefix('[') {
+            let (host, after) = bracketed
+                .split_once(']')
+                .ok_or_else(|| format!("Invalid IPv6 address in URL: {}", url))?;
+            (host, after.strip_prefix(':'))
+        } else {
+            match host_port.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            }
+        };
+
+        if host.is_empty() {
+            return Err(format!("Missing host in URL: {}", url));
         }
+        let port = match port.filter(|p| !p.is_empty()) {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("Invalid port in URL: {}", url))?,
+            None => 22,
+        };
+
+        Ok(Self {
+            username,
+            host: host.to_string(),
+            port,
+        })
     }
 }
 
+/// Saved connections in `connections.json`, optionally encrypted with a
+/// master password
 pub struct ConnectionStore {
     file_path: PathBuf,
+    /// Key of an unlocked encrypted store; while locked, it reads as empty
+    /// and refuses writes
+    key: Option<VaultKey>,
 }
 
 impl ConnectionStore {
     pub fn new(app_data_dir: PathBuf) -> Self {
         let file_path = app_data_dir.join("connections.json");
-        Self { file_path }
+        Self {
+            file_path,
+            key: None,
+        }
+    }
+
+    /// Use `key` to read the store and to encrypt what's written to it
+    pub fn with_key(mut self, key: Option<VaultKey>) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// The encrypted store, if it is one
+    pub fn envelope(&self) -> Option<Envelope> {
+        let data = fs::read_to_string(&self.file_path).ok()?;
+        Envelope::parse(&data)
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.envelope().is_some()
     }
 
     pub fn load(&self) -> Vec<Connection> {
         if !self.file_path.exists() {
             return Vec::new();
         }
-        match fs::read_to_string(&self.file_path) {
-            Ok(data) => serde_json::from_str(&data).unwrap_or_default(),
-            Err(_) => Vec::new(),
-        }
+        let data = match fs::read_to_string(&self.file_path) {
+            Ok(data) => data,
+            Err(_) => return Vec::new(),
+        };
+        let data = match Envelope::parse(&data) {
+            None => data,
+            Some(envelope) => match self.key.as_ref().map(|key| key.open(&envelope)) {
+                Some(Ok(plaintext)) => String::from_utf8_lossy(&plaintext).into_owned(),
+                // Locked
+                _ => return Vec::new(),
+            },
+        };
+        serde_json::from_str(&data).unwrap_or_default()
     }
 
     pub fn save(&self, connections: &[Connection]) -> Result<(), String> {
+        if self.key.is_none() && self.is_encrypted() {
+            return Err(LOCKED.to_string());
+        }
+        self.write(connections, self.key.as_ref())
+    }
+
+    /// Rewrite the store encrypted with `key`, or as plaintext for `None`.
+    /// Turning encryption on this way migrates an existing plaintext store.
+    pub fn rekey(&self, key: Option<&VaultKey>) -> Result<(), String> {
+        if self.key.is_none() && self.is_encrypted() {
+            return Err(LOCKED.to_string());
+        }
+        self.write(&self.load(), key)
+    }
+
+    fn write(&self, connections: &[Connection], key: Option<&VaultKey>) -> Result<(), String> {
         if let Some(parent) = self.file_path.parent() {
             fs::create_dir_all(parent).map_err(|e| e.to_string())?;
         }
-        let data = serde_json::to_string_pretty(connections).map_err(|e| e.to_string())?;
-        fs::write(&self.file_path, data).map_err(|e| e.to_string())?;
+        // Secrets of prompt-at-connect and keychain profiles must never reach
+        // the disk
+        let connections: Vec<Connection> = connections
+            .iter()
+            .cloned()
+            .map(|mut c| {
+                if c.prompt_credentials || c.secret_ref.is_some() {
+                    c.password = None;
+                    c.passphrase = None;
+                }
+                if c.secret_ref.is_some() {
+                    if let Some(proxy) = c.proxy.as_mut() {
+                        proxy.password = None;
+                    }
+                }
+                c
+            })
+            .collect();
+        let data = serde_json::to_string_pretty(&connections).map_err(|e| e.to_string())?;
+        let data = match key {
+            Some(key) => key.seal(data.as_bytes())?,
+            None => data,
+        };
+        // Replaced in one step, so a failed write can't leave the store half
+        // plaintext and half encrypted
+        let tmp_path = self.file_path.with_extension("json.tmp");
+        fs::write(&tmp_path, data).map_err(|e| e.to_string())?;
+        fs::rename(&tmp_path, &self.file_path).map_err(|e| e.to_string())?;
         Ok(())
     }
 
@@ -97,10 +749,143 @@ impl ConnectionStore {
         }
     }
 
+    /// Find the saved connection an `ssh://` link points at, or save a new
+    /// password profile for it
+    pub fn find_or_add_url(&self, url: &SshUrl) -> Result<Connection, String> {
+        let existing = self.load().into_iter().find(|c| {
+            c.host.eq_ignore_ascii_case(&url.host)
+                && c.port == url.port
+                && url.username.iter().all(|u| *u == c.username)
+        });
+        if let Some(conn) = existing {
+            return Ok(conn);
+        }
+
+        let username = url
+            .username
+            .clone()
+            .or_else(|| std::env::var("USER").ok())
+            .or_else(|| std::env::var("USERNAME").ok())
+            .unwrap_or_else(|| "root".to_string());
+        let conn = Connection::new(
+            format!("{}@{}", username, url.host),
+            url.host.clone(),
+            url.port,
+            username,
+            AuthType::Password,
+            None,
+            None,
+            None,
+        );
+        self.add(conn.clone())?;
+        Ok(conn)
+    }
+
     pub fn delete(&self, id: &str) -> Result<Vec<Connection>, String> {
         let mut connections = self.load();
         connections.retain(|c| c.id != id);
         self.save(&connections)?;
         Ok(connections)
     }
+
+    /// Saved connections matching `query` (see `Connection::matches`). Those
+    /// whose name starts with the first word come first, otherwise the saved
+    /// order is kept.
+    pub fn search(&self, query: &str) -> Vec<Connection> {
+        let first = query.split_whitespace().next().unwrap_or("").to_lowercase();
+        let mut found: Vec<Connection> =
+            self.load().into_iter().filter(|c| c.matches(query)).collect();
+        found.sort_by_key(|c| !c.name.to_lowercase().starts_with(&first));
+        found
+    }
+
+    /// What importing a bundle would create or update, without saving.
+    /// Settings that need the user's agreement are listed as warnings.
+    pub fn preview_bundle(&self, bundle: ConnectionBundle) -> Vec<ImportPreview> {
+        merge_bundle(&mut self.load(), bundle, None, true)
+    }
+
+    /// Add the bundle's connections named in `names`, replacing saved ones
+    /// with the same id. Proxy commands, PKCS#11 libraries and agent
+    /// forwarding only come along with `keep_risky`.
+    pub fn import_bundle(
+        &self,
+        bundle: ConnectionBundle,
+        names: &[String],
+        keep_risky: bool,
+    ) -> Result<Vec<Connection>, String> {
+        let mut connections = self.load();
+        merge_bundle(&mut connections, bundle, Some(names), keep_risky);
+        self.save(&connections)?;
+        Ok(connections)
+    }
+
+    /// What importing `hosts` would create or update, without saving
+    pub fn preview_import(&self, hosts: Vec<ImportedHost>) -> Vec<ImportPreview> {
+        merge_imported(&mut self.load(), hosts)
+    }
+
+    /// The saved connections with one created or updated for each of
+    /// `hosts`, for the caller to save once secrets are dealt with
+    pub fn merge_import(&self, hosts: Vec<ImportedHost>) -> Vec<Connection> {
+        let mut connections = self.load();
+        merge_imported(&mut connections, hosts);
+        connections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(name: &str, host: &str, username: &str, tags: &[&str]) -> Connection {
+        let mut conn = Connection::new(
+            name.to_string(),
+            host.to_string(),
+            22,
+            username.to_string(),
+            AuthType::Password,
+            None,
+            None,
+            None,
+        );
+        conn.tags = tags.iter().map(|t| t.to_string()).collect();
+        conn
+    }
+
+    #[test]
+    fn matches_every_word_against_any_field() {
+        let conn = connection("Web Server", "web1.example.com", "deploy", &["Prod"]);
+        assert!(conn.matches("web deploy"));
+        assert!(conn.matches("EXAMPLE prod"));
+        assert!(conn.matches(""));
+        assert!(!conn.matches("web staging"));
+    }
+
+    #[test]
+    fn tag_prefix_only_searches_tags() {
+        let conn = connection("prod-db", "db.example.com", "postgres", &["Database"]);
+        assert!(conn.matches("tag:data"));
+        assert!(!conn.matches("tag:prod"));
+    }
+
+    #[test]
+    fn matches_ignores_non_ascii_case() {
+        let conn = connection("Zürich", "zh.example.com", "root", &["ÄRGER"]);
+        assert!(conn.matches("ZÜRICH"));
+        assert!(conn.matches("tag:ärger"));
+    }
+
+    #[test]
+    fn normalize_tags_trims_and_drops_repeats() {
+        let tags = ["  prod ", "", "Prod", "web", "PROD", "   "];
+        let tags = normalize_tags(tags.iter().map(|t| t.to_string()).collect());
+        assert_eq!(tags, vec!["prod", "web"]);
+    }
+
+    #[test]
+    fn normalize_tags_folds_case_like_search() {
+        let tags = normalize_tags(vec!["Ärger".to_string(), "ärger".to_string()]);
+        assert_eq!(tags, vec!["Ärger"]);
+    }
 }
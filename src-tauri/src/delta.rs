@@ -0,0 +1,310 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+/// Size of the blocks a file's signature is made of
+pub const BLOCK_SIZE: usize = 16 * 1024;
+/// Bytes read from the new file at a time
+const READ_SIZE: usize = 64 * 1024;
+
+/// rsync's weak checksum of a window, cheap to slide along a byte at a time
+#[derive(Debug, Clone, Copy, Default)]
+struct RollingSum {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl RollingSum {
+    fn new(data: &[u8]) -> Self {
+        let mut sum = Self {
+            len: data.len() as u32,
+            ..Default::default()
+        };
+        for &byte in data {
+            sum.a = sum.a.wrapping_add(u32::from(byte));
+            sum.b = sum.b.wrapping_add(sum.a);
+        }
+        sum
+    }
+
+    /// Move the window on by one byte
+    fn roll(&mut self, out: u8, incoming: u8) {
+        self.a = self.a.wrapping_sub(u32::from(out)).wrapping_add(u32::from(incoming));
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(u32::from(out)))
+            .wrapping_add(self.a);
+    }
+
+    /// Drop the window's first byte, once there's nothing left to roll on to
+    fn shrink(&mut self, out: u8) {
+        self.a = self.a.wrapping_sub(u32::from(out));
+        self.b = self.b.wrapping_sub(self.len.wrapping_mul(u32::from(out)));
+        self.len -= 1;
+    }
+
+    fn digest(&self) -> u32 {
+        (self.b << 16) | (self.a & 0xffff)
+    }
+}
+
+/// Checksums of one block of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockSum {
+    weak: u32,
+    strong: [u8; 32],
+    len: usize,
+}
+
+impl BlockSum {
+    fn new(data: &[u8]) -> Self {
+        Self {
+            weak: RollingSum::new(data).digest(),
+            strong: Sha256::digest(data).into(),
+            len: data.len(),
+        }
+    }
+}
+
+/// Cuts data into `BLOCK_SIZE` blocks as it arrives and sums them
+#[derive(Default)]
+struct Signer {
+    blocks: Vec<BlockSum>,
+    pending: Vec<u8>,
+}
+
+impl Signer {
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = (BLOCK_SIZE - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.pending.len() == BLOCK_SIZE {
+                self.blocks.push(BlockSum::new(&self.pending));
+                self.pending.clear();
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<BlockSum> {
+        if !self.pending.is_empty() {
+            self.blocks.push(BlockSum::new(&self.pending));
+        }
+        self.blocks
+    }
+}
+
+/// One step of rebuilding the new file, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeltaOp {
+    /// `len` bytes the old file has at `from`
+    Copy { from: u64, len: u64 },
+    /// The new file's next `len` bytes, which the old file doesn't have
+    Literal { len: u64 },
+}
+
+impl DeltaOp {
+    /// Bytes the op adds to the new file
+    pub fn bytes(&self) -> u64 {
+        match *self {
+            Self::Copy { len, .. } | Self::Literal { len } => len,
+        }
+    }
+}
+
+/// Add an op, merging it into the last one where they continue each other
+fn push(ops: &mut Vec<DeltaOp>, op: DeltaOp) {
+    match (ops.last_mut(), op) {
+        (_, DeltaOp::Literal { len: 0 }) => {}
+        (Some(DeltaOp::Literal { len }), DeltaOp::Literal { len: more }) => *len += more,
+        (Some(DeltaOp::Copy { from, len }), DeltaOp::Copy { from: next, len: more })
+            if *from + *len == next =>
+        {
+            *len += more
+        }
+        _ => ops.push(op),
+    }
+}
+
+/// Compare `data` with the `blocks` of an old file the way rsync does: a
+/// rolling checksum slides over `data` a byte at a time, and where it
+/// matches a block's, SHA-256 confirms the match. Blocks are found wherever
+/// they moved to, so an insertion only costs the inserted bytes.
+///
+/// Returns the ops that rebuild `data` from the old file, and the blocks of
+/// `data` for the next comparison.
+pub fn compute(
+    mut data: impl Read,
+    blocks: &[BlockSum],
+) -> io::Result<(Vec<DeltaOp>, Vec<BlockSum>)> {
+    let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, block) in blocks.iter().enumerate() {
+        index.entry(block.weak).or_default().push(i);
+    }
+
+    let mut signer = Signer::default();
+    let mut ops = Vec::new();
+    let mut buf = Vec::new();
+    let mut chunk = vec![0u8; READ_SIZE];
+    let mut eof = false;
+    // File offset of `buf[0]`, the window's start in `buf`, and the file
+    // offset the unmatched bytes before the window begin at
+    let mut offset = 0u64;
+    let mut start = 0;
+    let mut literal = 0u64;
+    let mut sum: Option<RollingSum> = None;
+    loop {
+        // A full window plus the byte it rolls on to
+        while !eof && buf.len() - start <= BLOCK_SIZE {
+            let n = data.read(&mut chunk)?;
+            if n == 0 {
+                eof = true;
+            } else {
+                signer.update(&chunk[..n]);
+                buf.extend_from_slice(&chunk[..n]);
+            }
+        }
+        let len = (buf.len() - start).min(BLOCK_SIZE);
+        if len == 0 {
+            break;
+        }
+        let window = &buf[start..start + len];
+        let rolling = sum.get_or_insert_with(|| RollingSum::new(window));
+        let matched = index.get(&rolling.digest()).and_then(|candidates| {
+            let strong: [u8; 32] = Sha256::digest(window).into();
+            candidates
+                .iter()
+                .copied()
+                .find(|&i| blocks[i].len == len && blocks[i].strong == strong)
+        });
+        let at = offset + start as u64;
+        if let Some(i) = matched {
+            push(&mut ops, DeltaOp::Literal { len: at - literal });
+            let from = (i * BLOCK_SIZE) as u64;
+            push(&mut ops, DeltaOp::Copy { from, len: len as u64 });
+            start += len;
+            literal = at + len as u64;
+            sum = None;
+        } else if start + len < buf.len() {
+            rolling.roll(buf[start], buf[start + len]);
+            start += 1;
+        } else {
+            // Only the old file's last, short block can still match
+            rolling.shrink(buf[start]);
+            start += 1;
+        }
+        // Unmatched bytes are read again from the file when sent, so
+        // nothing before the window needs keeping
+        if start >= 4 * BLOCK_SIZE {
+            buf.drain(..start);
+            offset += start as u64;
+            start = 0;
+        }
+    }
+    push(&mut ops, DeltaOp::Literal { len: offset + buf.len() as u64 - literal });
+    Ok((ops, signer.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes that don't repeat, so blocks only match where they were copied
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn signature(data: &[u8]) -> Vec<BlockSum> {
+        compute(data, &[]).unwrap().1
+    }
+
+    /// Rebuild the new file from `old` and the bytes `ops` leave to send
+    fn apply(old: &[u8], new: &[u8], ops: &[DeltaOp]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for op in ops {
+            match *op {
+                DeltaOp::Copy { from, len } => {
+                    out.extend_from_slice(&old[from as usize..(from + len) as usize])
+                }
+                DeltaOp::Literal { len } => {
+                    out.extend_from_slice(&new[out.len()..out.len() + len as usize])
+                }
+            }
+        }
+        out
+    }
+
+    fn literal_bytes(ops: &[DeltaOp]) -> u64 {
+        ops.iter()
+            .map(|op| match *op {
+                DeltaOp::Literal { len } => len,
+                DeltaOp::Copy { .. } => 0,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn rolling_matches_a_fresh_sum() {
+        let data = noise(64, 1);
+        let mut sum = RollingSum::new(&data[..16]);
+        for start in 1..=48 {
+            sum.roll(data[start - 1], data[start + 15]);
+            assert_eq!(sum.digest(), RollingSum::new(&data[start..start + 16]).digest());
+        }
+        sum.shrink(data[48]);
+        assert_eq!(sum.digest(), RollingSum::new(&data[49..]).digest());
+    }
+
+    #[test]
+    fn unchanged_file_is_one_copy() {
+        let old = noise(BLOCK_SIZE * 7 / 2, 2);
+        let (ops, blocks) = compute(old.as_slice(), &signature(&old)).unwrap();
+        assert_eq!(ops, vec![DeltaOp::Copy { from: 0, len: old.len() as u64 }]);
+        assert_eq!(blocks, signature(&old));
+    }
+
+    #[test]
+    fn insertion_only_costs_the_block_it_lands_in() {
+        let old = noise(BLOCK_SIZE * 7 / 2, 3);
+        let mut new = old[..20_000].to_vec();
+        new.extend_from_slice(b"inserted");
+        new.extend_from_slice(&old[20_000..]);
+
+        let (ops, blocks) = compute(new.as_slice(), &signature(&old)).unwrap();
+        assert_eq!(apply(&old, &new, &ops), new);
+        assert!(literal_bytes(&ops) <= BLOCK_SIZE as u64 + 8);
+        assert_eq!(blocks, signature(&new));
+    }
+
+    #[test]
+    fn blocks_are_found_after_a_prefix_in_long_files() {
+        let old = noise(BLOCK_SIZE * 13 / 2, 4);
+        let mut new = b"a new header line\n".to_vec();
+        new.extend_from_slice(&old);
+
+        let (ops, _) = compute(new.as_slice(), &signature(&old)).unwrap();
+        assert_eq!(apply(&old, &new, &ops), new);
+        assert_eq!(literal_bytes(&ops), 18);
+    }
+
+    #[test]
+    fn unrelated_and_empty_files() {
+        let old = noise(BLOCK_SIZE * 2, 5);
+        let new = noise(BLOCK_SIZE + 100, 6);
+        let (ops, _) = compute(new.as_slice(), &signature(&old)).unwrap();
+        assert_eq!(ops, vec![DeltaOp::Literal { len: new.len() as u64 }]);
+
+        let (ops, blocks) = compute(io::empty(), &signature(&old)).unwrap();
+        assert!(ops.is_empty());
+        assert!(blocks.is_empty());
+    }
+}
@@ -0,0 +1,270 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// Cells the LCS table of `diff_lines` may have; past that, the differing
+/// middle is shown as replaced wholesale
+const MAX_DIFF_CELLS: usize = 4_000_000;
+/// Unchanged lines shown around each change in a unified diff
+const CONTEXT_LINES: usize = 3;
+/// Most bytes `unified_diff` holds per side from the first difference on
+const MAX_DIFF_BYTES: usize = 4 * 1024 * 1024;
+/// Longest line `unified_diff` reads
+const MAX_LINE_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffKind {
+    Same,
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffLine {
+    pub kind: DiffKind,
+    pub text: String,
+}
+
+fn line(kind: DiffKind, text: &str) -> DiffLine {
+    DiffLine {
+        kind,
+        text: text.to_string(),
+    }
+}
+
+/// Line diff that turns `old` into `new`
+pub fn line_diff(old: &str, new: &str) -> Vec<DiffLine> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    diff_lines(&a, &b)
+}
+
+/// Diff two files already split into lines
+pub fn diff_lines<S: AsRef<str> + PartialEq>(a: &[S], b: &[S]) -> Vec<DiffLine> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (old_mid, new_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut diff: Vec<DiffLine> =
+        a[..prefix].iter().map(|t| line(DiffKind::Same, t.as_ref())).collect();
+    let (n, m) = (old_mid.len(), new_mid.len());
+    if n.saturating_mul(m) > MAX_DIFF_CELLS {
+        diff.extend(old_mid.iter().map(|t| line(DiffKind::Removed, t.as_ref())));
+        diff.extend(new_mid.iter().map(|t| line(DiffKind::Added, t.as_ref())));
+    } else {
+        // lcs[i * (m + 1) + j]: longest common subsequence of old_mid[i..]
+        // and new_mid[j..]
+        let at = |i: usize, j: usize| i * (m + 1) + j;
+        let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[at(i, j)] = if old_mid[i] == new_mid[j] {
+                    lcs[at(i + 1, j + 1)] + 1
+                } else {
+                    lcs[at(i + 1, j)].max(lcs[at(i, j + 1)])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && old_mid[i] == new_mid[j] {
+                diff.push(line(DiffKind::Same, old_mid[i].as_ref()));
+                i += 1;
+                j += 1;
+            } else if i < n && (j == m || lcs[at(i + 1, j)] >= lcs[at(i, j + 1)]) {
+                diff.push(line(DiffKind::Removed, old_mid[i].as_ref()));
+                i += 1;
+            } else {
+                diff.push(line(DiffKind::Added, new_mid[j].as_ref()));
+                j += 1;
+            }
+        }
+    }
+    diff.extend(a[a.len() - suffix..].iter().map(|t| line(DiffKind::Same, t.as_ref())));
+    diff
+}
+
+/// Render a diff as unified diff hunks. `old_start` and `new_start` are the
+/// line numbers (from 1) of the diff's first line in each file.
+fn format_hunks(diff: &[DiffLine], old_start: usize, new_start: usize) -> String {
+    let mut out = String::new();
+    let changed: Vec<usize> = (0..diff.len())
+        .filter(|&i| diff[i].kind != DiffKind::Same)
+        .collect();
+    let mut k = 0;
+    while k < changed.len() {
+        // Changes closer than twice the context share a hunk
+        let first = changed[k];
+        let mut last = first;
+        while k + 1 < changed.len() && changed[k + 1] - last <= 2 * CONTEXT_LINES {
+            k += 1;
+            last = changed[k];
+        }
+        k += 1;
+        let start = first.saturating_sub(CONTEXT_LINES);
+        let end = (last + CONTEXT_LINES + 1).min(diff.len());
+
+        let before = &diff[..start];
+        let old_line = old_start + before.iter().filter(|l| l.kind != DiffKind::Added).count();
+        let new_line = new_start + before.iter().filter(|l| l.kind != DiffKind::Removed).count();
+        let hunk = &diff[start..end];
+        let old_len = hunk.iter().filter(|l| l.kind != DiffKind::Added).count();
+        let new_len = hunk.iter().filter(|l| l.kind != DiffKind::Removed).count();
+        // An empty range is numbered by the line before it
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            if old_len == 0 { old_line - 1 } else { old_line },
+            old_len,
+            if new_len == 0 { new_line - 1 } else { new_line },
+            new_len
+        ));
+        for l in hunk {
+            let sign = match l.kind {
+                DiffKind::Same => ' ',
+                DiffKind::Added => '+',
+                DiffKind::Removed => '-',
+            };
+            out.push(sign);
+            out.push_str(&l.text);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Next line without its line ending, refusing lines longer than
+/// `MAX_LINE_BYTES`
+async fn next_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+) -> Result<Option<String>, String> {
+    buf.clear();
+    let n = (&mut *reader)
+        .take(MAX_LINE_BYTES as u64 + 1)
+        .read_until(b'\n', buf)
+        .await
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    if n == 0 {
+        return Ok(None);
+    }
+    if buf.ends_with(b"\n") {
+        buf.pop();
+    } else if buf.len() > MAX_LINE_BYTES {
+        return Err(format!(
+            "A line is longer than {} MiB, too long to diff",
+            MAX_LINE_BYTES / (1024 * 1024)
+        ));
+    }
+    Ok(Some(String::from_utf8_lossy(buf).into_owned()))
+}
+
+/// Whether the rest of `old` and `new` is the same, compared a buffer at a
+/// time rather than by lines
+async fn same_rest<A, B>(old: &mut A, new: &mut B) -> Result<bool, String>
+where
+    A: AsyncBufRead + Unpin,
+    B: AsyncBufRead + Unpin,
+{
+    loop {
+        let a = old.fill_buf().await.map_err(|e| format!("Failed to read file: {}", e))?;
+        let b = new.fill_buf().await.map_err(|e| format!("Failed to read file: {}", e))?;
+        let n = a.len().min(b.len());
+        if n == 0 {
+            return Ok(a.len() == b.len());
+        }
+        if a[..n] != b[..n] {
+            return Ok(false);
+        }
+        old.consume(n);
+        new.consume(n);
+    }
+}
+
+/// Read the rest of a file into `lines`, refusing to hold more than
+/// `MAX_DIFF_BYTES`. Stops early at binary data (a NUL byte) and returns
+/// whether it found any.
+async fn rest_lines<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    lines: &mut Vec<String>,
+) -> Result<bool, String> {
+    let mut held: usize = lines.iter().map(|l| l.len() + 1).sum();
+    if lines.iter().any(|l| l.contains('\0')) {
+        return Ok(true);
+    }
+    while let Some(line) = next_line(reader, buf).await? {
+        if line.contains('\0') {
+            return Ok(true);
+        }
+        held += line.len() + 1;
+        if held > MAX_DIFF_BYTES {
+            return Err(format!(
+                "The files differ in more than {} MiB, too much to diff",
+                MAX_DIFF_BYTES / (1024 * 1024)
+            ));
+        }
+        lines.push(line);
+    }
+    Ok(false)
+}
+
+/// Unified diff from `old` to `new`, empty when they're the same. Both are
+/// streamed: the identical start is skipped keeping only the lines needed
+/// for context, and only from the first difference on are lines held, up to
+/// `MAX_DIFF_BYTES` a side. Binary files are reported, not diffed.
+pub async fn unified_diff<A, B>(
+    mut old: A,
+    mut new: B,
+    old_label: &str,
+    new_label: &str,
+) -> Result<String, String>
+where
+    A: AsyncBufRead + Unpin,
+    B: AsyncBufRead + Unpin,
+{
+    let (mut old_buf, mut new_buf) = (Vec::new(), Vec::new());
+    let mut context: VecDeque<String> = VecDeque::with_capacity(CONTEXT_LINES + 1);
+    // Lines dropped from the front of `context`
+    let mut skipped = 0;
+    let (old_first, new_first) = loop {
+        let a = next_line(&mut old, &mut old_buf).await?;
+        let b = next_line(&mut new, &mut new_buf).await?;
+        match (a, b) {
+            (None, None) => return Ok(String::new()),
+            // Binary from here on, so only whether the rest differs matters
+            (Some(a), Some(b)) if a == b && a.contains('\0') => {
+                if same_rest(&mut old, &mut new).await? {
+                    return Ok(String::new());
+                }
+                return Ok(format!("Binary files {} and {} differ\n", old_label, new_label));
+            }
+            (Some(a), Some(b)) if a == b => {
+                context.push_back(a);
+                if context.len() > CONTEXT_LINES {
+                    context.pop_front();
+                    skipped += 1;
+                }
+            }
+            (a, b) => break (a, b),
+        }
+    };
+
+    let mut old_lines: Vec<String> = context.iter().cloned().chain(old_first).collect();
+    let mut new_lines: Vec<String> = context.into_iter().chain(new_first).collect();
+    let old_binary = rest_lines(&mut old, &mut old_buf, &mut old_lines).await?;
+    let new_binary = rest_lines(&mut new, &mut new_buf, &mut new_lines).await?;
+    if old_binary || new_binary {
+        return Ok(format!("Binary files {} and {} differ\n", old_label, new_label));
+    }
+
+    let diff = diff_lines(&old_lines, &new_lines);
+    let mut out = format!("--- {}\n+++ {}\n", old_label, new_label);
+    out.push_str(&format_hunks(&diff, skipped + 1, skipped + 1));
+    Ok(out)
+}
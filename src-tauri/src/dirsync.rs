@@ -0,0 +1,770 @@
+use russh_sftp::client::SftpSession;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::connection::Connection;
+use crate::sftp::{self, DirTransferSummary, TransferFailure, TransferOptions, TransferProgress};
+
+/// What a tree walk records about one file or directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Node {
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<u64>,
+}
+
+impl Node {
+    /// Whether two files count as the same for syncing purposes
+    fn same_as(&self, other: &Node) -> bool {
+        self.is_dir == other.is_dir
+            && (self.is_dir || (self.size == other.size && self.modified == other.modified))
+    }
+}
+
+/// Everything below a sync root, keyed by `/`-separated relative path. Being
+/// ordered, a directory always comes before what's inside it.
+type Tree = BTreeMap<String, Node>;
+
+fn child(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+fn local_path(root: &Path, relative: &str) -> PathBuf {
+    relative
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .fold(root.to_path_buf(), |path, part| path.join(part))
+}
+
+fn remote_path(root: &str, relative: &str) -> String {
+    if relative.is_empty() {
+        root.to_string()
+    } else {
+        sftp::join(root, relative)
+    }
+}
+
+/// Whether `path` lies below any of `dirs`
+fn is_below(path: &str, dirs: &[String]) -> bool {
+    dirs.iter()
+        .any(|dir| path.strip_prefix(dir.as_str()).is_some_and(|rest| rest.starts_with('/')))
+}
+
+/// Walk a local tree. Symlinks are left out; an unreadable directory fails
+/// the whole walk, since planning against a partial tree could delete things.
+async fn local_tree(root: &Path) -> Result<Tree, String> {
+    let meta = tokio::fs::metadata(root)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", root.display(), e))?;
+    if !meta.is_dir() {
+        return Err(format!("{} is not a directory", root.display()));
+    }
+
+    let mut tree = Tree::new();
+    let mut pending = vec![String::new()];
+    while let Some(dir) = pending.pop() {
+        let path = local_path(root, &dir);
+        let read_error = |e: std::io::Error| format!("Failed to read {}: {}", path.display(), e);
+        let mut entries = tokio::fs::read_dir(&path).await.map_err(read_error)?;
+        while let Some(entry) = entries.next_entry().await.map_err(read_error)? {
+            // Not following symlinks here
+            let meta = entry.metadata().await.map_err(read_error)?;
+            if meta.is_symlink() {
+                continue;
+            }
+            let relative = child(&dir, &entry.file_name().to_string_lossy());
+            let modified = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs());
+            let node = Node {
+                is_dir: meta.is_dir(),
+                size: if meta.is_dir() { 0 } else { meta.len() },
+                modified,
+            };
+            if node.is_dir {
+                pending.push(relative.clone());
+            }
+            tree.insert(relative, node);
+        }
+    }
+    Ok(tree)
+}
+
+/// Walk a remote tree, with the same rules as `local_tree`
+async fn remote_tree(sftp: &SftpSession, root: &str) -> Result<Tree, String> {
+    let mut tree = Tree::new();
+    let mut pending = vec![String::new()];
+    while let Some(dir) = pending.pop() {
+        let path = remote_path(root, &dir);
+        for entry in sftp::list_dir(sftp, &path, &sftp::ListFilter::default()).await? {
+            if entry.is_symlink {
+                continue;
+            }
+            let relative = child(&dir, &entry.name);
+            let node = Node {
+                is_dir: entry.is_dir,
+                size: if entry.is_dir { 0 } else { entry.size },
+                modified: entry.modified,
+            };
+            if node.is_dir {
+                pending.push(relative.clone());
+            }
+            tree.insert(relative, node);
+        }
+    }
+    Ok(tree)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncActionKind {
+    /// Copy to the server; for a directory, create it there
+    Upload,
+    /// Copy from the server; for a directory, create it locally
+    Download,
+    DeleteLocal,
+    DeleteRemote,
+}
+
+/// One step of a sync
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncAction {
+    pub kind: SyncActionKind,
+    /// Relative to both sync roots, `/`-separated
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+}
+
+/// A path that changed on both sides since the last two-way sync. Neither
+/// copy is touched; the user has to settle it.
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncConflict {
+    pub path: String,
+    /// `None` on a side where it was deleted
+    pub local: Option<Node>,
+    pub remote: Option<Node>,
+}
+
+/// Everything a sync is going to do, in order
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncPlan {
+    pub actions: Vec<SyncAction>,
+    /// Paths left alone, with the reason
+    pub skipped: Vec<TransferFailure>,
+    pub conflicts: Vec<SyncConflict>,
+    /// Bytes to be copied
+    pub bytes: u64,
+    /// Hash of both trees and everything else the plan was made from, so a
+    /// previewed plan is only run while nothing has changed
+    pub hash: String,
+}
+
+impl SyncPlan {
+    fn push(&mut self, kind: SyncActionKind, path: &str, node: &Node) {
+        self.bytes += node.size;
+        self.actions.push(SyncAction {
+            kind,
+            path: path.to_string(),
+            is_dir: node.is_dir,
+            size: node.size,
+        });
+    }
+}
+
+/// Plan making `dest` look like `source`: copy whatever is missing or
+/// differs in size or mtime. With `delete_extra`, whatever only `dest` has
+/// is deleted, and so is anything in the way of a copy that changed between
+/// file and directory; without it, such clashes are skipped.
+fn plan_one_way(source: &Tree, dest: &Tree, upload: bool, delete_extra: bool) -> SyncPlan {
+    let (copy, delete) = if upload {
+        (SyncActionKind::Upload, SyncActionKind::DeleteRemote)
+    } else {
+        (SyncActionKind::Download, SyncActionKind::DeleteLocal)
+    };
+    let mut plan = SyncPlan::default();
+    let mut deleted: Vec<String> = Vec::new();
+    let mut blocked: Vec<String> = Vec::new();
+
+    // Deletions go first so clashing paths are free by the time they're copied
+    for (path, node) in dest {
+        if is_below(path, &deleted) {
+            continue;
+        }
+        let clashes = source.get(path).is_some_and(|other| other.is_dir != node.is_dir);
+        if source.contains_key(path) && !clashes {
+            continue;
+        }
+        if delete_extra {
+            plan.push(delete, path, &Node { size: 0, ..*node });
+            deleted.push(path.clone());
+        } else if clashes {
+            plan.skipped.push(TransferFailure {
+                path: path.clone(),
+                error: "A file and a directory have the same name".to_string(),
+            });
+            blocked.push(path.clone());
+        }
+    }
+
+    for (path, node) in source {
+        if blocked.contains(path) || is_below(path, &blocked) {
+            continue;
+        }
+        let up_to_date = dest
+            .get(path)
+            .is_some_and(|other| other.same_as(node) && !deleted.contains(path));
+        if !up_to_date {
+            plan.push(copy, path, node);
+        }
+    }
+    plan
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMode {
+    /// Make the server side match the local one
+    Upload,
+    /// Make the local side match the server
+    Download,
+    /// Carry changes made on either side over to the other
+    Both,
+}
+
+/// Both sides of a path right after the last two-way sync
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SyncedPair {
+    pub local: Node,
+    pub remote: Node,
+}
+
+/// State of a sync pair after its last two-way sync, which is what tells a
+/// deletion on one side from a file that is new on the other
+pub type Snapshot = BTreeMap<String, SyncedPair>;
+
+/// Held while the snapshot file is read, changed and written back
+static SAVING: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Snapshots of all two-way sync pairs, kept in the app data directory
+#[derive(Debug, Clone)]
+pub struct SnapshotStore {
+    file_path: PathBuf,
+}
+
+impl SnapshotStore {
+    pub fn new(app_data_dir: PathBuf) -> Self {
+        let file_path = app_data_dir.join("sync_snapshots.json");
+        Self { file_path }
+    }
+
+    fn load_all(&self) -> HashMap<String, Snapshot> {
+        match fs::read_to_string(&self.file_path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_default(),
+            Err(_) => HashMap::new(),
+        }
+    }
+
+    /// The pair's snapshot; empty before its first two-way sync
+    pub fn load(&self, key: &str) -> Snapshot {
+        self.load_all().remove(key).unwrap_or_default()
+    }
+
+    /// Store the pair's snapshot alongside the others. Saves are serialized,
+    /// so two syncs finishing together both keep their snapshot, and the
+    /// file is replaced in one step so a failed write can't truncate it.
+    pub fn save(&self, key: &str, snapshot: Snapshot) -> Result<(), String> {
+        let _saving = SAVING.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut all = self.load_all();
+        all.insert(key.to_string(), snapshot);
+        if let Some(parent) = self.file_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let data = serde_json::to_string(&all).map_err(|e| e.to_string())?;
+        let tmp_path = self.file_path.with_extension("json.tmp");
+        fs::write(&tmp_path, data).map_err(|e| e.to_string())?;
+        fs::rename(&tmp_path, &self.file_path).map_err(|e| e.to_string())
+    }
+}
+
+/// Identifies a local/remote directory pair across sessions
+pub fn pair_key(connection: &Connection, local_root: &Path, remote_root: &str) -> String {
+    format!(
+        "{}@{}:{}:{}|{}",
+        connection.username,
+        connection.host,
+        connection.port,
+        remote_root.trim_end_matches('/'),
+        local_root.display()
+    )
+}
+
+/// Whether a side differs from how the snapshot left it
+fn changed(now: Option<&Node>, then: Option<&Node>) -> bool {
+    match (now, then) {
+        (Some(now), Some(then)) => !now.same_as(then),
+        (None, None) => false,
+        _ => true,
+    }
+}
+
+/// What two-way planning decided for one path
+#[derive(Debug, Clone, Copy)]
+enum Decision {
+    Act(SyncActionKind, Node),
+    Conflict,
+}
+
+/// Delete kind -> the copy that recreates the directory on that side
+fn undo_delete(kind: SyncActionKind) -> SyncActionKind {
+    match kind {
+        SyncActionKind::DeleteRemote => SyncActionKind::Download,
+        _ => SyncActionKind::Upload,
+    }
+}
+
+/// Plan a two-way sync against the last `snapshot`: a change on one side is
+/// carried over to the other, and changes on both sides that don't agree
+/// become conflicts. A directory deleted on one side is only deleted on the
+/// other when nothing inside it changed there.
+fn plan_two_way(local: &Tree, remote: &Tree, snapshot: &Snapshot) -> SyncPlan {
+    let paths: BTreeSet<&String> =
+        local.keys().chain(remote.keys()).chain(snapshot.keys()).collect();
+    let mut decisions: BTreeMap<&str, Decision> = BTreeMap::new();
+    for path in paths {
+        let (l, r) = (local.get(path), remote.get(path));
+        let base = snapshot.get(path);
+        let l_changed = changed(l, base.map(|pair| &pair.local));
+        let r_changed = changed(r, base.map(|pair| &pair.remote));
+        let decision = match (l_changed, r_changed, l, r) {
+            (false, false, _, _) => continue,
+            // Both ended up the same, directories included
+            (_, _, Some(l), Some(r)) if l.same_as(r) => continue,
+            (true, true, None, None) => continue,
+            (true, true, _, _) => Decision::Conflict,
+            (true, false, Some(l), _) => Decision::Act(SyncActionKind::Upload, *l),
+            (true, false, None, Some(r)) => Decision::Act(SyncActionKind::DeleteRemote, *r),
+            (false, true, _, Some(r)) => Decision::Act(SyncActionKind::Download, *r),
+            (false, true, Some(l), None) => Decision::Act(SyncActionKind::DeleteLocal, *l),
+            (_, _, None, None) => continue,
+        };
+        decisions.insert(path, decision);
+    }
+
+    // A directory may only go away on a side if everything below it goes
+    // too. Children first, so their outcome is final when the parent looks.
+    let keys: Vec<&str> = decisions.keys().copied().collect();
+    for path in keys.into_iter().rev() {
+        let Some(Decision::Act(kind, node)) = decisions.get(path).copied() else {
+            continue;
+        };
+        // Deleting a directory, or replacing it with a file
+        let remote_dir_in_way = remote.get(path).is_some_and(|r| r.is_dir && !node.is_dir);
+        let local_dir_in_way = local.get(path).is_some_and(|l| l.is_dir && !node.is_dir);
+        let (side, replaces) = match kind {
+            SyncActionKind::DeleteLocal | SyncActionKind::DeleteRemote if node.is_dir => {
+                (kind, false)
+            }
+            SyncActionKind::Upload if remote_dir_in_way => (SyncActionKind::DeleteRemote, true),
+            SyncActionKind::Download if local_dir_in_way => (SyncActionKind::DeleteLocal, true),
+            _ => continue,
+        };
+        let prefix = format!("{}/", path);
+        let below: Vec<Decision> = decisions
+            .range(prefix.as_str()..)
+            .take_while(|(child, _)| child.starts_with(&prefix))
+            .map(|(_, decision)| *decision)
+            .collect();
+        let conflicted = below.iter().any(|d| matches!(d, Decision::Conflict));
+        let kept = below
+            .iter()
+            .any(|d| matches!(d, Decision::Act(child_kind, _) if *child_kind != side));
+        let decision = match (conflicted || kept, replaces) {
+            (false, _) => continue,
+            (true, true) => Decision::Conflict,
+            (true, false) if conflicted => {
+                decisions.remove(path);
+                continue;
+            }
+            (true, false) => Decision::Act(undo_delete(kind), node),
+        };
+        decisions.insert(path, decision);
+    }
+
+    let mut plan = SyncPlan::default();
+    // Deleted or conflicting paths, whose contents need nothing more
+    let mut covered: Vec<String> = Vec::new();
+    for (path, decision) in decisions {
+        if is_below(path, &covered) {
+            continue;
+        }
+        let (kind, node) = match decision {
+            Decision::Act(kind, node) => (kind, node),
+            Decision::Conflict => {
+                plan.conflicts.push(SyncConflict {
+                    path: path.to_string(),
+                    local: local.get(path).copied(),
+                    remote: remote.get(path).copied(),
+                });
+                covered.push(path.to_string());
+                continue;
+            }
+        };
+        // Whatever is in the way on the receiving side goes first
+        let in_the_way = match kind {
+            SyncActionKind::Upload => remote.get(path).map(|r| (SyncActionKind::DeleteRemote, r)),
+            SyncActionKind::Download => local.get(path).map(|l| (SyncActionKind::DeleteLocal, l)),
+            _ => None,
+        };
+        if let Some((delete, other)) = in_the_way.filter(|(_, other)| other.is_dir != node.is_dir) {
+            plan.push(delete, path, &Node { size: 0, ..*other });
+            if other.is_dir {
+                covered.push(path.to_string());
+            }
+        }
+        if matches!(kind, SyncActionKind::DeleteLocal | SyncActionKind::DeleteRemote) {
+            plan.push(kind, path, &Node { size: 0, ..node });
+            covered.push(path.to_string());
+        } else {
+            plan.push(kind, path, &node);
+        }
+    }
+    plan
+}
+
+/// The snapshot to keep after a two-way sync: every path both sides now
+/// have, except conflicts and failed steps, which keep their old entries so
+/// they come up again next time
+fn next_snapshot(local: &Tree, remote: &Tree, old: &Snapshot, unsettled: &[String]) -> Snapshot {
+    let mut snapshot = Snapshot::new();
+    for (path, l) in local {
+        if unsettled.contains(path) {
+            continue;
+        }
+        if let Some(r) = remote.get(path) {
+            snapshot.insert(path.clone(), SyncedPair { local: *l, remote: *r });
+        }
+    }
+    for path in unsettled {
+        if let Some(pair) = old.get(path) {
+            snapshot.insert(path.clone(), *pair);
+        }
+    }
+    snapshot
+}
+
+/// Walk both sides again after a two-way sync and work out the snapshot to
+/// save for next time
+pub async fn snapshot_after(
+    sftp: &SftpSession,
+    local_root: &Path,
+    remote_root: &str,
+    old: &Snapshot,
+    plan: &SyncPlan,
+    summary: &DirTransferSummary,
+) -> Result<Snapshot, String> {
+    let local = local_tree(local_root).await?;
+    let remote = remote_tree(sftp, remote_root).await?;
+    let unsettled: Vec<String> = plan
+        .conflicts
+        .iter()
+        .map(|conflict| conflict.path.clone())
+        .chain(summary.failures.iter().map(|failure| failure.path.clone()))
+        .collect();
+    Ok(next_snapshot(&local, &remote, old, &unsettled))
+}
+
+/// What a sync planned and, unless it was a dry run, how it went
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncReport {
+    pub plan: SyncPlan,
+    pub summary: Option<DirTransferSummary>,
+}
+
+/// Work out what a sync between `local_root` and `remote_root` would do.
+/// `delete_extra` only applies one way; two-way syncs compare against the
+/// pair's `snapshot` instead.
+pub async fn plan(
+    sftp: &SftpSession,
+    local_root: &Path,
+    remote_root: &str,
+    mode: SyncMode,
+    delete_extra: bool,
+    snapshot: &Snapshot,
+) -> Result<SyncPlan, String> {
+    let local = local_tree(local_root).await?;
+    let remote = remote_tree(sftp, remote_root).await?;
+    let mut plan = match mode {
+        SyncMode::Upload => plan_one_way(&local, &remote, true, delete_extra),
+        SyncMode::Download => plan_one_way(&remote, &local, false, delete_extra),
+        SyncMode::Both => plan_two_way(&local, &remote, snapshot),
+    };
+    plan.hash = plan_hash(&local, &remote, mode, delete_extra, snapshot)?;
+    Ok(plan)
+}
+
+/// Hash of what a plan is made from. Trees and snapshots are ordered maps,
+/// so the same inputs always serialize the same way.
+fn plan_hash(
+    local: &Tree,
+    remote: &Tree,
+    mode: SyncMode,
+    delete_extra: bool,
+    snapshot: &Snapshot,
+) -> Result<String, String> {
+    let inputs = (local, remote, format!("{:?}", mode), delete_extra, snapshot);
+    let data = serde_json::to_vec(&inputs).map_err(|e| e.to_string())?;
+    Ok(Sha256::digest(&data).iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Carry out `plan`, reporting combined progress over all copied bytes.
+/// Copies keep their mtime so the next plan sees them as up to date. Steps
+/// that fail are recorded and the rest carry on.
+pub async fn execute(
+    sftp: &SftpSession,
+    local_root: &Path,
+    remote_root: &str,
+    plan: &SyncPlan,
+    mut report: impl FnMut(TransferProgress),
+) -> Result<DirTransferSummary, String> {
+    let options = TransferOptions {
+        preserve: true,
+        ..Default::default()
+    };
+    let mut failures = Vec::new();
+    let mut files_done = 0;
+    let mut bytes_before = 0;
+
+    for action in &plan.actions {
+        let local = local_path(local_root, &action.path);
+        let local_str = local.to_string_lossy();
+        let remote = remote_path(remote_root, &action.path);
+        // Scale each file's own progress to the whole sync
+        let on_progress = |progress: TransferProgress| {
+            let bytes_done = bytes_before + progress.bytes_done;
+            let eta_secs = (progress.bytes_per_sec > 0)
+                .then(|| plan.bytes.saturating_sub(bytes_done) / progress.bytes_per_sec);
+            report(TransferProgress {
+                bytes_done,
+                bytes_total: plan.bytes,
+                bytes_per_sec: progress.bytes_per_sec,
+                eta_secs,
+            });
+        };
+
+        let result = match (action.kind, action.is_dir) {
+            (SyncActionKind::Upload, true) => match sftp.metadata(remote.as_str()).await {
+                Ok(attrs) if attrs.is_dir() => Ok(()),
+                _ => sftp::mkdir(sftp, &remote).await,
+            },
+            (SyncActionKind::Upload, false) => {
+                sftp::upload_file(sftp, &local_str, &remote, options, on_progress).await
+            }
+            (SyncActionKind::Download, true) => tokio::fs::create_dir_all(&local)
+                .await
+                .map_err(|e| format!("Failed to create local directory: {}", e)),
+            (SyncActionKind::Download, false) => {
+                sftp::download_file(sftp, &remote, &local_str, options, on_progress).await
+            }
+            (SyncActionKind::DeleteLocal, true) => tokio::fs::remove_dir_all(&local)
+                .await
+                .map_err(|e| format!("Failed to delete local directory: {}", e)),
+            (SyncActionKind::DeleteLocal, false) => tokio::fs::remove_file(&local)
+                .await
+                .map_err(|e| format!("Failed to delete local file: {}", e)),
+            (SyncActionKind::DeleteRemote, _) => sftp::remove_tree(sftp, &remote).await,
+        };
+        bytes_before += action.size;
+
+        let copies_file = !action.is_dir
+            && matches!(action.kind, SyncActionKind::Upload | SyncActionKind::Download);
+        match result {
+            Ok(()) if copies_file => files_done += 1,
+            Ok(()) => {}
+            Err(error) => failures.push(TransferFailure {
+                path: action.path.clone(),
+                error,
+            }),
+        }
+    }
+
+    Ok(DirTransferSummary {
+        files_done,
+        files_skipped: 0,
+        failures,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SyncActionKind::*;
+    use super::*;
+
+    fn file(size: u64, modified: u64) -> Node {
+        Node {
+            is_dir: false,
+            size,
+            modified: Some(modified),
+        }
+    }
+
+    fn dir() -> Node {
+        Node {
+            is_dir: true,
+            size: 0,
+            modified: None,
+        }
+    }
+
+    fn tree(entries: &[(&str, Node)]) -> Tree {
+        entries.iter().map(|(path, node)| (path.to_string(), *node)).collect()
+    }
+
+    fn snapshot(entries: &[(&str, Node)]) -> Snapshot {
+        entries
+            .iter()
+            .map(|(path, node)| (path.to_string(), SyncedPair { local: *node, remote: *node }))
+            .collect()
+    }
+
+    fn steps(plan: &SyncPlan) -> Vec<(SyncActionKind, &str)> {
+        plan.actions.iter().map(|a| (a.kind, a.path.as_str())).collect()
+    }
+
+    #[test]
+    fn is_below_needs_a_separator() {
+        let dirs = vec!["a".to_string()];
+        assert!(is_below("a/b", &dirs));
+        assert!(!is_below("a", &dirs));
+        assert!(!is_below("ab", &dirs));
+    }
+
+    #[test]
+    fn one_way_copies_what_differs() {
+        let source = tree(&[
+            ("a", dir()),
+            ("a/x", file(1, 1)),
+            ("b", file(2, 2)),
+            ("c", file(3, 3)),
+        ]);
+        let dest = tree(&[
+            ("a", dir()),
+            ("a/x", file(1, 1)),
+            ("b", file(2, 5)),
+            ("d", file(4, 4)),
+        ]);
+
+        let plan = plan_one_way(&source, &dest, true, false);
+        assert_eq!(steps(&plan), vec![(Upload, "b"), (Upload, "c")]);
+        assert_eq!(plan.bytes, 5);
+
+        let plan = plan_one_way(&source, &dest, true, true);
+        assert_eq!(steps(&plan), vec![(DeleteRemote, "d"), (Upload, "b"), (Upload, "c")]);
+        assert_eq!(plan.bytes, 5);
+
+        let plan = plan_one_way(&dest, &source, false, true);
+        assert_eq!(steps(&plan), vec![(DeleteLocal, "c"), (Download, "b"), (Download, "d")]);
+    }
+
+    #[test]
+    fn one_way_file_directory_clash() {
+        let source = tree(&[("x", file(1, 1))]);
+        let dest = tree(&[("x", dir()), ("x/y", file(2, 2))]);
+
+        let plan = plan_one_way(&source, &dest, true, false);
+        assert!(plan.actions.is_empty());
+        assert_eq!(plan.skipped.len(), 1);
+        assert_eq!(plan.skipped[0].path, "x");
+
+        let plan = plan_one_way(&source, &dest, true, true);
+        assert_eq!(steps(&plan), vec![(DeleteRemote, "x"), (Upload, "x")]);
+    }
+
+    #[test]
+    fn two_way_carries_changes_over_and_reports_conflicts() {
+        let base = snapshot(&[
+            ("both", file(3, 3)),
+            ("edit", file(4, 4)),
+            ("gone", file(2, 2)),
+            ("kept", file(1, 1)),
+        ]);
+        let local = tree(&[
+            ("both", file(3, 9)),
+            ("edit", file(5, 5)),
+            ("kept", file(1, 1)),
+            ("new", file(6, 6)),
+        ]);
+        let remote = tree(&[
+            ("both", file(7, 7)),
+            ("edit", file(4, 4)),
+            ("gone", file(2, 2)),
+            ("kept", file(1, 1)),
+        ]);
+
+        let plan = plan_two_way(&local, &remote, &base);
+        assert_eq!(steps(&plan), vec![(Upload, "edit"), (DeleteRemote, "gone"), (Upload, "new")]);
+        let conflicts: Vec<&str> = plan.conflicts.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(conflicts, vec!["both"]);
+    }
+
+    #[test]
+    fn two_way_keeps_a_deleted_directory_the_other_side_added_to() {
+        let base = snapshot(&[("d", dir()), ("d/f", file(1, 1))]);
+        let local = Tree::new();
+        let remote = tree(&[("d", dir()), ("d/f", file(1, 1)), ("d/g", file(2, 2))]);
+
+        let plan = plan_two_way(&local, &remote, &base);
+        assert_eq!(steps(&plan), vec![(Download, "d"), (DeleteRemote, "d/f"), (Download, "d/g")]);
+    }
+
+    #[test]
+    fn two_way_leaves_a_deleted_directory_with_conflicts_alone() {
+        let base = snapshot(&[("d", dir()), ("d/f", file(1, 1))]);
+        let local = Tree::new();
+        let remote = tree(&[("d", dir()), ("d/f", file(2, 2))]);
+
+        let plan = plan_two_way(&local, &remote, &base);
+        assert!(plan.actions.is_empty());
+        let conflicts: Vec<&str> = plan.conflicts.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(conflicts, vec!["d/f"]);
+    }
+
+    #[test]
+    fn next_snapshot_keeps_unsettled_entries() {
+        let local = tree(&[("a", file(1, 1)), ("b", file(2, 2))]);
+        let remote = tree(&[("a", file(1, 1)), ("b", file(3, 3)), ("c", file(4, 4))]);
+        let old = snapshot(&[("b", file(9, 9))]);
+
+        let next = next_snapshot(&local, &remote, &old, &["b".to_string()]);
+        assert_eq!(next.keys().collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(next["a"].remote, file(1, 1));
+        assert_eq!(next["b"].local, file(9, 9));
+    }
+
+    #[test]
+    fn plan_hash_depends_on_every_input() {
+        let local = tree(&[("a", file(1, 1))]);
+        let remote = Tree::new();
+        let base = Snapshot::new();
+        let hash =
+            |mode, delete_extra| plan_hash(&local, &remote, mode, delete_extra, &base).unwrap();
+        assert_eq!(hash(SyncMode::Upload, false), hash(SyncMode::Upload, false));
+        assert_ne!(hash(SyncMode::Upload, false), hash(SyncMode::Download, false));
+        assert_ne!(hash(SyncMode::Upload, false), hash(SyncMode::Upload, true));
+        let edited = tree(&[("a", file(1, 2))]);
+        assert_ne!(
+            hash(SyncMode::Upload, false),
+            plan_hash(&edited, &remote, SyncMode::Upload, false, &base).unwrap()
+        );
+    }
+}
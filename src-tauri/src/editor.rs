@@ -0,0 +1,336 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Passed as the editor to show the platform's "open with" chooser instead
+pub const OPEN_WITH_DIALOG: &str = "open-with-dialog";
+
+/// An editor the user can pick, with the ways of launching it on this platform
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditorEntry {
+    pub id: String,
+    pub name: String,
+    /// Executables tried in order. Bare names are looked up on PATH;
+    /// `%VAR%` and `$VAR` are expanded.
+    #[serde(default)]
+    pub commands: Vec<String>,
+    /// macOS application name, launched with `open -a`
+    #[serde(default)]
+    pub mac_app: Option<String>,
+}
+
+/// An editor from the table and whether it was found on this machine
+#[derive(Debug, Clone, Serialize)]
+pub struct EditorInfo {
+    #[serde(flatten)]
+    pub entry: EditorEntry,
+    pub available: bool,
+}
+
+fn entry(id: &str, name: &str, commands: &[&str], mac_app: Option<&str>) -> EditorEntry {
+    EditorEntry {
+        id: id.to_string(),
+        name: name.to_string(),
+        commands: commands.iter().map(|c| c.to_string()).collect(),
+        mac_app: mac_app.map(str::to_string),
+    }
+}
+
+/// Editors known out of the box on this platform
+#[cfg(windows)]
+pub fn builtin_editors() -> Vec<EditorEntry> {
+    vec![
+        entry(
+            "vscode",
+            "Visual Studio Code",
+            &[
+                "code",
+                "%LOCALAPPDATA%\\Programs\\Microsoft VS Code\\Code.exe",
+                "C:\\Program Files\\Microsoft VS Code\\Code.exe",
+                "C:\\Program Files (x86)\\Microsoft VS Code\\Code.exe",
+            ],
+            None,
+        ),
+        entry(
+            "notepad++",
+            "Notepad++",
+            &[
+                "notepad++",
+                "C:\\Program Files\\Notepad++\\notepad++.exe",
+                "C:\\Program Files (x86)\\Notepad++\\notepad++.exe",
+            ],
+            None,
+        ),
+        entry(
+            "sublime",
+            "Sublime Text",
+            &[
+                "subl",
+                "C:\\Program Files\\Sublime Text\\sublime_text.exe",
+                "C:\\Program Files\\Sublime Text 3\\sublime_text.exe",
+            ],
+            None,
+        ),
+        entry("atom", "Atom", &["atom", "%LOCALAPPDATA%\\atom\\atom.exe"], None),
+        entry("notepad", "Notepad", &["notepad.exe"], None),
+    ]
+}
+
+/// Editors known out of the box on this platform
+#[cfg(target_os = "macos")]
+pub fn builtin_editors() -> Vec<EditorEntry> {
+    vec![
+        entry("vscode", "Visual Studio Code", &["code"], Some("Visual Studio Code")),
+        entry("sublime", "Sublime Text", &["subl"], Some("Sublime Text")),
+        entry("bbedit", "BBEdit", &["bbedit"], Some("BBEdit")),
+        entry("textedit", "TextEdit", &[], Some("TextEdit")),
+    ]
+}
+
+/// Editors known out of the box on this platform
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn builtin_editors() -> Vec<EditorEntry> {
+    vec![
+        entry("vscode", "Visual Studio Code", &["code", "codium"], None),
+        entry("sublime", "Sublime Text", &["subl", "sublime_text"], None),
+        entry("gedit", "GNOME Text Editor", &["gnome-text-editor", "gedit"], None),
+        entry("kate", "Kate", &["kate"], None),
+        entry("mousepad", "Mousepad", &["mousepad"], None),
+        entry("gvim", "GVim", &["gvim"], None),
+    ]
+}
+
+/// User-defined editors in `editors.json`; an entry with the id of a
+/// built-in one replaces it
+#[derive(Debug, Clone)]
+pub struct EditorStore {
+    file_path: PathBuf,
+}
+
+impl EditorStore {
+    pub fn new(app_data_dir: PathBuf) -> Self {
+        let file_path = app_data_dir.join("editors.json");
+        Self { file_path }
+    }
+
+    pub fn load(&self) -> Vec<EditorEntry> {
+        match fs::read_to_string(&self.file_path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_default(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn save(&self, editors: &[EditorEntry]) -> Result<(), String> {
+        if let Some(parent) = self.file_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let data = serde_json::to_string_pretty(editors).map_err(|e| e.to_string())?;
+        fs::write(&self.file_path, data).map_err(|e| e.to_string())
+    }
+
+    /// The built-in table with the user's entries applied
+    pub fn table(&self) -> Vec<EditorEntry> {
+        let mut table = builtin_editors();
+        for custom in self.load() {
+            match table.iter_mut().find(|e| e.id == custom.id) {
+                Some(existing) => *existing = custom,
+                None => table.push(custom),
+            }
+        }
+        table
+    }
+}
+
+/// Expand `%VAR%` (Windows style) and `$VAR` references; unset ones become empty
+fn expand_env(command: &str) -> String {
+    let mut out = String::new();
+    let mut rest = command;
+    while let Some(i) = rest.find(['%', '$']) {
+        out.push_str(&rest[..i]);
+        let marker = rest.as_bytes()[i];
+        let after = &rest[i + 1..];
+        let (name, tail) = if marker == b'%' {
+            match after.find('%') {
+                Some(end) => (&after[..end], &after[end + 1..]),
+                None => ("", after),
+            }
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+        if name.is_empty() {
+            out.push(marker as char);
+        } else {
+            out.push_str(&std::env::var(name).unwrap_or_default());
+        }
+        rest = tail;
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Find an executable on PATH, trying the PATHEXT extensions on Windows
+/// (so `code` finds `code.cmd`)
+pub fn find_in_path(program: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    let extensions: Vec<String> = if cfg!(windows) {
+        let pathext = std::env::var("PATHEXT").unwrap_or_else(|_| ".EXE;.CMD;.BAT".to_string());
+        std::iter::once(String::new())
+            .chain(pathext.split(';').map(str::to_string))
+            .collect()
+    } else {
+        vec![String::new()]
+    };
+    std::env::split_paths(&path).find_map(|dir| {
+        extensions.iter().find_map(|ext| {
+            let candidate = dir.join(format!("{}{}", program, ext));
+            candidate.is_file().then_some(candidate)
+        })
+    })
+}
+
+/// Resolve a command from an editor entry to an executable that exists
+fn resolve(command: &str) -> Option<PathBuf> {
+    let command = expand_env(command);
+    let path = Path::new(&command);
+    if path.components().count() > 1 {
+        path.is_file().then(|| path.to_path_buf())
+    } else {
+        find_in_path(&command)
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn mac_app_exists(app: &str) -> bool {
+    Command::new("open")
+        .args(["-Ra", app])
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+impl EditorEntry {
+    /// Whether any of the entry's launch methods works on this machine
+    pub fn is_available(&self) -> bool {
+        #[cfg(target_os = "macos")]
+        if self.mac_app.as_deref().is_some_and(mac_app_exists) {
+            return true;
+        }
+        self.commands.iter().any(|command| resolve(command).is_some())
+    }
+
+    /// Open `file` with the first launch method that works
+    fn launch(&self, file: &Path) -> Result<(), String> {
+        #[cfg(target_os = "macos")]
+        if let Some(app) = &self.mac_app {
+            let status = Command::new("open").arg("-a").arg(app).arg(file).status();
+            if status.map(|status| status.success()).unwrap_or(false) {
+                return Ok(());
+            }
+        }
+        for command in &self.commands {
+            if let Some(program) = resolve(command) {
+                if Command::new(&program).arg(file).spawn().is_ok() {
+                    return Ok(());
+                }
+            }
+        }
+        Err(format!("{} was not found on this system", self.name))
+    }
+}
+
+/// Show Windows' native "open with" chooser for `file`
+#[cfg(windows)]
+pub fn open_with_dialog(file: &Path) -> Result<(), String> {
+    Command::new("rundll32.exe")
+        .arg("shell32.dll,OpenAs_RunDLL")
+        .arg(file)
+        .spawn()
+        .map_err(|e| format!("Failed to open system dialog: {}", e))?;
+    Ok(())
+}
+
+/// Let the user pick an application for `file` with `choose application`
+#[cfg(target_os = "macos")]
+pub fn open_with_dialog(file: &Path) -> Result<(), String> {
+    let output = Command::new("osascript")
+        .args(["-e", "POSIX path of (choose application with prompt \"Open with\")"])
+        .output()
+        .map_err(|e| format!("Failed to open system dialog: {}", e))?;
+    // Cancelling the chooser makes osascript fail
+    if !output.status.success() {
+        return Ok(());
+    }
+    let app = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Command::new("open")
+        .arg("-a")
+        .arg(&app)
+        .arg(file)
+        .spawn()
+        .map_err(|e| format!("Failed to open with {}: {}", app, e))?;
+    Ok(())
+}
+
+/// Let the user pick a program for `file` with zenity or kdialog; Linux has
+/// no common "open with" dialog
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn open_with_dialog(file: &Path) -> Result<(), String> {
+    let choosers: [(&str, &[&str]); 2] = [
+        ("zenity", &["--file-selection", "--title=Open with", "--filename=/usr/bin/"]),
+        ("kdialog", &["--title", "Open with", "--getopenfilename", "/usr/bin"]),
+    ];
+    let (program, args) = choosers
+        .iter()
+        .find(|(program, _)| find_in_path(program).is_some())
+        .ok_or("No application chooser found; install zenity or kdialog")?;
+    let output = Command::new(program)
+        .args(*args)
+        .output()
+        .map_err(|e| format!("Failed to open application chooser: {}", e))?;
+    // Cancelled
+    if !output.status.success() {
+        return Ok(());
+    }
+    let chosen = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Command::new(&chosen)
+        .arg(file)
+        .spawn()
+        .map_err(|e| format!("Failed to launch {}: {}", chosen, e))?;
+    Ok(())
+}
+
+/// Open `file` with `editor`: `None` for the system default, an id from
+/// `table`, `OPEN_WITH_DIALOG`, or a command line of its own (a path to an
+/// executable, or a program and arguments separated by spaces)
+pub fn open_file(editor: Option<&str>, file: &Path, table: &[EditorEntry]) -> Result<(), String> {
+    let Some(editor) = editor else {
+        return opener::open(file).map_err(|e| format!("Failed to open file: {}", e));
+    };
+    if editor == OPEN_WITH_DIALOG {
+        return open_with_dialog(file);
+    }
+    if let Some(entry) = table.iter().find(|entry| entry.id == editor) {
+        return entry.launch(file);
+    }
+
+    // A custom path may contain spaces, so try it whole first
+    let (program, args) = match resolve(editor) {
+        Some(program) => (program, Vec::new()),
+        None => {
+            let mut words = editor.split_whitespace();
+            let first = words.next().ok_or("No editor given")?;
+            let program =
+                resolve(first).ok_or_else(|| format!("Editor '{}' was not found", first))?;
+            (program, words.collect())
+        }
+    };
+    Command::new(&program)
+        .args(args)
+        .arg(file)
+        .spawn()
+        .map_err(|e| format!("Failed to launch editor '{}': {}", editor, e))?;
+    Ok(())
+}
@@ -0,0 +1,445 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::connection::{ImportedHost, ParsedImport};
+
+/// An export file's text; Windows tools don't always write valid UTF-8
+pub fn read_export(path: &Path) -> Result<String, String> {
+    let data =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// Decode `%XX` escapes, as WinSCP writes session names and paths
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Undo WinSCP's password obfuscation (not encryption: the key is the user
+/// and host name). `None` if it doesn't decode.
+fn winscp_password(encoded: &str, username: &str, host: &str) -> Option<String> {
+    const MAGIC: u8 = 0xA3;
+    const FLAG: u8 = 0xFF;
+    let mut digits = encoded.bytes().map(|b| (b as char).to_digit(16));
+    let mut next = || -> Option<u8> {
+        let high = digits.next()??;
+        let low = digits.next()??;
+        Some(!(((high << 4) + low) as u8 ^ MAGIC))
+    };
+    let flag = next()?;
+    let length = if flag == FLAG {
+        next()?;
+        next()?
+    } else {
+        flag
+    };
+    let skip = next()?;
+    for _ in 0..skip {
+        next()?;
+    }
+    let bytes: Vec<u8> = (0..length).map(|_| next()).collect::<Option<_>>()?;
+    let decoded = String::from_utf8_lossy(&bytes).into_owned();
+    if flag == FLAG {
+        let key = format!("{}{}", username, host);
+        decoded.strip_prefix(&key).map(str::to_string)
+    } else {
+        Some(decoded)
+    }
+}
+
+/// Sites from a WinSCP.ini configuration export
+pub fn winscp(data: &str) -> Result<ParsedImport, String> {
+    let mut sections: Vec<(String, HashMap<String, String>)> = Vec::new();
+    for line in data.lines() {
+        let line = line.trim();
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push((section.to_string(), HashMap::new()));
+        } else if let (Some((key, value)), Some((_, values))) =
+            (line.split_once('='), sections.last_mut())
+        {
+            values.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+
+    let mut parsed = ParsedImport::default();
+    for (section, values) in sections {
+        let Some(name) = section.strip_prefix("Sessions\\") else {
+            continue;
+        };
+        let name = percent_decode(name);
+        if name == "Default Settings" {
+            continue;
+        }
+        let get = |key: &str| values.get(key).map(|v| percent_decode(v));
+        // 0 and 1 are SCP, 2 (the default) SFTP; the rest are FTP, WebDAV and S3
+        let protocol = get("FSProtocol").unwrap_or_else(|| "2".to_string());
+        if !matches!(protocol.as_str(), "0" | "1" | "2") {
+            parsed.skipped.push(format!("{} (not an SSH site)", name));
+            continue;
+        }
+        let Some(host) = get("HostName").filter(|h| !h.is_empty()) else {
+            parsed.skipped.push(format!("{} (no host name)", name));
+            continue;
+        };
+        let username = get("UserName").unwrap_or_default();
+        let mut warnings = Vec::new();
+        let password = get("Password").and_then(|encoded| {
+            let password = winscp_password(&encoded, &username, &host);
+            if password.is_none() {
+                warnings.push("The saved password could not be read".to_string());
+            }
+            password
+        });
+        if get("Tunnel").as_deref() == Some("1") {
+            warnings.push("The SSH tunnel setting is not imported".to_string());
+        }
+        if get("ProxyMethod").is_some_and(|m| m != "0") {
+            warnings.push("The proxy setting is not imported".to_string());
+        }
+        parsed.hosts.push(ImportedHost {
+            port: get("PortNumber").and_then(|p| p.parse().ok()).unwrap_or(22),
+            private_key_path: get("PublicKeyFile").filter(|p| !p.is_empty()),
+            name,
+            host,
+            username,
+            password,
+            jump_hosts: Vec::new(),
+            warnings,
+        });
+    }
+    Ok(parsed)
+}
+
+/// Split one CSV record, honouring quotes and doubled quotes
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Hosts from a Termius CSV export. Columns are found by their header, so
+/// older and newer exports both work.
+pub fn termius(data: &str) -> Result<ParsedImport, String> {
+    let mut lines = data.lines().filter(|l| !l.trim().is_empty());
+    let header: Vec<String> = csv_fields(lines.next().ok_or("The file is empty")?)
+        .iter()
+        .map(|h| h.trim().trim_start_matches('\u{feff}').to_lowercase())
+        .collect();
+    let column = |names: &[&str]| header.iter().position(|h| names.contains(&h.as_str()));
+    let host_col = column(&["hostname/ip", "hostname", "host", "address", "ip"])
+        .ok_or("No host column found; is this a Termius CSV export?")?;
+    let name_col = column(&["label", "name", "alias"]);
+    let port_col = column(&["port"]);
+    let user_col = column(&["username", "user"]);
+    let password_col = column(&["password"]);
+    let protocol_col = column(&["protocol"]);
+    let key_col = column(&["ssh_key", "ssh key", "key"]);
+
+    let mut parsed = ParsedImport::default();
+    for line in lines {
+        let fields = csv_fields(line);
+        let field = |col: Option<usize>| {
+            col.and_then(|i| fields.get(i))
+                .map(|f| f.trim().to_string())
+                .filter(|f| !f.is_empty())
+        };
+        let Some(host) = field(Some(host_col)) else {
+            continue;
+        };
+        let name = field(name_col).unwrap_or_else(|| host.clone());
+        if field(protocol_col).is_some_and(|p| !p.eq_ignore_ascii_case("ssh")) {
+            parsed.skipped.push(format!("{} (not an SSH host)", name));
+            continue;
+        }
+        let mut warnings = Vec::new();
+        if field(key_col).is_some() {
+            // Termius exports the key's name, not a file
+            warnings.push("The SSH key is not imported; pick the key file".to_string());
+        }
+        parsed.hosts.push(ImportedHost {
+            name,
+            host,
+            port: field(port_col).and_then(|p| p.parse().ok()).unwrap_or(22),
+            username: field(user_col).unwrap_or_default(),
+            password: field(password_col),
+            private_key_path: None,
+            jump_hosts: Vec::new(),
+            warnings,
+        });
+    }
+    Ok(parsed)
+}
+
+/// Replace the five predefined XML entities and numeric references
+fn xml_unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(|dec| dec.parse()))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Attributes of an XML start tag, `tag` being the text after its name
+fn xml_attributes(tag: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut rest = tag;
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq].trim().to_string();
+        let after = rest[eq + 1..].trim_start();
+        let Some(quote) = after.chars().next().filter(|q| *q == '"' || *q == '\'') else {
+            break;
+        };
+        let Some(len) = after[1..].find(quote) else {
+            break;
+        };
+        attributes.insert(name, xml_unescape(&after[1..1 + len]));
+        rest = &after[len + 2..];
+    }
+    attributes
+}
+
+/// Connections from an mRemoteNG confCons.xml
+pub fn mremoteng(data: &str) -> Result<ParsedImport, String> {
+    if let Some(start) = data.find("<Connections") {
+        let end = data[start..].find('>').map_or(data.len(), |e| start + e);
+        let root = xml_attributes(&data[start + "<Connections".len()..end]);
+        if root.get("FullFileEncryption").map(String::as_str) == Some("true") {
+            return Err("The file is fully encrypted; export it from mRemoteNG without \
+                        full file encryption"
+                .to_string());
+        }
+    } else {
+        return Err("No connections found; is this an mRemoteNG confCons.xml?".to_string());
+    }
+
+    let mut parsed = ParsedImport::default();
+    for node in data.split("<Node").skip(1) {
+        let end = node.find('>').unwrap_or(node.len());
+        let attributes = xml_attributes(node[..end].trim_end_matches('/'));
+        if attributes.get("Type").map(String::as_str) != Some("Connection") {
+            continue;
+        }
+        let get = |key: &str| attributes.get(key).filter(|v| !v.is_empty()).cloned();
+        let name = get("Name").unwrap_or_default();
+        let protocol = get("Protocol").unwrap_or_default();
+        if protocol != "SSH1" && protocol != "SSH2" {
+            parsed.skipped.push(format!("{} ({})", name, protocol));
+            continue;
+        }
+        let Some(host) = get("Hostname") else {
+            parsed.skipped.push(format!("{} (no host name)", name));
+            continue;
+        };
+        let mut warnings = Vec::new();
+        if protocol == "SSH1" {
+            warnings.push("SSH-1 is not supported; SSH-2 will be used".to_string());
+        }
+        if get("Password").is_some() {
+            // Encrypted with the user's mRemoteNG master password
+            warnings.push("The saved password is not imported".to_string());
+        }
+        parsed.hosts.push(ImportedHost {
+            name: if name.is_empty() { host.clone() } else { name },
+            host,
+            port: get("Port").and_then(|p| p.parse().ok()).unwrap_or(22),
+            username: get("Username").unwrap_or_default(),
+            password: None,
+            private_key_path: None,
+            jump_hosts: Vec::new(),
+            warnings,
+        });
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The inverse of `winscp_password`, in the form WinSCP writes
+    fn winscp_encode(password: &str, username: &str, host: &str) -> String {
+        let secret = format!("{}{}{}", username, host, password);
+        let mut bytes = vec![0xFF, 0x00, secret.len() as u8, 0x00];
+        bytes.extend(secret.bytes());
+        bytes.iter().map(|b| format!("{:02X}", !b ^ 0xA3)).collect()
+    }
+
+    #[test]
+    fn percent_decode_leaves_bad_escapes() {
+        assert_eq!(percent_decode("a%20b%zz%4"), "a b%zz%4");
+    }
+
+    #[test]
+    fn winscp_password_round_trips() {
+        let encoded = winscp_encode("s3cret", "alice", "example.com");
+        assert_eq!(
+            winscp_password(&encoded, "alice", "example.com").as_deref(),
+            Some("s3cret")
+        );
+        assert_eq!(winscp_password(&encoded, "bob", "example.com"), None);
+        assert_eq!(winscp_password("zz", "alice", "example.com"), None);
+    }
+
+    #[test]
+    fn winscp_sites() {
+        let data = format!(
+            "[Sessions\\My%20Server]\n\
+             HostName=example.com\n\
+             UserName=alice\n\
+             PortNumber=2222\n\
+             Password={}\n\
+             Tunnel=1\n\
+             [Sessions\\Default%20Settings]\n\
+             HostName=defaults.example.com\n\
+             [Sessions\\Ftp]\n\
+             HostName=ftp.example.com\n\
+             FSProtocol=5\n\
+             [Sessions\\Empty]\n\
+             UserName=bob\n",
+            winscp_encode("s3cret", "alice", "example.com")
+        );
+        let parsed = winscp(&data).unwrap();
+        assert_eq!(parsed.hosts.len(), 1);
+        let host = &parsed.hosts[0];
+        assert_eq!(host.name, "My Server");
+        assert_eq!(host.host, "example.com");
+        assert_eq!(host.port, 2222);
+        assert_eq!(host.username, "alice");
+        assert_eq!(host.password.as_deref(), Some("s3cret"));
+        assert_eq!(host.warnings, vec!["The SSH tunnel setting is not imported"]);
+        assert_eq!(parsed.skipped, vec!["Ftp (not an SSH site)", "Empty (no host name)"]);
+    }
+
+    #[test]
+    fn csv_fields_handle_quotes() {
+        assert_eq!(csv_fields(r#"a,"b, c","d""e",,"#), vec!["a", "b, c", "d\"e", "", ""]);
+    }
+
+    #[test]
+    fn termius_hosts() {
+        let data = "\u{feff}Label,Hostname/IP,Port,Username,Password,Protocol,SSH Key\n\
+                    \"Web, prod\",web.example.com,2200,deploy,\"pa\"\"ss\",ssh,\n\
+                    db,db.example.com,,,,SSH,mykey\n\
+                    tel,10.0.0.1,23,,,telnet,\n\
+                    \n\
+                    nohost,,22,,,ssh,\n";
+        let parsed = termius(data).unwrap();
+        assert_eq!(parsed.hosts.len(), 2);
+        let web = &parsed.hosts[0];
+        assert_eq!(web.name, "Web, prod");
+        assert_eq!(web.port, 2200);
+        assert_eq!(web.username, "deploy");
+        assert_eq!(web.password.as_deref(), Some("pa\"ss"));
+        assert!(web.warnings.is_empty());
+        let db = &parsed.hosts[1];
+        assert_eq!(db.port, 22);
+        assert_eq!(db.password, None);
+        assert_eq!(db.warnings.len(), 1);
+        assert_eq!(parsed.skipped, vec!["tel (not an SSH host)"]);
+    }
+
+    #[test]
+    fn termius_needs_a_host_column() {
+        assert!(termius("Label,Port\nweb,22\n").is_err());
+        assert!(termius("").is_err());
+    }
+
+    #[test]
+    fn xml_unescape_entities() {
+        assert_eq!(xml_unescape("&lt;a&gt; &amp; &#65;&#x42; &bogus; &"), "<a> & AB &bogus; &");
+    }
+
+    #[test]
+    fn mremoteng_connections() {
+        let data = r#"<?xml version="1.0" encoding="utf-8"?>
+<Connections Name="Connections" FullFileEncryption="false">
+    <Node Name="Folder" Type="Container">
+        <Node Name="R&amp;D box" Type="Connection" Protocol="SSH2" Hostname="rd.example.com"
+              Port="2022" Username="dev" Password="abc" />
+        <Node Name="Windows" Type="Connection" Protocol="RDP" Hostname="win" />
+        <Node Name="old" Type="Connection" Protocol="SSH1" Hostname="old.example.com" />
+    </Node>
+</Connections>"#;
+        let parsed = mremoteng(data).unwrap();
+        assert_eq!(parsed.hosts.len(), 2);
+        let rd = &parsed.hosts[0];
+        assert_eq!(rd.name, "R&D box");
+        assert_eq!(rd.host, "rd.example.com");
+        assert_eq!(rd.port, 2022);
+        assert_eq!(rd.username, "dev");
+        assert_eq!(rd.password, None);
+        assert_eq!(rd.warnings, vec!["The saved password is not imported"]);
+        assert_eq!(parsed.hosts[1].warnings.len(), 1);
+        assert_eq!(parsed.skipped, vec!["Windows (RDP)"]);
+    }
+
+    #[test]
+    fn mremoteng_refuses_encrypted_files() {
+        let data = r#"<Connections Name="Connections" FullFileEncryption="true">abc</Connections>"#;
+        assert!(mremoteng(data).is_err());
+        assert!(mremoteng("<Other />").is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use crate::connection::Connection;
+
+/// Service the entries are filed under in Credential Manager, Keychain or
+/// the Secret Service
+const SERVICE: &str = "rustssh";
+const PASSWORD: &str = "password";
+const PASSPHRASE: &str = "passphrase";
+const PROXY_PASSWORD: &str = "proxy-password";
+
+fn entry(reference: &str, kind: &str) -> Result<keyring::Entry, String> {
+    keyring::Entry::new(SERVICE, &format!("{}:{}", reference, kind))
+        .map_err(|e| format!("Failed to open keychain entry: {}", e))
+}
+
+fn get(reference: &str, kind: &str) -> Result<Option<String>, String> {
+    match entry(reference, kind)?.get_password() {
+        Ok(secret) => Ok(Some(secret)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(format!("Failed to read {} from keychain: {}", kind, e)),
+    }
+}
+
+fn set(reference: &str, kind: &str, secret: &str) -> Result<(), String> {
+    entry(reference, kind)?
+        .set_password(secret)
+        .map_err(|e| format!("Failed to save {} to keychain: {}", kind, e))
+}
+
+fn remove(reference: &str, kind: &str) -> Result<(), String> {
+    match entry(reference, kind)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(format!("Failed to delete {} from keychain: {}", kind, e)),
+    }
+}
+
+/// Move `conn`'s password, passphrase and proxy password into the keychain,
+/// leaving only `secret_ref` on the profile. Secrets not given keep what's
+/// stored. Blocks on the platform's keychain.
+pub fn store_secrets(conn: &mut Connection) -> Result<(), String> {
+    let reference = conn.secret_ref.clone().unwrap_or_else(|| conn.id.clone());
+    if let Some(password) = conn.password.take().filter(|p| !p.is_empty()) {
+        set(&reference, PASSWORD, &password)?;
+    }
+    if let Some(passphrase) = conn.passphrase.take().filter(|p| !p.is_empty()) {
+        set(&reference, PASSPHRASE, &passphrase)?;
+    }
+    let proxy_password = conn.proxy.as_mut().and_then(|proxy| proxy.password.take());
+    if let Some(password) = proxy_password.filter(|p| !p.is_empty()) {
+        set(&reference, PROXY_PASSWORD, &password)?;
+    }
+    conn.secret_ref = Some(reference);
+    Ok(())
+}
+
+/// Move `conn`'s secrets out of the keychain back onto the profile, unless
+/// new ones were given. Blocks on the platform's keychain.
+pub fn take_secrets(conn: &mut Connection) -> Result<(), String> {
+    let Some(reference) = conn.secret_ref.take() else {
+        return Ok(());
+    };
+    if conn.password.is_none() {
+        conn.password = get(&reference, PASSWORD)?;
+    }
+    if conn.passphrase.is_none() {
+        conn.passphrase = get(&reference, PASSPHRASE)?;
+    }
+    if let Some(proxy) = conn.proxy.as_mut().filter(|proxy| proxy.password.is_none()) {
+        proxy.password = get(&reference, PROXY_PASSWORD)?;
+    }
+    forget_secrets(&reference)
+}
+
+/// Delete a profile's secrets from the keychain. Blocks on the platform's
+/// keychain.
+pub fn forget_secrets(reference: &str) -> Result<(), String> {
+    remove(reference, PASSWORD)?;
+    remove(reference, PASSPHRASE)?;
+    remove(reference, PROXY_PASSWORD)
+}
+
+/// Fill in the secrets `conn` keeps in the keychain. Ones that can't be
+/// read are left unset, so the user is prompted for them instead.
+pub async fn resolve(mut conn: Connection) -> Connection {
+    let Some(reference) = conn.secret_ref.clone() else {
+        return conn;
+    };
+    let wants_proxy = conn.proxy.is_some();
+    let lookup = tokio::task::spawn_blocking(move || {
+        let proxy_password = if wants_proxy {
+            get(&reference, PROXY_PASSWORD)?
+        } else {
+            None
+        };
+        Ok::<_, String>((
+            get(&reference, PASSWORD)?,
+            get(&reference, PASSPHRASE)?,
+            proxy_password,
+        ))
+    })
+    .await
+    .map_err(|e| e.to_string());
+    match lookup {
+        Ok(Ok((password, passphrase, proxy_password))) => {
+            conn.password = conn.password.or(password);
+            conn.passphrase = conn.passphrase.or(passphrase);
+            if let Some(proxy) = conn.proxy.as_mut() {
+                proxy.password = proxy.password.take().or(proxy_password);
+            }
+        }
+        Ok(Err(e)) | Err(e) => log::warn!("{}", e),
+    }
+    conn
+}
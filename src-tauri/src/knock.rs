@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpStream, UdpSocket};
+
+/// How long a TCP knock waits for the SYN to go out before moving on
+const TCP_KNOCK_TIMEOUT_MS: u64 = 300;
+
+fn default_knock_delay_ms() -> u64 {
+    200
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum KnockProtocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+/// One port in a port-knocking sequence
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnockStep {
+    pub port: u16,
+    #[serde(default)]
+    pub protocol: KnockProtocol,
+    /// Pause after this knock before the next one (or before dialing sshd)
+    #[serde(default = "default_knock_delay_ms")]
+    pub delay_ms: u64,
+}
+
+/// Knock on each port of `sequence` at `host`, in order. Knocks are expected
+/// to go unanswered, so only failing to resolve the host is an error.
+pub async fn knock(host: &str, sequence: &[KnockStep]) -> Result<(), String> {
+    if sequence.is_empty() {
+        return Ok(());
+    }
+
+    // Every knock must reach the same address for the firewall to match them
+    let addr = tokio::net::lookup_host((host, 0))
+        .await
+        .map_err(|e| format!("Failed to resolve {}: {}", host, e))?
+        .next()
+        .ok_or_else(|| format!("No addresses found for {}", host))?;
+
+    for step in sequence {
+        let target = SocketAddr::new(addr.ip(), step.port);
+        match step.protocol {
+            KnockProtocol::Tcp => {
+                let _ = tokio::time::timeout(
+                    Duration::from_millis(TCP_KNOCK_TIMEOUT_MS),
+                    TcpStream::connect(target),
+                )
+                .await;
+            }
+            KnockProtocol::Udp => {
+                let local = if target.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+                if let Ok(socket) = UdpSocket::bind(local).await {
+                    let _ = socket.send_to(&[0], target).await;
+                }
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(step.delay_ms)).await;
+    }
+
+    Ok(())
+}
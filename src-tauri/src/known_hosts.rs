@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct KnownHost {
+    pub host: String,
+    pub port: u16,
+    pub key_type: String,
+    pub fingerprint: String,
+}
+
+/// Result of looking up a server key in the store
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostKeyStatus {
+    Trusted,
+    Unknown,
+    Changed { previous_fingerprint: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct KnownHostsStore {
+    file_path: PathBuf,
+}
+
+impl KnownHostsStore {
+    pub fn new(app_data_dir: PathBuf) -> Self {
+        let file_path = app_data_dir.join("known_hosts.json");
+        Self { file_path }
+    }
+
+    pub fn load(&self) -> Vec<KnownHost> {
+        if !self.file_path.exists() {
+            return Vec::new();
+        }
+        match fs::read_to_string(&self.file_path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_default(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn save(&self, hosts: &[KnownHost]) -> Result<(), String> {
+        if let Some(parent) = self.file_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let data = serde_json::to_string_pretty(hosts).map_err(|e| e.to_string())?;
+        fs::write(&self.file_path, data).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    pub fn check(&self, host: &str, port: u16, fingerprint: &str) -> HostKeyStatus {
+        match self
+            .load()
+            .into_iter()
+            .find(|h| h.host == host && h.port == port)
+        {
+            Some(known) if known.fingerprint == fingerprint => HostKeyStatus::Trusted,
+            Some(known) => HostKeyStatus::Changed {
+                previous_fingerprint: known.fingerprint,
+            },
+            None => HostKeyStatus::Unknown,
+        }
+    }
+
+    /// Whether any key is trusted for `host` and `port`
+    pub fn is_known(&self, host: &str, port: u16) -> bool {
+        self.load().iter().any(|h| h.host == host && h.port == port)
+    }
+
+    /// Trust a key, replacing any previous entry for the same host and port
+    pub fn trust(&self, entry: KnownHost) -> Result<Vec<KnownHost>, String> {
+        let mut hosts = self.load();
+        hosts.retain(|h| !(h.host == entry.host && h.port == entry.port));
+        hosts.push(entry);
+        self.save(&hosts)?;
+        Ok(hosts)
+    }
+
+    pub fn remove(&self, host: &str, port: u16) -> Result<Vec<KnownHost>, String> {
+        let mut hosts = self.load();
+        hosts.retain(|h| !(h.host == host && h.port == port));
+        self.save(&hosts)?;
+        Ok(hosts)
+    }
+}
@@ -1,4 +1,28 @@
+pub mod channels;
 pub mod commands;
 pub mod connection;
+pub mod delta;
+pub mod dirsync;
+pub mod diff;
+pub mod editor;
+pub mod import;
+pub mod keychain;
+pub mod knock;
+pub mod mime;
+pub mod known_hosts;
+pub mod mosh;
+pub mod ppk;
+pub mod proxy;
+pub mod pty;
 pub mod sftp;
 pub mod ssh;
+pub mod ssh_config;
+pub mod telnet;
+pub mod terminal;
+pub mod transfer;
+pub mod trash;
+pub mod tunnel;
+pub mod vault;
+pub mod vpn;
+pub mod watch;
+pub mod wol;
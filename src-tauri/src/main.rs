@@ -3,6 +3,8 @@
 use std::sync::Arc;
 use rustssh::commands;
 use rustssh::ssh::SessionManager;
+use tauri::Emitter;
+use tauri_plugin_deep_link::DeepLinkExt;
 
 
 fn log_path() -> std::path::PathBuf {
@@ -27,8 +29,20 @@ fn main() {
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_os::init())
+        .plugin(tauri_plugin_deep_link::init())
         .manage(session_manager)
-        .setup(|_app| {
+        .setup(|app| {
+            // Installers register the ssh:// scheme; do it at runtime for dev builds
+            #[cfg(all(debug_assertions, any(windows, target_os = "linux")))]
+            app.deep_link().register_all()?;
+
+            let handle = app.handle().clone();
+            app.deep_link().on_open_url(move |event| {
+                let urls: Vec<String> = event.urls().iter().map(|u| u.to_string()).collect();
+                let _ = handle.emit("open-url", serde_json::json!({ "urls": urls }));
+            });
+
+            tauri::async_runtime::spawn(commands::prewarm_connections(app.handle().clone()));
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -49,6 +63,105 @@ fn main() {
             commands::sftp_chmod,
             commands::sftp_edit_file,
             commands::sftp_watch_file,
+            commands::ssh_host_key_respond,
+            commands::get_known_hosts,
+            commands::delete_known_host,
+            commands::ssh_auth_respond,
+            commands::ssh_connect_cancel,
+            commands::ssh_exec,
+            commands::ssh_open_channel,
+            commands::ssh_close_channel,
+            commands::ssh_send_signal,
+            commands::ssh_get_latency,
+            commands::ssh_credential_respond,
+            commands::ssh_quick_connect,
+            commands::open_ssh_url,
+            commands::get_launch_urls,
+            commands::ssh_probe_host,
+            commands::tunnel_create_remote,
+            commands::tunnel_list,
+            commands::tunnel_close,
+            commands::tunnel_create_local,
+            commands::tunnel_create_dynamic,
+            commands::ssh_exec_stream,
+            commands::ssh_exec_cancel,
+            commands::ssh_exec_multi,
+            commands::ssh_list_channels,
+            commands::ssh_kill_channel,
+            commands::ssh_wake_and_connect,
+            commands::get_tunnel_profiles,
+            commands::save_tunnel_profile,
+            commands::delete_tunnel_profile,
+            commands::tunnel_apply_profile,
+            commands::tunnel_open_web,
+            commands::vpn_start,
+            commands::vpn_stop,
+            commands::vpn_status,
+            commands::mosh_connect,
+            commands::term_write,
+            commands::term_resize,
+            commands::term_disconnect,
+            commands::local_open,
+            commands::telnet_connect,
+            commands::sftp_cancel_transfer,
+            commands::sftp_download_dir,
+            commands::transfer_enqueue,
+            commands::transfer_list,
+            commands::transfer_pause,
+            commands::transfer_resume,
+            commands::transfer_reorder,
+            commands::sftp_stat,
+            commands::sftp_symlink,
+            commands::sftp_find,
+            commands::sftp_find_cancel,
+            commands::sftp_delete_batch,
+            commands::sftp_chmod_batch,
+            commands::sftp_move_batch,
+            commands::sftp_download_batch,
+            commands::sftp_copy,
+            commands::sftp_transfer_between,
+            commands::sftp_chown,
+            commands::sftp_dir_size,
+            commands::sftp_read_file,
+            commands::sftp_write_file,
+            commands::sftp_read_range,
+            commands::sftp_tail,
+            commands::sftp_tail_stop,
+            commands::transfer_conflict_respond,
+            commands::sftp_download_compressed,
+            commands::remote_extract,
+            commands::sftp_duplicate,
+            commands::sftp_list_stream,
+            commands::sftp_statvfs,
+            commands::trash_list,
+            commands::trash_restore,
+            commands::trash_empty,
+            commands::sftp_upload_paths,
+            commands::sync_dirs,
+            commands::transfer_get_retry_policy,
+            commands::transfer_set_retry_policy,
+            commands::transfer_pending,
+            commands::transfer_restore,
+            commands::transfer_discard_pending,
+            commands::editor_list,
+            commands::editor_save,
+            commands::sftp_unwatch_file,
+            commands::sftp_list_watches,
+            commands::sftp_edit_resolve,
+            commands::sftp_edit_diff,
+            commands::sftp_diff,
+            commands::vault_status,
+            commands::vault_unlock,
+            commands::vault_set_password,
+            commands::vault_remove_password,
+            commands::connections_import_preview,
+            commands::connections_import,
+            commands::connections_export_bundle,
+            commands::connections_import_bundle,
+            commands::search_connections,
+            commands::connections_import_bundle_preview,
+            commands::ssh_config_preview,
+            commands::ssh_config_import,
         ])
         .run(tauri::generate_context!());
 
@@ -0,0 +1,205 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use crate::channels::ChannelRegistry;
+use crate::sftp::{FileEntry, COMMAND_PROBE_TIMEOUT_SECS};
+use crate::ssh::{self, ClientHandler};
+
+/// Most files a single `file` run is asked about
+const MAX_PROBE_FILES: usize = 200;
+/// Directories whose types `MimeCache` keeps
+const MAX_CACHED_DIRS: usize = 64;
+
+/// Types by lowercase extension, for the files where that's reliable enough
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("txt", "text/plain"),
+    ("log", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("ini", "text/plain"),
+    ("cfg", "text/plain"),
+    ("conf", "text/plain"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("xml", "application/xml"),
+    ("json", "application/json"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("toml", "application/toml"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("ts", "text/x-typescript"),
+    ("py", "text/x-python"),
+    ("rs", "text/x-rust"),
+    ("go", "text/x-go"),
+    ("java", "text/x-java"),
+    ("c", "text/x-c"),
+    ("h", "text/x-c"),
+    ("cpp", "text/x-c++"),
+    ("hpp", "text/x-c++"),
+    ("sh", "application/x-sh"),
+    ("bash", "application/x-sh"),
+    ("php", "application/x-php"),
+    ("rb", "text/x-ruby"),
+    ("sql", "application/sql"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("bmp", "image/bmp"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("tar", "application/x-tar"),
+    ("gz", "application/gzip"),
+    ("tgz", "application/gzip"),
+    ("bz2", "application/x-bzip2"),
+    ("xz", "application/x-xz"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/vnd.rar"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+    ("mkv", "video/x-matroska"),
+    ("webm", "video/webm"),
+];
+
+/// The type `name`'s extension implies, if it's a known one
+pub fn from_extension(name: &str) -> Option<&'static str> {
+    let (_, ext) = name.rsplit_once('.')?;
+    let ext = ext.to_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(known, _)| *known == ext)
+        .map(|(_, mime)| *mime)
+}
+
+/// Types the server's `file` reported, per session. Kept for the
+/// `MAX_CACHED_DIRS` directories listed most recently, so a long session
+/// doesn't collect the types of every file it ever saw.
+#[derive(Debug, Default)]
+pub struct MimeCache {
+    /// Type by file name, with the mtime it was found for, per directory
+    dirs: HashMap<String, HashMap<String, (Option<u64>, String)>>,
+    /// Directories in `dirs`, least recently used first
+    order: VecDeque<String>,
+    /// Set once the server turned out not to have `file`, or not to answer
+    /// commands at all
+    no_file_command: bool,
+}
+
+impl MimeCache {
+    /// The cached types of `dir`, which becomes the most recently used
+    fn dir(&mut self, dir: &str) -> &mut HashMap<String, (Option<u64>, String)> {
+        if let Some(i) = self.order.iter().position(|known| known == dir) {
+            let dir = self.order.remove(i).unwrap_or_default();
+            self.order.push_back(dir);
+        } else {
+            self.order.push_back(dir.to_string());
+            if self.order.len() > MAX_CACHED_DIRS {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.dirs.remove(&oldest);
+                }
+            }
+        }
+        self.dirs.entry(dir.to_string()).or_default()
+    }
+}
+
+/// Directory and file name of `path`
+fn split_path(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+/// Set `mime` on entries from what's certain without asking the server:
+/// directories, empty files and known extensions
+pub fn detect_local(entries: &mut [FileEntry]) {
+    for entry in entries {
+        entry.mime = if entry.is_dir {
+            Some("inode/directory".to_string())
+        } else if entry.size == 0 && !entry.is_symlink {
+            Some("inode/x-empty".to_string())
+        } else {
+            from_extension(&entry.name).map(str::to_string)
+        };
+    }
+}
+
+/// Set `mime` on entries: `detect_local` first, then the cache, then the
+/// server's `file --mime-type` for the files still unknown. The cache is
+/// only locked around lookups, never while `file` runs.
+pub async fn detect(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    cache: &Mutex<MimeCache>,
+    entries: &mut [FileEntry],
+) {
+    detect_local(entries);
+    let mut unknown = Vec::new();
+    {
+        let mut cache = cache.lock().await;
+        if cache.no_file_command {
+            return;
+        }
+        for (i, entry) in entries.iter_mut().enumerate() {
+            if entry.mime.is_some() {
+                continue;
+            }
+            let (dir, name) = split_path(&entry.path);
+            match cache.dir(dir).get(name) {
+                Some((modified, mime)) if *modified == entry.modified => {
+                    entry.mime = Some(mime.clone())
+                }
+                // `file` prints one line per name, so these can't be lined up
+                _ if entry.path.contains('\n') => {}
+                _ => unknown.push(i),
+            }
+        }
+    }
+    if unknown.is_empty() {
+        return;
+    }
+    unknown.truncate(MAX_PROBE_FILES);
+
+    let paths: Vec<String> = unknown
+        .iter()
+        .map(|&i| ssh::shell_quote(&entries[i].path))
+        .collect();
+    let command = format!("file -L -b --mime-type -- {}", paths.join(" "));
+    // A forced internal-sftp command would never answer, hence the timeout
+    let limit = Duration::from_secs(COMMAND_PROBE_TIMEOUT_SECS);
+    let probe = tokio::time::timeout(limit, ssh::exec(handle, channel_registry, &command));
+    let output = match probe.await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            log::warn!("Failed to detect file types: {}", e);
+            return;
+        }
+        Err(_) => {
+            log::warn!("Detecting file types timed out; not trying again");
+            cache.lock().await.no_file_command = true;
+            return;
+        }
+    };
+    let mut cache = cache.lock().await;
+    if output.exit_code == Some(127) {
+        cache.no_file_command = true;
+        return;
+    }
+    for (&i, line) in unknown.iter().zip(output.stdout.lines()) {
+        let mime = line.trim();
+        if mime.is_empty() || mime.contains(' ') {
+            continue;
+        }
+        let entry = &mut entries[i];
+        entry.mime = Some(mime.to_string());
+        let (dir, name) = split_path(&entry.path);
+        cache
+            .dir(dir)
+            .insert(name.to_string(), (entry.modified, mime.to_string()));
+    }
+}
@@ -0,0 +1,62 @@
+use portable_pty::CommandBuilder;
+use std::net::IpAddr;
+use tokio::sync::mpsc;
+
+use crate::channels::ChannelRegistry;
+use crate::pty::PtySession;
+use crate::ssh::{self, ClientHandler, SshEvent};
+
+/// Started on the server over SSH; prints the UDP port and session key
+const MOSH_SERVER_COMMAND: &str = "mosh-server new -s -c 256 -l LANG=en_US.UTF-8";
+
+/// Where `mosh-server` is listening, as announced by its `MOSH CONNECT` line
+#[derive(Debug, Clone)]
+pub struct MoshConnect {
+    pub port: u16,
+    pub key: String,
+}
+
+fn parse_connect(output: &str) -> Option<MoshConnect> {
+    output.lines().find_map(|line| {
+        let mut parts = line.trim().strip_prefix("MOSH CONNECT ")?.split_whitespace();
+        let port = parts.next()?.parse().ok()?;
+        let key = parts.next()?.to_string();
+        Some(MoshConnect { port, key })
+    })
+}
+
+/// Start `mosh-server` through an authenticated SSH connection
+pub async fn bootstrap(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+) -> Result<MoshConnect, String> {
+    let output = ssh::exec(handle, channel_registry, MOSH_SERVER_COMMAND).await?;
+    parse_connect(&output.stdout)
+        .or_else(|| parse_connect(&output.stderr))
+        .ok_or_else(|| {
+            let detail = output.stderr.trim();
+            if detail.is_empty() {
+                "mosh-server did not start (is mosh installed on the server?)".to_string()
+            } else {
+                format!("mosh-server did not start: {}", detail)
+            }
+        })
+}
+
+/// Run the local `mosh-client` against `server` in a PTY. mosh-client speaks
+/// the UDP state synchronisation protocol, so the session survives roaming and
+/// sleep; closing it shuts the server down too.
+pub fn spawn_client(
+    id: String,
+    server: IpAddr,
+    connect: &MoshConnect,
+    sender: mpsc::Sender<SshEvent>,
+) -> Result<PtySession, String> {
+    let mut cmd = CommandBuilder::new("mosh-client");
+    cmd.arg(server.to_string());
+    cmd.arg(connect.port.to_string());
+    cmd.env("MOSH_KEY", &connect.key);
+    cmd.env("TERM", "xterm-256color");
+    // Ctrl-^ . makes mosh-client stop the server before exiting
+    Ok(PtySession::spawn(id, cmd, sender)?.with_quit_input(b"\x1e."))
+}
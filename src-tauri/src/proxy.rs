@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ProxyType {
+    Http,
+    Socks5,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    pub proxy_type: ProxyType,
+    pub host: String,
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+/// Open a TCP stream to `host:port` tunnelled through the given proxy
+pub async fn dial(proxy: &ProxyConfig, host: &str, port: u16) -> Result<TcpStream, String> {
+    let mut stream = TcpStream::connect((proxy.host.as_str(), proxy.port))
+        .await
+        .map_err(|e| format!("Failed to connect to proxy: {}", e))?;
+
+    match proxy.proxy_type {
+        ProxyType::Http => http_connect(&mut stream, proxy, host, port).await?,
+        ProxyType::Socks5 => socks5_connect(&mut stream, proxy, host, port).await?,
+    }
+
+    Ok(stream)
+}
+
+async fn http_connect(
+    stream: &mut TcpStream,
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+) -> Result<(), String> {
+    let authority = if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if let Some(username) = &proxy.username {
+        let credentials = format!("{}:{}", username, proxy.password.as_deref().unwrap_or(""));
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64_encode(credentials.as_bytes())
+        ));
+    }
+    request.push_str("\r\n");
+
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| format!("Proxy write failed: {}", e))?;
+
+    // Read the response headers byte by byte so no tunnelled data is consumed
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8192 {
+            return Err("Proxy response headers too large".to_string());
+        }
+        let byte = stream
+            .read_u8()
+            .await
+            .map_err(|e| format!("Proxy read failed: {}", e))?;
+        response.push(byte);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or("");
+    let status = status_line.split_whitespace().nth(1).unwrap_or("");
+    if status != "200" {
+        return Err(format!("Proxy refused CONNECT: {}", status_line));
+    }
+
+    Ok(())
+}
+
+async fn socks5_connect(
+    stream: &mut TcpStream,
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+) -> Result<(), String> {
+    let io_err = |e: std::io::Error| format!("SOCKS5 proxy I/O failed: {}", e);
+
+    // Greeting: offer no-auth, plus username/password when configured
+    let greeting: &[u8] = if proxy.username.is_some() {
+        &[0x05, 0x02, 0x00, 0x02]
+    } else {
+        &[0x05, 0x01, 0x00]
+    };
+    stream.write_all(greeting).await.map_err(io_err)?;
+
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await.map_err(io_err)?;
+    match choice[1] {
+        0x00 => {}
+        0x02 => {
+            let username = proxy.username.as_deref().unwrap_or("");
+            let password = proxy.password.as_deref().unwrap_or("");
+            if username.len() > 255 || password.len() > 255 {
+                return Err("SOCKS5 credentials too long".to_string());
+            }
+            let mut auth = vec![0x01, username.len() as u8];
+            auth.extend_from_slice(username.as_bytes());
+            auth.push(password.len() as u8);
+            auth.extend_from_slice(password.as_bytes());
+            stream.write_all(&auth).await.map_err(io_err)?;
+
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await.map_err(io_err)?;
+            if status[1] != 0x00 {
+                return Err("SOCKS5 proxy authentication failed".to_string());
+            }
+        }
+        _ => return Err("SOCKS5 proxy rejected all authentication methods".to_string()),
+    }
+
+    // CONNECT by domain name so the proxy resolves the target
+    if host.len() > 255 {
+        return Err("Target host name too long for SOCKS5".to_string());
+    }
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, host.len() as u8];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await.map_err(io_err)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await.map_err(io_err)?;
+    if reply[1] != 0x00 {
+        return Err(format!("SOCKS5 proxy CONNECT failed (code {})", reply[1]));
+    }
+
+    // Skip the bound address
+    let addr_len = match reply[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => stream.read_u8().await.map_err(io_err)? as usize,
+        _ => return Err("SOCKS5 proxy sent an invalid address type".to_string()),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await.map_err(io_err)?;
+
+    Ok(())
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        out.push(TABLE[(n >> 18) as usize & 63] as char);
+        out.push(TABLE[(n >> 12) as usize & 63] as char);
+        out.push(if chunk.len() > 1 { TABLE[(n >> 6) as usize & 63] as char } else { '=' });
+        out.push(if chunk.len() > 2 { TABLE[n as usize & 63] as char } else { '=' });
+    }
+    out
+}
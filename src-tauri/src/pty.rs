@@ -0,0 +1,125 @@
+use portable_pty::{native_pty_system, Child, ChildKiller, CommandBuilder, MasterPty, PtySize};
+use std::io::{Read, Write};
+use tokio::sync::mpsc;
+
+use crate::ssh::SshEvent;
+use crate::terminal::TerminalSession;
+
+/// Time a program gets to exit after its quit input before it is killed
+const EXIT_GRACE_SECS: u64 = 2;
+
+/// A program running in a local PTY (a local shell, mosh-client, ...)
+pub struct PtySession {
+    master: Box<dyn MasterPty + Send>,
+    writer: Box<dyn Write + Send>,
+    child: Option<Box<dyn Child + Send + Sync>>,
+    /// Written on close so the program can shut down cleanly
+    quit_input: Option<&'static [u8]>,
+}
+
+impl PtySession {
+    /// Run `cmd` in a new PTY. Its output arrives as `SshEvent::Data` for
+    /// channel `id`, followed by `SshEvent::Close` when it exits.
+    pub fn spawn(
+        id: String,
+        cmd: CommandBuilder,
+        sender: mpsc::Sender<SshEvent>,
+    ) -> Result<Self, String> {
+        let pty = native_pty_system()
+            .openpty(PtySize {
+                rows: 24,
+                cols: 80,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .map_err(|e| format!("Failed to open PTY: {}", e))?;
+
+        let program = cmd.get_argv().first().cloned().unwrap_or_default();
+        let child = pty.slave.spawn_command(cmd).map_err(|e| {
+            format!("Failed to start {}: {}", program.to_string_lossy(), e)
+        })?;
+
+        let mut reader = pty
+            .master
+            .try_clone_reader()
+            .map_err(|e| format!("Failed to read from PTY: {}", e))?;
+        let writer = pty
+            .master
+            .take_writer()
+            .map_err(|e| format!("Failed to write to PTY: {}", e))?;
+
+        let channel_id = id.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 8192];
+            loop {
+                let n = match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                let event = SshEvent::Data {
+                    channel_id: channel_id.clone(),
+                    data: buf[..n].to_vec(),
+                    stderr: false,
+                };
+                if sender.blocking_send(event).is_err() {
+                    return;
+                }
+            }
+            let _ = sender.blocking_send(SshEvent::Close { reason: None });
+        });
+
+        Ok(Self {
+            master: pty.master,
+            writer,
+            child: Some(child),
+            quit_input: None,
+        })
+    }
+
+    /// Send `input` on close and give the program a moment to exit on its own
+    pub fn with_quit_input(mut self, input: &'static [u8]) -> Self {
+        self.quit_input = Some(input);
+        self
+    }
+
+}
+
+impl TerminalSession for PtySession {
+    fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        self.writer
+            .write_all(data)
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| format!("Write failed: {}", e))
+    }
+
+    fn resize(&mut self, cols: u32, rows: u32) -> Result<(), String> {
+        self.master
+            .resize(PtySize {
+                rows: rows as u16,
+                cols: cols as u16,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .map_err(|e| format!("Resize failed: {}", e))
+    }
+}
+
+impl Drop for PtySession {
+    fn drop(&mut self) {
+        let Some(mut child) = self.child.take() else {
+            return;
+        };
+        let Some(input) = self.quit_input else {
+            let _ = child.kill();
+            return;
+        };
+        let _ = self.writer.write_all(input);
+        let _ = self.writer.flush();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_secs(EXIT_GRACE_SECS));
+            if matches!(child.try_wait(), Ok(None)) {
+                let _ = child.kill();
+            }
+        });
+    }
+}
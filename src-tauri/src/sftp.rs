@@ -1,37 +1,255 @@
+use async_trait::async_trait;
+use russh_sftp::client::fs::DirEntry;
 use russh_sftp::client::SftpSession;
+use russh::ChannelId;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tokio::sync::Mutex;
 
-use crate::ssh::SshSession;
+use crate::channels::{self, ChannelKind, ChannelRegistry, CountingStream};
+use crate::delta::{self, BlockSum, DeltaOp};
+use crate::ssh::{self, ClientHandler, SshSession};
+
+/// Bytes moved per read/write call of a transfer
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Minimum time between two progress reports of one transfer
+const PROGRESS_INTERVAL_MS: u64 = 200;
+/// How long the check for a usable remote command may take
+pub const COMMAND_PROBE_TIMEOUT_SECS: u64 = 5;
+/// Largest file `read_text_file` will load into the editor
+const MAX_TEXT_FILE_SIZE: u64 = 5 * 1024 * 1024;
+/// Largest slice `read_range` returns in one call
+const MAX_RANGE_READ: u64 = 1024 * 1024;
+/// How much of the end of a file `tail` shows before following it
+const TAIL_INITIAL_BYTES: u64 = 16 * 1024;
+/// How often `tail` checks a followed file for new data
+const TAIL_POLL_MS: u64 = 1000;
+/// Entries per batch when listing a directory incrementally
+const LIST_CHUNK_SIZE: usize = 500;
+/// Most `dd` copies `upload_delta` runs in one exec
+const DELTA_COPIES_PER_EXEC: usize = 200;
+/// SFTP channels `download_parallel` reads over at once
+const PARALLEL_CHANNELS: usize = 4;
+/// Files below this size are downloaded over a single channel
+const PARALLEL_MIN_SIZE: u64 = 64 * 1024 * 1024;
+/// How long `sha256sum` may take before hashing over SFTP instead
+const CHECKSUM_EXEC_TIMEOUT_SECS: u64 = 120;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileEntry {
     pub name: String,
     pub path: String,
+    /// For a symlink, whether its target is a directory
     pub is_dir: bool,
+    pub is_symlink: bool,
+    pub link_target: Option<String>,
     pub size: u64,
     pub modified: Option<u64>,
     pub permissions: Option<u32>,
     pub uid: Option<u32>,
     pub gid: Option<u32>,
+    /// User and group names for `uid` and `gid`, where the server has them
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    /// MIME type, where it could be told (see `mime::detect`)
+    pub mime: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+/// Everything the server reports about one path
+#[derive(Debug, Clone, Serialize)]
+pub struct FileStat {
+    pub path: String,
+    pub kind: FileKind,
+    pub size: u64,
+    pub permissions: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub accessed: Option<u64>,
+    pub modified: Option<u64>,
+    /// Where a symlink points, as stored in the link
+    pub link_target: Option<String>,
+}
+
+/// Snapshot of a running transfer
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferProgress {
+    pub bytes_done: u64,
+    /// 0 when the size could not be determined up front
+    pub bytes_total: u64,
+    pub bytes_per_sec: u64,
+    pub eta_secs: Option<u64>,
+}
+
+/// How a single-file transfer should behave
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransferOptions {
+    /// Continue after whatever part of the file the destination already has
+    pub resume: bool,
+    /// Carry the modification time and permission bits over, like `scp -p`
+    pub preserve: bool,
+    /// Upload only: add to the end of an existing remote file instead of
+    /// replacing it. Takes precedence over `resume` and `atomic`.
+    pub append: bool,
+    /// Upload only: write to `<path>.part` and rename it into place once
+    /// complete, so nothing ever sees a half-written file
+    pub atomic: bool,
+}
+
+/// Counts a transfer's bytes and reports them, throttled to
+/// `PROGRESS_INTERVAL_MS`
+struct Progress<F> {
+    total: u64,
+    done: u64,
+    /// Bytes already present when a resumed transfer started; they don't
+    /// count towards the rate
+    skipped: u64,
+    started: Instant,
+    last_report: Instant,
+    report: F,
+}
+
+impl<F: FnMut(TransferProgress)> Progress<F> {
+    fn new(total: u64, report: F) -> Self {
+        let now = Instant::now();
+        Self {
+            total,
+            done: 0,
+            skipped: 0,
+            started: now,
+            last_report: now,
+            report,
+        }
+    }
+
+    /// Count bytes a resumed transfer doesn't need to move again
+    fn skip(&mut self, bytes: u64) {
+        self.done += bytes;
+        self.skipped += bytes;
+    }
+
+    fn advance(&mut self, bytes: usize) {
+        self.done += bytes as u64;
+        if self.last_report.elapsed() >= Duration::from_millis(PROGRESS_INTERVAL_MS) {
+            self.emit();
+        }
+    }
+
+    /// Always report the final count, however recent the last report was
+    fn finish(&mut self) {
+        self.emit();
+    }
+
+    fn emit(&mut self) {
+        self.last_report = Instant::now();
+        let secs = self.started.elapsed().as_secs_f64();
+        let bytes_per_sec = if secs > 0.0 {
+            ((self.done - self.skipped) as f64 / secs) as u64
+        } else {
+            0
+        };
+        let eta_secs = (bytes_per_sec > 0 && self.total > 0)
+            .then(|| self.total.saturating_sub(self.done) / bytes_per_sec);
+        (self.report)(TransferProgress {
+            bytes_done: self.done,
+            bytes_total: self.total,
+            bytes_per_sec,
+            eta_secs,
+        });
+    }
+}
+
+/// An open SFTP session with the channel it runs on
+pub struct CachedSftp {
+    channel_id: ChannelId,
+    counters: Arc<channels::ChannelCounters>,
+    sftp: Arc<SftpSession>,
+}
+
+/// What's needed to get a session's shared SFTP session, cloned out of it so
+/// the sessions lock isn't held while a new one is opened
+#[derive(Clone)]
+pub struct SftpOpener {
+    handle: Arc<russh::client::Handle<ClientHandler>>,
+    channel_registry: ChannelRegistry,
+    cached: Arc<Mutex<Option<CachedSftp>>>,
+}
+
+impl SftpOpener {
+    pub fn new(session: &SshSession) -> Self {
+        Self {
+            handle: session.handle.clone(),
+            channel_registry: session.channel_registry.clone(),
+            cached: session.sftp.clone(),
+        }
+    }
+
+    /// The shared SFTP session, opened on first use and again whenever its
+    /// channel has closed or its stream failed since
+    pub async fn get(&self) -> Result<Arc<SftpSession>, String> {
+        let mut cached = self.cached.lock().await;
+        if let Some(old) = cached.take() {
+            let open = self.channel_registry.lock().await.contains_key(&old.channel_id);
+            if open && !old.counters.ended.load(Ordering::Relaxed) {
+                let sftp = old.sftp.clone();
+                *cached = Some(old);
+                return Ok(sftp);
+            }
+            channels::unregister(&self.channel_registry, old.channel_id).await;
+        }
+        let opened = open_sftp(&self.handle, &self.channel_registry).await?;
+        let sftp = opened.sftp.clone();
+        *cached = Some(opened);
+        Ok(sftp)
+    }
 }
 
-/// Open an SFTP session from an existing SSH session
-pub async fn open_sftp(session: &SshSession) -> Result<SftpSession, String> {
-    let channel = session
-        .handle
+/// Open a new SFTP session over an SSH connection
+async fn open_sftp(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+) -> Result<CachedSftp, String> {
+    let channel = handle
         .channel_open_session()
         .await
         .map_err(|e| format!("Failed to open SFTP channel: {}", e))?;
 
-    channel
-        .request_subsystem(false, "sftp")
-        .await
-        .map_err(|e| format!("Failed to request SFTP subsystem: {}", e))?;
-
-    let sftp = SftpSession::new(channel.into_stream()).await
-        .map_err(|e| format!("Failed to create SFTP session: {}", e))?;
+    // The SFTP session owns the channel, so it can't be closed on its own
+    let channel_id = channel.id();
+    let (counters, _) =
+        channels::register(channel_registry, channel_id, ChannelKind::Sftp, "sftp").await;
 
-    Ok(sftp)
+    let started = match channel.request_subsystem(false, "sftp").await {
+        Ok(()) => {
+            let stream = CountingStream::new(channel.into_stream(), counters.clone());
+            SftpSession::new(stream)
+                .await
+                .map_err(|e| format!("Failed to create SFTP session: {}", e))
+        }
+        Err(e) => Err(format!("Failed to request SFTP subsystem: {}", e)),
+    };
+    match started {
+        Ok(sftp) => Ok(CachedSftp {
+            channel_id,
+            counters,
+            sftp: Arc::new(sftp),
+        }),
+        Err(e) => {
+            channels::unregister(channel_registry, channel_id).await;
+            Err(e)
+        }
+    }
 }
 
 /// Get the user's home directory (resolves "." to absolute path)
@@ -44,48 +262,278 @@ pub async fn get_home_dir(sftp: &SftpSession) -> Result<String, String> {
     Ok(path)
 }
 
-/// List directory contents
-pub async fn list_dir(sftp: &SftpSession, path: &str) -> Result<Vec<FileEntry>, String> {
+/// Attributes of `path` itself; symlinks are described, not followed
+pub async fn stat(sftp: &SftpSession, path: &str) -> Result<FileStat, String> {
+    let attrs = sftp
+        .symlink_metadata(path)
+        .await
+        .map_err(|e| format!("Failed to stat {}: {}", path, e))?;
+
+    let kind = if attrs.is_symlink() {
+        FileKind::Symlink
+    } else if attrs.is_dir() {
+        FileKind::Dir
+    } else if attrs.is_regular() {
+        FileKind::File
+    } else {
+        FileKind::Other
+    };
+    let link_target = if attrs.is_symlink() {
+        Some(
+            sftp.read_link(path)
+                .await
+                .map_err(|e| format!("Failed to read link {}: {}", path, e))?,
+        )
+    } else {
+        None
+    };
+
+    Ok(FileStat {
+        path: path.to_string(),
+        kind,
+        size: attrs.len(),
+        permissions: attrs.permissions,
+        uid: attrs.uid,
+        gid: attrs.gid,
+        accessed: attrs.atime.map(u64::from),
+        modified: attrs.mtime.map(u64::from),
+        link_target,
+    })
+}
+
+/// Which entries a listing returns. Extension and size limits only apply to
+/// files, so directories stay navigable while filtering.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ListFilter {
+    /// Leave out names starting with `.`
+    pub hide_hidden: bool,
+    /// `*`/`?` pattern the name must match, ignoring case
+    pub glob: Option<String>,
+    /// Extensions without the dot, ignoring case
+    pub extensions: Option<Vec<String>>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+}
+
+impl ListFilter {
+    pub fn matches(&self, entry: &FileEntry) -> bool {
+        if self.hide_hidden && entry.name.starts_with('.') {
+            return false;
+        }
+        if let Some(glob) = &self.glob {
+            if !glob_match(&glob.to_lowercase(), &entry.name.to_lowercase()) {
+                return false;
+            }
+        }
+        if entry.is_dir {
+            return true;
+        }
+        if let Some(extensions) = &self.extensions {
+            let extension = entry.name.rsplit_once('.').map(|(_, ext)| ext).unwrap_or("");
+            if !extensions.iter().any(|ext| ext.eq_ignore_ascii_case(extension)) {
+                return false;
+            }
+        }
+        self.min_size.is_none_or(|min| entry.size >= min)
+            && self.max_size.is_none_or(|max| entry.size <= max)
+    }
+}
+
+/// Raw directory entries of `path`, without `.` and `..`
+async fn read_entries(sftp: &SftpSession, path: &str) -> Result<Vec<DirEntry>, String> {
     let entries = sftp
         .read_dir(path)
         .await
         .map_err(|e| format!("Failed to read directory: {}", e))?;
+    Ok(entries
+        .filter(|entry| {
+            let name = entry.file_name();
+            name != "." && name != ".."
+        })
+        .collect())
+}
 
-    let mut files: Vec<FileEntry> = Vec::new();
+async fn to_file_entry(sftp: &SftpSession, path: &str, entry: DirEntry) -> FileEntry {
+    let name = entry.file_name();
+    let full_path = if path.ends_with('/') {
+        format!("{}{}", path, name)
+    } else {
+        format!("{}/{}", path, name)
+    };
 
-    for entry in entries {
-        let name = entry.file_name();
-        if name == "." || name == ".." {
-            continue;
+    let attrs = entry.metadata();
+    let is_symlink = attrs.is_symlink();
+    let mut is_dir = attrs.is_dir();
+    let mut link_target = None;
+    if is_symlink {
+        // Follow the link so symlinked directories can be opened; a
+        // dangling link is shown as a file
+        link_target = sftp.read_link(&full_path).await.ok();
+        is_dir = sftp
+            .metadata(&full_path)
+            .await
+            .is_ok_and(|target| target.is_dir());
+    }
+    let size = attrs.len();
+    let modified = attrs.modified().ok().and_then(|t| {
+        t.duration_since(std::time::UNIX_EPOCH).ok()
+    }).map(|d| d.as_secs());
+
+    FileEntry {
+        name,
+        path: full_path,
+        is_dir,
+        is_symlink,
+        link_target,
+        size,
+        modified,
+        permissions: attrs.permissions,
+        uid: attrs.uid,
+        gid: attrs.gid,
+        owner: None,
+        group: None,
+        mime: None,
+    }
+}
+
+/// User and group names by id, as far as the server has been asked about
+/// them. An id the server has no name for is kept as `None`, so it isn't
+/// asked about again.
+#[derive(Debug, Default)]
+pub struct IdNames {
+    users: HashMap<u32, Option<String>>,
+    groups: HashMap<u32, Option<String>>,
+}
+
+impl IdNames {
+    /// Parse passwd or group lines, which both have the name first and the
+    /// id third
+    fn parse(text: &str) -> HashMap<u32, String> {
+        text.lines()
+            .filter_map(|line| {
+                let mut fields = line.split(':');
+                let name = fields.next()?;
+                let id = fields.nth(1)?.parse().ok()?;
+                Some((id, name.to_string()))
+            })
+            .collect()
+    }
+
+    /// Fill in `owner` and `group` from `uid` and `gid`
+    pub fn apply(&self, entries: &mut [FileEntry]) {
+        for entry in entries {
+            entry.owner = entry.uid.and_then(|uid| self.users.get(&uid).cloned().flatten());
+            entry.group = entry.gid.and_then(|gid| self.groups.get(&gid).cloned().flatten());
         }
+    }
+}
 
-        let full_path = if path.ends_with('/') {
-            format!("{}{}", path, name)
-        } else {
-            format!("{}/{}", path, name)
+/// Look `ids` up in one of the server's databases with `getent`, or in the
+/// `/etc` file where there's no `getent` (BusyBox, macOS). Only the given
+/// ids are asked for, as the whole database can be huge behind LDAP. A
+/// server that fails or doesn't answer in time yields no names at all.
+async fn fetch_id_names(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    database: &str,
+    ids: &[u32],
+) -> HashMap<u32, String> {
+    let keys: Vec<String> = ids.iter().map(u32::to_string).collect();
+    let command = format!(
+        "if command -v getent >/dev/null 2>&1; then getent {0} {1}; \
+         else grep -E '^[^:]*:[^:]*:({2}):' /etc/{0}; fi",
+        database,
+        keys.join(" "),
+        keys.join("|"),
+    );
+    let limit = Duration::from_secs(COMMAND_PROBE_TIMEOUT_SECS);
+    let lookup = tokio::time::timeout(limit, ssh::exec(handle, channel_registry, &command));
+    match lookup.await {
+        Ok(Ok(output)) => IdNames::parse(&output.stdout),
+        Ok(Err(e)) => {
+            log::warn!("Failed to read the server's {} database: {}", database, e);
+            HashMap::new()
+        }
+        Err(_) => {
+            log::warn!("Reading the server's {} database timed out", database);
+            HashMap::new()
+        }
+    }
+}
+
+/// The distinct `ids` not in `known` yet
+fn unknown_ids(
+    known: &HashMap<u32, Option<String>>,
+    ids: impl Iterator<Item = u32>,
+) -> Vec<u32> {
+    let mut ids: Vec<u32> = ids.filter(|id| !known.contains_key(id)).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
+
+/// What's needed to name a listing's owners, cloned out of the session so
+/// the lookups don't hold the sessions lock
+#[derive(Clone)]
+pub struct IdLookup {
+    pub handle: Arc<russh::client::Handle<ClientHandler>>,
+    pub channel_registry: ChannelRegistry,
+    pub names: Arc<Mutex<IdNames>>,
+}
+
+impl IdLookup {
+    pub fn new(session: &SshSession) -> Self {
+        Self {
+            handle: session.handle.clone(),
+            channel_registry: session.channel_registry.clone(),
+            names: session.id_names.clone(),
+        }
+    }
+
+    /// Fill in `owner` and `group`, asking the server only about the ids
+    /// this session hasn't looked up yet. The names lock isn't held while
+    /// the server is asked, and ids a failed lookup left unnamed are
+    /// recorded like any other, so they aren't asked about again.
+    pub async fn apply(&self, entries: &mut [FileEntry]) {
+        let (uids, gids) = {
+            let names = self.names.lock().await;
+            (
+                unknown_ids(&names.users, entries.iter().filter_map(|e| e.uid)),
+                unknown_ids(&names.groups, entries.iter().filter_map(|e| e.gid)),
+            )
         };
+        let mut users = HashMap::new();
+        if !uids.is_empty() {
+            users = fetch_id_names(&self.handle, &self.channel_registry, "passwd", &uids).await;
+        }
+        let mut groups = HashMap::new();
+        if !gids.is_empty() {
+            groups = fetch_id_names(&self.handle, &self.channel_registry, "group", &gids).await;
+        }
+        let mut names = self.names.lock().await;
+        for uid in uids {
+            names.users.insert(uid, users.remove(&uid));
+        }
+        for gid in gids {
+            names.groups.insert(gid, groups.remove(&gid));
+        }
+        names.apply(entries);
+    }
+}
 
-        let attrs = entry.metadata();
-        let is_dir = attrs.is_dir();
-        let size = attrs.len();
-        let modified = attrs.modified().ok().and_then(|t| {
-            t.duration_since(std::time::UNIX_EPOCH).ok()
-        }).map(|d| d.as_secs());
-
-        let permissions = attrs.permissions;
-        let uid = attrs.uid;
-        let gid = attrs.gid;
-
-        files.push(FileEntry {
-            name,
-            path: full_path,
-            is_dir,
-            size,
-            modified,
-            permissions,
-            uid,
-            gid,
-        });
+/// List directory contents, keeping the entries `filter` lets through
+pub async fn list_dir(
+    sftp: &SftpSession,
+    path: &str,
+    filter: &ListFilter,
+) -> Result<Vec<FileEntry>, String> {
+    let mut files: Vec<FileEntry> = Vec::new();
+    for entry in read_entries(sftp, path).await? {
+        let entry = to_file_entry(sftp, path, entry).await;
+        if filter.matches(&entry) {
+            files.push(entry);
+        }
     }
 
     // Sort: directories first, then alphabetical
@@ -96,98 +544,2202 @@ pub async fn list_dir(sftp: &SftpSession, path: &str) -> Result<Vec<FileEntry>,
     Ok(files)
 }
 
-/// Download a file from remote
+/// Like `list_dir`, but hands the entries over `LIST_CHUNK_SIZE` at a time so
+/// a huge directory can be shown before all of it has been processed.
+/// Ordering uses each entry's own attributes, so symlinked directories sort
+/// among the files. Returns the number of entries passed on.
+pub async fn list_dir_chunked(
+    sftp: &SftpSession,
+    path: &str,
+    filter: &ListFilter,
+    ids: &IdLookup,
+    mut on_chunk: impl FnMut(Vec<FileEntry>),
+) -> Result<usize, String> {
+    let mut entries = read_entries(sftp, path).await?;
+    entries.sort_by_cached_key(|entry| {
+        (!entry.metadata().is_dir(), entry.file_name().to_lowercase())
+    });
+
+    let mut total = 0;
+    let mut chunk = Vec::with_capacity(LIST_CHUNK_SIZE);
+    for entry in entries {
+        let entry = to_file_entry(sftp, path, entry).await;
+        if !filter.matches(&entry) {
+            continue;
+        }
+        total += 1;
+        chunk.push(entry);
+        if chunk.len() == LIST_CHUNK_SIZE {
+            ids.apply(&mut chunk).await;
+            on_chunk(std::mem::take(&mut chunk));
+        }
+    }
+    if !chunk.is_empty() {
+        ids.apply(&mut chunk).await;
+        on_chunk(chunk);
+    }
+    Ok(total)
+}
+
+/// Download a file from remote, reporting progress as it goes. Only one
+/// `CHUNK_SIZE` buffer is held in memory, whatever the file size. When
+/// resuming, an existing shorter local file is taken as the start of the
+/// download and only the rest is fetched.
 pub async fn download_file(
     sftp: &SftpSession,
     remote_path: &str,
     local_path: &str,
+    options: TransferOptions,
+    report: impl FnMut(TransferProgress),
 ) -> Result<(), String> {
-    use tokio::io::AsyncReadExt;
+    let attrs = sftp.metadata(remote_path).await.ok();
+    let total = attrs.as_ref().map(|attrs| attrs.len()).unwrap_or(0);
+    let mut offset = 0;
+    if options.resume {
+        if let Ok(meta) = tokio::fs::metadata(local_path).await {
+            // A longer local file is something else; start over
+            if meta.len() <= total {
+                offset = meta.len();
+            }
+        }
+    }
+    let mut progress = Progress::new(total, report);
+    progress.skip(offset);
+    copy_to_local(sftp, remote_path, local_path, offset, &mut progress).await?;
+    if let Some(attrs) = attrs.filter(|_| options.preserve) {
+        preserve_local(local_path, attrs.mtime.map(u64::from), attrs.permissions)?;
+    }
+    progress.finish();
+    Ok(())
+}
+
+/// Copy bytes `start..end` of a remote file into the same range of an
+/// existing local file, adding to `done` as it goes
+async fn download_range(
+    sftp: Arc<SftpSession>,
+    remote_path: String,
+    local_path: String,
+    start: u64,
+    end: u64,
+    done: Arc<AtomicU64>,
+) -> Result<(), String> {
+    use std::io::SeekFrom;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
     let mut remote_file = sftp
-        .open(remote_path)
+        .open(remote_path.as_str())
         .await
         .map_err(|e| format!("Failed to open remote file: {}", e))?;
-
-    let mut contents = Vec::new();
     remote_file
-        .read_to_end(&mut contents)
+        .seek(SeekFrom::Start(start))
         .await
-        .map_err(|e| format!("Failed to read remote file: {}", e))?;
+        .map_err(|e| format!("Failed to seek remote file: {}", e))?;
+    let mut local_file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(&local_path)
+        .await
+        .map_err(|e| format!("Failed to open local file: {}", e))?;
+    local_file
+        .seek(SeekFrom::Start(start))
+        .await
+        .map_err(|e| format!("Failed to seek local file: {}", e))?;
 
-    tokio::fs::write(local_path, &contents)
+    let mut remaining = end - start;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    while remaining > 0 {
+        let want = remaining.min(CHUNK_SIZE as u64) as usize;
+        let n = remote_file
+            .read(&mut buf[..want])
+            .await
+            .map_err(|e| format!("Failed to read remote file: {}", e))?;
+        if n == 0 {
+            return Err("Remote file got shorter during the download".to_string());
+        }
+        local_file
+            .write_all(&buf[..n])
+            .await
+            .map_err(|e| format!("Failed to write local file: {}", e))?;
+        remaining -= n as u64;
+        done.fetch_add(n as u64, Ordering::Relaxed);
+    }
+    local_file
+        .flush()
         .await
-        .map_err(|e| format!("Failed to write local file: {}", e))?;
+        .map_err(|e| format!("Failed to write local file: {}", e))
+}
 
-    Ok(())
+/// `download_parallel`'s extra SFTP sessions, closed however the download
+/// ends, cancellation included
+struct ExtraSessions(Vec<Arc<SftpSession>>);
+
+impl Drop for ExtraSessions {
+    fn drop(&mut self) {
+        let extras = std::mem::take(&mut self.0);
+        if extras.is_empty() {
+            return;
+        }
+        tokio::spawn(async move {
+            for extra in extras {
+                let _ = extra.close().await;
+            }
+        });
+    }
 }
 
-/// Upload a file to remote
-pub async fn upload_file(
-    sftp: &SftpSession,
-    local_path: &str,
+/// A download's `<path>.part` file, deleted unless `keep` was called
+struct PartFile {
+    path: String,
+    keep: bool,
+}
+
+impl PartFile {
+    fn new(local_path: &str) -> Self {
+        Self {
+            path: format!("{}.part", local_path),
+            keep: false,
+        }
+    }
+
+    /// Move the finished download into place
+    async fn finish(mut self, local_path: &str) -> Result<(), String> {
+        tokio::fs::rename(&self.path, local_path)
+            .await
+            .map_err(|e| format!("Failed to move download into place: {}", e))?;
+        self.keep = true;
+        Ok(())
+    }
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Download a large file over several SFTP channels at once, each reading
+/// its own slice, which makes up for per-channel window limits on
+/// high-latency links. Files under `PARALLEL_MIN_SIZE` take the single
+/// channel path. Only `preserve` of `options` applies; resuming isn't
+/// supported. The slices go to `<path>.part`, which replaces the file only
+/// once all of them arrived, so a failed download never leaves a
+/// full-length file that a later resume would take as complete.
+pub async fn download_parallel(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    sftp: &Arc<SftpSession>,
     remote_path: &str,
+    local_path: &str,
+    options: TransferOptions,
+    report: impl FnMut(TransferProgress),
 ) -> Result<(), String> {
-    use tokio::io::AsyncWriteExt;
-
-    let contents = tokio::fs::read(local_path)
+    let attrs = sftp
+        .metadata(remote_path)
         .await
-        .map_err(|e| format!("Failed to read local file: {}", e))?;
+        .map_err(|e| format!("Failed to read {}: {}", remote_path, e))?;
+    let total = attrs.len();
+    let options = TransferOptions {
+        preserve: options.preserve,
+        ..Default::default()
+    };
+    if total < PARALLEL_MIN_SIZE {
+        return download_file(sftp, remote_path, local_path, options, report).await;
+    }
 
-    let mut remote_file = sftp
-        .create(remote_path)
-        .await
-        .map_err(|e| format!("Failed to create remote file: {}", e))?;
+    let mut extras = ExtraSessions(Vec::new());
+    while extras.0.len() + 1 < PARALLEL_CHANNELS {
+        match open_sftp(handle, channel_registry).await {
+            Ok((_, extra)) => extras.0.push(Arc::new(extra)),
+            // Servers may cap sessions per connection; use what we got
+            Err(e) => {
+                log::warn!(
+                    "Parallel download continues with {} channels: {}",
+                    extras.0.len() + 1,
+                    e
+                );
+                break;
+            }
+        }
+    }
 
-    remote_file
-        .write_all(&contents)
-        .await
-        .map_err(|e| format!("Failed to write remote file: {}", e))?;
+    let part = PartFile::new(local_path);
+    download_slices(sftp, &extras.0, remote_path, &part.path, total, report).await?;
+    drop(extras);
+    part.finish(local_path).await?;
 
-    remote_file
-        .shutdown()
+    if options.preserve {
+        preserve_local(local_path, attrs.mtime.map(u64::from), attrs.permissions)?;
+    }
+    Ok(())
+}
+
+/// Split a download of `total` bytes evenly across `sftp` and `extras`
+async fn download_slices(
+    sftp: &Arc<SftpSession>,
+    extras: &[Arc<SftpSession>],
+    remote_path: &str,
+    local_path: &str,
+    total: u64,
+    report: impl FnMut(TransferProgress),
+) -> Result<(), String> {
+    let sessions: Vec<_> = std::iter::once(sftp).chain(extras).cloned().collect();
+    let local_file = tokio::fs::File::create(local_path)
         .await
-        .map_err(|e| format!("Failed to close remote file: {}", e))?;
+        .map_err(|e| format!("Failed to create local file: {}", e))?;
+    local_file
+        .set_len(total)
+        .await
+        .map_err(|e| format!("Failed to allocate local file: {}", e))?;
+    drop(local_file);
 
+    let done = Arc::new(AtomicU64::new(0));
+    let slice = total.div_ceil(sessions.len() as u64);
+    // Dropping the JoinSet (on error or cancellation) stops every slice
+    let mut slices = tokio::task::JoinSet::new();
+    for (i, session) in sessions.into_iter().enumerate() {
+        let start = i as u64 * slice;
+        let end = (start + slice).min(total);
+        slices.spawn(download_range(
+            session,
+            remote_path.to_string(),
+            local_path.to_string(),
+            start,
+            end,
+            done.clone(),
+        ));
+    }
+
+    let mut progress = Progress::new(total, report);
+    let mut counted = 0;
+    let mut ticker = tokio::time::interval(Duration::from_millis(PROGRESS_INTERVAL_MS));
+    loop {
+        tokio::select! {
+            finished = slices.join_next() => match finished {
+                Some(Ok(Ok(()))) => {}
+                Some(Ok(Err(e))) => return Err(e),
+                Some(Err(e)) => return Err(format!("Download task failed: {}", e)),
+                None => break,
+            },
+            _ = ticker.tick() => {}
+        }
+        let now = done.load(Ordering::Relaxed);
+        progress.advance((now - counted) as usize);
+        counted = now;
+    }
+    progress.finish();
     Ok(())
 }
 
-/// Create a remote directory
-pub async fn mkdir(sftp: &SftpSession, path: &str) -> Result<(), String> {
-    sftp.create_dir(path)
-        .await
-        .map_err(|e| format!("Failed to create directory: {}", e))
+/// Give a downloaded file the remote file's modification time and
+/// permission bits (the latter only where the local OS has them)
+fn preserve_local(
+    local_path: &str,
+    mtime: Option<u64>,
+    permissions: Option<u32>,
+) -> Result<(), String> {
+    // Before the permissions, which may make the file read-only
+    if let Some(mtime) = mtime {
+        std::fs::File::options()
+            .write(true)
+            .open(local_path)
+            .and_then(|file| file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime)))
+            .map_err(|e| format!("Failed to set modification time: {}", e))?;
+    }
+    #[cfg(unix)]
+    if let Some(mode) = permissions {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(local_path, std::fs::Permissions::from_mode(mode & 0o7777))
+            .map_err(|e| format!("Failed to set permissions: {}", e))?;
+    }
+    #[cfg(not(unix))]
+    let _ = permissions;
+    Ok(())
 }
 
-/// Remove a remote file
-pub async fn remove_file(sftp: &SftpSession, path: &str) -> Result<(), String> {
-    sftp.remove_file(path)
+/// Give an uploaded file the local file's modification time and, on Unix,
+/// its permission bits
+async fn preserve_remote(
+    sftp: &SftpSession,
+    remote_path: &str,
+    meta: &std::fs::Metadata,
+) -> Result<(), String> {
+    use russh_sftp::protocol::FileAttributes;
+
+    let secs = |time: std::io::Result<std::time::SystemTime>| {
+        time.ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as u32)
+    };
+    #[cfg(unix)]
+    let permissions = {
+        use std::os::unix::fs::PermissionsExt;
+        Some(meta.permissions().mode() & 0o7777)
+    };
+    #[cfg(not(unix))]
+    let permissions = None;
+
+    let attrs = FileAttributes {
+        permissions,
+        atime: secs(meta.accessed()),
+        mtime: secs(meta.modified()),
+        ..FileAttributes::empty()
+    };
+    sftp.set_metadata(remote_path, attrs)
         .await
-        .map_err(|e| format!("Failed to remove file: {}", e))
+        .map_err(|e| format!("Failed to set attributes: {}", e))
 }
 
-/// Remove a remote directory
-pub async fn remove_dir(sftp: &SftpSession, path: &str) -> Result<(), String> {
-    sftp.remove_dir(path)
+/// Stream one remote file into `local_path` from byte `offset` on, counting
+/// into `progress`
+async fn copy_to_local<F: FnMut(TransferProgress)>(
+    sftp: &SftpSession,
+    remote_path: &str,
+    local_path: &str,
+    offset: u64,
+    progress: &mut Progress<F>,
+) -> Result<(), String> {
+    use std::io::SeekFrom;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    let mut remote_file = sftp
+        .open(remote_path)
         .await
-        .map_err(|e| format!("Failed to remove directory: {}", e))
-}
+        .map_err(|e| format!("Failed to open remote file: {}", e))?;
+    let mut local_file = if offset > 0 {
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(local_path)
+            .await
+            .map_err(|e| format!("Failed to open local file: {}", e))?;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|e| format!("Failed to seek local file: {}", e))?;
+        remote_file
+            .seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|e| format!("Failed to seek remote file: {}", e))?;
+        file
+    } else {
+        tokio::fs::File::create(local_path)
+            .await
+            .map_err(|e| format!("Failed to create local file: {}", e))?
+    };
 
-/// Rename/move a remote file or directory
-pub async fn rename(sftp: &SftpSession, from: &str, to: &str) -> Result<(), String> {
-    sftp.rename(from, to)
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = remote_file
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("Failed to read remote file: {}", e))?;
+        if n == 0 {
+            break;
+        }
+        local_file
+            .write_all(&buf[..n])
+            .await
+            .map_err(|e| format!("Failed to write local file: {}", e))?;
+        progress.advance(n);
+    }
+
+    local_file
+        .flush()
         .await
-        .map_err(|e| format!("Failed to rename: {}", e))
+        .map_err(|e| format!("Failed to write local file: {}", e))
 }
 
-/// Set file permissions (chmod)
-pub async fn set_permissions(sftp: &SftpSession, path: &str, mode: u32) -> Result<(), String> {
-    use russh_sftp::protocol::FileAttributes;
-    let attrs = FileAttributes {
-        permissions: Some(mode),
-        ..FileAttributes::empty()
-    };
-    sftp.set_metadata(path, attrs)
+/// A file or directory a recursive transfer had to skip
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferFailure {
+    pub path: String,
+    pub error: String,
+}
+
+/// Outcome of a recursive transfer
+#[derive(Debug, Clone, Serialize)]
+pub struct DirTransferSummary {
+    pub files_done: usize,
+    /// Files left alone because their destination existed
+    pub files_skipped: usize,
+    pub failures: Vec<TransferFailure>,
+}
+
+/// Download the remote directory tree at `remote_path` into `local_path`.
+/// The tree is listed first so progress covers the whole job; files or
+/// directories that fail are recorded and the rest carry on. Files that
+/// already exist locally are handled by `conflicts`.
+pub async fn download_dir(
+    sftp: &SftpSession,
+    remote_path: &str,
+    local_path: &str,
+    preserve: bool,
+    conflicts: &dyn ConflictHandler,
+    report: impl FnMut(TransferProgress),
+) -> Result<DirTransferSummary, String> {
+    let attrs = sftp
+        .metadata(remote_path)
         .await
-        .map_err(|e| format!("Failed to set permissions: {}", e))
+        .map_err(|e| format!("Failed to read {}: {}", remote_path, e))?;
+    if !attrs.is_dir() {
+        return Err(format!("{} is not a directory", remote_path));
+    }
+
+    let mut failures = Vec::new();
+    let mut files = Vec::new();
+    let mut pending = vec![(
+        remote_path.to_string(),
+        std::path::PathBuf::from(local_path),
+    )];
+
+    while let Some((remote_dir, local_dir)) = pending.pop() {
+        if let Err(e) = tokio::fs::create_dir_all(&local_dir).await {
+            failures.push(TransferFailure {
+                path: remote_dir,
+                error: format!("Failed to create local directory: {}", e),
+            });
+            continue;
+        }
+        let entries = match list_dir(sftp, &remote_dir, &ListFilter::default()).await {
+            Ok(entries) => entries,
+            Err(error) => {
+                failures.push(TransferFailure { path: remote_dir, error });
+                continue;
+            }
+        };
+        for entry in entries {
+            let local = local_dir.join(&entry.name);
+            if entry.is_dir && entry.is_symlink {
+                // Following these could loop forever
+                failures.push(TransferFailure {
+                    path: entry.path,
+                    error: "Skipped symlinked directory".to_string(),
+                });
+            } else if entry.is_dir {
+                pending.push((entry.path, local));
+            } else {
+                files.push((entry, local));
+            }
+        }
+    }
+
+    let total = files.iter().map(|(entry, _)| entry.size).sum();
+    let mut progress = Progress::new(total, report);
+    let mut files_done = 0;
+    let mut files_skipped = 0;
+    for (entry, local) in files {
+        let local = local.to_string_lossy();
+        let Some(local) = download_target(sftp, conflicts, &entry.path, &local).await else {
+            progress.skip(entry.size);
+            files_skipped += 1;
+            continue;
+        };
+        let mut result = copy_to_local(sftp, &entry.path, &local, 0, &mut progress).await;
+        if result.is_ok() && preserve {
+            result = preserve_local(&local, entry.modified, entry.permissions);
+        }
+        match result {
+            Ok(()) => files_done += 1,
+            Err(error) => failures.push(TransferFailure {
+                path: entry.path,
+                error,
+            }),
+        }
+    }
+    progress.finish();
+
+    Ok(DirTransferSummary {
+        files_done,
+        files_skipped,
+        failures,
+    })
+}
+
+/// Stream one local file into a new `remote_path`, counting into `progress`
+async fn copy_to_remote<F: FnMut(TransferProgress)>(
+    sftp: &SftpSession,
+    local_path: &std::path::Path,
+    remote_path: &str,
+    progress: &mut Progress<F>,
+) -> Result<(), String> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut local_file = tokio::fs::File::open(local_path)
+        .await
+        .map_err(|e| format!("Failed to open local file: {}", e))?;
+    let mut remote_file = sftp
+        .create(remote_path)
+        .await
+        .map_err(|e| format!("Failed to create remote file: {}", e))?;
+
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = local_file
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("Failed to read local file: {}", e))?;
+        if n == 0 {
+            break;
+        }
+        remote_file
+            .write_all(&buf[..n])
+            .await
+            .map_err(|e| format!("Failed to write remote file: {}", e))?;
+        progress.advance(n);
+    }
+
+    remote_file
+        .shutdown()
+        .await
+        .map_err(|e| format!("Failed to close remote file: {}", e))
+}
+
+/// Create `path` on the server unless a directory is already there
+async fn ensure_remote_dir(sftp: &SftpSession, path: &str) -> Result<(), String> {
+    match sftp.metadata(path).await {
+        Ok(attrs) if attrs.is_dir() => Ok(()),
+        _ => mkdir(sftp, path).await,
+    }
+}
+
+/// Upload a mix of local files and directories into `remote_dir`, such as
+/// an OS drag-and-drop. Directories are expanded recursively and everything
+/// is listed first, so progress covers the whole job; items that fail are
+/// recorded and the rest carry on. Symlinked directories are skipped, and
+/// files that already exist on the server are handled by `conflicts`.
+pub async fn upload_paths(
+    sftp: &SftpSession,
+    local_paths: &[String],
+    remote_dir: &str,
+    conflicts: &dyn ConflictHandler,
+    report: impl FnMut(TransferProgress),
+) -> Result<DirTransferSummary, String> {
+    use std::path::PathBuf;
+
+    ensure_remote_dir(sftp, remote_dir).await?;
+
+    let mut failures = Vec::new();
+    let mut files: Vec<(PathBuf, String, u64)> = Vec::new();
+    let mut pending: Vec<(PathBuf, String)> = Vec::new();
+
+    for local_path in local_paths {
+        let local = PathBuf::from(local_path);
+        let Some(name) = local.file_name().map(|name| name.to_string_lossy().into_owned()) else {
+            failures.push(TransferFailure {
+                path: local_path.clone(),
+                error: "Not a file or directory name".to_string(),
+            });
+            continue;
+        };
+        let remote = join(remote_dir, &name);
+        match tokio::fs::metadata(&local).await {
+            Ok(meta) if meta.is_dir() => pending.push((local, remote)),
+            Ok(meta) => files.push((local, remote, meta.len())),
+            Err(e) => failures.push(TransferFailure {
+                path: local_path.clone(),
+                error: format!("Failed to read {}: {}", local_path, e),
+            }),
+        }
+    }
+
+    while let Some((local_dir, remote)) = pending.pop() {
+        let path = local_dir.to_string_lossy().into_owned();
+        if let Err(error) = ensure_remote_dir(sftp, &remote).await {
+            failures.push(TransferFailure { path, error });
+            continue;
+        }
+        let mut entries = match tokio::fs::read_dir(&local_dir).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                let error = format!("Failed to read directory: {}", e);
+                failures.push(TransferFailure { path, error });
+                continue;
+            }
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let local = entry.path();
+            let remote = join(&remote, &entry.file_name().to_string_lossy());
+            let is_link = entry.file_type().await.is_ok_and(|kind| kind.is_symlink());
+            match tokio::fs::metadata(&local).await {
+                Ok(meta) if meta.is_dir() && is_link => failures.push(TransferFailure {
+                    // Following these could loop forever
+                    path: local.to_string_lossy().into_owned(),
+                    error: "Skipped symlinked directory".to_string(),
+                }),
+                Ok(meta) if meta.is_dir() => pending.push((local, remote)),
+                Ok(meta) => files.push((local, remote, meta.len())),
+                Err(e) => failures.push(TransferFailure {
+                    path: local.to_string_lossy().into_owned(),
+                    error: format!("Failed to read file: {}", e),
+                }),
+            }
+        }
+    }
+
+    let total = files.iter().map(|(_, _, size)| size).sum();
+    let mut progress = Progress::new(total, report);
+    let mut files_done = 0;
+    let mut files_skipped = 0;
+    for (local, remote, size) in files {
+        let local_path = local.to_string_lossy();
+        let Some(remote) = upload_target(sftp, conflicts, &local_path, &remote).await else {
+            progress.skip(size);
+            files_skipped += 1;
+            continue;
+        };
+        match copy_to_remote(sftp, &local, &remote, &mut progress).await {
+            Ok(()) => files_done += 1,
+            Err(error) => failures.push(TransferFailure {
+                path: local.to_string_lossy().into_owned(),
+                error,
+            }),
+        }
+    }
+    progress.finish();
+
+    Ok(DirTransferSummary {
+        files_done,
+        files_skipped,
+        failures,
+    })
+}
+
+/// Upload a file to remote, reporting progress as it goes. Like
+/// `download_file`, memory use is bounded by `CHUNK_SIZE`, and resuming
+/// continues after whatever part of the file the server already has.
+pub async fn upload_file(
+    sftp: &SftpSession,
+    local_path: &str,
+    remote_path: &str,
+    options: TransferOptions,
+    report: impl FnMut(TransferProgress),
+) -> Result<(), String> {
+    use russh_sftp::protocol::{FileAttributes, OpenFlags};
+    use std::io::SeekFrom;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    let mut local_file = tokio::fs::File::open(local_path)
+        .await
+        .map_err(|e| format!("Failed to open local file: {}", e))?;
+    let meta = local_file.metadata().await.ok();
+    let total = meta.as_ref().map(|meta| meta.len()).unwrap_or(0);
+    let atomic = options.atomic && !options.append;
+    let part_path = format!("{}.part", remote_path);
+    // Where the bytes go; resuming an atomic upload continues its part file
+    let target = if atomic { part_path.as_str() } else { remote_path };
+    let mut offset = 0;
+    if options.resume && !options.append {
+        if let Ok(attrs) = sftp.metadata(target).await {
+            if attrs.len() <= total {
+                offset = attrs.len();
+            }
+        }
+    }
+    let mut progress = Progress::new(total, report);
+    progress.skip(offset);
+
+    let mut remote_file = if options.append {
+        // Servers differ in whether APPEND alone moves writes to the end, so
+        // start there explicitly too
+        let end = sftp.metadata(remote_path).await.map(|a| a.len()).unwrap_or(0);
+        let flags = OpenFlags::WRITE | OpenFlags::APPEND | OpenFlags::CREATE;
+        let mut file = sftp
+            .open_with_flags(remote_path, flags)
+            .await
+            .map_err(|e| format!("Failed to open remote file: {}", e))?;
+        file.seek(SeekFrom::Start(end))
+            .await
+            .map_err(|e| format!("Failed to seek remote file: {}", e))?;
+        file
+    } else if offset > 0 {
+        let mut file = sftp
+            .open_with_flags(target, OpenFlags::WRITE)
+            .await
+            .map_err(|e| format!("Failed to open remote file: {}", e))?;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|e| format!("Failed to seek remote file: {}", e))?;
+        local_file
+            .seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|e| format!("Failed to seek local file: {}", e))?;
+        file
+    } else {
+        sftp.create(target)
+            .await
+            .map_err(|e| format!("Failed to create remote file: {}", e))?
+    };
+
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = local_file
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("Failed to read local file: {}", e))?;
+        if n == 0 {
+            break;
+        }
+        remote_file
+            .write_all(&buf[..n])
+            .await
+            .map_err(|e| format!("Failed to write remote file: {}", e))?;
+        progress.advance(n);
+    }
+
+    remote_file
+        .shutdown()
+        .await
+        .map_err(|e| format!("Failed to close remote file: {}", e))?;
+
+    if atomic {
+        // The replacement keeps the mode of the file it replaces
+        let original = sftp.metadata(remote_path).await.ok();
+        if let Some(permissions) = original.and_then(|attrs| attrs.permissions) {
+            let attrs = FileAttributes {
+                permissions: Some(permissions),
+                ..FileAttributes::empty()
+            };
+            let _ = sftp.set_metadata(target, attrs).await;
+        }
+        // A finished part file stays put so that a resumed upload only
+        // needs to retry the rename
+        replace_file(sftp, target, remote_path)
+            .await
+            .map_err(|e| format!("{} (the upload is kept in {})", e, target))?;
+    }
+    if let Some(meta) = meta.filter(|_| options.preserve) {
+        preserve_remote(sftp, remote_path, &meta).await?;
+    }
+    progress.finish();
+    Ok(())
+}
+
+/// What `upload_delta` last left in a remote file: its blocks' checksums,
+/// plus the remote size and mtime right after, to notice when someone else
+/// changed it
+#[derive(Debug, Clone)]
+pub struct BlockSignature {
+    blocks: Vec<BlockSum>,
+    size: u64,
+    modified: Option<u32>,
+}
+
+/// Whether the server's `dd` takes byte offsets and counts (GNU and
+/// BusyBox do), which `upload_delta` needs to move blocks around
+async fn dd_takes_bytes(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+) -> bool {
+    let command = "dd if=/dev/null of=/dev/null iflag=skip_bytes,count_bytes oflag=seek_bytes \
+                   2>/dev/null";
+    let probe = tokio::time::timeout(
+        Duration::from_secs(COMMAND_PROBE_TIMEOUT_SECS),
+        ssh::exec(handle, channel_registry, command),
+    )
+    .await;
+    matches!(probe, Ok(Ok(output)) if output.exit_code == Some(0))
+}
+
+/// `dd` command copying `len` bytes at `from` in `source` to `at` in `dest`
+fn dd_copy(source: &str, dest: &str, from: u64, at: u64, len: u64) -> String {
+    format!(
+        "dd if={} of={} bs=1048576 iflag=skip_bytes,count_bytes oflag=seek_bytes conv=notrunc \
+         skip={} seek={} count={} 2>/dev/null",
+        ssh::shell_quote(source),
+        ssh::shell_quote(dest),
+        from,
+        at,
+        len
+    )
+}
+
+/// Upload a file again, sending only what `previous` says the server
+/// doesn't have yet. The changes are found rsync-style (see
+/// `delta::compute`), so blocks that moved are found too. The new version
+/// is built in a `.part` file and then moved into place, so the file is
+/// never seen half-written: the server's `cp` starts it off as a copy of
+/// the old version, which leaves blocks that stayed put in place, and its
+/// `dd` copies the blocks that moved. Whatever the server can't copy is
+/// sent, as is everything when there is no `previous` or the remote file
+/// changed since.
+///
+/// Returns the signature for the next upload and how many bytes were sent.
+pub async fn upload_delta(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    sftp: &SftpSession,
+    local_path: &str,
+    remote_path: &str,
+    previous: Option<&BlockSignature>,
+) -> Result<(BlockSignature, u64), String> {
+    use russh_sftp::protocol::{FileAttributes, OpenFlags};
+    use std::io::SeekFrom;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    let current = sftp.metadata(remote_path).await.ok();
+    let previous = previous.filter(|previous| {
+        current
+            .as_ref()
+            .is_some_and(|attrs| attrs.len() == previous.size && attrs.mtime == previous.modified)
+    });
+    let part_path = format!("{}.part", remote_path);
+
+    let old_blocks = previous.map(|p| p.blocks.clone()).unwrap_or_default();
+    let path = local_path.to_string();
+    let (ops, blocks) = tokio::task::spawn_blocking(move || {
+        delta::compute(std::fs::File::open(&path)?, &old_blocks)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("Failed to read local file: {}", e))?;
+
+    // Where each op's bytes go in the new file
+    let mut steps = Vec::with_capacity(ops.len());
+    let mut size = 0u64;
+    for op in ops {
+        steps.push((size, op));
+        size += op.bytes();
+    }
+
+    let has_copies = steps.iter().any(|(_, op)| matches!(op, DeltaOp::Copy { .. }));
+    let mut base = false;
+    if has_copies && has_command(handle, channel_registry, "cp").await {
+        let command = format!(
+            "cp -p -- {} {}",
+            ssh::shell_quote(remote_path),
+            ssh::shell_quote(&part_path)
+        );
+        base = ssh::exec(handle, channel_registry, &command).await?.exit_code == Some(0);
+    }
+    let moved = steps
+        .iter()
+        .any(|(at, op)| matches!(op, DeltaOp::Copy { from, .. } if !base || from != at));
+    let can_dd = moved && dd_takes_bytes(handle, channel_registry).await;
+
+    let mut local_file = tokio::fs::File::open(local_path)
+        .await
+        .map_err(|e| format!("Failed to open local file: {}", e))?;
+    let mut remote_file = if base {
+        sftp.open_with_flags(part_path.as_str(), OpenFlags::WRITE)
+            .await
+            .map_err(|e| format!("Failed to open remote file: {}", e))?
+    } else {
+        sftp.create(part_path.as_str())
+            .await
+            .map_err(|e| format!("Failed to create remote file: {}", e))?
+    };
+
+    let mut copies = Vec::new();
+    let mut sent = 0u64;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    for (at, op) in steps {
+        match op {
+            DeltaOp::Copy { from, .. } if base && from == at => continue,
+            DeltaOp::Copy { from, len } if can_dd => {
+                copies.push(dd_copy(remote_path, &part_path, from, at, len));
+                continue;
+            }
+            // New data, and moved blocks the server can't copy
+            _ => {}
+        }
+        local_file
+            .seek(SeekFrom::Start(at))
+            .await
+            .map_err(|e| format!("Failed to seek local file: {}", e))?;
+        remote_file
+            .seek(SeekFrom::Start(at))
+            .await
+            .map_err(|e| format!("Failed to seek remote file: {}", e))?;
+        let mut remaining = op.bytes();
+        while remaining > 0 {
+            let n = remaining.min(CHUNK_SIZE as u64) as usize;
+            local_file
+                .read_exact(&mut buf[..n])
+                .await
+                .map_err(|e| format!("Failed to read local file: {}", e))?;
+            remote_file
+                .write_all(&buf[..n])
+                .await
+                .map_err(|e| format!("Failed to write remote file: {}", e))?;
+            remaining -= n as u64;
+            sent += n as u64;
+        }
+    }
+    remote_file
+        .shutdown()
+        .await
+        .map_err(|e| format!("Failed to close remote file: {}", e))?;
+
+    for batch in copies.chunks(DELTA_COPIES_PER_EXEC) {
+        let output = ssh::exec(handle, channel_registry, &batch.join(" && ")).await?;
+        if output.exit_code != Some(0) {
+            return Err("Failed to copy unchanged blocks on the server".to_string());
+        }
+    }
+
+    if base && previous.is_some_and(|p| p.size > size) {
+        let attrs = FileAttributes {
+            size: Some(size),
+            ..FileAttributes::empty()
+        };
+        sftp.set_metadata(part_path.as_str(), attrs)
+            .await
+            .map_err(|e| format!("Failed to truncate remote file: {}", e))?;
+    }
+    if !base {
+        // The replacement keeps the mode of the file it replaces
+        if let Some(permissions) = current.and_then(|attrs| attrs.permissions) {
+            let attrs = FileAttributes {
+                permissions: Some(permissions),
+                ..FileAttributes::empty()
+            };
+            let _ = sftp.set_metadata(part_path.as_str(), attrs).await;
+        }
+    }
+    replace_file(sftp, &part_path, remote_path).await?;
+
+    let modified = sftp.metadata(remote_path).await.ok().and_then(|attrs| attrs.mtime);
+    Ok((BlockSignature { blocks, size, modified }, sent))
+}
+
+/// What to do when a transfer's destination already exists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    #[default]
+    Overwrite,
+    /// Emit `transfer-conflict` and wait for the user's choice
+    Ask,
+    Skip,
+    /// Write to a free name like `report (1).pdf` instead
+    Rename,
+    /// Overwrite only when the source was modified more recently
+    OverwriteIfNewer,
+}
+
+/// How one conflict was settled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictResolution {
+    Overwrite,
+    Skip,
+    Rename,
+}
+
+/// Both sides of a transfer whose destination exists
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+    pub source_path: String,
+    pub dest_path: String,
+    pub source_size: u64,
+    pub source_modified: Option<u64>,
+    pub dest_size: u64,
+    pub dest_modified: Option<u64>,
+}
+
+impl ConflictPolicy {
+    /// Settle `conflict` without asking; `None` for `Ask`
+    pub fn resolve(self, conflict: &Conflict) -> Option<ConflictResolution> {
+        match self {
+            Self::Overwrite => Some(ConflictResolution::Overwrite),
+            Self::Ask => None,
+            Self::Skip => Some(ConflictResolution::Skip),
+            Self::Rename => Some(ConflictResolution::Rename),
+            Self::OverwriteIfNewer => {
+                if conflict.source_modified > conflict.dest_modified {
+                    Some(ConflictResolution::Overwrite)
+                } else {
+                    Some(ConflictResolution::Skip)
+                }
+            }
+        }
+    }
+}
+
+/// Settles the conflicts of a transfer as they come up, one file at a time
+#[async_trait]
+pub trait ConflictHandler: Send + Sync {
+    async fn settle(&self, conflict: &Conflict) -> ConflictResolution;
+}
+
+/// With nobody to ask, `Ask` skips
+#[async_trait]
+impl ConflictHandler for ConflictPolicy {
+    async fn settle(&self, conflict: &Conflict) -> ConflictResolution {
+        self.resolve(conflict).unwrap_or(ConflictResolution::Skip)
+    }
+}
+
+fn local_modified(meta: &std::fs::Metadata) -> Option<u64> {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+}
+
+/// The conflict a download would run into, if `local_path` exists
+pub async fn download_conflict(
+    sftp: &SftpSession,
+    remote_path: &str,
+    local_path: &str,
+) -> Option<Conflict> {
+    let dest = tokio::fs::metadata(local_path).await.ok()?;
+    let source = sftp.metadata(remote_path).await.ok()?;
+    Some(Conflict {
+        source_path: remote_path.to_string(),
+        dest_path: local_path.to_string(),
+        source_size: source.len(),
+        source_modified: source.mtime.map(u64::from),
+        dest_size: dest.len(),
+        dest_modified: local_modified(&dest),
+    })
+}
+
+/// The conflict an upload would run into, if `remote_path` exists
+pub async fn upload_conflict(
+    sftp: &SftpSession,
+    local_path: &str,
+    remote_path: &str,
+) -> Option<Conflict> {
+    let dest = sftp.metadata(remote_path).await.ok()?;
+    let source = tokio::fs::metadata(local_path).await.ok()?;
+    Some(Conflict {
+        source_path: local_path.to_string(),
+        dest_path: remote_path.to_string(),
+        source_size: source.len(),
+        source_modified: local_modified(&source),
+        dest_size: dest.len(),
+        dest_modified: dest.mtime.map(u64::from),
+    })
+}
+
+/// Where a download to `local_path` should write, as settled by `conflicts`
+/// if the file exists; `None` to skip it
+pub async fn download_target(
+    sftp: &SftpSession,
+    conflicts: &dyn ConflictHandler,
+    remote_path: &str,
+    local_path: &str,
+) -> Option<String> {
+    let Some(conflict) = download_conflict(sftp, remote_path, local_path).await else {
+        return Some(local_path.to_string());
+    };
+    match conflicts.settle(&conflict).await {
+        ConflictResolution::Overwrite => Some(local_path.to_string()),
+        ConflictResolution::Skip => None,
+        ConflictResolution::Rename => Some(free_local_name(local_path).await),
+    }
+}
+
+/// Where an upload to `remote_path` should write, as settled by `conflicts`
+/// if the file exists; `None` to skip it
+pub async fn upload_target(
+    sftp: &SftpSession,
+    conflicts: &dyn ConflictHandler,
+    local_path: &str,
+    remote_path: &str,
+) -> Option<String> {
+    let Some(conflict) = upload_conflict(sftp, local_path, remote_path).await else {
+        return Some(remote_path.to_string());
+    };
+    match conflicts.settle(&conflict).await {
+        ConflictResolution::Overwrite => Some(remote_path.to_string()),
+        ConflictResolution::Skip => None,
+        ConflictResolution::Rename => Some(free_remote_name(sftp, remote_path).await),
+    }
+}
+
+/// `report.pdf` -> `report (tag).pdf`; directories keep dots in their name
+fn tagged_name(path: &str, tag: &str, is_dir: bool) -> String {
+    let (dir, name) = match path.rfind(['/', '\\']) {
+        Some(i) => path.split_at(i + 1),
+        None => ("", path),
+    };
+    match name.rfind('.').filter(|&i| i > 0 && !is_dir) {
+        Some(i) => format!("{}{} ({}){}", dir, &name[..i], tag, &name[i..]),
+        None => format!("{}{} ({})", dir, name, tag),
+    }
+}
+
+/// `report.pdf` -> `report (n).pdf`
+fn numbered_name(path: &str, n: u32) -> String {
+    tagged_name(path, &n.to_string(), false)
+}
+
+/// First numbered variant of `path` that doesn't exist locally
+pub async fn free_local_name(path: &str) -> String {
+    let mut n = 1;
+    loop {
+        let candidate = numbered_name(path, n);
+        if tokio::fs::metadata(&candidate).await.is_err() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// First numbered variant of `path` that doesn't exist on the server
+pub async fn free_remote_name(sftp: &SftpSession, path: &str) -> String {
+    let mut n = 1;
+    loop {
+        let candidate = numbered_name(path, n);
+        if sftp.symlink_metadata(candidate.as_str()).await.is_err() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// First free `name (copy)`, `name (copy 2)`, ... next to `path` on the server
+pub async fn free_copy_name(sftp: &SftpSession, path: &str, is_dir: bool) -> String {
+    let path = path.trim_end_matches('/');
+    let mut n = 1;
+    loop {
+        let tag = match n {
+            1 => "copy".to_string(),
+            n => format!("copy {}", n),
+        };
+        let candidate = tagged_name(path, &tag, is_dir);
+        if sftp.symlink_metadata(candidate.as_str()).await.is_err() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// What a single-file transfer ended up doing
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferResult {
+    /// Where the file was written; differs from the request after a rename
+    pub destination: String,
+    /// The destination existed and was left alone
+    pub skipped: bool,
+    pub checksum: Option<ChecksumResult>,
+}
+
+/// SHA-256 digests of both ends of a finished transfer
+#[derive(Debug, Clone, Serialize)]
+pub struct ChecksumResult {
+    pub local: String,
+    pub remote: String,
+    pub matches: bool,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// SHA-256 of a local file, in hex
+pub async fn local_sha256(path: &str) -> Result<String, String> {
+    use sha2::{Digest, Sha256};
+    use tokio::io::AsyncReadExt;
+
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| format!("Failed to open local file: {}", e))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("Failed to read local file: {}", e))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Hash a remote file with the server's `sha256sum`, or by reading it back
+/// over SFTP when that isn't available
+async fn remote_sha256(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    sftp: &SftpSession,
+    path: &str,
+) -> Result<String, String> {
+    use sha2::{Digest, Sha256};
+    use tokio::io::AsyncReadExt;
+
+    let command = format!("sha256sum -- {}", ssh::shell_quote(path));
+    let exec = tokio::time::timeout(
+        Duration::from_secs(CHECKSUM_EXEC_TIMEOUT_SECS),
+        ssh::exec(handle, channel_registry, &command),
+    )
+    .await;
+    if let Ok(Ok(output)) = exec {
+        let digest = output.stdout.split_whitespace().next().unwrap_or_default();
+        if output.exit_code == Some(0)
+            && digest.len() == 64
+            && digest.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            return Ok(digest.to_lowercase());
+        }
+    }
+
+    let mut file = sftp
+        .open(path)
+        .await
+        .map_err(|e| format!("Failed to open remote file: {}", e))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("Failed to read remote file: {}", e))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Compare the SHA-256 of a local file and its remote copy
+pub async fn verify_checksum(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    sftp: &SftpSession,
+    local_path: &str,
+    remote_path: &str,
+) -> Result<ChecksumResult, String> {
+    let local = local_sha256(local_path).await?;
+    let remote = remote_sha256(handle, channel_registry, sftp, remote_path).await?;
+    Ok(ChecksumResult {
+        matches: local == remote,
+        local,
+        remote,
+    })
+}
+
+/// A remote text file opened in the built-in editor
+#[derive(Debug, Clone, Serialize)]
+pub struct TextFile {
+    pub content: String,
+    pub size: u64,
+    /// Passed back on save to detect changes made on the server meanwhile
+    pub modified: Option<u64>,
+}
+
+/// Read a remote file for editing. Files over `MAX_TEXT_FILE_SIZE` and
+/// anything that isn't UTF-8 text are refused.
+pub async fn read_text_file(sftp: &SftpSession, path: &str) -> Result<TextFile, String> {
+    use tokio::io::AsyncReadExt;
+
+    let attrs = sftp
+        .metadata(path)
+        .await
+        .map_err(|e| format!("Failed to stat {}: {}", path, e))?;
+    if attrs.is_dir() {
+        return Err(format!("{} is a directory", path));
+    }
+    if attrs.len() > MAX_TEXT_FILE_SIZE {
+        return Err(format!(
+            "File is too large to edit here ({} bytes, limit {})",
+            attrs.len(),
+            MAX_TEXT_FILE_SIZE
+        ));
+    }
+
+    let file = sftp
+        .open(path)
+        .await
+        .map_err(|e| format!("Failed to open remote file: {}", e))?;
+    let mut bytes = Vec::with_capacity(attrs.len() as usize);
+    // Bounded even if the file grows while being read
+    file.take(MAX_TEXT_FILE_SIZE + 1)
+        .read_to_end(&mut bytes)
+        .await
+        .map_err(|e| format!("Failed to read remote file: {}", e))?;
+    if bytes.len() as u64 > MAX_TEXT_FILE_SIZE {
+        return Err("File is too large to edit here".to_string());
+    }
+    if bytes.contains(&0) {
+        return Err("File looks binary".to_string());
+    }
+    let content = String::from_utf8(bytes).map_err(|_| "File is not UTF-8 text".to_string())?;
+
+    Ok(TextFile {
+        content,
+        size: attrs.len(),
+        modified: attrs.mtime.map(u64::from),
+    })
+}
+
+/// Save edited text to a remote file. The content is written to a temporary
+/// file next to it, which then replaces the original, so a dropped
+/// connection never leaves a half-written file. With `expected_modified`,
+/// the save is refused if the file changed on the server since it was read.
+pub async fn write_text_file(
+    sftp: &SftpSession,
+    path: &str,
+    content: &str,
+    expected_modified: Option<u64>,
+) -> Result<(), String> {
+    use russh_sftp::protocol::FileAttributes;
+    use tokio::io::AsyncWriteExt;
+
+    let current = sftp.metadata(path).await.ok();
+    if let (Some(expected), Some(current)) = (expected_modified, &current) {
+        if current.mtime.map(u64::from) != Some(expected) {
+            return Err("File changed on the server since it was opened".to_string());
+        }
+    }
+
+    let temp = format!("{}.rustssh-{}", path, uuid::Uuid::new_v4().simple());
+    let write = async {
+        let mut file = sftp
+            .create(temp.as_str())
+            .await
+            .map_err(|e| format!("Failed to create temporary file: {}", e))?;
+        file.write_all(content.as_bytes())
+            .await
+            .map_err(|e| format!("Failed to write remote file: {}", e))?;
+        file.shutdown()
+            .await
+            .map_err(|e| format!("Failed to close remote file: {}", e))?;
+        if let Some(permissions) = current.as_ref().and_then(|attrs| attrs.permissions) {
+            let attrs = FileAttributes {
+                permissions: Some(permissions),
+                ..FileAttributes::empty()
+            };
+            let _ = sftp.set_metadata(temp.as_str(), attrs).await;
+        }
+        Ok(())
+    };
+    let result: Result<(), String> = write.await;
+    if let Err(e) = result {
+        let _ = sftp.remove_file(temp.as_str()).await;
+        return Err(e);
+    }
+    let result = replace_file(sftp, &temp, path).await;
+    // The temporary file is all that's left of the content when neither
+    // version ended up at `path`
+    if result.is_err() && sftp.metadata(path).await.is_ok() {
+        let _ = sftp.remove_file(temp.as_str()).await;
+    }
+    result
+}
+
+/// Move the finished file `temp` over `path`. Plain SFTP rename refuses to
+/// overwrite, and russh-sftp offers no `posix-rename@openssh.com`, so when
+/// it fails the original is first moved aside and is put back should the
+/// second rename fail too. The original is never deleted before the new
+/// content is in place, and `temp` is left for the caller on failure.
+async fn replace_file(sftp: &SftpSession, temp: &str, path: &str) -> Result<(), String> {
+    if sftp.rename(temp, path).await.is_ok() {
+        return Ok(());
+    }
+    let backup = format!("{}.rustssh-old-{}", path, uuid::Uuid::new_v4().simple());
+    rename(sftp, path, &backup).await?;
+    if let Err(e) = rename(sftp, temp, path).await {
+        return match rename(sftp, &backup, path).await {
+            Ok(()) => Err(e),
+            Err(_) => Err(format!(
+                "{} (the old content is in {} and the new in {})",
+                e, backup, temp
+            )),
+        };
+    }
+    if let Err(e) = sftp.remove_file(backup.as_str()).await {
+        log::warn!("Failed to remove {}: {}", backup, e);
+    }
+    Ok(())
+}
+
+/// A slice of a remote file
+#[derive(Debug, Clone, Serialize)]
+pub struct FileRange {
+    pub offset: u64,
+    pub data: Vec<u8>,
+    pub file_size: u64,
+    /// Whether the slice reaches the end of the file
+    pub eof: bool,
+}
+
+/// Read up to `len` bytes (at most `MAX_RANGE_READ`) from `offset`, for
+/// previews and paging through large files
+pub async fn read_range(
+    sftp: &SftpSession,
+    path: &str,
+    offset: u64,
+    len: u64,
+) -> Result<FileRange, String> {
+    use std::io::SeekFrom;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let file_size = sftp
+        .metadata(path)
+        .await
+        .map_err(|e| format!("Failed to stat {}: {}", path, e))?
+        .len();
+    let len = len.min(MAX_RANGE_READ);
+
+    let mut file = sftp
+        .open(path)
+        .await
+        .map_err(|e| format!("Failed to open remote file: {}", e))?;
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .map_err(|e| format!("Failed to seek remote file: {}", e))?;
+    let mut data = Vec::with_capacity(len as usize);
+    file.take(len)
+        .read_to_end(&mut data)
+        .await
+        .map_err(|e| format!("Failed to read remote file: {}", e))?;
+
+    Ok(FileRange {
+        offset,
+        eof: offset + data.len() as u64 >= file_size,
+        data,
+        file_size,
+    })
+}
+
+/// Create a remote directory
+pub async fn mkdir(sftp: &SftpSession, path: &str) -> Result<(), String> {
+    sftp.create_dir(path)
+        .await
+        .map_err(|e| format!("Failed to create directory: {}", e))
+}
+
+/// Remove a remote file
+pub async fn remove_file(sftp: &SftpSession, path: &str) -> Result<(), String> {
+    sftp.remove_file(path)
+        .await
+        .map_err(|e| format!("Failed to remove file: {}", e))
+}
+
+/// Remove a remote directory
+pub async fn remove_dir(sftp: &SftpSession, path: &str) -> Result<(), String> {
+    sftp.remove_dir(path)
+        .await
+        .map_err(|e| format!("Failed to remove directory: {}", e))
+}
+
+/// Turn a user or group given by name or number into its numeric id, asking
+/// the server (`id -u` / `getent group`) for names
+async fn resolve_id(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    name: &str,
+    is_group: bool,
+) -> Result<u32, String> {
+    if let Ok(id) = name.trim().parse() {
+        return Ok(id);
+    }
+    let kind = if is_group { "group" } else { "user" };
+    let command = if is_group {
+        format!("getent group {}", ssh::shell_quote(name.trim()))
+    } else {
+        format!("id -u {}", ssh::shell_quote(name.trim()))
+    };
+    let output = ssh::exec(handle, channel_registry, &command).await?;
+    // `getent group` prints name:password:gid:members
+    let field = if is_group {
+        output.stdout.trim().split(':').nth(2)
+    } else {
+        Some(output.stdout.trim())
+    };
+    field
+        .filter(|_| output.exit_code == Some(0))
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| format!("Unknown {} on the server: {}", kind, name))
+}
+
+/// Change the owner and/or group of `path` (chown). Either may be a name or
+/// a numeric id; the one left out is kept.
+pub async fn chown(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    sftp: &SftpSession,
+    path: &str,
+    owner: Option<&str>,
+    group: Option<&str>,
+) -> Result<(), String> {
+    use russh_sftp::protocol::FileAttributes;
+
+    if owner.is_none() && group.is_none() {
+        return Err("Nothing to change".to_string());
+    }
+    // SFTP sets uid and gid together, so start from the current pair
+    let current = sftp
+        .metadata(path)
+        .await
+        .map_err(|e| format!("Failed to stat {}: {}", path, e))?;
+    let uid = match owner {
+        Some(owner) => Some(resolve_id(handle, channel_registry, owner, false).await?),
+        None => current.uid,
+    };
+    let gid = match group {
+        Some(group) => Some(resolve_id(handle, channel_registry, group, true).await?),
+        None => current.gid,
+    };
+
+    let attrs = FileAttributes {
+        uid,
+        gid,
+        ..FileAttributes::empty()
+    };
+    sftp.set_metadata(path, attrs)
+        .await
+        .map_err(|e| format!("Failed to change owner: {}", e))
+}
+
+/// Remove a remote file, symlink or empty directory, whichever `path` is
+pub async fn remove(sftp: &SftpSession, path: &str) -> Result<(), String> {
+    let attrs = sftp
+        .symlink_metadata(path)
+        .await
+        .map_err(|e| format!("Failed to stat {}: {}", path, e))?;
+    if attrs.is_dir() {
+        remove_dir(sftp, path).await
+    } else {
+        remove_file(sftp, path).await
+    }
+}
+
+/// Remove `path` and, for a directory, everything below it. Symlinks are
+/// removed, not followed.
+pub async fn remove_tree(sftp: &SftpSession, path: &str) -> Result<(), String> {
+    let attrs = sftp
+        .symlink_metadata(path)
+        .await
+        .map_err(|e| format!("Failed to stat {}: {}", path, e))?;
+    if !attrs.is_dir() {
+        return remove_file(sftp, path).await;
+    }
+
+    // Files go as they're found; directories once they're empty, deepest first
+    let mut dirs = Vec::new();
+    let mut pending = vec![path.to_string()];
+    while let Some(dir) = pending.pop() {
+        for entry in read_entries(sftp, &dir).await? {
+            let child = join(&dir, &entry.file_name());
+            if entry.metadata().is_dir() {
+                pending.push(child);
+            } else {
+                remove_file(sftp, &child).await?;
+            }
+        }
+        dirs.push(dir);
+    }
+    for dir in dirs.iter().rev() {
+        remove_dir(sftp, dir).await?;
+    }
+    Ok(())
+}
+
+/// Outcome for one path of a batch operation
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResult {
+    pub path: String,
+    pub error: Option<String>,
+}
+
+impl BatchResult {
+    pub fn new(path: String, result: Result<(), String>) -> Self {
+        Self {
+            path,
+            error: result.err(),
+        }
+    }
+}
+
+/// Last component of a remote path
+pub fn file_name(path: &str) -> &str {
+    path.trim_end_matches('/').rsplit('/').next().unwrap_or(path)
+}
+
+/// `dir/name`, without doubling the separator
+pub fn join(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
+
+/// Create a symlink at `link_path` pointing to `target`
+pub async fn symlink(sftp: &SftpSession, target: &str, link_path: &str) -> Result<(), String> {
+    sftp.symlink(link_path, target)
+        .await
+        .map_err(|e| format!("Failed to create symlink: {}", e))
+}
+
+/// Rename/move a remote file or directory
+pub async fn rename(sftp: &SftpSession, from: &str, to: &str) -> Result<(), String> {
+    sftp.rename(from, to)
+        .await
+        .map_err(|e| format!("Failed to rename: {}", e))
+}
+
+/// Set file permissions (chmod)
+pub async fn set_permissions(sftp: &SftpSession, path: &str, mode: u32) -> Result<(), String> {
+    use russh_sftp::protocol::FileAttributes;
+    let attrs = FileAttributes {
+        permissions: Some(mode),
+        ..FileAttributes::empty()
+    };
+    sftp.set_metadata(path, attrs)
+        .await
+        .map_err(|e| format!("Failed to set permissions: {}", e))
+}
+
+/// Whether the server lets us run shell commands and has `name` installed
+async fn has_command(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    name: &str,
+) -> bool {
+    // A forced internal-sftp command would never answer, hence the timeout
+    let probe = tokio::time::timeout(
+        Duration::from_secs(COMMAND_PROBE_TIMEOUT_SECS),
+        ssh::exec(handle, channel_registry, &format!("command -v {}", name)),
+    )
+    .await;
+    matches!(
+        probe,
+        Ok(Ok(output)) if output.exit_code == Some(0) && !output.stdout.trim().is_empty()
+    )
+}
+
+/// Whether `name` matches a `find -name` style glob (`*`, `?`)
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to retry from after the last `*`
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Search below `root` with the server's `find`, passing each batch of
+/// matching paths to `on_matches`. Returns `Ok(false)` without searching when
+/// the server can't run `find`, e.g. on SFTP-only accounts.
+pub async fn find_by_exec(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    root: &str,
+    pattern: &str,
+    mut on_matches: impl FnMut(Vec<String>),
+) -> Result<bool, String> {
+    use russh::ChannelMsg;
+
+    if !has_command(handle, channel_registry, "find").await {
+        return Ok(false);
+    }
+
+    let command = format!(
+        "find {} -name {} 2>/dev/null",
+        ssh::shell_quote(root),
+        ssh::shell_quote(pattern)
+    );
+    let (mut channel, _) = ssh::open_exec(handle, channel_registry, &command).await?;
+    let mut pending = Vec::new();
+    while let Some(msg) = channel.wait().await {
+        if let ChannelMsg::Data { data } = msg {
+            pending.extend_from_slice(&data);
+            // Only whole lines; the rest waits for the next chunk
+            let Some(end) = pending.iter().rposition(|&b| b == b'\n') else {
+                continue;
+            };
+            let lines: Vec<u8> = pending.drain(..=end).collect();
+            let paths: Vec<String> = String::from_utf8_lossy(&lines)
+                .lines()
+                .map(str::to_string)
+                .collect();
+            on_matches(paths);
+        }
+    }
+    if !pending.is_empty() {
+        on_matches(vec![String::from_utf8_lossy(&pending).into_owned()]);
+    }
+    Ok(true)
+}
+
+/// Search below `root` by listing every directory over SFTP. Unreadable
+/// directories are skipped, as `find` would.
+pub async fn find_by_walk(
+    sftp: &SftpSession,
+    root: &str,
+    pattern: &str,
+    mut on_matches: impl FnMut(Vec<String>),
+) -> Result<(), String> {
+    let mut pending = vec![root.to_string()];
+    let mut first = true;
+    while let Some(dir) = pending.pop() {
+        let entries = match sftp.read_dir(dir.as_str()).await {
+            Ok(entries) => entries,
+            Err(e) if first => return Err(format!("Failed to read directory: {}", e)),
+            Err(_) => continue,
+        };
+        first = false;
+
+        let mut matches = Vec::new();
+        for entry in entries {
+            let name = entry.file_name();
+            if name == "." || name == ".." {
+                continue;
+            }
+            let path = format!("{}/{}", dir.trim_end_matches('/'), name);
+            if glob_match(pattern, &name) {
+                matches.push(path.clone());
+            }
+            // lstat attributes, so symlinked directories aren't followed
+            if entry.metadata().is_dir() {
+                pending.push(path);
+            }
+        }
+        if !matches.is_empty() {
+            on_matches(matches);
+        }
+    }
+    Ok(())
+}
+
+/// Copy `from` to `to` on the server with `cp -a`, so no data passes through
+/// this machine. Returns `Ok(false)` without copying when the server can't
+/// run `cp`.
+pub async fn copy_by_exec(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    from: &str,
+    to: &str,
+) -> Result<bool, String> {
+    if !has_command(handle, channel_registry, "cp").await {
+        return Ok(false);
+    }
+    let command = format!("cp -a -- {} {}", ssh::shell_quote(from), ssh::shell_quote(to));
+    let output = ssh::exec(handle, channel_registry, &command).await?;
+    if output.exit_code == Some(0) {
+        Ok(true)
+    } else {
+        Err(format!("Copy failed: {}", output.stderr.trim()))
+    }
+}
+
+/// Copy `from` to `to` by reading it back and writing it over SFTP
+pub async fn copy_by_sftp(sftp: &SftpSession, from: &str, to: &str) -> Result<(), String> {
+    copy_between(sftp, from, sftp, to, |_| {}).await
+}
+
+/// Refuse to copy `from` to itself or somewhere inside it, which would go
+/// on copying what it had just copied. Paths are resolved on the server
+/// first so a symlink can't hide that `to` is inside `from`.
+async fn check_not_inside(sftp: &SftpSession, from: &str, to: &str) -> Result<(), String> {
+    let resolve = |path: String| async move { sftp.canonicalize(&path).await.unwrap_or(path) };
+    let source = resolve(from.to_string()).await;
+    let source = source.trim_end_matches('/');
+    let to = to.trim_end_matches('/');
+    let (parent, name) = match to.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some(split) => split,
+        None => (".", to),
+    };
+    let target = join(&resolve(parent.to_string()).await, name);
+    if target == source || target.starts_with(&format!("{}/", source)) {
+        return Err(format!("Can't copy {} into itself", from));
+    }
+    Ok(())
+}
+
+/// Stream `from` on one SFTP session to `to` on another (or the same) one,
+/// without touching the local disk. Directories are copied recursively and
+/// symlinks are recreated as links. Progress totals are only known for a
+/// single file. Within one session, `to` can't be inside `from`.
+pub async fn copy_between(
+    source: &SftpSession,
+    from: &str,
+    dest: &SftpSession,
+    to: &str,
+    report: impl FnMut(TransferProgress),
+) -> Result<(), String> {
+    use russh_sftp::protocol::FileAttributes;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    if std::ptr::eq(source, dest) {
+        check_not_inside(source, from, to).await?;
+    }
+
+    let total = match source.symlink_metadata(from).await {
+        Ok(attrs) if attrs.is_regular() => attrs.len(),
+        _ => 0,
+    };
+    let mut progress = Progress::new(total, report);
+
+    let mut pending = vec![(from.to_string(), to.to_string())];
+    while let Some((from, to)) = pending.pop() {
+        let attrs = source
+            .symlink_metadata(from.as_str())
+            .await
+            .map_err(|e| format!("Failed to stat {}: {}", from, e))?;
+
+        if attrs.is_symlink() {
+            let target = source
+                .read_link(from.as_str())
+                .await
+                .map_err(|e| format!("Failed to read link {}: {}", from, e))?;
+            symlink(dest, &target, &to).await?;
+            continue;
+        }
+
+        if attrs.is_dir() {
+            mkdir(dest, &to).await?;
+            for entry in list_dir(source, &from, &ListFilter::default()).await? {
+                pending.push((entry.path, join(&to, &entry.name)));
+            }
+        } else {
+            let mut reader = source
+                .open(from.as_str())
+                .await
+                .map_err(|e| format!("Failed to open {}: {}", from, e))?;
+            let mut writer = dest
+                .create(to.as_str())
+                .await
+                .map_err(|e| format!("Failed to create {}: {}", to, e))?;
+            let mut buf = vec![0u8; CHUNK_SIZE];
+            loop {
+                let n = reader
+                    .read(&mut buf)
+                    .await
+                    .map_err(|e| format!("Failed to read {}: {}", from, e))?;
+                if n == 0 {
+                    break;
+                }
+                writer
+                    .write_all(&buf[..n])
+                    .await
+                    .map_err(|e| format!("Failed to write {}: {}", to, e))?;
+                progress.advance(n);
+            }
+            writer
+                .shutdown()
+                .await
+                .map_err(|e| format!("Failed to close {}: {}", to, e))?;
+        }
+
+        // Keep the mode and times, like `cp -a`
+        let keep = FileAttributes {
+            permissions: attrs.permissions,
+            atime: attrs.atime,
+            mtime: attrs.mtime,
+            ..FileAttributes::empty()
+        };
+        let _ = dest.set_metadata(to.as_str(), keep).await;
+    }
+
+    progress.finish();
+    Ok(())
+}
+
+/// Total size of the regular files below a directory
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct DirSize {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+/// Size a directory with the server's `du -sb` (GNU) and `find`. Returns
+/// `Ok(None)` when those aren't available or didn't give a clean answer.
+pub async fn dir_size_by_exec(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    path: &str,
+) -> Result<Option<DirSize>, String> {
+    if !has_command(handle, channel_registry, "du").await {
+        return Ok(None);
+    }
+    let quoted = ssh::shell_quote(path);
+    let command = format!("du -sb -- {0} | cut -f1 && find {0} -type f | wc -l", quoted);
+    let output = ssh::exec(handle, channel_registry, &command).await?;
+    if output.exit_code != Some(0) {
+        return Ok(None);
+    }
+    let mut lines = output.stdout.lines().map(str::trim);
+    let bytes = lines.next().and_then(|l| l.parse().ok());
+    let files = lines.next().and_then(|l| l.parse().ok());
+    Ok(bytes.zip(files).map(|(bytes, files)| DirSize { bytes, files }))
+}
+
+/// Size a directory by listing it recursively over SFTP, reporting the
+/// running total as it goes. Symlinks aren't followed and unreadable
+/// subdirectories are skipped.
+pub async fn dir_size_by_walk(
+    sftp: &SftpSession,
+    path: &str,
+    mut report: impl FnMut(DirSize),
+) -> Result<DirSize, String> {
+    let mut size = DirSize::default();
+    let mut last_report = Instant::now();
+    let mut pending = vec![path.to_string()];
+    let mut first = true;
+    while let Some(dir) = pending.pop() {
+        let entries = match sftp.read_dir(dir.as_str()).await {
+            Ok(entries) => entries,
+            Err(e) if first => return Err(format!("Failed to read directory: {}", e)),
+            Err(_) => continue,
+        };
+        first = false;
+
+        for entry in entries {
+            let name = entry.file_name();
+            if name == "." || name == ".." {
+                continue;
+            }
+            let attrs = entry.metadata();
+            if attrs.is_dir() {
+                pending.push(join(&dir, &name));
+            } else if attrs.is_regular() {
+                size.bytes += attrs.len();
+                size.files += 1;
+            }
+        }
+        if last_report.elapsed() >= Duration::from_millis(PROGRESS_INTERVAL_MS) {
+            last_report = Instant::now();
+            report(size);
+        }
+    }
+    Ok(size)
+}
+
+/// Size of the filesystem holding a path, in bytes
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DiskSpace {
+    pub total: u64,
+    pub free: u64,
+    /// What an unprivileged user can still write; less than `free` when
+    /// blocks are reserved for root
+    pub available: u64,
+}
+
+/// Ask the server through the `statvfs@openssh.com` extension. Returns
+/// `Ok(None)` when the server doesn't support it.
+pub async fn disk_space_by_statvfs(
+    sftp: &SftpSession,
+    path: &str,
+) -> Result<Option<DiskSpace>, String> {
+    let stats = sftp
+        .fs_info(path)
+        .await
+        .map_err(|e| format!("Failed to query free space: {}", e))?;
+    Ok(stats.map(|stats| DiskSpace {
+        total: stats.blocks * stats.fragment_size,
+        free: stats.blocks_free * stats.fragment_size,
+        available: stats.blocks_avail * stats.fragment_size,
+    }))
+}
+
+/// Ask the server's `df` (POSIX output, 1 KiB blocks). Returns `Ok(None)`
+/// when `df` isn't available or its output can't be read.
+pub async fn disk_space_by_exec(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    path: &str,
+) -> Result<Option<DiskSpace>, String> {
+    if !has_command(handle, channel_registry, "df").await {
+        return Ok(None);
+    }
+    let command = format!("df -Pk -- {}", ssh::shell_quote(path));
+    let output = ssh::exec(handle, channel_registry, &command).await?;
+    if output.exit_code != Some(0) {
+        return Ok(None);
+    }
+    // Filesystem 1024-blocks Used Available Capacity Mounted-on
+    let Some(line) = output.stdout.lines().nth(1) else {
+        return Ok(None);
+    };
+    let fields: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .take(3)
+        .filter_map(|field| field.parse().ok())
+        .collect();
+    let [total, used, available] = fields[..] else {
+        return Ok(None);
+    };
+    Ok(Some(DiskSpace {
+        total: total * 1024,
+        free: total.saturating_sub(used) * 1024,
+        available: available * 1024,
+    }))
+}
+
+/// Pack a remote directory into a gzipped tarball in the server's temp
+/// directory and return the archive's path; the caller removes it when done.
+/// Returns `Ok(None)` when the server can't run `tar`.
+pub async fn archive_dir(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    path: &str,
+) -> Result<Option<String>, String> {
+    if !has_command(handle, channel_registry, "tar").await {
+        return Ok(None);
+    }
+    // Keep the directory's own name as the archive's top level
+    let trimmed = path.trim_end_matches('/');
+    let (parent, name) = match trimmed.rfind('/') {
+        Some(0) => ("/", &trimmed[1..]),
+        Some(i) => (&trimmed[..i], &trimmed[i + 1..]),
+        None => (".", trimmed),
+    };
+    let (parent, name) = if name.is_empty() { ("/", ".") } else { (parent, name) };
+    let command = format!(
+        "f=$(mktemp) || exit 1; \
+         if tar -czf \"$f\" -C {} -- {}; then echo \"$f\"; else rm -f \"$f\"; exit 1; fi",
+        ssh::shell_quote(parent),
+        ssh::shell_quote(name)
+    );
+    let output = ssh::exec(handle, channel_registry, &command).await?;
+    let archive = output.stdout.trim();
+    if output.exit_code != Some(0) || archive.is_empty() {
+        return Err(format!("Failed to compress {}: {}", path, output.stderr.trim()));
+    }
+    Ok(Some(archive.to_string()))
+}
+
+/// Ways to unpack an archive on the server, in order of preference: the
+/// program that has to be installed and the command to run
+fn extract_commands(archive: &str, dest: &str) -> Result<Vec<(&'static str, String)>, String> {
+    let lower = archive.to_lowercase();
+    let (a, d) = (ssh::shell_quote(archive), ssh::shell_quote(dest));
+    let tarball = [".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tbz2", ".tar.xz", ".txz"];
+    if tarball.iter().any(|ext| lower.ends_with(ext)) {
+        // tar works out the compression by itself when extracting
+        Ok(vec![
+            ("tar", format!("tar -xf {} -C {}", a, d)),
+            ("python3", format!("python3 -m tarfile -e {} {}", a, d)),
+        ])
+    } else if lower.ends_with(".zip") {
+        Ok(vec![
+            ("unzip", format!("unzip -o -q {} -d {}", a, d)),
+            ("bsdtar", format!("bsdtar -xf {} -C {}", a, d)),
+            ("python3", format!("python3 -m zipfile -e {} {}", a, d)),
+        ])
+    } else {
+        Err(format!("Unsupported archive type: {}", file_name(archive)))
+    }
+}
+
+/// Unpack a tar (optionally compressed) or zip archive into `dest` on the
+/// server, creating `dest` if needed. Uses the first of the usual tools the
+/// server has, falling back to Python's archive modules.
+pub async fn extract(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    archive: &str,
+    dest: &str,
+) -> Result<(), String> {
+    let mut command = None;
+    for (program, candidate) in extract_commands(archive, dest)? {
+        if has_command(handle, channel_registry, program).await {
+            command = Some(candidate);
+            break;
+        }
+    }
+    let command = command
+        .ok_or_else(|| format!("The server has no tool to extract {}", file_name(archive)))?;
+    let command = format!("mkdir -p -- {} && {}", ssh::shell_quote(dest), command);
+    let output = ssh::exec(handle, channel_registry, &command).await?;
+    if output.exit_code == Some(0) {
+        Ok(())
+    } else {
+        Err(format!("Extraction failed: {}", output.stderr.trim()))
+    }
+}
+
+/// Whether `e` is the server saying there's no such file, rather than the
+/// session or channel failing
+fn is_no_such_file(e: &russh_sftp::client::error::Error) -> bool {
+    use russh_sftp::client::error::Error;
+    use russh_sftp::protocol::StatusCode;
+
+    matches!(e, Error::Status(status) if status.status_code == StatusCode::NoSuchFile)
+}
+
+/// Emit the last lines of a remote file, then (with `follow`) keep polling
+/// it and emit lines as they are appended, like `tail -F`. A file that
+/// shrinks is taken to have been truncated or rotated and is read again from
+/// the start. With `follow` this only returns on error: a missing file is
+/// waited for, but a failing session or channel ends the tail.
+pub async fn tail(
+    sftp: &SftpSession,
+    path: &str,
+    follow: bool,
+    mut on_lines: impl FnMut(Vec<String>),
+) -> Result<(), String> {
+    let mut offset = sftp
+        .metadata(path)
+        .await
+        .map(|attrs| attrs.len())
+        .map_err(|e| format!("Failed to stat {}: {}", path, e))?
+        .saturating_sub(TAIL_INITIAL_BYTES);
+    // Starting mid-file, the first partial line is dropped
+    let mut skip_partial = offset > 0;
+    let mut partial: Vec<u8> = Vec::new();
+
+    loop {
+        let file_size = match sftp.metadata(path).await {
+            Ok(attrs) => attrs.len(),
+            // Between a rotation's rename and the new file appearing
+            Err(e) if follow && is_no_such_file(&e) => {
+                tokio::time::sleep(Duration::from_millis(TAIL_POLL_MS)).await;
+                continue;
+            }
+            Err(e) => return Err(format!("Failed to stat {}: {}", path, e)),
+        };
+        if file_size < offset {
+            offset = 0;
+            partial.clear();
+            skip_partial = false;
+        }
+
+        while offset < file_size {
+            let range = read_range(sftp, path, offset, file_size - offset).await?;
+            if range.data.is_empty() {
+                break;
+            }
+            offset += range.data.len() as u64;
+            partial.extend_from_slice(&range.data);
+
+            let Some(end) = partial.iter().rposition(|&b| b == b'\n') else {
+                continue;
+            };
+            let complete: Vec<u8> = partial.drain(..=end).collect();
+            let mut lines: Vec<String> = String::from_utf8_lossy(&complete)
+                .lines()
+                .map(str::to_string)
+                .collect();
+            if skip_partial {
+                skip_partial = false;
+                if !lines.is_empty() {
+                    lines.remove(0);
+                }
+            }
+            if !lines.is_empty() {
+                on_lines(lines);
+            }
+        }
+
+        if !follow {
+            if !partial.is_empty() {
+                on_lines(vec![String::from_utf8_lossy(&partial).into_owned()]);
+            }
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(TAIL_POLL_MS)).await;
+    }
 }
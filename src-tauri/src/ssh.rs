@@ -1,24 +1,153 @@
 use async_trait::async_trait;
 use russh::*;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::process::{Child, ChildStdin, ChildStdout};
+use tokio::sync::{oneshot, Mutex};
 use uuid::Uuid;
 
-use crate::connection::{AuthType, Connection};
+use crate::channels::{self, ChannelKind, ChannelRegistry};
+use crate::connection::{AuthType, Connection, IpPreference};
+use crate::keychain;
+use crate::knock;
+use crate::mime::MimeCache;
+use crate::terminal::TerminalSession;
+use crate::known_hosts::{HostKeyStatus, KnownHost, KnownHostsStore};
+use crate::ppk;
+use crate::proxy;
+use crate::sftp::{CachedSftp, ConflictResolution, IdNames};
+use crate::transfer::TransferQueue;
+use crate::tunnel::{self, RemoteForwards, Tunnel};
+use crate::vault::VaultKey;
+use crate::vpn::Vpn;
+use crate::watch::FileWatches;
+
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 30;
+/// Head start given to each address before the next one is tried in parallel
+const CONNECTION_ATTEMPT_DELAY_MS: u64 = 250;
+/// Keepalive interval for profiles that don't set one
+const DEFAULT_KEEPALIVE_INTERVAL_SECS: u64 = 5;
+
+/// Maps russh channel ids to the terminal ids the frontend knows them by
+pub type TerminalChannels = Arc<Mutex<HashMap<ChannelId, String>>>;
+
+/// Answers awaited from the frontend, keyed by session id
+pub type PendingPrompts<T> = Arc<Mutex<HashMap<String, oneshot::Sender<T>>>>;
 
 /// Client handler for russh - receives server events
 pub struct ClientHandler {
     pub session_id: String,
+    pub host: String,
+    pub port: u16,
     pub sender: tokio::sync::mpsc::Sender<SshEvent>,
-    pub shell_channel_id: Arc<Mutex<Option<ChannelId>>>,
+    pub terminal_channels: TerminalChannels,
+    pub known_hosts: KnownHostsStore,
+    pub host_key_prompts: PendingPrompts<bool>,
+    pub forward_agent: bool,
+    pub remote_forwards: RemoteForwards,
+    pub channel_registry: ChannelRegistry,
+    /// Why the connection ended, reported with `SshEvent::Close`
+    pub disconnect_reason: Option<String>,
+    /// Whether dropping the handler reports `SshEvent::Close`; jump hosts
+    /// share the session's sender but not its lifetime
+    pub reports_close: bool,
 }
 
 #[derive(Debug, Clone)]
 pub enum SshEvent {
-    Data(Vec<u8>),
+    Data {
+        channel_id: String,
+        data: Vec<u8>,
+        /// Sent on the extended data stream (SSH_EXTENDED_DATA_STDERR)
+        stderr: bool,
+    },
     Error(String),
-    Close,
+    Close {
+        reason: Option<String>,
+    },
+    HostKeyPrompt {
+        host: String,
+        port: u16,
+        key_type: String,
+        fingerprint: String,
+        previous_fingerprint: Option<String>,
+    },
+    AuthPrompt {
+        name: String,
+        instructions: String,
+        prompts: Vec<AuthPrompt>,
+    },
+    SecurityKeyTouch {
+        fingerprint: String,
+    },
+    Banner(String),
+    CredentialRequest {
+        kind: String,
+        host: String,
+        username: String,
+    },
+    ExitStatus {
+        channel_id: String,
+        exit_status: u32,
+    },
+    ExitSignal {
+        channel_id: String,
+        signal: String,
+        core_dumped: bool,
+        error_message: String,
+    },
+}
+
+/// A single keyboard-interactive question sent by the server
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthPrompt {
+    pub prompt: String,
+    pub echo: bool,
+}
+
+impl ClientHandler {
+    pub fn new(
+        session_id: &str,
+        connection: &Connection,
+        sender: tokio::sync::mpsc::Sender<SshEvent>,
+        terminal_channels: TerminalChannels,
+        remote_forwards: RemoteForwards,
+        channel_registry: ChannelRegistry,
+        known_hosts: KnownHostsStore,
+        manager: &SessionManager,
+    ) -> Self {
+        Self {
+            session_id: session_id.to_string(),
+            host: connection.host.clone(),
+            port: connection.port,
+            sender,
+            terminal_channels,
+            known_hosts,
+            host_key_prompts: manager.host_key_prompts.clone(),
+            forward_agent: connection.forward_agent,
+            remote_forwards,
+            channel_registry,
+            disconnect_reason: None,
+            reports_close: true,
+        }
+    }
+}
+
+impl Drop for ClientHandler {
+    fn drop(&mut self) {
+        // The session task owns the handler, so it is only dropped once the
+        // connection is gone (server disconnect, keepalive timeout, ...)
+        if !self.reports_close {
+            return;
+        }
+        let _ = self.sender.try_send(SshEvent::Close {
+            reason: self.disconnect_reason.take(),
+        });
+    }
 }
 
 #[async_trait]
@@ -27,11 +156,170 @@ impl client::Handler for ClientHandler {
 
     async fn check_server_key(
         &mut self,
-        _server_public_key: &russh_keys::key::PublicKey,
+        server_public_key: &russh_keys::key::PublicKey,
     ) -> Result<bool, Self::Error> {
-        // Accept all server keys for now (MVP)
-        // TODO: Implement known_hosts verification
-        Ok(true)
+        let fingerprint = server_public_key.fingerprint();
+        let previous_fingerprint = match self.known_hosts.check(&self.host, self.port, &fingerprint) {
+            HostKeyStatus::Trusted => return Ok(true),
+            HostKeyStatus::Unknown => None,
+            HostKeyStatus::Changed { previous_fingerprint } => Some(previous_fingerprint),
+        };
+
+        // Unknown or changed key: ask the frontend and wait for the answer
+        let (tx, rx) = oneshot::channel();
+        self.host_key_prompts
+            .lock()
+            .await
+            .insert(self.session_id.clone(), tx);
+
+        let key_type = server_public_key.name().to_string();
+        let _ = self
+            .sender
+            .send(SshEvent::HostKeyPrompt {
+                host: self.host.clone(),
+                port: self.port,
+                key_type: key_type.clone(),
+                fingerprint: fingerprint.clone(),
+                previous_fingerprint,
+            })
+            .await;
+
+        let accepted = rx.await.unwrap_or(false);
+        if accepted {
+            self.known_hosts
+                .trust(KnownHost {
+                    host: self.host.clone(),
+                    port: self.port,
+                    key_type,
+                    fingerprint,
+                })
+                .map_err(anyhow::Error::msg)?;
+        }
+        Ok(accepted)
+    }
+
+    async fn disconnected(
+        &mut self,
+        reason: client::DisconnectReason<Self::Error>,
+    ) -> Result<(), Self::Error> {
+        match reason {
+            client::DisconnectReason::ReceivedDisconnect(info) => {
+                let code = format!("{:?}", info.reason_code);
+                self.disconnect_reason = Some(if info.message.is_empty() {
+                    code
+                } else {
+                    format!("{} ({})", info.message, code)
+                });
+                Ok(())
+            }
+            client::DisconnectReason::Error(e) => {
+                self.disconnect_reason = Some(format!("Connection lost: {}", e));
+                Err(e)
+            }
+        }
+    }
+
+    async fn auth_banner(
+        &mut self,
+        banner: &str,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        let _ = self.sender.send(SshEvent::Banner(banner.to_string())).await;
+        Ok(())
+    }
+
+    async fn server_channel_open_agent_forward(
+        &mut self,
+        channel: Channel<client::Msg>,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        if !self.forward_agent {
+            return Ok(());
+        }
+        // Pipe the remote agent requests straight into the local agent
+        tokio::spawn(async move {
+            match connect_local_agent().await {
+                Ok(mut agent) => {
+                    let mut stream = channel.into_stream();
+                    let _ = tokio::io::copy_bidirectional(&mut stream, &mut agent).await;
+                }
+                Err(e) => log::warn!("Agent forwarding failed: {}", e),
+            }
+        });
+        Ok(())
+    }
+
+    async fn server_channel_open_forwarded_tcpip(
+        &mut self,
+        channel: Channel<client::Msg>,
+        connected_address: &str,
+        connected_port: u32,
+        _originator_address: &str,
+        _originator_port: u32,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        match tunnel::remote_target(&self.remote_forwards, connected_address, connected_port).await
+        {
+            Some(forward) => {
+                tokio::spawn(tunnel::serve_forwarded(
+                    channel,
+                    forward,
+                    self.channel_registry.clone(),
+                ));
+            }
+            None => {
+                log::warn!(
+                    "Server opened a forward for unknown port {}:{}",
+                    connected_address,
+                    connected_port
+                );
+                let _ = channel.close().await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn exit_status(
+        &mut self,
+        channel: ChannelId,
+        exit_status: u32,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        let terminal_id = self.terminal_channels.lock().await.get(&channel).cloned();
+        if let Some(channel_id) = terminal_id {
+            let _ = self
+                .sender
+                .send(SshEvent::ExitStatus {
+                    channel_id,
+                    exit_status,
+                })
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn exit_signal(
+        &mut self,
+        channel: ChannelId,
+        signal_name: Sig,
+        core_dumped: bool,
+        error_message: &str,
+        _lang_tag: &str,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        let terminal_id = self.terminal_channels.lock().await.get(&channel).cloned();
+        if let Some(channel_id) = terminal_id {
+            let _ = self
+                .sender
+                .send(SshEvent::ExitSignal {
+                    channel_id,
+                    signal: signal_name_of(&signal_name),
+                    core_dumped,
+                    error_message: error_message.to_string(),
+                })
+                .await;
+        }
+        Ok(())
     }
 
     async fn data(
@@ -40,70 +328,1029 @@ impl client::Handler for ClientHandler {
         data: &[u8],
         _session: &mut client::Session,
     ) -> Result<(), Self::Error> {
-        let shell_id = self.shell_channel_id.lock().await;
-        if let Some(id) = *shell_id {
-            if id == channel {
-                let _ = self.sender.send(SshEvent::Data(data.to_vec())).await;
-            }
+        channels::record_in(&self.channel_registry, channel, data.len()).await;
+        let terminal_id = self.terminal_channels.lock().await.get(&channel).cloned();
+        if let Some(channel_id) = terminal_id {
+            let _ = self
+                .sender
+                .send(SshEvent::Data {
+                    channel_id,
+                    data: data.to_vec(),
+                    stderr: false,
+                })
+                .await;
         }
         Ok(())
     }
+
+    async fn extended_data(
+        &mut self,
+        channel: ChannelId,
+        ext: u32,
+        data: &[u8],
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        channels::record_in(&self.channel_registry, channel, data.len()).await;
+        // Type 1 is stderr, the only extended data type RFC 4254 defines
+        if ext != 1 {
+            return Ok(());
+        }
+        let terminal_id = self.terminal_channels.lock().await.get(&channel).cloned();
+        if let Some(channel_id) = terminal_id {
+            let _ = self
+                .sender
+                .send(SshEvent::Data {
+                    channel_id,
+                    data: data.to_vec(),
+                    stderr: true,
+                })
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn channel_close(
+        &mut self,
+        channel: ChannelId,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        channels::unregister(&self.channel_registry, channel).await;
+        Ok(())
+    }
+}
+
+/// Output of a one-shot command run on an exec channel
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: Option<u32>,
 }
 
 /// Represents an active SSH session
 pub struct SshSession {
     pub id: String,
-    pub handle: client::Handle<ClientHandler>,
-    pub channel: Channel<client::Msg>,
+    pub handle: Arc<client::Handle<ClientHandler>>,
+    /// Terminal channels by terminal id; the first one is keyed by the session id
+    pub channels: HashMap<String, Channel<client::Msg>>,
+    pub terminal_channels: TerminalChannels,
+    pub connection: Connection,
+    /// Bastion connections the session is tunnelled through; kept alive with it
+    pub jump_handles: Vec<client::Handle<ClientHandler>>,
+    pub remote_forwards: RemoteForwards,
+    /// Every open channel (shells, SFTP, exec, forwards) for the inspector
+    pub channel_registry: ChannelRegistry,
+    /// Port forwards by tunnel id
+    pub tunnels: HashMap<String, Tunnel>,
+    /// Connected without a shell, for SFTP only
+    pub sftp_only: bool,
+    /// Subnet routing, when started
+    pub vpn: Option<Vpn>,
+    /// SFTP session shared by the file commands, with the channel it runs on
+    pub sftp: Arc<Mutex<Option<CachedSftp>>>,
+    /// The server's user and group names, looked up as listings show them
+    pub id_names: Arc<Mutex<IdNames>>,
+    /// File types the server's `file` reported, for listings
+    pub mime_cache: Arc<Mutex<MimeCache>>,
 }
 
 impl SshSession {
     pub async fn connect(
+        session_id: String,
         connection: &Connection,
+        jump_hosts: &[Connection],
         sender: tokio::sync::mpsc::Sender<SshEvent>,
+        known_hosts: KnownHostsStore,
+        manager: &SessionManager,
+        sftp_only: bool,
     ) -> Result<Self, String> {
-        let session_id = Uuid::new_v4().to_string();
+        // Secrets kept in the OS keychain are only read now
+        let connection = &keychain::resolve(connection.clone()).await;
+        let mut resolved_jumps = Vec::with_capacity(jump_hosts.len());
+        for jump in jump_hosts {
+            resolved_jumps.push(keychain::resolve(jump.clone()).await);
+        }
+        let jump_hosts = &resolved_jumps;
 
-        let config = Arc::new(client::Config {
-            ..Default::default()
-        });
+        let terminal_channels: TerminalChannels = Arc::new(Mutex::new(HashMap::new()));
+
+        // Chain through each bastion in turn, tunnelling the next hop over a
+        // direct-tcpip channel of the previous one (like `ssh -J`)
+        let mut jump_handles: Vec<client::Handle<ClientHandler>> = Vec::new();
+        for jump in jump_hosts {
+            let mut handler = ClientHandler::new(
+                &session_id,
+                jump,
+                sender.clone(),
+                Arc::new(Mutex::new(HashMap::new())),
+                Arc::new(Mutex::new(HashMap::new())),
+                Arc::new(Mutex::new(HashMap::new())),
+                known_hosts.clone(),
+                manager,
+            );
+            // A bastion going away takes the tunnelled connection down with
+            // it, whose handler then reports the close
+            handler.reports_close = false;
+            let handle = connect_hop(jump, jump_handles.last(), handler, manager).await?;
+            jump_handles.push(handle);
+        }
+
+        let remote_forwards: RemoteForwards = Arc::new(Mutex::new(HashMap::new()));
+        let channel_registry: ChannelRegistry = Arc::new(Mutex::new(HashMap::new()));
+        let handler = ClientHandler::new(
+            &session_id,
+            connection,
+            sender.clone(),
+            terminal_channels.clone(),
+            remote_forwards.clone(),
+            channel_registry.clone(),
+            known_hosts,
+            manager,
+        );
+        let handle = connect_hop(connection, jump_handles.last(), handler, manager).await?;
+
+        let mut session = Self {
+            id: session_id,
+            sftp_only: true,
+            handle: Arc::new(handle),
+            channels: HashMap::new(),
+            terminal_channels,
+            connection: connection.clone(),
+            jump_handles,
+            remote_forwards,
+            channel_registry,
+            tunnels: HashMap::new(),
+            vpn: None,
+            sftp: Arc::default(),
+            id_names: Arc::default(),
+            mime_cache: Arc::default(),
+        };
 
-        let shell_channel_id = Arc::new(Mutex::new(None));
+        // File-transfer-only sessions never get a PTY or shell
+        if !sftp_only {
+            session.start_shell().await?;
+        }
+        Ok(session)
+    }
 
-        let handler = ClientHandler {
-            session_id: session_id.clone(),
-            sender: sender.clone(),
-            shell_channel_id: shell_channel_id.clone(),
+    /// Open the main terminal (keyed by the session id), running the profile's
+    /// auto-attach command in it if there is one
+    pub async fn start_shell(&mut self) -> Result<(), String> {
+        let channel = open_terminal(
+            &self.handle,
+            &self.connection,
+            &self.terminal_channels,
+            &self.channel_registry,
+            &self.id,
+            80,
+            24,
+        )
+        .await?;
+        if let Some(auto_attach) = &self.connection.auto_attach {
+            let command = format!("{}\n", auto_attach.command());
+            channel
+                .data(command.as_bytes())
+                .await
+                .map_err(|e| format!("Failed to attach to multiplexer session: {}", e))?;
+        }
+        self.channels.insert(self.id.clone(), channel);
+        self.sftp_only = false;
+        Ok(())
+    }
+
+    fn terminal(&self, channel_id: &str) -> Result<&Channel<client::Msg>, String> {
+        self.channels
+            .get(channel_id)
+            .ok_or_else(|| "Channel not found".to_string())
+    }
+
+    pub async fn write(&self, channel_id: &str, data: &[u8]) -> Result<(), String> {
+        let channel = self.terminal(channel_id)?;
+        channel
+            .data(data)
+            .await
+            .map_err(|e| format!("Write failed: {}", e))?;
+        channels::record_out(&self.channel_registry, channel.id(), data.len()).await;
+        Ok(())
+    }
+
+    /// Open another PTY/shell on the existing connection, returning its terminal id
+    pub async fn open_channel(&mut self, cols: u32, rows: u32) -> Result<String, String> {
+        let channel_id = Uuid::new_v4().to_string();
+        let channel = open_terminal(
+            &self.handle,
+            &self.connection,
+            &self.terminal_channels,
+            &self.channel_registry,
+            &channel_id,
+            cols,
+            rows,
+        )
+        .await?;
+        self.channels.insert(channel_id.clone(), channel);
+        Ok(channel_id)
+    }
+
+    pub async fn close_channel(&mut self, channel_id: &str) -> Result<(), String> {
+        let channel = self
+            .channels
+            .remove(channel_id)
+            .ok_or("Channel not found")?;
+        self.terminal_channels.lock().await.remove(&channel.id());
+        channel
+            .close()
+            .await
+            .map_err(|e| format!("Close failed: {}", e))
+    }
+
+    /// Deliver a signal (e.g. "INT", "SIGKILL") to the process behind a terminal
+    pub async fn send_signal(&self, channel_id: &str, signal: &str) -> Result<(), String> {
+        let name = signal.trim().to_uppercase();
+        let name = name.strip_prefix("SIG").unwrap_or(&name);
+        let sig = match name {
+            "ABRT" => Sig::ABRT,
+            "ALRM" => Sig::ALRM,
+            "FPE" => Sig::FPE,
+            "HUP" => Sig::HUP,
+            "ILL" => Sig::ILL,
+            "INT" => Sig::INT,
+            "KILL" => Sig::KILL,
+            "PIPE" => Sig::PIPE,
+            "QUIT" => Sig::QUIT,
+            "SEGV" => Sig::SEGV,
+            "TERM" => Sig::TERM,
+            "USR1" => Sig::USR1,
+            "" => return Err("Signal name is empty".to_string()),
+            other => Sig::Custom(other.to_string()),
         };
+        self.terminal(channel_id)?
+            .signal(sig)
+            .await
+            .map_err(|e| format!("Signal failed: {}", e))
+    }
 
-        let addr = format!("{}:{}", connection.host, connection.port);
-        let mut handle = client::connect(config, addr, handler)
+    pub async fn resize(&self, channel_id: &str, cols: u32, rows: u32) -> Result<(), String> {
+        self.terminal(channel_id)?
+            .window_change(cols, rows, 0, 0)
             .await
-            .map_err(|e| format!("Connection failed: {}", e))?;
-
-        // Authenticate
-        let authenticated = match connection.auth_type {
-            AuthType::Password => {
-                let password = connection
-                    .password
-                    .as_deref()
-                    .ok_or("Password not provided")?;
-                handle
-                    .authenticate_password(&connection.username, password)
+            .map_err(|e| format!("Resize failed: {}", e))
+    }
+
+    pub async fn close(self) -> Result<(), String> {
+        for channel in self.channels.values() {
+            channel
+                .close()
+                .await
+                .map_err(|e| format!("Close failed: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Start a command on a new exec channel (no PTY); its output arrives on
+/// the channel, and the receiver fires if the inspector closes it
+pub async fn open_exec(
+    handle: &client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    command: &str,
+) -> Result<(Channel<client::Msg>, oneshot::Receiver<()>), String> {
+    let channel = handle
+        .channel_open_session()
+        .await
+        .map_err(|e| format!("Channel open failed: {}", e))?;
+    let (_, close) =
+        channels::register(channel_registry, channel.id(), ChannelKind::Exec, command).await;
+
+    channel
+        .exec(true, command)
+        .await
+        .map_err(|e| format!("Exec failed: {}", e))?;
+    Ok((channel, close))
+}
+
+/// Quote `s` as a single POSIX shell word
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Output of an exec channel, gathered as its messages arrive
+#[derive(Default)]
+struct ExecCollector {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    exit_code: Option<u32>,
+}
+
+impl ExecCollector {
+    fn push(&mut self, msg: &ChannelMsg) {
+        match msg {
+            ChannelMsg::Data { data } => self.stdout.extend_from_slice(data),
+            // Extended data type 1 is stderr
+            ChannelMsg::ExtendedData { data, ext: 1 } => self.stderr.extend_from_slice(data),
+            ChannelMsg::ExitStatus { exit_status } => self.exit_code = Some(*exit_status),
+            _ => {}
+        }
+    }
+
+    fn finish(self) -> ExecOutput {
+        ExecOutput {
+            stdout: String::from_utf8_lossy(&self.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&self.stderr).into_owned(),
+            exit_code: self.exit_code,
+        }
+    }
+}
+
+/// Run a command on its own exec channel (no PTY) and collect its output.
+/// The command gets an empty stdin. There's no timeout: it runs until the
+/// server closes the channel, so callers that can't wait on a hung command
+/// wrap this in `tokio::time::timeout`.
+pub async fn exec(
+    handle: &client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    command: &str,
+) -> Result<ExecOutput, String> {
+    let (mut channel, mut close) = open_exec(handle, channel_registry, command).await?;
+    // Nothing is written to stdin, so a command reading it ends instead of hanging
+    channel
+        .eof()
+        .await
+        .map_err(|e| format!("Exec failed: {}", e))?;
+
+    let mut output = ExecCollector::default();
+    let mut close_seen = false;
+
+    loop {
+        let msg = tokio::select! {
+            msg = channel.wait() => msg,
+            requested = &mut close, if !close_seen => {
+                close_seen = true;
+                if requested.is_ok() {
+                    let _ = channel.close().await;
+                }
+                continue;
+            }
+        };
+        match msg {
+            Some(msg) => output.push(&msg),
+            None => break,
+        }
+    }
+
+    Ok(output.finish())
+}
+
+/// Outcome of one host's run in `ssh_exec_multi`
+#[derive(Debug, Clone, Serialize)]
+pub struct HostExecResult {
+    pub session_id: String,
+    pub host: String,
+    pub exit_code: Option<u32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// Set when the command could not be run at all
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// Server host key seen by [`probe_host_key`]
+#[derive(Debug, Clone, Serialize)]
+pub struct HostKeyInfo {
+    pub key_type: String,
+    /// SHA256 fingerprint, base64 encoded
+    pub fingerprint: String,
+}
+
+/// Handler that records the host key and then aborts the handshake
+struct ProbeHandler {
+    key: Option<oneshot::Sender<HostKeyInfo>>,
+}
+
+#[async_trait]
+impl client::Handler for ProbeHandler {
+    type Error = anyhow::Error;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &russh_keys::key::PublicKey,
+    ) -> Result<bool, Self::Error> {
+        if let Some(key) = self.key.take() {
+            let _ = key.send(HostKeyInfo {
+                key_type: server_public_key.name().to_string(),
+                fingerprint: server_public_key.fingerprint(),
+            });
+        }
+        Ok(false)
+    }
+}
+
+/// Run the key exchange with `connection`'s host just far enough to learn its
+/// host key, without authenticating. The host is reached the way `connect`
+/// reaches it, through `via` when it sits behind a bastion.
+pub async fn probe_host_key(
+    connection: &Connection,
+    via: Option<&client::Handle<ClientHandler>>,
+) -> Result<HostKeyInfo, String> {
+    let config = client_config(connection)?;
+    let (tx, rx) = oneshot::channel();
+    let handler = ProbeHandler { key: Some(tx) };
+
+    let transport = open_transport(connection, via, config, handler);
+    // Rejecting the key always fails the handshake; only the key matters
+    let handshake = with_connect_timeout(connection, None, transport).await;
+    match rx.await {
+        Ok(key) => Ok(key),
+        Err(_) => Err(handshake
+            .err()
+            .unwrap_or_else(|| format!("{} did not present a host key", connection.host))),
+    }
+}
+
+/// Measure one round trip (in ms) by opening and closing a throwaway session channel
+pub async fn measure_latency(handle: &client::Handle<ClientHandler>) -> Result<u64, String> {
+    let started = std::time::Instant::now();
+    let channel = handle
+        .channel_open_session()
+        .await
+        .map_err(|e| format!("Latency probe failed: {}", e))?;
+    let elapsed = started.elapsed();
+    let _ = channel.close().await;
+    Ok(elapsed.as_millis() as u64)
+}
+
+/// Open a session channel with a PTY and shell, routing its output to `terminal_id`
+async fn open_terminal(
+    handle: &client::Handle<ClientHandler>,
+    connection: &Connection,
+    terminal_channels: &TerminalChannels,
+    channel_registry: &ChannelRegistry,
+    terminal_id: &str,
+    cols: u32,
+    rows: u32,
+) -> Result<Channel<client::Msg>, String> {
+    // Open a session channel
+    let channel = handle
+        .channel_open_session()
+        .await
+        .map_err(|e| format!("Channel open failed: {}", e))?;
+    // Shells are closed through their terminal id, not the inspector receiver
+    channels::register(channel_registry, channel.id(), ChannelKind::Shell, terminal_id).await;
+
+    // Store the channel ID so the handler knows which data to forward
+    terminal_channels
+        .lock()
+        .await
+        .insert(channel.id(), terminal_id.to_string());
+
+    if connection.forward_agent {
+        channel
+            .agent_forward(false)
+            .await
+            .map_err(|e| format!("Agent forwarding request failed: {}", e))?;
+    }
+
+    for (name, value) in &connection.env {
+        channel
+            .set_env(false, name.as_str(), value.as_str())
+            .await
+            .map_err(|e| format!("Failed to set {}: {}", name, e))?;
+    }
+
+    // Request PTY
+    channel
+        .request_pty(
+            false,
+            "xterm-256color",
+            cols,
+            rows,
+            0,
+            0,
+            &[],
+        )
+        .await
+        .map_err(|e| format!("PTY request failed: {}", e))?;
+
+    // Request shell
+    channel
+        .request_shell(false)
+        .await
+        .map_err(|e| format!("Shell request failed: {}", e))?;
+
+    Ok(channel)
+}
+
+/// Signal name without the SIG prefix, as sent on the wire
+fn signal_name_of(sig: &Sig) -> String {
+    match sig {
+        Sig::ABRT => "ABRT".to_string(),
+        Sig::ALRM => "ALRM".to_string(),
+        Sig::FPE => "FPE".to_string(),
+        Sig::HUP => "HUP".to_string(),
+        Sig::ILL => "ILL".to_string(),
+        Sig::INT => "INT".to_string(),
+        Sig::KILL => "KILL".to_string(),
+        Sig::PIPE => "PIPE".to_string(),
+        Sig::QUIT => "QUIT".to_string(),
+        Sig::SEGV => "SEGV".to_string(),
+        Sig::TERM => "TERM".to_string(),
+        Sig::USR1 => "USR1".to_string(),
+        Sig::Custom(name) => name.clone(),
+    }
+}
+
+/// Build the russh client configuration from a connection's settings
+fn client_config(connection: &Connection) -> Result<Arc<client::Config>, String> {
+    // russh sends `keepalive@openssh.com` global requests with want-reply set;
+    // missing `keepalive_count_max` replies in a row make it drop the session,
+    // which in turn emits `SshEvent::Close` from the handler. They're on by
+    // default so half-open connections are noticed long before TCP gives up.
+    let keepalive_interval = match connection.keepalive_interval {
+        None => Some(DEFAULT_KEEPALIVE_INTERVAL_SECS),
+        Some(0) => None,
+        Some(secs) => Some(secs),
+    }
+    .map(std::time::Duration::from_secs);
+
+    let mut preferred = Preferred::default();
+    if let Some(algorithms) = &connection.algorithms {
+        preferred.kex = parse_algorithms(&algorithms.kex, "key exchange", preferred.kex)?;
+        preferred.cipher = parse_algorithms(&algorithms.ciphers, "cipher", preferred.cipher)?;
+        preferred.mac = parse_algorithms(&algorithms.macs, "MAC", preferred.mac)?;
+        preferred.key = parse_algorithms(
+            &algorithms.host_key_algorithms,
+            "host key",
+            preferred.key,
+        )?;
+    }
+
+    // The client's order wins negotiation, so only offer zlib when asked to
+    preferred.compression = if connection.compression {
+        std::borrow::Cow::Borrowed(&[
+            compression::ZLIB_LEGACY,
+            compression::ZLIB,
+            compression::NONE,
+        ])
+    } else {
+        std::borrow::Cow::Borrowed(&[compression::NONE])
+    };
+
+    Ok(Arc::new(client::Config {
+        keepalive_interval,
+        keepalive_max: connection.keepalive_count_max.unwrap_or(3),
+        preferred,
+        ..Default::default()
+    }))
+}
+
+/// Map user-supplied algorithm names onto russh's, keeping `default` when none are given
+fn parse_algorithms<N>(
+    names: &[String],
+    kind: &str,
+    default: std::borrow::Cow<'static, [N]>,
+) -> Result<std::borrow::Cow<'static, [N]>, String>
+where
+    N: Clone + for<'a> TryFrom<&'a str>,
+{
+    if names.is_empty() {
+        return Ok(default);
+    }
+    names
+        .iter()
+        .map(|name| {
+            N::try_from(name.as_str())
+                .map_err(|_| format!("Unsupported {} algorithm: {}", kind, name))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(std::borrow::Cow::Owned)
+}
+
+/// Fail opening the transport to a hop (dialing, ProxyCommand startup and
+/// the key exchange) once it takes longer than the hop's connect timeout.
+/// The clock stops while the host key prompt `prompt` names is open and
+/// restarts once it's answered, so the user can take their time with it.
+/// Authentication comes after and isn't bounded.
+async fn with_connect_timeout<T>(
+    hop: &Connection,
+    prompt: Option<(&PendingPrompts<bool>, &str)>,
+    step: impl std::future::Future<Output = Result<T, String>>,
+) -> Result<T, String> {
+    use tokio::time::{Duration, Instant};
+
+    let secs = hop.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS);
+    let limit = Duration::from_secs(secs);
+    let mut step = std::pin::pin!(step);
+    let mut deadline = Instant::now() + limit;
+    let mut prompted = false;
+    loop {
+        if let Ok(result) = tokio::time::timeout_at(deadline, &mut step).await {
+            return result;
+        }
+        let prompting = match prompt {
+            Some((prompts, session_id)) => prompts.lock().await.contains_key(session_id),
+            None => false,
+        };
+        deadline = match (prompting, prompted) {
+            (true, _) => Instant::now() + Duration::from_secs(1),
+            (false, true) => Instant::now() + limit,
+            (false, false) => {
+                return Err(format!("Connection to {} timed out after {}s", hop.host, secs))
+            }
+        };
+        prompted = prompting;
+    }
+}
+
+/// Open the SSH transport to `hop` the way its profile reaches it: tunnelled
+/// through the bastion `via`, over its ProxyCommand or proxy, or directly
+async fn open_transport<H>(
+    hop: &Connection,
+    via: Option<&client::Handle<ClientHandler>>,
+    config: Arc<client::Config>,
+    handler: H,
+) -> Result<client::Handle<H>, String>
+where
+    H: client::Handler<Error = anyhow::Error> + Send + 'static,
+{
+    match via {
+        Some(bastion) => {
+            let channel = bastion
+                .channel_open_direct_tcpip(hop.host.clone(), hop.port as u32, "127.0.0.1", 0)
+                .await
+                .map_err(|e| format!("Failed to tunnel to {}: {}", hop.host, e))?;
+            client::connect_stream(config, channel.into_stream(), handler)
+                .await
+                .map_err(|e| format!("Connection to {} failed: {}", hop.host, e))
+        }
+        None => match (hop.proxy_command.as_deref(), hop.proxy.as_ref()) {
+            (Some(command), _) => {
+                let stream = spawn_proxy_command(command, hop)?;
+                client::connect_stream(config, stream, handler)
+                    .await
+                    .map_err(|e| format!("Connection via ProxyCommand failed: {}", e))
+            }
+            (None, Some(proxy_config)) => {
+                let stream = proxy::dial(proxy_config, &hop.host, hop.port).await?;
+                client::connect_stream(config, stream, handler)
+                    .await
+                    .map_err(|e| format!("Connection via proxy failed: {}", e))
+            }
+            (None, None) => {
+                knock::knock(&hop.host, &hop.knock_sequence).await?;
+                let stream = dial_tcp(hop).await?;
+                client::connect_stream(config, stream, handler)
+                    .await
+                    .map_err(|e| format!("Connection failed: {}", e))
+            }
+        },
+    }
+}
+
+/// Open the SSH transport to a single hop and authenticate it
+async fn connect_hop(
+    hop: &Connection,
+    via: Option<&client::Handle<ClientHandler>>,
+    handler: ClientHandler,
+    manager: &SessionManager,
+) -> Result<client::Handle<ClientHandler>, String> {
+    let session_id = handler.session_id.clone();
+    let sender = handler.sender.clone();
+    let config = client_config(hop)?;
+
+    let prompt = Some((&manager.host_key_prompts, session_id.as_str()));
+    let transport = open_transport(hop, via, config, handler);
+    let mut handle = with_connect_timeout(hop, prompt, transport).await?;
+
+    if !authenticate(&mut handle, hop, &session_id, &sender, manager).await? {
+        return Err(format!("Authentication failed for {}", hop.host));
+    }
+
+    Ok(handle)
+}
+
+/// Connect to every address `hop.host` resolves to, happy-eyeballs style:
+/// attempts are started in preference order, each a short delay after the
+/// previous one (or immediately once it fails), and the first to succeed wins
+async fn dial_tcp(hop: &Connection) -> Result<tokio::net::TcpStream, String> {
+    use std::net::{IpAddr, SocketAddr};
+    use std::time::Duration;
+
+    let bind_ip = match hop.bind_address.as_deref().map(str::trim) {
+        Some(address) => Some(
+            address
+                .parse::<IpAddr>()
+                .map_err(|_| format!("Invalid bind address: {}", address))?,
+        ),
+        None => None,
+    };
+
+    let mut resolved: Vec<SocketAddr> = tokio::net::lookup_host((hop.host.as_str(), hop.port))
+        .await
+        .map_err(|e| format!("Failed to resolve {}: {}", hop.host, e))?
+        .collect();
+
+    // A bound socket can only reach addresses of its own family
+    if let Some(ip) = bind_ip {
+        resolved.retain(|a| a.is_ipv6() == ip.is_ipv6());
+    }
+
+    let addrs = order_addresses(resolved, hop.ip_preference);
+    if addrs.is_empty() {
+        return Err(format!("No usable addresses found for {}", hop.host));
+    }
+
+    let mut pending = addrs.into_iter();
+    let mut attempts = tokio::task::JoinSet::new();
+    let mut last_error = None;
+
+    loop {
+        if let Some(addr) = pending.next() {
+            attempts.spawn(async move {
+                connect_from(addr, bind_ip)
                     .await
-                    .map_err(|e| format!("Auth failed: {}", e))?
+                    .map_err(|e| format!("{}: {}", addr, e))
+            });
+        }
+
+        let finished = if !pending.as_slice().is_empty() {
+            match tokio::time::timeout(
+                Duration::from_millis(CONNECTION_ATTEMPT_DELAY_MS),
+                attempts.join_next(),
+            )
+            .await
+            {
+                Ok(finished) => finished,
+                // Still waiting; start the next address alongside
+                Err(_) => continue,
             }
-            AuthType::KeyFile => {
-                let key_path = connection
-                    .private_key_path
-                    .as_deref()
-                    .ok_or("Private key path not provided")?;
+        } else {
+            attempts.join_next().await
+        };
+
+        match finished {
+            // Dropping the JoinSet aborts the attempts still in flight
+            Some(Ok(Ok(stream))) => return Ok(stream),
+            Some(Ok(Err(e))) => last_error = Some(e),
+            Some(Err(e)) => last_error = Some(e.to_string()),
+            None if pending.as_slice().is_empty() => break,
+            None => {}
+        }
+    }
 
-                let key_pair = russh_keys::load_secret_key(
-                    key_path,
-                    connection.passphrase.as_deref(),
+    Err(format!(
+        "Connection failed: {}",
+        last_error.unwrap_or_else(|| "no reachable address".to_string())
+    ))
+}
+
+/// Open a TCP connection, optionally from a specific local address
+async fn connect_from(
+    addr: std::net::SocketAddr,
+    bind_ip: Option<std::net::IpAddr>,
+) -> std::io::Result<tokio::net::TcpStream> {
+    let Some(ip) = bind_ip else {
+        return tokio::net::TcpStream::connect(addr).await;
+    };
+    let socket = if addr.is_ipv6() {
+        tokio::net::TcpSocket::new_v6()?
+    } else {
+        tokio::net::TcpSocket::new_v4()?
+    };
+    socket.bind(std::net::SocketAddr::new(ip, 0))?;
+    socket.connect(addr).await
+}
+
+/// Order resolved addresses by family preference. `Auto` interleaves the two
+/// families starting with whichever the resolver listed first.
+fn order_addresses(
+    addrs: Vec<std::net::SocketAddr>,
+    preference: IpPreference,
+) -> Vec<std::net::SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.iter().copied().partition(|a| a.is_ipv6());
+    let (first, second) = match preference {
+        IpPreference::Ipv4 => (v4, v6),
+        IpPreference::Ipv6 => (v6, v4),
+        IpPreference::Auto => {
+            if addrs.first().is_some_and(|a| a.is_ipv6()) {
+                (v6, v4)
+            } else {
+                (v4, v6)
+            }
+        }
+    };
+
+    if preference != IpPreference::Auto {
+        return first.into_iter().chain(second).collect();
+    }
+
+    let mut ordered = Vec::with_capacity(addrs.len());
+    let mut first = first.into_iter();
+    let mut second = second.into_iter();
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => break,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+    ordered
+}
+
+/// Spawn a ProxyCommand and join its stdout/stdin into a single stream
+fn spawn_proxy_command(command: &str, hop: &Connection) -> Result<ProxyCommandStream, String> {
+    use std::process::Stdio;
+
+    let mut expanded = String::with_capacity(command.len());
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('h') => expanded.push_str(&hop.host),
+            Some('p') => expanded.push_str(&hop.port.to_string()),
+            Some('r') => expanded.push_str(&hop.username),
+            Some('%') => expanded.push('%'),
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            }
+            None => expanded.push('%'),
+        }
+    }
+    let command = expanded;
+
+    #[cfg(windows)]
+    let mut cmd = {
+        let mut cmd = tokio::process::Command::new("cmd");
+        cmd.args(["/C", &command]);
+        cmd
+    };
+    #[cfg(not(windows))]
+    let mut cmd = {
+        let mut cmd = tokio::process::Command::new("sh");
+        cmd.args(["-c", &command]);
+        cmd
+    };
+
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to start ProxyCommand: {}", e))?;
+
+    let stdin = child.stdin.take().ok_or("ProxyCommand stdin unavailable")?;
+    let stdout = child.stdout.take().ok_or("ProxyCommand stdout unavailable")?;
+
+    Ok(ProxyCommandStream {
+        stdout,
+        stdin,
+        _child: child,
+    })
+}
+
+/// A ProxyCommand's stdio as the transport. It owns the process, which is
+/// killed along with the transport rather than trusted to exit once its
+/// stdin closes.
+struct ProxyCommandStream {
+    stdout: ChildStdout,
+    stdin: ChildStdin,
+    _child: Child,
+}
+
+impl AsyncRead for ProxyCommandStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxyCommandStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stdin).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stdin).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stdin).poll_shutdown(cx)
+    }
+}
+
+/// Try each method of the connection's auth chain until one succeeds. Errors
+/// from a single method (no agent running, unreadable key, ...) don't end the
+/// chain; they're reported only if nothing was accepted.
+async fn authenticate(
+    handle: &mut client::Handle<ClientHandler>,
+    connection: &Connection,
+    session_id: &str,
+    sender: &tokio::sync::mpsc::Sender<SshEvent>,
+    manager: &SessionManager,
+) -> Result<bool, String> {
+    let mut errors = Vec::new();
+    for method in auth_chain(connection) {
+        // The server hangs up once MaxAuthTries is exceeded
+        if handle.is_closed() {
+            break;
+        }
+        match authenticate_method(handle, connection, &method, session_id, sender, manager).await {
+            Ok(true) => return Ok(true),
+            Ok(false) => log::info!("{:?} authentication rejected by {}", method, connection.host),
+            Err(e) => {
+                log::warn!("{:?} authentication failed: {}", method, e);
+                errors.push(e);
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(false)
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+/// Methods to try, in order. Without an explicit list this mirrors OpenSSH:
+/// the configured method first, then public keys, the agent,
+/// keyboard-interactive and finally a password. russh doesn't expose the
+/// methods the server advertises in its failure reply, so methods the server
+/// doesn't allow are simply rejected and the chain moves on.
+fn auth_chain(connection: &Connection) -> Vec<AuthType> {
+    let candidates = if connection.auth_methods.is_empty() {
+        vec![
+            connection.auth_type.clone(),
+            AuthType::KeyFile,
+            AuthType::Agent,
+            AuthType::KeyboardInteractive,
+            AuthType::Password,
+        ]
+    } else {
+        connection.auth_methods.clone()
+    };
+
+    let mut chain: Vec<AuthType> = Vec::new();
+    for method in candidates {
+        let usable = match method {
+            AuthType::KeyFile => connection.private_key_path.is_some(),
+            AuthType::Pkcs11 => connection.pkcs11_module.is_some(),
+            _ => true,
+        };
+        if usable && !chain.contains(&method) {
+            chain.push(method);
+        }
+    }
+    chain
+}
+
+async fn authenticate_method(
+    handle: &mut client::Handle<ClientHandler>,
+    connection: &Connection,
+    method: &AuthType,
+    session_id: &str,
+    sender: &tokio::sync::mpsc::Sender<SshEvent>,
+    manager: &SessionManager,
+) -> Result<bool, String> {
+    let authenticated = match method {
+        AuthType::Password => {
+            let password = match &connection.password {
+                Some(password) => password.clone(),
+                None => {
+                    request_credential(session_id, sender, manager, connection, "password").await?
+                }
+            };
+            handle
+                .authenticate_password(&connection.username, password)
+                .await
+                .map_err(|e| format!("Auth failed: {}", e))?
+        }
+        AuthType::KeyFile => {
+            let key_path = connection
+                .private_key_path
+                .as_deref()
+                .ok_or("Private key path not provided")?;
+
+            // FIDO2 keys can't be signed locally; the agent drives the
+            // authenticator (and its touch confirmation) for us
+            if let Some(security_key) = security_key_blob(key_path) {
+                authenticate_with_agent(
+                    handle,
+                    &connection.username,
+                    sender,
+                    AgentKeys::SecurityKey(&security_key),
                 )
-                .map_err(|e| format!("Failed to load key: {}", e))?;
+                .await?
+            } else {
+                let key_pair =
+                    load_private_key(key_path, connection, session_id, sender, manager).await?;
 
                 let key_pair = Arc::new(key_pair);
                 handle
@@ -111,85 +1358,365 @@ impl SshSession {
                     .await
                     .map_err(|e| format!("Key auth failed: {}", e))?
             }
-        };
+        }
+        AuthType::Agent => {
+            authenticate_with_agent(handle, &connection.username, sender, AgentKeys::All).await?
+        }
+        AuthType::Pkcs11 => {
+            let module = connection
+                .pkcs11_module
+                .as_deref()
+                .ok_or("PKCS#11 module path not provided")?;
+            let pin = request_credential(session_id, sender, manager, connection, "PIN").await?;
+            authenticate_with_agent(
+                handle,
+                &connection.username,
+                sender,
+                AgentKeys::Smartcard { module, pin: &pin },
+            )
+            .await?
+        }
+        AuthType::KeyboardInteractive => {
+            let mut response = handle
+                .authenticate_keyboard_interactive_start(&connection.username, None)
+                .await
+                .map_err(|e| format!("Keyboard-interactive auth failed: {}", e))?;
 
-        if !authenticated {
-            return Err("Authentication failed".to_string());
+            // The server may send any number of info requests (e.g. password, then OTP)
+            loop {
+                match response {
+                    client::KeyboardInteractiveAuthResponse::Success => break true,
+                    client::KeyboardInteractiveAuthResponse::Failure => break false,
+                    client::KeyboardInteractiveAuthResponse::InfoRequest {
+                        name,
+                        instructions,
+                        prompts,
+                    } => {
+                        let answers = if prompts.is_empty() {
+                            Vec::new()
+                        } else {
+                            let prompts = prompts
+                                .into_iter()
+                                .map(|p| AuthPrompt {
+                                    prompt: p.prompt,
+                                    echo: p.echo,
+                                })
+                                .collect();
+                            request_auth_responses(
+                                session_id,
+                                sender,
+                                &manager.auth_prompts,
+                                name,
+                                instructions,
+                                prompts,
+                            )
+                            .await?
+                        };
+                        response = handle
+                            .authenticate_keyboard_interactive_respond(answers)
+                            .await
+                            .map_err(|e| format!("Keyboard-interactive auth failed: {}", e))?;
+                    }
+                }
+            }
         }
+    };
+
+    Ok(authenticated)
+}
+
+/// Load an OpenSSH/PEM or PuTTY PPK private key, asking for the passphrase
+/// when the key is encrypted and none was saved
+async fn load_private_key(
+    key_path: &str,
+    connection: &Connection,
+    session_id: &str,
+    sender: &tokio::sync::mpsc::Sender<SshEvent>,
+    manager: &SessionManager,
+) -> Result<russh_keys::key::KeyPair, String> {
+    let contents = std::fs::read_to_string(key_path)
+        .map_err(|e| format!("Failed to load key: {}", e))?;
 
-        // Open a session channel
-        let channel = handle
-            .channel_open_session()
+    if ppk::is_ppk(&contents) {
+        let ppk_file = ppk::PpkFile::parse(&contents)?;
+        let passphrase = match &connection.passphrase {
+            None if ppk_file.is_encrypted() => Some(
+                request_credential(session_id, sender, manager, connection, "passphrase").await?,
+            ),
+            passphrase => passphrase.clone(),
+        };
+        let decode = move || ppk_file.decode(passphrase.as_deref());
+        return tokio::task::spawn_blocking(decode)
             .await
-            .map_err(|e| format!("Channel open failed: {}", e))?;
+            .map_err(|e| e.to_string())?;
+    }
 
-        // Store the channel ID so the handler knows which data to forward
-        {
-            let mut id_lock = shell_channel_id.lock().await;
-            *id_lock = Some(channel.id());
+    match russh_keys::decode_secret_key(&contents, connection.passphrase.as_deref()) {
+        Err(russh_keys::Error::KeyIsEncrypted) if connection.passphrase.is_none() => {
+            let passphrase =
+                request_credential(session_id, sender, manager, connection, "passphrase").await?;
+            russh_keys::decode_secret_key(&contents, Some(&passphrase))
         }
+        result => result,
+    }
+    .map_err(|e| format!("Failed to load key: {}", e))
+}
 
-        // Request PTY
-        channel
-            .request_pty(
-                false,
-                "xterm-256color",
-                80,
-                24,
-                0,
-                0,
-                &[],
-            )
-            .await
-            .map_err(|e| format!("PTY request failed: {}", e))?;
+/// Return the public key blob (base64) if `key_path` is a FIDO2 security key
+fn security_key_blob(key_path: &str) -> Option<String> {
+    let pub_path = if key_path.ends_with(".pub") {
+        key_path.to_string()
+    } else {
+        format!("{}.pub", key_path)
+    };
+    let contents = std::fs::read_to_string(pub_path).ok()?;
+    let mut parts = contents.split_whitespace();
+    let key_type = parts.next()?;
+    if key_type.starts_with("sk-") {
+        parts.next().map(|blob| blob.to_string())
+    } else {
+        None
+    }
+}
 
-        // Request shell
-        channel
-            .request_shell(false)
-            .await
-            .map_err(|e| format!("Shell request failed: {}", e))?;
+/// Which agent identities to offer the server
+enum AgentKeys<'a> {
+    All,
+    /// Only the FIDO2 key with this public key blob (base64)
+    SecurityKey(&'a str),
+    /// Load a PKCS#11 provider into the agent first, like `ssh-add -s`; the
+    /// private keys stay on the token
+    Smartcard { module: &'a str, pin: &'a str },
+}
 
-        Ok(Self {
-            id: session_id,
-            handle,
-            channel,
-        })
+/// Authenticate with the local ssh-agent (Pageant on Windows)
+async fn authenticate_with_agent(
+    handle: &mut client::Handle<ClientHandler>,
+    username: &str,
+    sender: &tokio::sync::mpsc::Sender<SshEvent>,
+    keys: AgentKeys<'_>,
+) -> Result<bool, String> {
+    #[cfg(unix)]
+    let agent = russh_keys::agent::client::AgentClient::connect_env()
+        .await
+        .map_err(|e| format!("Failed to connect to SSH agent: {}", e))?;
+    #[cfg(windows)]
+    let agent = russh_keys::agent::client::AgentClient::connect_pageant().await;
+
+    try_agent_identities(handle, username, agent, sender, keys).await
+}
+
+/// Open a raw stream to the local agent for forwarding
+#[cfg(unix)]
+async fn connect_local_agent() -> Result<tokio::net::UnixStream, String> {
+    let path = std::env::var("SSH_AUTH_SOCK").map_err(|_| "SSH_AUTH_SOCK is not set".to_string())?;
+    tokio::net::UnixStream::connect(path)
+        .await
+        .map_err(|e| format!("Failed to connect to SSH agent: {}", e))
+}
+
+/// Open a raw stream to the local agent for forwarding
+#[cfg(windows)]
+async fn connect_local_agent() -> Result<tokio::net::windows::named_pipe::NamedPipeClient, String> {
+    tokio::net::windows::named_pipe::ClientOptions::new()
+        .open(r"\\.\pipe\openssh-ssh-agent")
+        .map_err(|e| format!("Failed to connect to SSH agent: {}", e))
+}
+
+/// Try each identity held by the agent until one is accepted
+async fn try_agent_identities<S>(
+    handle: &mut client::Handle<ClientHandler>,
+    username: &str,
+    mut agent: russh_keys::agent::client::AgentClient<S>,
+    sender: &tokio::sync::mpsc::Sender<SshEvent>,
+    keys: AgentKeys<'_>,
+) -> Result<bool, String>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin + 'static,
+{
+    use russh_keys::PublicKeyBase64;
+
+    if let AgentKeys::Smartcard { module, pin } = keys {
+        // The agent refuses providers it already has loaded, so a failure here
+        // is only fatal if no identities turn up below
+        if let Err(e) = agent.add_smartcard_key(module, pin.as_bytes()).await {
+            log::warn!("Failed to add PKCS#11 provider to agent: {}", e);
+        }
     }
 
-    pub async fn write(&self, data: &[u8]) -> Result<(), String> {
-        self.channel
-            .data(data)
-            .await
-            .map_err(|e| format!("Write failed: {}", e))
+    let identities = agent
+        .request_identities()
+        .await
+        .map_err(|e| format!("Failed to list agent identities: {}", e))?;
+
+    if identities.is_empty() {
+        return Err("SSH agent has no identities".to_string());
     }
 
-    pub async fn resize(&self, cols: u32, rows: u32) -> Result<(), String> {
-        self.channel
-            .window_change(cols, rows, 0, 0)
-            .await
-            .map_err(|e| format!("Resize failed: {}", e))
+    let security_key = match keys {
+        AgentKeys::SecurityKey(blob) => Some(blob),
+        _ => None,
+    };
+    let identities: Vec<_> = match security_key {
+        Some(blob) => identities
+            .into_iter()
+            .filter(|key| key.public_key_base64() == blob)
+            .collect(),
+        None => identities,
+    };
+
+    if identities.is_empty() {
+        return Err("Security key is not loaded in the SSH agent (run ssh-add)".to_string());
     }
 
-    pub async fn close(self) -> Result<(), String> {
-        self.channel
-            .close()
-            .await
-            .map_err(|e| format!("Close failed: {}", e))
+    for key in identities {
+        if security_key.is_some() {
+            let _ = sender
+                .send(SshEvent::SecurityKeyTouch {
+                    fingerprint: key.fingerprint(),
+                })
+                .await;
+        }
+        let (returned, result) = handle.authenticate_future(username, key, agent).await;
+        agent = returned;
+        if result.map_err(|e| format!("Agent auth failed: {}", e))? {
+            return Ok(true);
+        }
     }
+
+    Ok(false)
+}
+
+/// Ask the frontend to answer keyboard-interactive prompts
+async fn request_auth_responses(
+    session_id: &str,
+    sender: &tokio::sync::mpsc::Sender<SshEvent>,
+    auth_prompts: &PendingPrompts<Vec<String>>,
+    name: String,
+    instructions: String,
+    prompts: Vec<AuthPrompt>,
+) -> Result<Vec<String>, String> {
+    let (tx, rx) = oneshot::channel();
+    auth_prompts.lock().await.insert(session_id.to_string(), tx);
+
+    let _ = sender
+        .send(SshEvent::AuthPrompt {
+            name,
+            instructions,
+            prompts,
+        })
+        .await;
+
+    rx.await.map_err(|_| "Authentication cancelled".to_string())
+}
+
+/// Ask the frontend for a secret that was deliberately not stored
+async fn request_credential(
+    session_id: &str,
+    sender: &tokio::sync::mpsc::Sender<SshEvent>,
+    manager: &SessionManager,
+    connection: &Connection,
+    kind: &str,
+) -> Result<String, String> {
+    let (tx, rx) = oneshot::channel();
+    manager
+        .credential_prompts
+        .lock()
+        .await
+        .insert(session_id.to_string(), tx);
+
+    let _ = sender
+        .send(SshEvent::CredentialRequest {
+            kind: kind.to_string(),
+            host: connection.host.clone(),
+            username: connection.username.clone(),
+        })
+        .await;
+
+    rx.await.map_err(|_| "Authentication cancelled".to_string())
 }
 
 /// Global session registry
 pub struct SessionManager {
     pub sessions: Mutex<HashMap<String, SshSession>>,
+    pub host_key_prompts: PendingPrompts<bool>,
+    pub auth_prompts: PendingPrompts<Vec<String>>,
+    pub credential_prompts: PendingPrompts<String>,
+    /// Transfers waiting for the user to settle a conflict, keyed by transfer id
+    pub conflict_prompts: PendingPrompts<ConflictResolution>,
+    /// In-flight `ssh_connect` calls, keyed by session id
+    pub connect_attempts: Mutex<HashMap<String, ConnectAttempt>>,
+    /// Cancel handles of running `ssh_exec_stream` commands, keyed by exec id
+    pub exec_streams: Mutex<HashMap<String, oneshot::Sender<()>>>,
+    /// Cancel handles of running SFTP transfers, keyed by transfer id; see
+    /// `register_transfer`
+    pub transfers: std::sync::Mutex<HashMap<String, TransferRun>>,
+    /// Last generation handed out to a transfer run
+    pub transfer_generation: std::sync::atomic::AtomicU64,
+    pub transfer_queue: TransferQueue,
+    /// Cancel handles of running `sftp_find` searches, keyed by search id
+    pub searches: Mutex<HashMap<String, oneshot::Sender<()>>>,
+    /// Stop handles of running `sftp_tail` streams, keyed by tail id
+    pub tails: Mutex<HashMap<String, oneshot::Sender<()>>>,
+    /// Connected favorites waiting to be opened, keyed by connection id
+    pub prewarmed: Mutex<HashMap<String, SshSession>>,
+    /// Terminals not backed by SSH (local shells, Mosh, telnet), keyed by session id
+    pub terminal_sessions: Mutex<HashMap<String, Box<dyn TerminalSession>>>,
+    /// Edited files synced back to the server on save
+    pub file_watches: FileWatches,
+    /// Key of the connection store once unlocked with the master password
+    pub vault_key: std::sync::Mutex<Option<VaultKey>>,
+}
+
+/// Cancel handle of one run of a transfer. A paused and resumed transfer
+/// keeps its id, so the generation tells its runs apart.
+pub struct TransferRun {
+    pub generation: u64,
+    pub cancel: oneshot::Sender<()>,
+}
+
+/// A connection attempt that can still be cancelled
+pub struct ConnectAttempt {
+    pub connection_id: String,
+    pub cancel: oneshot::Sender<()>,
 }
 
 impl SessionManager {
     pub fn new() -> Self {
         Self {
             sessions: Mutex::new(HashMap::new()),
+            host_key_prompts: Arc::new(Mutex::new(HashMap::new())),
+            auth_prompts: Arc::new(Mutex::new(HashMap::new())),
+            credential_prompts: Arc::new(Mutex::new(HashMap::new())),
+            conflict_prompts: Arc::new(Mutex::new(HashMap::new())),
+            connect_attempts: Mutex::new(HashMap::new()),
+            exec_streams: Mutex::new(HashMap::new()),
+            transfers: std::sync::Mutex::new(HashMap::new()),
+            transfer_generation: std::sync::atomic::AtomicU64::new(0),
+            transfer_queue: TransferQueue::new(),
+            searches: Mutex::new(HashMap::new()),
+            tails: Mutex::new(HashMap::new()),
+            prewarmed: Mutex::new(HashMap::new()),
+            terminal_sessions: Mutex::new(HashMap::new()),
+            file_watches: Mutex::new(HashMap::new()),
+            vault_key: std::sync::Mutex::new(None),
         }
     }
 
+    pub fn vault_key(&self) -> Option<VaultKey> {
+        self.vault_key
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn set_vault_key(&self, key: Option<VaultKey>) {
+        *self
+            .vault_key
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = key;
+    }
+
     pub async fn add_session(&self, session: SshSession) -> String {
         let id = session.id.clone();
         self.sessions.lock().await.insert(id.clone(), session);
@@ -203,4 +1730,117 @@ impl SessionManager {
     pub async fn has_session(&self, id: &str) -> bool {
         self.sessions.lock().await.contains_key(id)
     }
+
+    /// Register a new run of `transfer_id`, replacing the cancel handle of
+    /// any earlier run. Returns the run's generation and the receiver that
+    /// fires when it's cancelled.
+    pub fn register_transfer(&self, transfer_id: &str) -> (u64, oneshot::Receiver<()>) {
+        use std::sync::atomic::Ordering;
+
+        let generation = self.transfer_generation.fetch_add(1, Ordering::Relaxed) + 1;
+        let (cancel, cancelled) = oneshot::channel();
+        self.transfers
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(transfer_id.to_string(), TransferRun { generation, cancel });
+        (generation, cancelled)
+    }
+
+    /// Drop the cancel handle of run `generation`, unless a newer run of the
+    /// transfer has replaced it
+    pub fn unregister_transfer(&self, transfer_id: &str, generation: u64) {
+        let mut transfers = self
+            .transfers
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if transfers.get(transfer_id).is_some_and(|run| run.generation == generation) {
+            transfers.remove(transfer_id);
+        }
+    }
+
+    /// Cancel the current run of a transfer; `false` if none is going
+    pub fn cancel_transfer(&self, transfer_id: &str) -> bool {
+        let run = self
+            .transfers
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .remove(transfer_id);
+        match run {
+            Some(run) => {
+                let _ = run.cancel.send(());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// A session's SSH handle and channel registry, cloned out so that
+    /// round-trips over them don't hold the sessions lock
+    pub async fn handle(
+        &self,
+        id: &str,
+    ) -> Result<(Arc<client::Handle<ClientHandler>>, ChannelRegistry), String> {
+        let sessions = self.sessions.lock().await;
+        let session = sessions.get(id).ok_or("Session not found")?;
+        Ok((session.handle.clone(), session.channel_registry.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(text: &str) -> ChannelMsg {
+        ChannelMsg::Data {
+            data: CryptoVec::from_slice(text.as_bytes()),
+        }
+    }
+
+    fn extended(text: &str, ext: u32) -> ChannelMsg {
+        ChannelMsg::ExtendedData {
+            data: CryptoVec::from_slice(text.as_bytes()),
+            ext,
+        }
+    }
+
+    #[test]
+    fn exec_collects_stdout_and_stderr_in_order() {
+        let mut output = ExecCollector::default();
+        for msg in [data("one "), extended("oops", 1), data("two"), extended("\n", 1)] {
+            output.push(&msg);
+        }
+        let output = output.finish();
+        assert_eq!(output.stdout, "one two");
+        assert_eq!(output.stderr, "oops\n");
+        assert_eq!(output.exit_code, None);
+    }
+
+    #[test]
+    fn exec_ignores_other_extended_data() {
+        let mut output = ExecCollector::default();
+        output.push(&extended("not stderr", 2));
+        output.push(&ChannelMsg::Eof);
+        let output = output.finish();
+        assert!(output.stdout.is_empty());
+        assert!(output.stderr.is_empty());
+    }
+
+    #[test]
+    fn exec_keeps_the_exit_status() {
+        let mut output = ExecCollector::default();
+        output.push(&data("done"));
+        output.push(&ChannelMsg::ExitStatus { exit_status: 3 });
+        output.push(&ChannelMsg::Eof);
+        output.push(&ChannelMsg::Close);
+        assert_eq!(output.finish().exit_code, Some(3));
+    }
+
+    #[test]
+    fn exec_output_tolerates_invalid_utf8() {
+        let mut output = ExecCollector::default();
+        output.push(&ChannelMsg::Data {
+            data: CryptoVec::from_slice(b"caf\xe9"),
+        });
+        assert_eq!(output.finish().stdout, "caf\u{fffd}");
+    }
 }
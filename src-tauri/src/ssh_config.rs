@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::connection::ImportedHost;
+
+/// How deep `Include`s may nest, as in OpenSSH
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Options that have no counterpart on an imported profile and are worth
+/// pointing out; anything else unknown is quietly ignored
+const UNSUPPORTED: &[&str] = &[
+    "proxycommand",
+    "localforward",
+    "remoteforward",
+    "dynamicforward",
+    "certificatefile",
+    "forwardx11",
+    "controlmaster",
+];
+
+/// The user's OpenSSH client config, `~/.ssh/config`
+pub fn default_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".ssh").join("config"))
+}
+
+/// A `Host` section, or the options before the first one (patterns `*`)
+struct Block {
+    patterns: Vec<String>,
+    /// Lowercased keyword, keyword as written, and value
+    options: Vec<(String, String, String)>,
+}
+
+/// `*` and `?` wildcard match
+fn wildcard(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|i| wildcard(rest, &text[i..])),
+        Some((&p, rest)) => match text.split_first() {
+            Some((&t, text)) => (p == b'?' || p.eq_ignore_ascii_case(&t)) && wildcard(rest, text),
+            None => false,
+        },
+    }
+}
+
+/// Whether a `Host` line's patterns select `alias`: any pattern matches and
+/// no negated one does
+fn matches(patterns: &[String], alias: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        match pattern.strip_prefix('!') {
+            Some(negated) if wildcard(negated.as_bytes(), alias.as_bytes()) => return false,
+            Some(_) => {}
+            None => matched |= wildcard(pattern.as_bytes(), alias.as_bytes()),
+        }
+    }
+    matched
+}
+
+/// Split arguments on whitespace, keeping double-quoted ones together
+fn split_args(value: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in value.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+    args
+}
+
+fn expand_tilde(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Files an `Include` argument names: relative paths are under `~/.ssh`,
+/// and `*` or `?` in the file name match like a shell glob
+fn include_paths(arg: &str) -> Vec<PathBuf> {
+    let mut path = expand_tilde(arg);
+    if path.is_relative() {
+        if let Some(home) = dirs::home_dir() {
+            path = home.join(".ssh").join(path);
+        }
+    }
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if !name.contains(['*', '?']) {
+        return vec![path];
+    }
+    let Some(dir) = path.parent() else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| {
+                    wildcard(name.as_bytes(), entry.file_name().to_string_lossy().as_bytes())
+                })
+                .map(|entry| entry.path())
+                .collect()
+        })
+        .unwrap_or_default();
+    paths.sort();
+    paths
+}
+
+/// Read a config file into `blocks`, following `Include`s in place
+fn read_blocks(path: &Path, depth: usize, blocks: &mut Vec<Block>) -> Result<(), String> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(format!("Includes nested too deeply at {}", path.display()));
+    }
+    let data = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    for line in data.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let split = line
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(line.len());
+        let (keyword, value) = line.split_at(split);
+        let value = value.trim_start().trim_start_matches('=').trim();
+        let key = keyword.to_lowercase();
+        match key.as_str() {
+            "host" => blocks.push(Block {
+                patterns: split_args(value),
+                options: Vec::new(),
+            }),
+            // Conditions can't be evaluated ahead of connecting; a block
+            // without patterns never applies
+            "match" => blocks.push(Block {
+                patterns: Vec::new(),
+                options: Vec::new(),
+            }),
+            "include" => {
+                for arg in split_args(value) {
+                    for included in include_paths(&arg) {
+                        // Missing includes are skipped, as by OpenSSH
+                        if included.is_file() {
+                            read_blocks(&included, depth + 1, blocks)?;
+                        }
+                    }
+                }
+            }
+            _ => {
+                if let Some(block) = blocks.last_mut() {
+                    block.options.push((key, keyword.to_string(), value.to_string()));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The settings `alias` ends up with: the first value for each option among
+/// the blocks that select it wins
+fn resolve(blocks: &[Block], alias: &str) -> ImportedHost {
+    let mut options: HashMap<&str, &str> = HashMap::new();
+    let mut warnings = Vec::new();
+    for block in blocks.iter().filter(|b| matches(&b.patterns, alias)) {
+        for (key, keyword, value) in &block.options {
+            if !options.contains_key(key.as_str()) && UNSUPPORTED.contains(&key.as_str()) {
+                warnings.push(format!("{} is not imported", keyword));
+            }
+            options.entry(key.as_str()).or_insert(value.as_str());
+        }
+    }
+
+    let host = options
+        .get("hostname")
+        .map(|h| h.replace("%h", alias))
+        .unwrap_or_else(|| alias.to_string());
+    let port = match options.get("port") {
+        Some(port) => port.parse().unwrap_or_else(|_| {
+            warnings.push(format!("Invalid port '{}', using 22", port));
+            22
+        }),
+        None => 22,
+    };
+    // OpenSSH falls back to the local user name too
+    let username = options
+        .get("user")
+        .map(|u| u.to_string())
+        .or_else(|| std::env::var("USER").ok())
+        .or_else(|| std::env::var("USERNAME").ok())
+        .unwrap_or_else(|| "root".to_string());
+    let private_key_path = options
+        .get("identityfile")
+        .and_then(|value| split_args(value).into_iter().next())
+        .map(|path| expand_tilde(&path).to_string_lossy().into_owned());
+    let jump_hosts = match options.get("proxyjump") {
+        Some(jumps) if !jumps.eq_ignore_ascii_case("none") => jumps
+            .split(',')
+            .map(|j| j.trim().to_string())
+            .filter(|j| !j.is_empty())
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    ImportedHost {
+        name: alias.to_string(),
+        host,
+        port,
+        username,
+        password: None,
+        private_key_path,
+        jump_hosts,
+        warnings,
+    }
+}
+
+/// Hosts defined in an OpenSSH client config, one per concrete `Host` alias.
+/// Wildcard patterns only contribute defaults.
+pub fn parse_file(path: &Path) -> Result<Vec<ImportedHost>, String> {
+    let mut blocks = vec![Block {
+        patterns: vec!["*".to_string()],
+        options: Vec::new(),
+    }];
+    read_blocks(path, 0, &mut blocks)?;
+
+    let mut aliases: Vec<&str> = Vec::new();
+    for block in &blocks {
+        for pattern in &block.patterns {
+            if !pattern.contains(['*', '?', '!']) && !aliases.contains(&pattern.as_str()) {
+                aliases.push(pattern);
+            }
+        }
+    }
+    Ok(aliases.into_iter().map(|alias| resolve(&blocks, alias)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_config(contents: &str) -> PathBuf {
+        let name = format!("rustssh-config-{}", uuid::Uuid::new_v4().simple());
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn wildcards_and_negation() {
+        assert!(wildcard(b"*.example.com", b"web.EXAMPLE.com"));
+        assert!(wildcard(b"db?", b"db1"));
+        assert!(!wildcard(b"db?", b"db12"));
+        let patterns = vec!["*.internal".to_string(), "!bastion.internal".to_string()];
+        assert!(matches(&patterns, "db.internal"));
+        assert!(!matches(&patterns, "bastion.internal"));
+        assert!(!matches(&patterns, "example.com"));
+    }
+
+    #[test]
+    fn split_args_keeps_quoted_words_together() {
+        assert_eq!(split_args(r#"a "b c"  d"#), vec!["a", "b c", "d"]);
+    }
+
+    #[test]
+    fn parse_file_resolves_each_alias() {
+        let path = write_config(
+            "Host *.internal !bastion.internal\n\
+             \x20   User admin\n\
+             Host web\n\
+             \x20   HostName %h.example.com\n\
+             \x20   Port 2222\n\
+             \x20   IdentityFile \"/keys/id web\"\n\
+             \x20   ProxyJump bastion, gw\n\
+             \x20   LocalForward 8080 localhost:80\n\
+             # a comment\n\
+             Host db\n\
+             \x20   Port=abc\n\
+             \x20   User = dbuser\n\
+             Host *\n\
+             \x20   User fallback\n\
+             \x20   Port 2200\n",
+        );
+        let hosts = parse_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(hosts.len(), 2);
+        let web = &hosts[0];
+        assert_eq!(web.name, "web");
+        assert_eq!(web.host, "web.example.com");
+        assert_eq!(web.port, 2222);
+        assert_eq!(web.username, "fallback");
+        assert_eq!(web.private_key_path.as_deref(), Some("/keys/id web"));
+        assert_eq!(web.jump_hosts, vec!["bastion", "gw"]);
+        assert_eq!(web.warnings, vec!["LocalForward is not imported"]);
+
+        let db = &hosts[1];
+        assert_eq!(db.host, "db");
+        assert_eq!(db.port, 22);
+        assert_eq!(db.username, "dbuser");
+        assert_eq!(db.warnings, vec!["Invalid port 'abc', using 22"]);
+    }
+
+    #[test]
+    fn parse_file_follows_includes() {
+        let included = write_config("Host inner\n    Port 2022\n");
+        let path = write_config(&format!(
+            "Include {}\nHost outer\n    User someone\n",
+            included.display()
+        ));
+        let hosts = parse_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&included).unwrap();
+
+        let names: Vec<&str> = hosts.iter().map(|h| h.name.as_str()).collect();
+        assert_eq!(names, vec!["inner", "outer"]);
+        assert_eq!(hosts[0].port, 2022);
+    }
+
+    #[test]
+    fn includes_that_loop_are_refused() {
+        let path = std::env::temp_dir().join(format!(
+            "rustssh-config-{}",
+            uuid::Uuid::new_v4().simple()
+        ));
+        fs::write(&path, format!("Include {}\n", path.display())).unwrap();
+        let result = parse_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::ssh::SshEvent;
+use crate::terminal::TerminalSession;
+
+const SE: u8 = 240;
+const SB: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const IAC: u8 = 255;
+
+const OPT_ECHO: u8 = 1;
+const OPT_SGA: u8 = 3;
+const OPT_TTYPE: u8 = 24;
+const OPT_NAWS: u8 = 31;
+
+const TTYPE_IS: u8 = 0;
+const TTYPE_SEND: u8 = 1;
+const TERMINAL_TYPE: &[u8] = b"XTERM-256COLOR";
+
+const CONNECT_TIMEOUT_SECS: u64 = 15;
+/// Longest subnegotiation kept; the options agreed to need only a few bytes,
+/// so longer ones are dropped
+const MAX_SUBNEGOTIATION: usize = 1024;
+
+/// A telnet connection to a legacy device. Only echo, suppress-go-ahead,
+/// terminal type and window size options are agreed to; everything else is
+/// refused.
+pub struct TelnetSession {
+    out: mpsc::UnboundedSender<Vec<u8>>,
+    /// Shared with the reader, which answers the server's requests
+    negotiation: Arc<Mutex<Negotiation>>,
+    reader: JoinHandle<()>,
+    writer: JoinHandle<()>,
+}
+
+/// Option state of one connection
+struct Negotiation {
+    /// Options we agreed to perform
+    local: [bool; 256],
+    /// Options the server agreed to perform
+    remote: [bool; 256],
+    cols: u16,
+    rows: u16,
+}
+
+impl Negotiation {
+    fn naws(&self) -> Vec<u8> {
+        let mut msg = vec![IAC, SB, OPT_NAWS];
+        for byte in self.cols.to_be_bytes().into_iter().chain(self.rows.to_be_bytes()) {
+            // A 255 in the payload must be doubled like any other IAC
+            msg.push(byte);
+            if byte == IAC {
+                msg.push(IAC);
+            }
+        }
+        msg.extend_from_slice(&[IAC, SE]);
+        msg
+    }
+
+    /// Reply to WILL/WONT/DO/DONT for `option`. Replies are only sent when the
+    /// state changes, which keeps both sides from looping.
+    fn respond(&mut self, command: u8, option: u8) -> Vec<u8> {
+        let i = option as usize;
+        match command {
+            WILL if !self.remote[i] => {
+                if matches!(option, OPT_ECHO | OPT_SGA) {
+                    self.remote[i] = true;
+                    vec![IAC, DO, option]
+                } else {
+                    vec![IAC, DONT, option]
+                }
+            }
+            WONT if self.remote[i] => {
+                self.remote[i] = false;
+                vec![IAC, DONT, option]
+            }
+            DO if !self.local[i] => {
+                if matches!(option, OPT_SGA | OPT_TTYPE | OPT_NAWS) {
+                    self.local[i] = true;
+                    let mut reply = vec![IAC, WILL, option];
+                    if option == OPT_NAWS {
+                        reply.extend(self.naws());
+                    }
+                    reply
+                } else {
+                    vec![IAC, WONT, option]
+                }
+            }
+            // Already agreed; the server may still want the current size
+            DO if option == OPT_NAWS => self.naws(),
+            DONT if self.local[i] => {
+                self.local[i] = false;
+                vec![IAC, WONT, option]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn subnegotiate(&self, payload: &[u8]) -> Vec<u8> {
+        match payload {
+            [OPT_TTYPE, TTYPE_SEND, ..] if self.local[OPT_TTYPE as usize] => {
+                let mut reply = vec![IAC, SB, OPT_TTYPE, TTYPE_IS];
+                reply.extend_from_slice(TERMINAL_TYPE);
+                reply.extend_from_slice(&[IAC, SE]);
+                reply
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Where the parser is within the incoming byte stream
+enum ParseState {
+    Data,
+    Iac,
+    Command(u8),
+    Sub,
+    SubIac,
+}
+
+impl TelnetSession {
+    /// Connect to `host:port`. Received text arrives as `SshEvent::Data` for
+    /// channel `id`, followed by `SshEvent::Close` when the server hangs up.
+    pub async fn connect(
+        id: String,
+        host: &str,
+        port: u16,
+        sender: mpsc::Sender<SshEvent>,
+    ) -> Result<Self, String> {
+        let stream = tokio::time::timeout(
+            std::time::Duration::from_secs(CONNECT_TIMEOUT_SECS),
+            TcpStream::connect((host, port)),
+        )
+        .await
+        .map_err(|_| format!("Connection to {} timed out", host))?
+        .map_err(|e| format!("Connection failed: {}", e))?;
+        let (mut rd, mut wr) = stream.into_split();
+
+        let (out, mut out_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        let writer = tokio::spawn(async move {
+            while let Some(buf) = out_rx.recv().await {
+                if wr.write_all(&buf).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let negotiation = Arc::new(Mutex::new(Negotiation {
+            local: [false; 256],
+            remote: [false; 256],
+            cols: 80,
+            rows: 24,
+        }));
+
+        let state = negotiation.clone();
+        let replies = out.clone();
+        let reader = tokio::spawn(async move {
+            let mut buf = [0u8; 8192];
+            let mut parse = ParseState::Data;
+            let mut sub = Vec::new();
+            let mut sub_oversized = false;
+            loop {
+                let n = match rd.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                let mut data = Vec::with_capacity(n);
+                for &byte in &buf[..n] {
+                    parse = match parse {
+                        ParseState::Data if byte == IAC => ParseState::Iac,
+                        ParseState::Data => {
+                            data.push(byte);
+                            ParseState::Data
+                        }
+                        ParseState::Iac => match byte {
+                            IAC => {
+                                data.push(IAC);
+                                ParseState::Data
+                            }
+                            WILL | WONT | DO | DONT => ParseState::Command(byte),
+                            SB => {
+                                sub.clear();
+                                sub_oversized = false;
+                                ParseState::Sub
+                            }
+                            // NOP, GA and friends carry nothing for a terminal
+                            _ => ParseState::Data,
+                        },
+                        ParseState::Command(command) => {
+                            let reply = state.lock().unwrap().respond(command, byte);
+                            if !reply.is_empty() {
+                                let _ = replies.send(reply);
+                            }
+                            ParseState::Data
+                        }
+                        ParseState::Sub if byte == IAC => ParseState::SubIac,
+                        ParseState::SubIac if byte == SE => {
+                            if !sub_oversized {
+                                let reply = state.lock().unwrap().subnegotiate(&sub);
+                                if !reply.is_empty() {
+                                    let _ = replies.send(reply);
+                                }
+                            }
+                            ParseState::Data
+                        }
+                        // Subnegotiation data; after an IAC, the byte it escaped
+                        ParseState::Sub | ParseState::SubIac => {
+                            if sub.len() < MAX_SUBNEGOTIATION {
+                                sub.push(byte);
+                            } else {
+                                sub_oversized = true;
+                            }
+                            ParseState::Sub
+                        }
+                    };
+                }
+                if data.is_empty() {
+                    continue;
+                }
+                let event = SshEvent::Data {
+                    channel_id: id.clone(),
+                    data,
+                    stderr: false,
+                };
+                if sender.send(event).await.is_err() {
+                    return;
+                }
+            }
+            let _ = sender.send(SshEvent::Close { reason: None }).await;
+        });
+
+        // Many servers wait for the client to offer these
+        {
+            let mut state = negotiation.lock().unwrap();
+            state.local[OPT_NAWS as usize] = true;
+            state.local[OPT_TTYPE as usize] = true;
+            state.remote[OPT_SGA as usize] = true;
+        }
+        let _ = out.send(vec![IAC, WILL, OPT_NAWS, IAC, WILL, OPT_TTYPE, IAC, DO, OPT_SGA]);
+
+        Ok(Self {
+            out,
+            negotiation,
+            reader,
+            writer,
+        })
+    }
+}
+
+impl TerminalSession for TelnetSession {
+    fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut buf = Vec::with_capacity(data.len());
+        let mut bytes = data.iter().peekable();
+        while let Some(&byte) = bytes.next() {
+            buf.push(byte);
+            match byte {
+                IAC => buf.push(IAC),
+                // A bare CR must be followed by NUL (RFC 854)
+                b'\r' if bytes.peek() != Some(&&b'\n') => buf.push(0),
+                _ => {}
+            }
+        }
+        self.out.send(buf).map_err(|_| "Connection closed".to_string())
+    }
+
+    fn resize(&mut self, cols: u32, rows: u32) -> Result<(), String> {
+        let mut state = self.negotiation.lock().unwrap();
+        state.cols = cols as u16;
+        state.rows = rows as u16;
+        if state.local[OPT_NAWS as usize] {
+            self.out
+                .send(state.naws())
+                .map_err(|_| "Connection closed".to_string())?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TelnetSession {
+    fn drop(&mut self) {
+        self.reader.abort();
+        self.writer.abort();
+    }
+}
@@ -0,0 +1,6 @@
+/// The terminal behind a tab that isn't an SSH channel (a local shell, Mosh,
+/// telnet). Output is delivered as `SshEvent`s, like SSH terminals.
+pub trait TerminalSession: Send {
+    fn write(&mut self, data: &[u8]) -> Result<(), String>;
+    fn resize(&mut self, cols: u32, rows: u32) -> Result<(), String>;
+}
@@ -0,0 +1,430 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::sftp::{ConflictPolicy, TransferProgress};
+
+/// How many queued transfers run at once on one SSH session
+pub const MAX_CONCURRENT_PER_SESSION: usize = 3;
+
+/// How queued transfers that hit a transient error are retried
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Attempts after the first one; 0 turns retrying off
+    pub max_retries: u32,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_delay_ms: 1000,
+            max_delay_ms: 30_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before retry number `retry` (from 0), doubling each time
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 1u64.checked_shl(retry).unwrap_or(u64::MAX);
+        Duration::from_millis(self.initial_delay_ms.saturating_mul(factor).min(self.max_delay_ms))
+    }
+}
+
+/// Whether a transfer error looks like a passing problem (a generic SFTP
+/// failure, a dropped channel or connection) rather than one that another
+/// attempt would run into again
+pub fn is_transient(error: &str) -> bool {
+    let error = error.to_lowercase();
+    let permanent = ["cancelled", "not found", "no such file", "permission denied", "disk full"];
+    if permanent.iter().any(|marker| error.contains(marker)) {
+        return false;
+    }
+    let transient = [
+        "failure",
+        "closed",
+        "eof",
+        "timeout",
+        "timed out",
+        "broken pipe",
+        "connection reset",
+        "connection lost",
+        "no connection",
+    ];
+    transient.iter().any(|marker| error.contains(marker))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferState {
+    Queued,
+    Running,
+    Paused,
+    Done,
+    Failed,
+}
+
+impl TransferState {
+    /// Queued, running or paused; what is worth picking up after a restart
+    pub fn is_unfinished(self) -> bool {
+        matches!(self, Self::Queued | Self::Running | Self::Paused)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferItem {
+    pub id: String,
+    pub session_id: String,
+    /// Saved connection the session was opened from, so the transfer can be
+    /// offered again when it's next connected to
+    pub connection_id: String,
+    pub direction: TransferDirection,
+    pub local_path: String,
+    pub remote_path: String,
+    pub state: TransferState,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub error: Option<String>,
+    /// Times the transfer has been retried after a transient error
+    pub retries: u32,
+    /// What to do if the destination exists when the transfer first starts
+    #[serde(default)]
+    pub conflict_policy: ConflictPolicy,
+    /// Set once the transfer has been paused, so it continues where it
+    /// stopped instead of starting over
+    #[serde(skip)]
+    pub resume: bool,
+}
+
+impl TransferItem {
+    /// Whether a saved transfer may continue from what's already at the
+    /// destination: only one that had started, and for downloads only while
+    /// the local file is no shorter than what was reported done and no
+    /// longer than the remote file. Anything else was never ours or has
+    /// changed since, and appending to it would corrupt it.
+    fn can_resume(&self) -> bool {
+        let started = matches!(self.state, TransferState::Running | TransferState::Paused);
+        if !started || self.bytes_done == 0 {
+            return false;
+        }
+        match self.direction {
+            TransferDirection::Download => fs::metadata(&self.local_path)
+                .map(|meta| meta.len() >= self.bytes_done && meta.len() <= self.bytes_total)
+                .unwrap_or(false),
+            // The upload checks the remote size itself when it resumes
+            TransferDirection::Upload => true,
+        }
+    }
+}
+
+/// Ordered list of queued, running and finished transfers. The commands
+/// layer does the actual transferring; this only tracks what should run next.
+pub struct TransferQueue {
+    items: Mutex<Vec<TransferItem>>,
+    retry_policy: Mutex<RetryPolicy>,
+    /// Held while the queue is written to disk, so saves don't interleave
+    saving: Mutex<()>,
+}
+
+impl TransferQueue {
+    pub fn new() -> Self {
+        Self {
+            items: Mutex::new(Vec::new()),
+            retry_policy: Mutex::new(RetryPolicy::default()),
+            saving: Mutex::new(()),
+        }
+    }
+
+    /// Save the queue's unfinished transfers to `store`. Each save writes the
+    /// queue as it is once the previous save is done, so saves running out
+    /// of order never leave an older state on disk. Blocks on file I/O.
+    pub fn persist(&self, store: &PendingTransferStore) -> Result<(), String> {
+        let _saving = self.saving.lock().unwrap();
+        store.save(&self.list())
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        *self.retry_policy.lock().unwrap()
+    }
+
+    pub fn set_retry_policy(&self, policy: RetryPolicy) {
+        *self.retry_policy.lock().unwrap() = policy;
+    }
+
+    pub fn list(&self) -> Vec<TransferItem> {
+        self.items.lock().unwrap().clone()
+    }
+
+    pub fn enqueue(
+        &self,
+        session_id: String,
+        connection_id: String,
+        direction: TransferDirection,
+        local_path: String,
+        remote_path: String,
+        conflict_policy: ConflictPolicy,
+    ) -> String {
+        let id = Uuid::new_v4().to_string();
+        self.items.lock().unwrap().push(TransferItem {
+            id: id.clone(),
+            session_id,
+            connection_id,
+            direction,
+            local_path,
+            remote_path,
+            state: TransferState::Queued,
+            bytes_done: 0,
+            bytes_total: 0,
+            error: None,
+            retries: 0,
+            conflict_policy,
+            resume: false,
+        });
+        id
+    }
+
+    /// Queue transfers saved by an earlier run under `session_id`. Ones that
+    /// had started continue from whatever part made it across; ones already
+    /// in the queue are left alone. Returns the ids added.
+    pub fn restore(&self, saved: Vec<TransferItem>, session_id: &str) -> Vec<String> {
+        let mut items = self.items.lock().unwrap();
+        let mut added = Vec::new();
+        for mut item in saved {
+            if items.iter().any(|existing| existing.id == item.id) {
+                continue;
+            }
+            item.resume = item.can_resume();
+            item.session_id = session_id.to_string();
+            item.state = TransferState::Queued;
+            item.error = None;
+            added.push(item.id.clone());
+            items.push(item);
+        }
+        added
+    }
+
+    /// Mark as many queued transfers running as the per-session limit allows,
+    /// in queue order, and return them
+    pub fn start_next(&self) -> Vec<TransferItem> {
+        let mut items = self.items.lock().unwrap();
+        let mut running: HashMap<String, usize> = HashMap::new();
+        for item in items.iter().filter(|item| item.state == TransferState::Running) {
+            *running.entry(item.session_id.clone()).or_default() += 1;
+        }
+
+        let mut started = Vec::new();
+        for item in items.iter_mut() {
+            if item.state != TransferState::Queued {
+                continue;
+            }
+            let count = running.entry(item.session_id.clone()).or_default();
+            if *count < MAX_CONCURRENT_PER_SESSION {
+                *count += 1;
+                item.state = TransferState::Running;
+                item.error = None;
+                started.push(item.clone());
+            }
+        }
+        started
+    }
+
+    pub fn update_progress(&self, id: &str, progress: &TransferProgress) {
+        let mut items = self.items.lock().unwrap();
+        if let Some(item) = items.iter_mut().find(|item| item.id == id) {
+            item.bytes_done = progress.bytes_done;
+            item.bytes_total = progress.bytes_total;
+        }
+    }
+
+    /// Point a transfer at a new destination, such as a free name found
+    /// for it when its own was taken
+    pub fn set_destination(&self, id: &str, path: &str) {
+        let mut items = self.items.lock().unwrap();
+        if let Some(item) = items.iter_mut().find(|item| item.id == id) {
+            match item.direction {
+                TransferDirection::Download => item.local_path = path.to_string(),
+                TransferDirection::Upload => item.remote_path = path.to_string(),
+            }
+        }
+    }
+
+    /// Whether the transfer is still running, i.e. hasn't been paused
+    pub fn is_running(&self, id: &str) -> bool {
+        let items = self.items.lock().unwrap();
+        items
+            .iter()
+            .any(|item| item.id == id && item.state == TransferState::Running)
+    }
+
+    /// Note that a running transfer failed with `error` and will be retried
+    pub fn record_retry(&self, id: &str, error: &str) {
+        let mut items = self.items.lock().unwrap();
+        if let Some(item) = items.iter_mut().find(|item| item.id == id) {
+            item.retries += 1;
+            item.error = Some(error.to_string());
+        }
+    }
+
+    /// Record how a running transfer ended. A transfer that was paused while
+    /// running stays paused rather than showing the cancellation as a failure.
+    pub fn finish(&self, id: &str, result: Result<(), String>) {
+        let mut items = self.items.lock().unwrap();
+        let Some(item) = items.iter_mut().find(|item| item.id == id) else {
+            return;
+        };
+        if item.state != TransferState::Running {
+            return;
+        }
+        match result {
+            Ok(()) => {
+                item.state = TransferState::Done;
+                item.error = None;
+            }
+            Err(e) => {
+                item.state = TransferState::Failed;
+                item.error = Some(e);
+            }
+        }
+    }
+
+    /// Pause a queued or running transfer. Returns whether it was running, in
+    /// which case the caller has to stop it.
+    pub fn pause(&self, id: &str) -> Result<bool, String> {
+        let mut items = self.items.lock().unwrap();
+        let item = items
+            .iter_mut()
+            .find(|item| item.id == id)
+            .ok_or("Transfer not found")?;
+        let was_running = match item.state {
+            TransferState::Queued => false,
+            TransferState::Running => true,
+            _ => return Err("Only queued or running transfers can be paused".to_string()),
+        };
+        item.state = TransferState::Paused;
+        item.resume = true;
+        Ok(was_running)
+    }
+
+    /// Put a paused or failed transfer back in the queue
+    pub fn resume(&self, id: &str) -> Result<(), String> {
+        let mut items = self.items.lock().unwrap();
+        let item = items
+            .iter_mut()
+            .find(|item| item.id == id)
+            .ok_or("Transfer not found")?;
+        match item.state {
+            TransferState::Paused => {}
+            // Whatever made it across before the failure is kept
+            TransferState::Failed => item.resume = true,
+            _ => return Err("Only paused or failed transfers can be resumed".to_string()),
+        }
+        item.state = TransferState::Queued;
+        Ok(())
+    }
+
+    /// Move a transfer to `index` in the queue
+    pub fn reorder(&self, id: &str, index: usize) -> Result<(), String> {
+        let mut items = self.items.lock().unwrap();
+        let from = items
+            .iter()
+            .position(|item| item.id == id)
+            .ok_or("Transfer not found")?;
+        let item = items.remove(from);
+        let index = index.min(items.len());
+        items.insert(index, item);
+        Ok(())
+    }
+}
+
+/// Unfinished transfers saved across restarts, in `pending_transfers.json`
+pub struct PendingTransferStore {
+    file_path: PathBuf,
+}
+
+impl PendingTransferStore {
+    pub fn new(app_data_dir: PathBuf) -> Self {
+        let file_path = app_data_dir.join("pending_transfers.json");
+        Self { file_path }
+    }
+
+    fn load_all(&self) -> Vec<TransferItem> {
+        match fs::read_to_string(&self.file_path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_default(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn save_all(&self, items: &[TransferItem]) -> Result<(), String> {
+        if let Some(parent) = self.file_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let data = serde_json::to_string(items).map_err(|e| e.to_string())?;
+        fs::write(&self.file_path, data).map_err(|e| e.to_string())
+    }
+
+    /// Saved transfers of a connection
+    pub fn load(&self, connection_id: &str) -> Vec<TransferItem> {
+        let mut items = self.load_all();
+        items.retain(|item| item.connection_id == connection_id);
+        items
+    }
+
+    /// Save the unfinished transfers in `queue`. Saved transfers that aren't
+    /// in the queue (not restored since the restart) are kept.
+    pub fn save(&self, queue: &[TransferItem]) -> Result<(), String> {
+        let queued: HashSet<&str> = queue.iter().map(|item| item.id.as_str()).collect();
+        let mut items = self.load_all();
+        items.retain(|item| !queued.contains(item.id.as_str()));
+        items.extend(queue.iter().filter(|item| item.state.is_unfinished()).cloned());
+        self.save_all(&items)
+    }
+
+    /// Forget a connection's saved transfers
+    pub fn discard(&self, connection_id: &str) -> Result<(), String> {
+        let mut items = self.load_all();
+        items.retain(|item| item.connection_id != connection_id);
+        self.save_all(&items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_cap() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(0), Duration::from_millis(1000));
+        assert_eq!(policy.delay(1), Duration::from_millis(2000));
+        assert_eq!(policy.delay(4), Duration::from_millis(16_000));
+        assert_eq!(policy.delay(5), Duration::from_millis(30_000));
+        assert_eq!(policy.delay(63), Duration::from_millis(30_000));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_millis(30_000));
+    }
+
+    #[test]
+    fn transient_errors() {
+        assert!(is_transient("Failed to write remote file: Failure"));
+        assert!(is_transient("Channel closed"));
+        assert!(is_transient("Connection reset by peer"));
+        assert!(is_transient("Operation TIMED OUT"));
+        assert!(!is_transient("Transfer cancelled"));
+        assert!(!is_transient("Failed to open remote file: No such file"));
+        assert!(!is_transient("Failure: Permission denied"));
+        assert!(!is_transient("Invalid path"));
+    }
+}
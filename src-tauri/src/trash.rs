@@ -0,0 +1,153 @@
+use russh_sftp::client::SftpSession;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::sftp;
+
+/// Where deleted items go, relative to the remote home directory
+const TRASH_DIR: &str = ".rustssh-trash";
+/// Written next to each trashed item, holding the path it was deleted from
+const ORIGIN_FILE: &str = ".origin";
+
+/// Something that was moved to the trash
+#[derive(Debug, Clone, Serialize)]
+pub struct TrashItem {
+    /// Name of the item's `<timestamp>` directory in the trash
+    pub id: String,
+    pub name: String,
+    pub original_path: String,
+    /// Seconds since the epoch
+    pub deleted_at: u64,
+    pub is_dir: bool,
+}
+
+async fn trash_dir(sftp: &SftpSession) -> Result<String, String> {
+    Ok(sftp::join(&sftp::get_home_dir(sftp).await?, TRASH_DIR))
+}
+
+async fn exists(sftp: &SftpSession, path: &str) -> bool {
+    sftp.symlink_metadata(path).await.is_ok()
+}
+
+/// Move `path` into `~/.rustssh-trash/<timestamp>/` instead of deleting it.
+/// This is a rename, so it fails for paths on another filesystem than the
+/// home directory.
+pub async fn move_to_trash(sftp: &SftpSession, path: &str) -> Result<(), String> {
+    let trash = trash_dir(sftp).await?;
+    if path.starts_with(&format!("{}/", trash)) {
+        return Err(format!("{} is already in the trash", path));
+    }
+    if !exists(sftp, &trash).await {
+        sftp::mkdir(sftp, &trash).await?;
+    }
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let mut entry = sftp::join(&trash, &millis.to_string());
+    let mut n = 1;
+    // Batch deletes can land in the same millisecond
+    while exists(sftp, &entry).await {
+        entry = sftp::join(&trash, &format!("{}-{}", millis, n));
+        n += 1;
+    }
+    sftp::mkdir(sftp, &entry).await?;
+
+    let trash_item = async {
+        let origin = sftp::join(&entry, ORIGIN_FILE);
+        let mut file = sftp
+            .create(origin.as_str())
+            .await
+            .map_err(|e| format!("Failed to create {}: {}", origin, e))?;
+        file.write_all(path.as_bytes())
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", origin, e))?;
+        file.shutdown()
+            .await
+            .map_err(|e| format!("Failed to close {}: {}", origin, e))?;
+        sftp::rename(sftp, path, &sftp::join(&entry, sftp::file_name(path))).await
+    };
+    let result: Result<(), String> = trash_item.await;
+    if result.is_err() {
+        // Nothing was moved, so only the origin note can be in there
+        let _ = sftp::remove_tree(sftp, &entry).await;
+    }
+    result
+}
+
+async fn read_origin(sftp: &SftpSession, entry: &str) -> Result<String, String> {
+    let origin = sftp::join(entry, ORIGIN_FILE);
+    let mut file = sftp
+        .open(origin.as_str())
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", origin, e))?;
+    let mut path = String::new();
+    file.read_to_string(&mut path)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", origin, e))?;
+    Ok(path)
+}
+
+/// Everything in the trash, most recently deleted first. Entries that
+/// weren't made by `move_to_trash` are left out.
+pub async fn list(sftp: &SftpSession) -> Result<Vec<TrashItem>, String> {
+    let trash = trash_dir(sftp).await?;
+    if !exists(sftp, &trash).await {
+        return Ok(Vec::new());
+    }
+    let mut items = Vec::new();
+    for entry in sftp::list_dir(sftp, &trash, &sftp::ListFilter::default()).await? {
+        let Ok(original_path) = read_origin(sftp, &entry.path).await else {
+            continue;
+        };
+        let item = sftp::join(&entry.path, sftp::file_name(&original_path));
+        let Ok(attrs) = sftp.symlink_metadata(item.as_str()).await else {
+            continue;
+        };
+        // The id is `<millis>` or `<millis>-<n>`
+        let deleted_at = entry
+            .name
+            .split('-')
+            .next()
+            .and_then(|millis| millis.parse::<u64>().ok())
+            .map(|millis| millis / 1000)
+            .unwrap_or(0);
+        items.push(TrashItem {
+            name: sftp::file_name(&original_path).to_string(),
+            id: entry.name,
+            original_path,
+            deleted_at,
+            is_dir: attrs.is_dir(),
+        });
+    }
+    items.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+    Ok(items)
+}
+
+/// Put a trashed item back where it was deleted from and return that path.
+/// Refuses to overwrite something that has since taken its place.
+pub async fn restore(sftp: &SftpSession, id: &str) -> Result<String, String> {
+    if id.is_empty() || id.contains('/') {
+        return Err(format!("Invalid trash item: {}", id));
+    }
+    let entry = sftp::join(&trash_dir(sftp).await?, id);
+    let original_path = read_origin(sftp, &entry).await?;
+    if exists(sftp, &original_path).await {
+        return Err(format!("{} already exists", original_path));
+    }
+    let item = sftp::join(&entry, sftp::file_name(&original_path));
+    sftp::rename(sftp, &item, &original_path).await?;
+    sftp::remove_tree(sftp, &entry).await?;
+    Ok(original_path)
+}
+
+/// Permanently delete everything in the trash
+pub async fn empty(sftp: &SftpSession) -> Result<(), String> {
+    let trash = trash_dir(sftp).await?;
+    if !exists(sftp, &trash).await {
+        return Ok(());
+    }
+    sftp::remove_tree(sftp, &trash).await
+}
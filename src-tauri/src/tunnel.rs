@@ -0,0 +1,738 @@
+use russh::client;
+use russh::Channel;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::channels::{self, ChannelKind, ChannelRegistry, CountingStream};
+use crate::ssh::{ClientHandler, SshSession};
+
+/// Consecutive failed channel opens after which a tunnel is considered dead
+const MAX_CHANNEL_FAILURES: usize = 3;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum TunnelKind {
+    /// A local port forwarded to a fixed address via the server (`ssh -L`)
+    Local,
+    /// A port on the server forwarded back to a local service (`ssh -R`)
+    Remote,
+    /// A local SOCKS5 proxy whose connections leave from the server (`ssh -D`)
+    Dynamic,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelSpec {
+    pub kind: TunnelKind,
+    /// Address and port the listening side binds to; port 0 picks a free one
+    pub bind_host: String,
+    pub bind_port: u16,
+    /// Where forwarded connections are delivered; unused for dynamic tunnels
+    #[serde(default)]
+    pub target_host: String,
+    #[serde(default)]
+    pub target_port: u16,
+    /// Listen on any free port instead of failing when `bind_port` is taken
+    /// (local and dynamic tunnels)
+    #[serde(default)]
+    pub auto_port: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TunnelInfo {
+    pub id: String,
+    pub spec: TunnelSpec,
+    /// Port actually listening, which differs from the spec when it asked for 0
+    pub bound_port: u16,
+}
+
+/// A named set of forwards that can be attached to any session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelProfile {
+    pub id: String,
+    pub name: String,
+    pub tunnels: Vec<TunnelSpec>,
+}
+
+pub struct TunnelProfileStore {
+    file_path: PathBuf,
+}
+
+impl TunnelProfileStore {
+    pub fn new(app_data_dir: PathBuf) -> Self {
+        let file_path = app_data_dir.join("tunnel_profiles.json");
+        Self { file_path }
+    }
+
+    pub fn load(&self) -> Vec<TunnelProfile> {
+        if !self.file_path.exists() {
+            return Vec::new();
+        }
+        match fs::read_to_string(&self.file_path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_default(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn save(&self, profiles: &[TunnelProfile]) -> Result<(), String> {
+        if let Some(parent) = self.file_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let data = serde_json::to_string_pretty(profiles).map_err(|e| e.to_string())?;
+        fs::write(&self.file_path, data).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Insert `profile`, or replace the stored one with the same id
+    pub fn upsert(&self, profile: TunnelProfile) -> Result<Vec<TunnelProfile>, String> {
+        let mut profiles = self.load();
+        match profiles.iter().position(|p| p.id == profile.id) {
+            Some(pos) => profiles[pos] = profile,
+            None => profiles.push(profile),
+        }
+        self.save(&profiles)?;
+        Ok(profiles)
+    }
+
+    pub fn delete(&self, id: &str) -> Result<Vec<TunnelProfile>, String> {
+        let mut profiles = self.load();
+        profiles.retain(|p| p.id != id);
+        self.save(&profiles)?;
+        Ok(profiles)
+    }
+
+    /// The forwards of the given profiles, in order
+    pub fn specs(&self, ids: &[String]) -> Result<Vec<TunnelSpec>, String> {
+        let profiles = self.load();
+        let mut specs = Vec::new();
+        for id in ids {
+            let profile = profiles
+                .iter()
+                .find(|p| &p.id == id)
+                .ok_or_else(|| format!("Tunnel profile {} not found", id))?;
+            specs.extend(profile.tunnels.iter().cloned());
+        }
+        Ok(specs)
+    }
+}
+
+/// Live traffic counters shared by a tunnel's connections
+#[derive(Debug, Default)]
+pub struct TunnelStats {
+    /// Bytes delivered to the local side
+    bytes_in: AtomicU64,
+    /// Bytes sent from the local side
+    bytes_out: AtomicU64,
+    active_connections: AtomicUsize,
+    /// Unix time (seconds) of the last transfer; 0 if the tunnel was never used
+    last_activity: AtomicU64,
+    /// Channel opens in a row the server failed (not counting unreachable targets)
+    channel_failures: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TunnelStatsSnapshot {
+    pub tunnel_id: String,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub active_connections: usize,
+    pub last_activity: Option<u64>,
+}
+
+impl TunnelStats {
+    fn record(&self, counter: &AtomicU64, bytes: usize) {
+        if bytes == 0 {
+            return;
+        }
+        counter.fetch_add(bytes as u64, Ordering::Relaxed);
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.last_activity.store(now, Ordering::Relaxed);
+    }
+
+    /// Track whether the server is still opening channels for this tunnel. A
+    /// refused open (target unreachable) says nothing about the tunnel itself.
+    fn record_channel_open<T>(&self, result: &Result<T, russh::Error>) {
+        match result {
+            Ok(_) | Err(russh::Error::ChannelOpenFailure(_)) => {
+                self.channel_failures.store(0, Ordering::Relaxed)
+            }
+            Err(_) => {
+                self.channel_failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn snapshot(&self, tunnel_id: &str) -> TunnelStatsSnapshot {
+        let last_activity = self.last_activity.load(Ordering::Relaxed);
+        TunnelStatsSnapshot {
+            tunnel_id: tunnel_id.to_string(),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+            last_activity: (last_activity > 0).then_some(last_activity),
+        }
+    }
+}
+
+/// Local socket wrapper that counts the bytes passing through it
+struct Counted<'a> {
+    inner: TcpStream,
+    stats: &'a TunnelStats,
+}
+
+impl AsyncRead for Counted<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            self.stats.record(&self.stats.bytes_out, buf.filled().len() - before);
+        }
+        result
+    }
+}
+
+impl AsyncWrite for Counted<'_> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            self.stats.record(&self.stats.bytes_in, written);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Copy both ways between a local socket and a forwarding channel until
+/// either side closes or the channel inspector closes it
+async fn relay(
+    stats: &TunnelStats,
+    registry: &ChannelRegistry,
+    local: TcpStream,
+    channel: Channel<client::Msg>,
+    target: &str,
+) {
+    let (counters, close) =
+        channels::register(registry, channel.id(), ChannelKind::Forward, target).await;
+    stats.active_connections.fetch_add(1, Ordering::Relaxed);
+    let mut local = Counted { inner: local, stats };
+    let mut remote = CountingStream::new(channel.into_stream(), counters);
+    tokio::select! {
+        _ = tokio::io::copy_bidirectional(&mut local, &mut remote) => {}
+        Ok(()) = close => {}
+    }
+    stats.active_connections.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Where the server's connections for one remote forward are delivered
+#[derive(Clone)]
+pub struct RemoteForward {
+    pub target: (String, u16),
+    pub stats: Arc<TunnelStats>,
+}
+
+/// The session's remote forwards, keyed by the address and port the server
+/// listens on
+pub type RemoteForwards = Arc<Mutex<HashMap<(String, u32), RemoteForward>>>;
+
+/// The parts of a session tunnels are started with, cloned out of it so a
+/// tunnel can be restarted without holding the sessions lock
+#[derive(Clone)]
+pub struct TunnelContext {
+    pub handle: Arc<client::Handle<ClientHandler>>,
+    pub channel_registry: ChannelRegistry,
+    pub remote_forwards: RemoteForwards,
+}
+
+impl TunnelContext {
+    pub fn new(session: &SshSession) -> Self {
+        Self {
+            handle: session.handle.clone(),
+            channel_registry: session.channel_registry.clone(),
+            remote_forwards: session.remote_forwards.clone(),
+        }
+    }
+}
+
+pub struct Tunnel {
+    pub info: TunnelInfo,
+    pub stats: Arc<TunnelStats>,
+    /// Accept loop of local and dynamic tunnels
+    listener: Option<JoinHandle<()>>,
+}
+
+impl Tunnel {
+    /// Whether the tunnel still forwards: its accept loop is running and the
+    /// server hasn't failed its last few channels
+    pub fn is_healthy(&self) -> bool {
+        let listening = !self.listener.as_ref().is_some_and(|l| l.is_finished());
+        listening && self.stats.channel_failures.load(Ordering::Relaxed) < MAX_CHANNEL_FAILURES
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        if let Some(listener) = self.listener.take() {
+            listener.abort();
+        }
+    }
+}
+
+/// Start a tunnel of any kind
+pub async fn open(session: &SshSession, spec: TunnelSpec) -> Result<Tunnel, String> {
+    let context = TunnelContext::new(session);
+    let stats = Arc::new(TunnelStats::default());
+    match spec.kind {
+        TunnelKind::Remote => open_remote(&context, spec, stats).await,
+        TunnelKind::Local | TunnelKind::Dynamic => open_local(&context, spec, stats).await,
+    }
+}
+
+/// Re-establish a dead tunnel on the port it had, keeping its id and counters.
+/// If that fails the tunnel stays unhealthy, so the next check tries again.
+pub async fn restart(context: &TunnelContext, tunnel: &mut Tunnel) -> Result<(), String> {
+    let mut spec = tunnel.info.spec.clone();
+    spec.bind_port = tunnel.info.bound_port;
+    spec.auto_port = false;
+
+    if let Some(listener) = tunnel.listener.take() {
+        // Wait for the old accept loop to go so its port is free again
+        listener.abort();
+        let _ = listener.await;
+    }
+    if spec.kind == TunnelKind::Remote {
+        let key = (spec.bind_host.clone(), tunnel.info.bound_port as u32);
+        context.remote_forwards.lock().await.remove(&key);
+        let _ = context.handle.cancel_tcpip_forward(key.0, key.1).await;
+    }
+
+    let fresh = match spec.kind {
+        TunnelKind::Remote => open_remote(context, spec, tunnel.stats.clone()).await,
+        TunnelKind::Local | TunnelKind::Dynamic => {
+            open_local(context, spec, tunnel.stats.clone()).await
+        }
+    };
+    let mut fresh = match fresh {
+        Ok(fresh) => fresh,
+        Err(e) => {
+            tunnel.stats.channel_failures.store(MAX_CHANNEL_FAILURES, Ordering::Relaxed);
+            return Err(e);
+        }
+    };
+    tunnel.stats.channel_failures.store(0, Ordering::Relaxed);
+    tunnel.info.bound_port = fresh.info.bound_port;
+    tunnel.listener = fresh.listener.take();
+    Ok(())
+}
+
+/// Listen locally on `spec.bind_host:bind_port` and carry each accepted
+/// connection over a direct-tcpip channel, to the fixed target for local
+/// tunnels or to wherever the SOCKS5 client asks for dynamic ones
+async fn open_local(
+    context: &TunnelContext,
+    spec: TunnelSpec,
+    stats: Arc<TunnelStats>,
+) -> Result<Tunnel, String> {
+    let listener = match TcpListener::bind((spec.bind_host.as_str(), spec.bind_port)).await {
+        Ok(listener) => listener,
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse && spec.auto_port => {
+            TcpListener::bind((spec.bind_host.as_str(), 0))
+                .await
+                .map_err(|e| format!("Failed to listen on {}: {}", spec.bind_host, e))?
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            return Err(format!(
+                "Port {} on {} is already in use",
+                spec.bind_port, spec.bind_host
+            ));
+        }
+        Err(e) => {
+            return Err(format!(
+                "Failed to listen on {}:{}: {}",
+                spec.bind_host, spec.bind_port, e
+            ));
+        }
+    };
+    let bound_port = listener
+        .local_addr()
+        .map_err(|e| e.to_string())?
+        .port();
+
+    let handle = context.handle.clone();
+    let registry = context.channel_registry.clone();
+    let kind = spec.kind;
+    let target = (spec.target_host.clone(), spec.target_port);
+    let tunnel_stats = stats.clone();
+    let task = tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Ends the loop; the health check brings the listener back
+                    log::warn!("Tunnel accept failed: {}", e);
+                    break;
+                }
+            };
+            let handle = handle.clone();
+            let registry = registry.clone();
+            let target = target.clone();
+            let stats = tunnel_stats.clone();
+            tokio::spawn(async move {
+                let result = match kind {
+                    TunnelKind::Dynamic => {
+                        serve_socks5(&handle, &registry, &stats, stream, peer).await
+                    }
+                    _ => serve_local(&handle, &registry, &stats, stream, peer, target).await,
+                };
+                if let Err(e) = result {
+                    log::warn!("Tunnel connection from {} failed: {}", peer, e);
+                }
+            });
+        }
+    });
+
+    Ok(Tunnel {
+        info: TunnelInfo {
+            id: Uuid::new_v4().to_string(),
+            spec,
+            bound_port,
+        },
+        stats,
+        listener: Some(task),
+    })
+}
+
+pub async fn serve_local(
+    handle: &client::Handle<ClientHandler>,
+    registry: &ChannelRegistry,
+    stats: &TunnelStats,
+    stream: TcpStream,
+    peer: SocketAddr,
+    target: (String, u16),
+) -> Result<(), String> {
+    let label = format!("{}:{}", target.0, target.1);
+    let channel = handle
+        .channel_open_direct_tcpip(
+            target.0,
+            target.1 as u32,
+            peer.ip().to_string(),
+            peer.port() as u32,
+        )
+        .await;
+    stats.record_channel_open(&channel);
+    let channel = channel.map_err(|e| format!("Failed to open forwarding channel: {}", e))?;
+    relay(stats, registry, stream, channel, &label).await;
+    Ok(())
+}
+
+/// Answer a SOCKS5 CONNECT (no authentication) and relay it through the server
+async fn serve_socks5(
+    handle: &client::Handle<ClientHandler>,
+    registry: &ChannelRegistry,
+    stats: &TunnelStats,
+    mut stream: TcpStream,
+    peer: SocketAddr,
+) -> Result<(), String> {
+    let io_err = |e: std::io::Error| format!("SOCKS5 I/O failed: {}", e);
+    let (host, port) = socks5_request(&mut stream).await?;
+
+    let channel = handle
+        .channel_open_direct_tcpip(
+            host.clone(),
+            port as u32,
+            peer.ip().to_string(),
+            peer.port() as u32,
+        )
+        .await;
+    stats.record_channel_open(&channel);
+    let channel = match channel {
+        Ok(channel) => channel,
+        Err(e) => {
+            let _ = stream.write_all(&socks5_reply(0x05)).await;
+            return Err(format!("Server could not reach {}:{}: {}", host, port, e));
+        }
+    };
+    stream.write_all(&socks5_reply(0x00)).await.map_err(io_err)?;
+
+    relay(stats, registry, stream, channel, &format!("{}:{}", host, port)).await;
+    Ok(())
+}
+
+/// Negotiate no authentication with a SOCKS5 client and read its CONNECT
+/// request, returning the host and port it asks for. Requests that can't be
+/// served are answered with an error reply.
+async fn socks5_request<S>(stream: &mut S) -> Result<(String, u16), String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let io_err = |e: std::io::Error| format!("SOCKS5 I/O failed: {}", e);
+
+    let mut greeting = [0u8; 2];
+    stream.read_exact(&mut greeting).await.map_err(io_err)?;
+    if greeting[0] != 0x05 {
+        return Err("Not a SOCKS5 client".to_string());
+    }
+    let mut methods = vec![0u8; greeting[1] as usize];
+    stream.read_exact(&mut methods).await.map_err(io_err)?;
+    if !methods.contains(&0x00) {
+        let _ = stream.write_all(&[0x05, 0xff]).await;
+        return Err("SOCKS5 client does not support unauthenticated access".to_string());
+    }
+    stream.write_all(&[0x05, 0x00]).await.map_err(io_err)?;
+
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request).await.map_err(io_err)?;
+    let host = match request[3] {
+        0x01 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await.map_err(io_err)?;
+            std::net::Ipv4Addr::from(ip).to_string()
+        }
+        0x03 => {
+            let len = stream.read_u8().await.map_err(io_err)? as usize;
+            let mut name = vec![0u8; len];
+            stream.read_exact(&mut name).await.map_err(io_err)?;
+            String::from_utf8_lossy(&name).into_owned()
+        }
+        0x04 => {
+            let mut ip = [0u8; 16];
+            stream.read_exact(&mut ip).await.map_err(io_err)?;
+            std::net::Ipv6Addr::from(ip).to_string()
+        }
+        _ => {
+            let _ = stream.write_all(&socks5_reply(0x08)).await;
+            return Err("Unsupported SOCKS5 address type".to_string());
+        }
+    };
+    let port = stream.read_u16().await.map_err(io_err)?;
+
+    // Only CONNECT can be carried by a direct-tcpip channel
+    if request[1] != 0x01 {
+        let _ = stream.write_all(&socks5_reply(0x07)).await;
+        return Err(format!("Unsupported SOCKS5 command {}", request[1]));
+    }
+    Ok((host, port))
+}
+
+/// A SOCKS5 reply with the given status and an all-zero bound address
+fn socks5_reply(status: u8) -> [u8; 10] {
+    [0x05, status, 0x00, 0x01, 0, 0, 0, 0, 0, 0]
+}
+
+/// Ask the server to listen on `spec.bind_host:bind_port` and route the
+/// connections it accepts to `spec.target_host:target_port`
+async fn open_remote(
+    context: &TunnelContext,
+    spec: TunnelSpec,
+    stats: Arc<TunnelStats>,
+) -> Result<Tunnel, String> {
+    let bound_port = context
+        .handle
+        .tcpip_forward(spec.bind_host.clone(), spec.bind_port as u32)
+        .await
+        .map_err(|e| format!("Remote forward request failed: {}", e))?;
+    // The server only reports the port when it chose one
+    let bound_port = if spec.bind_port == 0 {
+        bound_port as u16
+    } else {
+        spec.bind_port
+    };
+
+    context.remote_forwards.lock().await.insert(
+        (spec.bind_host.clone(), bound_port as u32),
+        RemoteForward {
+            target: (spec.target_host.clone(), spec.target_port),
+            stats: stats.clone(),
+        },
+    );
+
+    Ok(Tunnel {
+        info: TunnelInfo {
+            id: Uuid::new_v4().to_string(),
+            spec,
+            bound_port,
+        },
+        stats,
+        listener: None,
+    })
+}
+
+/// Stop a tunnel and release its listening port
+pub async fn close(session: &SshSession, tunnel: Tunnel) -> Result<(), String> {
+    let info = tunnel.info.clone();
+    match info.spec.kind {
+        // Dropping the tunnel stops its accept loop
+        TunnelKind::Local | TunnelKind::Dynamic => Ok(()),
+        TunnelKind::Remote => {
+            session
+                .remote_forwards
+                .lock()
+                .await
+                .remove(&(info.spec.bind_host.clone(), info.bound_port as u32));
+            session
+                .handle
+                .cancel_tcpip_forward(info.spec.bind_host, info.bound_port as u32)
+                .await
+                .map_err(|e| format!("Failed to cancel remote forward: {}", e))
+        }
+    }
+}
+
+/// Find the remote forward a forwarded-tcpip channel belongs to. Servers may
+/// report a different spelling of the bind address (e.g. "" for
+/// "localhost"), so fall back to matching on the port alone.
+pub async fn remote_target(
+    remote_forwards: &RemoteForwards,
+    address: &str,
+    port: u32,
+) -> Option<RemoteForward> {
+    let forwards = remote_forwards.lock().await;
+    if let Some(forward) = forwards.get(&(address.to_string(), port)) {
+        return Some(forward.clone());
+    }
+    let mut by_port = forwards.iter().filter(|((_, p), _)| *p == port);
+    match (by_port.next(), by_port.next()) {
+        (Some((_, forward)), None) => Some(forward.clone()),
+        _ => None,
+    }
+}
+
+/// Pipe a channel opened by the server into a new connection to the forward's target
+pub async fn serve_forwarded(
+    channel: Channel<client::Msg>,
+    forward: RemoteForward,
+    registry: ChannelRegistry,
+) {
+    // The server delivering a connection shows the forward still works
+    forward.stats.record_channel_open(&Ok::<_, russh::Error>(()));
+    let (host, port) = &forward.target;
+    match TcpStream::connect((host.as_str(), *port)).await {
+        Ok(local) => {
+            let label = format!("{}:{}", host, port);
+            relay(&forward.stats, &registry, local, channel, &label).await
+        }
+        Err(e) => {
+            log::warn!("Remote forward to {}:{} failed: {}", host, port, e);
+            let _ = channel.close().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `socks5_request` against a client that sends `input`, returning
+    /// its result and everything it wrote back
+    async fn socks5(input: &[u8]) -> (Result<(String, u16), String>, Vec<u8>) {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(input).await.unwrap();
+        let result = socks5_request(&mut server).await;
+        drop(server);
+        let mut replies = Vec::new();
+        client.read_to_end(&mut replies).await.unwrap();
+        (result, replies)
+    }
+
+    fn forwards(keys: &[(&str, u32)]) -> RemoteForwards {
+        let forwards = keys
+            .iter()
+            .map(|(host, port)| {
+                let forward = RemoteForward {
+                    target: (format!("target-of-{}", host), *port as u16),
+                    stats: Arc::default(),
+                };
+                ((host.to_string(), *port), forward)
+            })
+            .collect();
+        Arc::new(Mutex::new(forwards))
+    }
+
+    #[test]
+    fn socks5_reply_carries_the_status() {
+        assert_eq!(socks5_reply(0x00), [0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(socks5_reply(0x07)[1], 0x07);
+    }
+
+    #[tokio::test]
+    async fn socks5_reads_each_address_type() {
+        let ipv4 = [5, 1, 0, 5, 1, 0, 1, 10, 0, 0, 1, 0, 80];
+        let (result, replies) = socks5(&ipv4).await;
+        assert_eq!(result.unwrap(), ("10.0.0.1".to_string(), 80));
+        assert_eq!(replies, [0x05, 0x00]);
+
+        let mut name = vec![5, 1, 0, 5, 1, 0, 3, 11];
+        name.extend_from_slice(b"example.org");
+        name.extend_from_slice(&443u16.to_be_bytes());
+        assert_eq!(socks5(&name).await.0.unwrap(), ("example.org".to_string(), 443));
+
+        let mut ipv6 = vec![5, 1, 0, 5, 1, 0, 4];
+        ipv6.extend_from_slice(&std::net::Ipv6Addr::LOCALHOST.octets());
+        ipv6.extend_from_slice(&22u16.to_be_bytes());
+        assert_eq!(socks5(&ipv6).await.0.unwrap(), ("::1".to_string(), 22));
+    }
+
+    #[tokio::test]
+    async fn socks5_refuses_what_it_cant_serve() {
+        let (result, _) = socks5(&[4, 1, 0]).await;
+        assert_eq!(result.unwrap_err(), "Not a SOCKS5 client");
+
+        // Only username/password offered
+        let (result, replies) = socks5(&[5, 1, 2]).await;
+        assert!(result.is_err());
+        assert_eq!(replies, [0x05, 0xff]);
+
+        // BIND
+        let (result, replies) = socks5(&[5, 1, 0, 5, 2, 0, 1, 127, 0, 0, 1, 0, 80]).await;
+        assert_eq!(result.unwrap_err(), "Unsupported SOCKS5 command 2");
+        assert_eq!(replies[2..], socks5_reply(0x07));
+
+        let (result, replies) = socks5(&[5, 1, 0, 5, 1, 0, 9]).await;
+        assert_eq!(result.unwrap_err(), "Unsupported SOCKS5 address type");
+        assert_eq!(replies[2..], socks5_reply(0x08));
+    }
+
+    #[tokio::test]
+    async fn remote_target_prefers_the_exact_address() {
+        let forwards = forwards(&[("localhost", 8080), ("0.0.0.0", 8080)]);
+        let forward = remote_target(&forwards, "0.0.0.0", 8080).await.unwrap();
+        assert_eq!(forward.target.0, "target-of-0.0.0.0");
+        // Ambiguous on the port alone
+        assert!(remote_target(&forwards, "", 8080).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn remote_target_falls_back_to_the_port() {
+        let forwards = forwards(&[("localhost", 8080), ("localhost", 9090)]);
+        let forward = remote_target(&forwards, "", 9090).await.unwrap();
+        assert_eq!(forward.target, ("target-of-localhost".to_string(), 9090));
+        assert!(remote_target(&forwards, "localhost", 7070).await.is_none());
+    }
+}
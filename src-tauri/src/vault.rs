@@ -0,0 +1,256 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// Marks a file written by `VaultKey::seal`
+const FORMAT: &str = "rustssh-vault";
+const VERSION: u32 = 1;
+/// Argon2id cost for new keys: 64 MiB, 3 passes
+const KDF_MEMORY_KIB: u32 = 64 * 1024;
+const KDF_PASSES: u32 = 3;
+const KDF_PARALLELISM: u32 = 1;
+/// Most a file may ask Argon2 for; anything above is refused rather than
+/// let a crafted file exhaust memory or stall the app
+const MAX_KDF_MEMORY_KIB: u32 = 256 * 1024;
+const MAX_KDF_PASSES: u32 = 10;
+const MAX_KDF_PARALLELISM: u32 = 4;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+fn encode(data: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(data)
+}
+
+fn decode(data: &str) -> Result<Vec<u8>, String> {
+    base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| format!("Invalid encrypted data: {}", e))
+}
+
+/// Argon2id parameters a key was derived with, stored beside the data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub salt: String,
+    pub memory: u32,
+    pub passes: u32,
+    pub parallelism: u32,
+}
+
+/// Encrypted data as written to disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    pub format: String,
+    pub version: u32,
+    pub kdf: KdfParams,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+impl Envelope {
+    /// Parse `data` if it's an envelope; anything else, like plaintext JSON,
+    /// gives `None`
+    pub fn parse(data: &str) -> Option<Self> {
+        let envelope: Self = serde_json::from_str(data).ok()?;
+        (envelope.format == FORMAT).then_some(envelope)
+    }
+}
+
+/// Whether the connection store is encrypted and, if so, unlocked
+#[derive(Debug, Clone, Serialize)]
+pub struct VaultStatus {
+    pub encrypted: bool,
+    pub unlocked: bool,
+}
+
+/// AES-256-GCM key derived from a master password
+#[derive(Clone)]
+pub struct VaultKey {
+    key: [u8; 32],
+    kdf: KdfParams,
+}
+
+impl VaultKey {
+    /// Derive a key with a fresh salt, for encrypting data anew
+    pub fn create(password: &str) -> Result<Self, String> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Self::derive(
+            password,
+            KdfParams {
+                salt: encode(&salt),
+                memory: KDF_MEMORY_KIB,
+                passes: KDF_PASSES,
+                parallelism: KDF_PARALLELISM,
+            },
+        )
+    }
+
+    /// Derive the key for `kdf`, which is refused if it asks for more than
+    /// the `MAX_KDF_*` limits. Deliberately slow; run it off the async
+    /// runtime.
+    pub fn derive(password: &str, kdf: KdfParams) -> Result<Self, String> {
+        if password.is_empty() {
+            return Err("The master password must not be empty".to_string());
+        }
+        if kdf.memory > MAX_KDF_MEMORY_KIB
+            || kdf.passes > MAX_KDF_PASSES
+            || kdf.parallelism > MAX_KDF_PARALLELISM
+        {
+            return Err("Unsupported key derivation parameters".to_string());
+        }
+        let salt = decode(&kdf.salt)?;
+        let params = argon2::Params::new(kdf.memory, kdf.passes, kdf.parallelism, Some(32))
+            .map_err(|e| format!("Invalid Argon2 parameters: {}", e))?;
+        let mut key = [0u8; 32];
+        argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), &salt, &mut key)
+            .map_err(|e| format!("Failed to derive key: {}", e))?;
+        Ok(Self { key, kdf })
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.key))
+    }
+
+    /// Encrypt `plaintext` into a serialized envelope
+    pub fn seal(&self, plaintext: &[u8]) -> Result<String, String> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher()
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| "Failed to encrypt data".to_string())?;
+        let envelope = Envelope {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            kdf: self.kdf.clone(),
+            nonce: encode(&nonce),
+            ciphertext: encode(&ciphertext),
+        };
+        serde_json::to_string_pretty(&envelope).map_err(|e| e.to_string())
+    }
+
+    /// Decrypt an envelope; fails if it was sealed with another key or was
+    /// tampered with
+    pub fn open(&self, envelope: &Envelope) -> Result<Vec<u8>, String> {
+        if envelope.version != VERSION {
+            return Err(format!("Unsupported encryption version {}", envelope.version));
+        }
+        let nonce = decode(&envelope.nonce)?;
+        if nonce.len() != NONCE_LEN {
+            return Err("Invalid encrypted data: bad nonce".to_string());
+        }
+        let ciphertext = decode(&envelope.ciphertext)?;
+        self.cipher()
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| "Wrong master password or damaged data".to_string())
+    }
+}
+
+/// Derive the key `envelope` was sealed with from `password`, checking it
+/// by decrypting the envelope. Slow, like `VaultKey::derive`.
+pub fn unlock(password: &str, envelope: &Envelope) -> Result<VaultKey, String> {
+    let key = VaultKey::derive(password, envelope.kdf.clone())?;
+    key.open(envelope)?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters, so the tests don't spend their time in Argon2
+    fn params() -> KdfParams {
+        KdfParams {
+            salt: encode(b"0123456789abcdef"),
+            memory: 64,
+            passes: 1,
+            parallelism: 1,
+        }
+    }
+
+    fn sealed(password: &str, plaintext: &[u8]) -> Envelope {
+        let key = VaultKey::derive(password, params()).unwrap();
+        Envelope::parse(&key.seal(plaintext).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn seal_and_open_round_trip() {
+        let key = VaultKey::derive("hunter2", params()).unwrap();
+        let data = key.seal(b"[{\"host\":\"example.com\"}]").unwrap();
+        assert!(!data.contains("example.com"));
+        let envelope = Envelope::parse(&data).unwrap();
+        assert_eq!(envelope.kdf, params());
+        assert_eq!(key.open(&envelope).unwrap(), b"[{\"host\":\"example.com\"}]");
+    }
+
+    #[test]
+    fn every_seal_gets_its_own_nonce() {
+        let key = VaultKey::derive("hunter2", params()).unwrap();
+        let first = Envelope::parse(&key.seal(b"data").unwrap()).unwrap();
+        let second = Envelope::parse(&key.seal(b"data").unwrap()).unwrap();
+        assert_ne!(first.nonce, second.nonce);
+        assert_ne!(first.ciphertext, second.ciphertext);
+    }
+
+    #[test]
+    fn unlock_needs_the_right_password() {
+        let envelope = sealed("hunter2", b"data");
+        assert_eq!(unlock("hunter2", &envelope).unwrap().open(&envelope).unwrap(), b"data");
+        assert_eq!(
+            unlock("hunter3", &envelope).err().as_deref(),
+            Some("Wrong master password or damaged data")
+        );
+    }
+
+    #[test]
+    fn open_refuses_damaged_envelopes() {
+        let key = VaultKey::derive("hunter2", params()).unwrap();
+        let envelope = sealed("hunter2", b"data");
+
+        let mut ciphertext = decode(&envelope.ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        let tampered = Envelope {
+            ciphertext: encode(&ciphertext),
+            ..envelope.clone()
+        };
+        assert!(key.open(&tampered).is_err());
+
+        let short_nonce = Envelope {
+            nonce: encode(&[0; 8]),
+            ..envelope.clone()
+        };
+        assert!(key.open(&short_nonce).is_err());
+
+        let newer = Envelope {
+            version: VERSION + 1,
+            ..envelope
+        };
+        assert!(key.open(&newer).is_err());
+    }
+
+    #[test]
+    fn derive_refuses_bad_input() {
+        assert!(VaultKey::derive("", params()).is_err());
+        let greedy = KdfParams {
+            memory: MAX_KDF_MEMORY_KIB + 1,
+            ..params()
+        };
+        assert!(VaultKey::derive("hunter2", greedy).is_err());
+        let slow = KdfParams {
+            passes: MAX_KDF_PASSES + 1,
+            ..params()
+        };
+        assert!(VaultKey::derive("hunter2", slow).is_err());
+    }
+
+    #[test]
+    fn parse_only_accepts_envelopes() {
+        assert!(Envelope::parse("[]").is_none());
+        assert!(Envelope::parse("not json").is_none());
+        let mut other = sealed("hunter2", b"data");
+        other.format = "something-else".to_string();
+        assert!(Envelope::parse(&serde_json::to_string(&other).unwrap()).is_none());
+    }
+}
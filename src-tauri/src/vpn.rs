@@ -0,0 +1,244 @@
+use serde::Serialize;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use crate::channels::ChannelRegistry;
+use crate::ssh::ClientHandler;
+use crate::tunnel::{self, TunnelStats};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VpnInfo {
+    pub subnets: Vec<String>,
+    /// Local port the redirected connections arrive on
+    pub port: u16,
+}
+
+/// sshuttle-style routing of whole subnets through a session: firewall rules
+/// redirect matching outgoing TCP connections to a local listener, which
+/// recovers each one's original destination and forwards it over a
+/// direct-tcpip channel. Linux (iptables) only; UDP and DNS are not routed.
+pub struct Vpn {
+    pub info: VpnInfo,
+    pub stats: Arc<TunnelStats>,
+    listener: JoinHandle<()>,
+    /// iptables nat chain holding this VPN's rules
+    chain: String,
+}
+
+impl Drop for Vpn {
+    fn drop(&mut self) {
+        self.listener.abort();
+        // Leaving the rules behind would black-hole the subnets, so clean up
+        // even when the session died without `stop`
+        let script = teardown_script(&self.chain);
+        std::thread::spawn(move || {
+            if let Err(e) = run_privileged_blocking(&script) {
+                log::warn!("Failed to remove VPN firewall rules: {}", e);
+            }
+        });
+    }
+}
+
+/// Parse an IPv4 `a.b.c.d/n` range (a bare address means `/32`)
+fn parse_cidr(cidr: &str) -> Result<(Ipv4Addr, u8), String> {
+    let invalid = || format!("Invalid IPv4 subnet: {}", cidr);
+    let (address, prefix) = cidr.trim().split_once('/').unwrap_or((cidr.trim(), "32"));
+    let address: Ipv4Addr = address.parse().map_err(|_| invalid())?;
+    let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+    if prefix > 32 {
+        return Err(invalid());
+    }
+    Ok((address, prefix))
+}
+
+/// Start routing `subnets` through the session connected to `host`:`port`.
+/// May wait on the user answering a polkit prompt.
+pub async fn start(
+    handle: Arc<russh::client::Handle<ClientHandler>>,
+    registry: ChannelRegistry,
+    host: &str,
+    port: u16,
+    subnets: Vec<String>,
+) -> Result<Vpn, String> {
+    if !cfg!(target_os = "linux") {
+        return Err("VPN mode is only supported on Linux".to_string());
+    }
+    if subnets.is_empty() {
+        return Err("No subnets to route".to_string());
+    }
+    let ranges = subnets
+        .iter()
+        .map(|s| parse_cidr(s))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    // The SSH connection itself must not be redirected into the tunnel
+    let server: Vec<Ipv4Addr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("Failed to resolve {}: {}", host, e))?
+        .filter_map(|addr| match addr {
+            SocketAddr::V4(v4) => Some(*v4.ip()),
+            SocketAddr::V6(_) => None,
+        })
+        .collect();
+
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(|e| format!("Failed to listen for VPN traffic: {}", e))?;
+    let listen_port = listener.local_addr().map_err(|e| e.to_string())?.port();
+    let chain = format!("RUSTSSH-VPN-{}", listen_port);
+
+    let mut script = vec![
+        format!("iptables -t nat -N {}", chain),
+        format!("iptables -t nat -A {} -d 127.0.0.0/8 -j RETURN", chain),
+    ];
+    for ip in &server {
+        script.push(format!(
+            "iptables -t nat -A {} -d {} -p tcp --dport {} -j RETURN",
+            chain, ip, port
+        ));
+    }
+    for (address, prefix) in &ranges {
+        script.push(format!(
+            "iptables -t nat -A {} -d {}/{} -p tcp -j REDIRECT --to-ports {}",
+            chain, address, prefix, listen_port
+        ));
+    }
+    script.push(format!("iptables -t nat -I OUTPUT 1 -j {}", chain));
+    let script = script.join(" && ");
+
+    if let Err(e) = run_privileged(&script).await {
+        // Undo whatever part of the rules made it in
+        let _ = run_privileged(&teardown_script(&chain)).await;
+        return Err(e);
+    }
+
+    let stats = Arc::new(TunnelStats::default());
+    let vpn_stats = stats.clone();
+    let task = tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::warn!("VPN accept failed: {}", e);
+                    break;
+                }
+            };
+            let handle = handle.clone();
+            let registry = registry.clone();
+            let stats = vpn_stats.clone();
+            tokio::spawn(async move {
+                let target = match original_destination(&stream) {
+                    Ok(target) => target,
+                    Err(e) => {
+                        log::warn!("VPN connection from {} has no destination: {}", peer, e);
+                        return;
+                    }
+                };
+                let target = (target.ip().to_string(), target.port());
+                let result =
+                    tunnel::serve_local(&handle, &registry, &stats, stream, peer, target).await;
+                if let Err(e) = result {
+                    log::warn!("VPN connection from {} failed: {}", peer, e);
+                }
+            });
+        }
+    });
+
+    Ok(Vpn {
+        info: VpnInfo {
+            subnets,
+            port: listen_port,
+        },
+        stats,
+        listener: task,
+        chain,
+    })
+}
+
+fn teardown_script(chain: &str) -> String {
+    format!(
+        "iptables -t nat -D OUTPUT -j {0}; iptables -t nat -F {0}; iptables -t nat -X {0}",
+        chain
+    )
+}
+
+/// Run a shell script as root, asking through polkit unless already root
+async fn run_privileged(script: &str) -> Result<(), String> {
+    let script = script.to_string();
+    tokio::task::spawn_blocking(move || run_privileged_blocking(&script))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Whether the app runs with root's effective user id, as under sudo
+#[cfg(target_os = "linux")]
+fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and can't fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_root() -> bool {
+    false
+}
+
+fn run_privileged_blocking(script: &str) -> Result<(), String> {
+    let mut cmd = if is_root() {
+        std::process::Command::new("sh")
+    } else {
+        let mut cmd = std::process::Command::new("pkexec");
+        cmd.arg("sh");
+        cmd
+    };
+    let output = cmd
+        .arg("-c")
+        .arg(script)
+        .output()
+        .map_err(|e| format!("Failed to run iptables: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Failed to set up VPN firewall rules: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// Where a connection redirected by iptables was originally headed
+#[cfg(target_os = "linux")]
+fn original_destination(stream: &TcpStream) -> std::io::Result<SocketAddr> {
+    use std::os::fd::AsRawFd;
+
+    // From <linux/netfilter_ipv4.h>
+    const SO_ORIGINAL_DST: libc::c_int = 80;
+
+    // SAFETY: getsockopt writes at most `len` bytes into `addr`, and an
+    // all-zero sockaddr_in is a valid value
+    let mut addr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_IP,
+            SO_ORIGINAL_DST,
+            &mut addr as *mut libc::sockaddr_in as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+    Ok(SocketAddr::new(ip.into(), u16::from_be(addr.sin_port)))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn original_destination(_stream: &TcpStream) -> std::io::Result<SocketAddr> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "VPN mode is only supported on Linux",
+    ))
+}
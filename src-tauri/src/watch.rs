@@ -0,0 +1,229 @@
+use notify::RecommendedWatcher;
+use russh_sftp::client::SftpSession;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+use crate::channels::ChannelRegistry;
+use crate::sftp::{self, BlockSignature, TransferOptions};
+use crate::ssh::ClientHandler;
+
+/// Folder `sftp_edit_file` downloads a session's files into
+pub fn edit_dir(session_id: &str) -> PathBuf {
+    std::env::temp_dir().join("rustssh-edit").join(session_id)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchInfo {
+    pub session_id: String,
+    pub local_path: String,
+    pub remote_path: String,
+    /// Copy the server's version aside before each upload replaces it
+    pub backup: bool,
+}
+
+/// Remote size and mtime, to notice when someone else changes the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RemoteStamp {
+    pub size: u64,
+    pub modified: Option<u64>,
+}
+
+/// The remote file's stamp, or `None` if it doesn't exist
+pub async fn remote_stamp(sftp: &SftpSession, path: &str) -> Option<RemoteStamp> {
+    let attrs = sftp.metadata(path).await.ok()?;
+    Some(RemoteStamp {
+        size: attrs.len(),
+        modified: attrs.mtime.map(u64::from),
+    })
+}
+
+/// Sync state of a watch, shared by its upload loop and the conflict commands
+#[derive(Debug, Default)]
+pub struct EditState {
+    /// What the last upload left on the server, so the next one can send
+    /// only the changed blocks
+    pub signature: Option<BlockSignature>,
+    /// The remote file when the local copy was last in step with it
+    pub remote: Option<RemoteStamp>,
+    /// SHA-256 of the local copy at that point; saves that leave it the same
+    /// aren't uploaded
+    pub local_hash: Option<String>,
+}
+
+pub type SharedEditState = Arc<Mutex<EditState>>;
+
+/// Active watches, keyed by local path
+pub type FileWatches = Mutex<HashMap<String, FileWatch>>;
+
+/// A local copy being synced back to the server. Dropping it stops the
+/// watcher, which ends the sync loop.
+pub struct FileWatch {
+    pub info: WatchInfo,
+    pub watcher: RecommendedWatcher,
+    pub state: SharedEditState,
+}
+
+/// How a save of the local copy was handled
+#[derive(Debug)]
+pub enum SaveOutcome {
+    /// Same content as last synced
+    Unchanged,
+    Uploaded {
+        bytes_sent: u64,
+        /// Where the replaced version was backed up to
+        backup: Option<String>,
+    },
+    /// The server's copy changed since it was last synced; nothing was sent
+    Conflict {
+        expected: Option<RemoteStamp>,
+        current: Option<RemoteStamp>,
+    },
+}
+
+/// What to do about a `SaveOutcome::Conflict`
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EditResolution {
+    /// Upload the local copy anyway
+    Overwrite,
+    /// Replace the local copy with the server's version
+    Reload,
+}
+
+/// Upload the saved local copy, unless it matches what was last synced or,
+/// without `force`, the server's copy has changed since then
+pub async fn sync_save(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    sftp: &SftpSession,
+    info: &WatchInfo,
+    state: &mut EditState,
+    force: bool,
+) -> Result<SaveOutcome, String> {
+    let hash = sftp::local_sha256(&info.local_path).await?;
+    if !force && state.local_hash.as_ref() == Some(&hash) {
+        return Ok(SaveOutcome::Unchanged);
+    }
+    let current = remote_stamp(sftp, &info.remote_path).await;
+    if !force && state.remote.is_some() && current != state.remote {
+        return Ok(SaveOutcome::Conflict {
+            expected: state.remote,
+            current,
+        });
+    }
+
+    let backup = match (info.backup, current) {
+        (true, Some(_)) => {
+            Some(backup_remote(handle, channel_registry, sftp, &info.remote_path).await?)
+        }
+        _ => None,
+    };
+
+    // Editors and services may read the file at any moment, so
+    // upload_delta never leaves it half-written
+    let (signature, bytes_sent) = sftp::upload_delta(
+        handle,
+        channel_registry,
+        sftp,
+        &info.local_path,
+        &info.remote_path,
+        state.signature.as_ref(),
+    )
+    .await?;
+    state.signature = Some(signature);
+    state.local_hash = Some(hash);
+    state.remote = remote_stamp(sftp, &info.remote_path).await;
+    Ok(SaveOutcome::Uploaded { bytes_sent, backup })
+}
+
+/// `YYYYMMDD-HHMMSS` in UTC for a Unix time
+fn utc_timestamp(secs: u64) -> String {
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60
+    )
+}
+
+/// Copy the server's current version of `path` to `path.bak-<timestamp>`
+/// beside it, for a one-step rollback. Returns the backup's path.
+pub async fn backup_remote(
+    handle: &russh::client::Handle<ClientHandler>,
+    channel_registry: &ChannelRegistry,
+    sftp: &SftpSession,
+    path: &str,
+) -> Result<String, String> {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let backup = format!("{}.bak-{}", path, utc_timestamp(secs));
+    if !sftp::copy_by_exec(handle, channel_registry, path, &backup).await? {
+        sftp::copy_by_sftp(sftp, path, &backup).await?;
+    }
+    Ok(backup)
+}
+
+/// Replace the local copy with the server's current version
+pub async fn reload(
+    sftp: &SftpSession,
+    info: &WatchInfo,
+    state: &mut EditState,
+) -> Result<(), String> {
+    let options = TransferOptions::default();
+    sftp::download_file(sftp, &info.remote_path, &info.local_path, options, |_| {}).await?;
+    // Recorded before the watcher sees the write, so it isn't sent back
+    state.local_hash = Some(sftp::local_sha256(&info.local_path).await?);
+    state.remote = remote_stamp(sftp, &info.remote_path).await;
+    // Any block may differ from what was last uploaded
+    state.signature = None;
+    Ok(())
+}
+
+/// Delete a watch's local copy if `sftp_edit_file` made it; files the user
+/// pointed the watch at elsewhere are left alone
+fn remove_temp_copy(info: &WatchInfo) {
+    let path = Path::new(&info.local_path);
+    if path.starts_with(edit_dir(&info.session_id)) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Stop watching `local_path` and delete its temp copy
+pub async fn unwatch(watches: &FileWatches, local_path: &str) -> Result<WatchInfo, String> {
+    let watch = watches
+        .lock()
+        .await
+        .remove(local_path)
+        .ok_or("File is not being watched")?;
+    remove_temp_copy(&watch.info);
+    Ok(watch.info)
+}
+
+/// Stop all of a session's watches and delete its edit folder
+pub async fn clear_session(watches: &FileWatches, session_id: &str) {
+    watches
+        .lock()
+        .await
+        .retain(|_, watch| watch.info.session_id != session_id);
+    let _ = std::fs::remove_dir_all(edit_dir(session_id));
+}
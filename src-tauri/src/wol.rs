@@ -0,0 +1,49 @@
+use std::time::Duration;
+use tokio::net::{TcpStream, UdpSocket};
+
+/// Parse a MAC address written as `aa:bb:cc:dd:ee:ff`, `aa-bb-...` or `aabbccddeeff`
+pub fn parse_mac(mac: &str) -> Result<[u8; 6], String> {
+    let hex: String = mac
+        .trim()
+        .chars()
+        .filter(|c| !matches!(c, ':' | '-' | '.'))
+        .collect();
+    if hex.len() != 12 || !hex.is_ascii() {
+        return Err(format!("Invalid MAC address: {}", mac));
+    }
+
+    let mut bytes = [0u8; 6];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("Invalid MAC address: {}", mac))?;
+    }
+    Ok(bytes)
+}
+
+/// Broadcast a Wake-on-LAN magic packet (6 x 0xFF followed by the MAC 16 times)
+pub async fn send_magic_packet(mac: &[u8; 6]) -> Result<(), String> {
+    let mut packet = vec![0xFF; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(mac);
+    }
+
+    let socket = UdpSocket::bind("0.0.0.0:0")
+        .await
+        .map_err(|e| format!("Failed to open UDP socket: {}", e))?;
+    socket
+        .set_broadcast(true)
+        .map_err(|e| format!("Failed to enable broadcast: {}", e))?;
+    socket
+        .send_to(&packet, "255.255.255.255:9")
+        .await
+        .map_err(|e| format!("Failed to send magic packet: {}", e))?;
+    Ok(())
+}
+
+/// Check once whether something accepts TCP connections on `host:port`
+pub async fn port_open(host: &str, port: u16, timeout: Duration) -> bool {
+    matches!(
+        tokio::time::timeout(timeout, TcpStream::connect((host, port))).await,
+        Ok(Ok(_))
+    )
+}